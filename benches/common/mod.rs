@@ -0,0 +1,99 @@
+//! Data generators shared by the benchmarks, and the tests of the library which include them.
+
+// Each benchmark uses some of them only.
+#![allow(dead_code)]
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// A record of a payload, with fields in alphabetical order as a [Value] would sort them.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    pub id: u64,
+    pub name: String,
+    pub tags: Vec<String>,
+}
+
+impl Record {
+    /// The record `id`, whose name needs escaping in JSON.
+    pub fn new(id: u64) -> Self {
+        Self {
+            id,
+            name: format!("record \"{id}\" \u{e9}"),
+            tags: vec!["alpha".to_string(), "beta".to_string()],
+        }
+    }
+}
+
+/// Records adding up to about `bytes` of JSON.
+pub fn records(bytes: usize) -> Vec<Record> {
+    let mut size = 1;
+
+    (0..)
+        .map(Record::new)
+        .take_while(|record| {
+            size += serde_json::to_string(record).unwrap().len() + 1;
+            size <= bytes.max(1)
+        })
+        .collect()
+}
+
+/// A payload of about `bytes` of JSON, an array of [Record]s.
+pub fn payload(bytes: usize) -> Value {
+    serde_json::to_value(records(bytes)).unwrap()
+}
+
+/// An action call frame, as sent by the server.
+pub fn action_frame(action: &str, action_id: u64, payload: &Value) -> Vec<u8> {
+    let message = json!({
+        "type": "action",
+        "data": {
+            "action": action,
+            "actionID": action_id,
+            "agentID": 1,
+            "payload": payload,
+            "payment": null,
+        },
+    });
+
+    serde_json::to_vec(&message).unwrap()
+}
+
+/// A search tools response body listing about `bytes` of actions.
+pub fn search_response(bytes: usize) -> String {
+    let mut size = 1;
+    let actions: Vec<Value> = (0..)
+        .map(|id| {
+            json!({
+                "action": format!("Toolkit/{id}/action_{id}"),
+                "description": format!("Action number {id}, doing something \"useful\""),
+                "payload": {
+                    "query": { "type": "string", "description": "The query", "required": true },
+                    "limit": { "type": "number", "description": "At most that many results" },
+                },
+                "payment": null,
+            })
+        })
+        .take_while(|action| {
+            size += action.to_string().len() + 1;
+            size <= bytes.max(1)
+        })
+        .collect();
+
+    Value::Array(actions).to_string()
+}
+
+// Run with the tests of the library, the benchmarks build without a test harness.
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_sizes() {
+        for bytes in [1024, 100 * 1024] {
+            let payload = super::payload(bytes).to_string().len();
+            assert!(payload <= bytes && payload > bytes * 9 / 10, "{payload}");
+
+            let response = super::search_response(bytes).len();
+            assert!(response <= bytes && response > bytes * 9 / 10, "{response}");
+        }
+    }
+}
@@ -0,0 +1,242 @@
+//! Dispatch of action calls, from the call frame received to the result message sent back.
+//!
+//! Run with `cargo bench --bench dispatch --features test-utils`, and compare against a baseline
+//! with `--save-baseline main` and `--baseline main`.
+
+mod common;
+
+use bytes::Bytes;
+use common::Record;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::{convert::Infallible, sync::Arc, time::Instant};
+use tokio::sync::Mutex;
+use unifai_sdk::toolkit::{
+    Action, ActionContext, ActionDefinition, ActionParams, ActionResult, ExecutionMode,
+    ToolkitService,
+};
+
+const SIZES: [(&str, usize); 3] = [
+    ("1kb", 1024),
+    ("100kb", 100 * 1024),
+    ("5mb", 5 * 1024 * 1024),
+];
+
+fn definition(description: &str) -> ActionDefinition {
+    ActionDefinition {
+        description: description.to_string(),
+        payload: json!({}),
+        payment: None,
+        ..Default::default()
+    }
+}
+
+struct Noop;
+
+impl Action for Noop {
+    const NAME: &'static str = "noop";
+
+    type Error = Infallible;
+    type Args = Value;
+    type Output = Value;
+
+    async fn definition(&self) -> ActionDefinition {
+        definition("Do nothing")
+    }
+
+    async fn call(
+        &self,
+        _ctx: ActionContext,
+        params: ActionParams<Self::Args>,
+    ) -> Result<ActionResult<Self::Output>, Self::Error> {
+        Ok(ActionResult {
+            payload: params.payload,
+            payment: None,
+        })
+    }
+}
+
+/// Records shared between calls, so the benchmark doesn't measure cloning them.
+#[derive(Clone)]
+struct Records(Arc<Vec<Record>>);
+
+impl Serialize for Records {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+struct Large(Records);
+
+impl Action for Large {
+    const NAME: &'static str = "large";
+
+    type Error = Infallible;
+    type Args = Value;
+    type Output = Records;
+
+    async fn definition(&self) -> ActionDefinition {
+        definition("Return 5 MB of records")
+    }
+
+    async fn call(
+        &self,
+        _ctx: ActionContext,
+        _params: ActionParams<Self::Args>,
+    ) -> Result<ActionResult<Self::Output>, Self::Error> {
+        Ok(ActionResult {
+            payload: self.0.clone(),
+            payment: None,
+        })
+    }
+}
+
+struct Count;
+
+impl Action for Count {
+    const NAME: &'static str = "count";
+
+    type Error = Infallible;
+    type Args = Vec<Record>;
+    type Output = usize;
+
+    async fn definition(&self) -> ActionDefinition {
+        definition("Count the records of the payload")
+    }
+
+    async fn call(
+        &self,
+        _ctx: ActionContext,
+        params: ActionParams<Self::Args>,
+    ) -> Result<ActionResult<Self::Output>, Self::Error> {
+        Ok(ActionResult {
+            payload: params.payload.len(),
+            payment: None,
+        })
+    }
+}
+
+/// Deserializes the records of the payload and serializes them back, through the bridge between
+/// the typed actions and the wire.
+struct Echo;
+
+impl Action for Echo {
+    const NAME: &'static str = "echo";
+
+    type Error = Infallible;
+    type Args = Vec<Record>;
+    type Output = Vec<Record>;
+
+    async fn definition(&self) -> ActionDefinition {
+        definition("Return the records of the payload")
+    }
+
+    async fn call(
+        &self,
+        _ctx: ActionContext,
+        params: ActionParams<Self::Args>,
+    ) -> Result<ActionResult<Self::Output>, Self::Error> {
+        Ok(ActionResult {
+            payload: params.payload,
+            payment: None,
+        })
+    }
+}
+
+fn dispatch(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+
+    let mut service = ToolkitService::new("bench");
+    service.add_action(Noop);
+    service.add_action(Large(Records(Arc::new(common::records(5 * 1024 * 1024)))));
+    service.add_action(Count);
+    service.add_action(Echo);
+    let service = Arc::new(service);
+
+    let noop = common::action_frame("noop", 1, &Value::Null);
+    let large = common::action_frame("large", 1, &Value::Null);
+    let records = common::payload(1024 * 1024);
+    let payload = common::action_frame("count", 1, &records);
+    let string_payload = common::action_frame("count", 1, &Value::String(records.to_string()));
+
+    c.bench_function("dispatch_noop", |b| {
+        b.to_async(&runtime)
+            .iter(|| service.clone().__handle_frame(&noop))
+    });
+
+    let mut group = c.benchmark_group("dispatch_large");
+    group.sample_size(20);
+    group.bench_function("5mb_result", |b| {
+        b.to_async(&runtime)
+            .iter(|| service.clone().__handle_frame(&large))
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("dispatch_payload");
+    group.sample_size(20);
+    group.bench_function("1mb_payload", |b| {
+        b.to_async(&runtime)
+            .iter(|| service.clone().__handle_frame(&payload))
+    });
+    group.bench_function("1mb_string_payload", |b| {
+        b.to_async(&runtime)
+            .iter(|| service.clone().__handle_frame(&string_payload))
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("bridge");
+    group.sample_size(20);
+    for (name, bytes) in SIZES {
+        let frame = common::action_frame("echo", 1, &common::payload(bytes));
+        group.throughput(Throughput::Bytes(frame.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &frame, |b, frame| {
+            b.to_async(&runtime)
+                .iter(|| service.clone().__handle_frame(frame))
+        });
+    }
+    group.finish();
+}
+
+/// A no-op call through the whole service: the frame goes through the transport and the run loop,
+/// the action runs on its own task or inline, and the result comes back through the transport.
+fn transport(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let noop = Bytes::from(common::action_frame("noop", 1, &Value::Null));
+    for (name, mode) in [
+        ("transport_noop", ExecutionMode::Spawned),
+        ("transport_noop_inline", ExecutionMode::Inline),
+    ] {
+        let mut service = ToolkitService::new("bench");
+        service.add_action_with_mode(Noop, mode);
+        let (handle, peer) = runtime.block_on(async { service.__start_over_channels() });
+        let peer = Arc::new(Mutex::new(peer));
+
+        c.bench_function(name, |b| {
+            b.to_async(&runtime).iter_custom(|iters| {
+                let (peer, noop) = (peer.clone(), noop.clone());
+                async move {
+                    let mut peer = peer.lock().await;
+                    let started = Instant::now();
+                    for _ in 0..iters {
+                        peer.send(noop.clone());
+                        black_box(peer.recv().await.unwrap());
+                    }
+                    started.elapsed()
+                }
+            })
+        });
+
+        runtime.block_on(async { peer.lock().await.close() });
+        runtime.block_on(handle).unwrap().unwrap();
+    }
+}
+
+criterion_group!(benches, dispatch, transport);
+criterion_main!(benches);
@@ -0,0 +1,53 @@
+//! Encoding and decoding of the messages exchanged with the server, without calling any action.
+//!
+//! Run with `cargo bench --bench messages`.
+
+mod common;
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use serde_json::value::to_raw_value;
+use unifai_sdk::toolkit::messages::{ActionCallResult, ToolkitMessage};
+
+const SIZES: [(&str, usize); 3] = [
+    ("1kb", 1024),
+    ("100kb", 100 * 1024),
+    ("5mb", 5 * 1024 * 1024),
+];
+
+fn messages(c: &mut Criterion) {
+    let mut group = c.benchmark_group("action_round_trip");
+    for (name, bytes) in SIZES {
+        let frame = common::action_frame("echo", 1, &common::payload(bytes));
+        group.throughput(Throughput::Bytes(frame.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &frame, |b, frame| {
+            b.iter(|| {
+                let message = ToolkitMessage::from_frame(frame).unwrap();
+                serde_json::to_vec(&message).unwrap()
+            })
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("result_encode");
+    for (name, bytes) in SIZES {
+        let payload = to_raw_value(&common::records(bytes)).unwrap();
+        group.throughput(Throughput::Bytes(payload.get().len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &payload, |b, payload| {
+            let message = || ToolkitMessage::ActionResult {
+                data: ActionCallResult {
+                    payload: payload.clone(),
+                    ..ActionCallResult::new("echo")
+                },
+            };
+            b.iter_batched(
+                message,
+                |message| serde_json::to_string(&message).unwrap(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, messages);
+criterion_main!(benches);
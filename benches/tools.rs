@@ -0,0 +1,134 @@
+//! The tools side: serializing the arguments of the tools and reading the responses of the
+//! backend, served locally so the network doesn't weigh in.
+//!
+//! Run with `cargo bench --bench tools`.
+
+mod common;
+
+use bytes::Bytes;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use http_body_util::{BodyExt, Full};
+use hyper::{body::Incoming, server::conn::http1, service::service_fn, Request, Response};
+use hyper_util::rt::TokioIo;
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+use unifai_sdk::{
+    config::UnifaiConfig,
+    tools::{CallToolArgs, SearchToolsArgs, UnifaiClient},
+};
+
+const SIZES: [(&str, usize); 2] = [("1kb", 1024), ("100kb", 100 * 1024)];
+
+/// A backend answering every request with `body`.
+async fn backend(body: String) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let body = Bytes::from(body);
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let body = body.clone();
+            let service = service_fn(move |request: Request<Incoming>| {
+                let body = body.clone();
+                async move {
+                    request.into_body().collect().await?;
+                    Ok::<_, hyper::Error>(Response::new(Full::new(body)))
+                }
+            });
+
+            tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+        }
+    });
+
+    format!("http://{addr}")
+}
+
+fn client(endpoint: String) -> UnifaiClient {
+    UnifaiClient::from_config(&UnifaiConfig {
+        agent_api_key: Some("bench".to_string()),
+        backend_api_endpoint: endpoint,
+        ..UnifaiConfig::default()
+    })
+    .unwrap()
+}
+
+fn call_args(bytes: usize) -> CallToolArgs {
+    CallToolArgs {
+        action: "Toolkit/1/echo".to_string(),
+        payload: common::payload(bytes),
+        payment: None,
+        ..Default::default()
+    }
+}
+
+fn args(c: &mut Criterion) {
+    let mut group = c.benchmark_group("call_tool_args");
+    for (name, bytes) in SIZES {
+        let args = call_args(bytes);
+        group.throughput(Throughput::Bytes(bytes as u64));
+        group.bench_with_input(BenchmarkId::new("encode", name), &args, |b, args| {
+            b.iter(|| serde_json::to_vec(args).unwrap())
+        });
+
+        // The arguments as written by a model, with the payload as an object or a JSON string.
+        let object = serde_json::to_string(&args).unwrap();
+        group.bench_with_input(BenchmarkId::new("decode", name), &object, |b, text| {
+            b.iter(|| serde_json::from_str::<CallToolArgs>(text).unwrap())
+        });
+        let string = json!({
+            "action": args.action,
+            "payload": args.payload.to_string(),
+        })
+        .to_string();
+        group.bench_with_input(
+            BenchmarkId::new("decode_string", name),
+            &string,
+            |b, text| b.iter(|| serde_json::from_str::<CallToolArgs>(text).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+fn responses(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let mut group = c.benchmark_group("search_tools");
+    for (name, bytes) in SIZES {
+        let client = client(runtime.block_on(backend(common::search_response(bytes))));
+        let args = SearchToolsArgs {
+            query: "weather in Paris & London".to_string(),
+            limit: Some(10),
+            ..Default::default()
+        };
+
+        group.throughput(Throughput::Bytes(bytes as u64));
+        group.bench_function(name, |b| {
+            b.to_async(&runtime).iter(|| async {
+                let body = client.search_tools(&args).await.unwrap();
+                serde_json::from_str::<Value>(&body).unwrap()
+            })
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("call_tool");
+    for (name, bytes) in SIZES {
+        let client = client(runtime.block_on(backend(common::payload(bytes).to_string())));
+        let args = call_args(bytes);
+
+        group.throughput(Throughput::Bytes(2 * bytes as u64));
+        group.bench_function(name, |b| {
+            b.to_async(&runtime).iter(|| async {
+                let body = client.call_tool(&args).await.unwrap();
+                serde_json::from_str::<Value>(&body).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, args, responses);
+criterion_main!(benches);
@@ -0,0 +1,9 @@
+use std::env;
+
+fn main() {
+    // Expose the target triple to the User-Agent header.
+    println!(
+        "cargo:rustc-env=UNIFAI_SDK_TARGET={}",
+        env::var("TARGET").unwrap()
+    );
+}
@@ -0,0 +1,35 @@
+use std::env;
+use unifai_sdk::{agents::AgentClient, prelude::*};
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt().init();
+
+    let ping_api_key =
+        env::var("UNIFAI_PING_AGENT_API_KEY").expect("UNIFAI_PING_AGENT_API_KEY not set");
+    let pong_api_key =
+        env::var("UNIFAI_PONG_AGENT_API_KEY").expect("UNIFAI_PONG_AGENT_API_KEY not set");
+    let pong_agent_id: u64 = env::var("UNIFAI_PONG_AGENT_ID")
+        .expect("UNIFAI_PONG_AGENT_ID not set")
+        .parse()
+        .expect("UNIFAI_PONG_AGENT_ID is a number");
+
+    // The pong agent answers every message in its conversation.
+    let mut pong = AgentClient::new(&pong_api_key).connect().await.unwrap();
+    tokio::spawn(async move {
+        while let Some(message) = pong.recv().await {
+            pong.reply(&message, json!({ "text": "pong" })).unwrap();
+        }
+    });
+
+    let mut ping = AgentClient::new(&ping_api_key).connect().await.unwrap();
+    ping.send(pong_agent_id, json!({ "text": "ping" })).unwrap();
+
+    for _ in 0..3 {
+        let message = ping.recv().await.unwrap();
+        println!("Agent {} said {}", message.sender_id, message.payload);
+        ping.reply(&message, json!({ "text": "ping" })).unwrap();
+    }
+
+    ping.close().await.unwrap();
+}
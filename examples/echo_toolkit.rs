@@ -1,16 +1,11 @@
 use std::env;
 use thiserror::Error;
-use unifai_sdk::{
-    serde::{self, Deserialize, Serialize},
-    serde_json::json,
-    tokio,
-    toolkit::{
-        Action, ActionContext, ActionDefinition, ActionParams, ActionResult, ToolkitInfo,
-        ToolkitService,
-    },
-};
-
-struct EchoSlam;
+use unifai_sdk::prelude::*;
+
+/// Echoes for free, or for 100 per call when paid.
+struct EchoSlam {
+    paid: bool,
+}
 
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "serde")]
@@ -29,6 +24,14 @@ impl Action for EchoSlam {
     type Args = EchoSlamArgs;
     type Output = String;
 
+    fn name(&self) -> String {
+        if self.paid {
+            "paid_echo".to_string()
+        } else {
+            Self::NAME.to_string()
+        }
+    }
+
     async fn definition(&self) -> ActionDefinition {
         ActionDefinition {
             description: "Echo the message".to_string(),
@@ -39,7 +42,14 @@ impl Action for EchoSlam {
                     "required": true
                 }
             }),
-            payment: None,
+            payment: self.paid.then(|| {
+                PaymentDefinition::fixed(100)
+                    .with_description("per call")
+                    .into()
+            }),
+            display_name: Some("Echo".to_string()),
+            examples: Some(vec![json!({ "content": "Hello!" })]),
+            tags: Some(vec!["demo".to_string()]),
         }
     }
 
@@ -76,7 +86,8 @@ async fn main() {
 
     service.update_info(info).await.unwrap();
 
-    service.add_action(EchoSlam);
+    service.add_action(EchoSlam { paid: false });
+    service.add_action(EchoSlam { paid: true });
 
     let runner = service.start().await.unwrap();
     let _ = runner.await.unwrap();
@@ -6,7 +6,6 @@ use unifai_sdk::{
         providers::openai,
         OneOrMany,
     },
-    tokio,
     tools::get_tools,
 };
 
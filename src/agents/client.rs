@@ -0,0 +1,383 @@
+use super::{
+    errors::AgentError,
+    messages::{AgentFrame, AgentMessage, OutgoingMessage},
+};
+use crate::{
+    config::{ConfigError, UnifaiConfig},
+    logging::{events, targets, WireLogging},
+    secrets::{ApiKeyProvider, SecretError, SecretSource},
+    toolkit::{
+        transport::{connect_any, Incoming, ToolkitTransport, WebSocketTransport},
+        CancellationToken,
+    },
+};
+use futures_util::Stream;
+use serde::Serialize;
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    spawn,
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    task::JoinHandle,
+    time::sleep,
+};
+use tokio_util::sync::DropGuard;
+
+/// A client of the messaging between agents, for an agent to talk with the others.
+///
+/// Cloning the client is cheap.
+#[derive(Clone, Debug)]
+pub struct AgentClient {
+    api_key: ApiKeyProvider,
+    config: Arc<UnifaiConfig>,
+    wire: WireLogging,
+}
+
+impl AgentClient {
+    /// Create a client with Unifai Agent API Key.
+    ///
+    /// Endpoints are read from environment variables once, see [UnifaiConfig::from_env].
+    ///
+    /// # Panics
+    ///
+    /// If the API key isn't a valid header value, see [AgentClient::try_new].
+    pub fn new(api_key: &str) -> Self {
+        Self::try_new(api_key).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Create a client like [AgentClient::new], failing if the API key isn't a valid header value,
+    /// such as one with a trailing newline.
+    pub fn try_new(api_key: &str) -> Result<Self, ConfigError> {
+        let api_key = ApiKeyProvider::fixed(api_key, "agent_api_key")?;
+
+        Ok(Self::with_api_key(api_key, UnifaiConfig::from_env()))
+    }
+
+    /// Create a client with the Unifai Agent API Key read from `source`, failing if it can't be
+    /// read.
+    ///
+    /// Endpoints are read from environment variables once, see [UnifaiConfig::from_env].
+    pub async fn from_secret(source: SecretSource) -> Result<Self, SecretError> {
+        let api_key = ApiKeyProvider::new(source).await?;
+
+        Ok(Self::with_api_key(api_key, UnifaiConfig::from_env()))
+    }
+
+    /// Create a client from a config, which must contain the agent API key.
+    pub fn from_config(config: UnifaiConfig) -> Result<Self, ConfigError> {
+        let api_key = ApiKeyProvider::fixed(config.require_agent_api_key()?, "agent_api_key")?;
+
+        Ok(Self::with_api_key(api_key, config))
+    }
+
+    fn with_api_key(api_key: ApiKeyProvider, config: UnifaiConfig) -> Self {
+        Self {
+            api_key,
+            config: Arc::new(config),
+            wire: WireLogging::default(),
+        }
+    }
+
+    /// Log the frames exchanged with the server, see [WireLogging].
+    pub fn with_wire_logging(mut self, wire: WireLogging) -> Self {
+        self.wire = wire;
+        self
+    }
+
+    /// Connect to the server to send and receive messages, like a Toolkit service connects to it:
+    /// to the first reachable endpoint of [UnifaiConfig::backend_ws_endpoints], pinging it every
+    /// [UnifaiConfig::ping_interval]. A lost connection is opened again, the messages sent
+    /// meanwhile wait for it.
+    pub async fn connect(&self) -> Result<AgentConnection, AgentError> {
+        let transport = self.open().await?;
+        let client = self.clone();
+        let reconnect = move || {
+            let client = client.clone();
+            async move { client.open().await }
+        };
+
+        Ok(AgentConnection::spawn(
+            transport,
+            reconnect,
+            self.config.ping_interval,
+        ))
+    }
+
+    async fn open(&self) -> Result<WebSocketTransport, AgentError> {
+        let (_, transport) = connect_any(&self.config, &self.wire, "agent", &self.api_key).await?;
+
+        Ok(transport)
+    }
+}
+
+/// The connection of an agent to the server, yielding the messages of the other agents as a
+/// [Stream].
+///
+/// Dropping the connection closes it.
+pub struct AgentConnection {
+    sender: AgentSender,
+    incoming: UnboundedReceiver<AgentMessage>,
+    task: JoinHandle<Result<(), AgentError>>,
+    _closing: DropGuard,
+}
+
+impl AgentConnection {
+    fn spawn<T, F>(
+        transport: T,
+        reconnect: impl FnMut() -> F + Send + 'static,
+        ping_interval: Duration,
+    ) -> Self
+    where
+        T: ToolkitTransport,
+        F: Future<Output = Result<T, AgentError>> + Send + 'static,
+    {
+        let (outgoing, outgoing_receiver) = unbounded_channel();
+        let (incoming_sender, incoming) = unbounded_channel();
+        let closing = CancellationToken::new();
+        let task = spawn(run(
+            transport,
+            reconnect,
+            outgoing_receiver,
+            incoming_sender,
+            closing.clone(),
+            ping_interval,
+        ));
+
+        Self {
+            sender: AgentSender { outgoing },
+            incoming,
+            task,
+            _closing: closing.drop_guard(),
+        }
+    }
+
+    /// A sender of messages on this connection, for other tasks.
+    pub fn sender(&self) -> AgentSender {
+        self.sender.clone()
+    }
+
+    /// Send `payload` to the agent `recipient_id`, starting a new conversation.
+    pub fn send(&self, recipient_id: u64, payload: impl Serialize) -> Result<(), AgentError> {
+        self.sender.send(recipient_id, payload)
+    }
+
+    /// Answer `message` with `payload`, in its conversation.
+    pub fn reply(&self, message: &AgentMessage, payload: impl Serialize) -> Result<(), AgentError> {
+        self.sender.reply(message, payload)
+    }
+
+    /// Wait for the next message, `None` once the connection is closed.
+    pub async fn recv(&mut self) -> Option<AgentMessage> {
+        self.incoming.recv().await
+    }
+
+    /// Close the connection, once the messages sent so far went out, returning the error that
+    /// ended it, if any.
+    pub async fn close(self) -> Result<(), AgentError> {
+        let Self { task, _closing, .. } = self;
+        drop(_closing);
+
+        task.await.expect("the connection doesn't panic")
+    }
+}
+
+impl Stream for AgentConnection {
+    type Item = AgentMessage;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.incoming.poll_recv(cx)
+    }
+}
+
+/// Sends messages on an [AgentConnection]. Cloning it is cheap.
+#[derive(Clone, Debug)]
+pub struct AgentSender {
+    outgoing: UnboundedSender<OutgoingMessage>,
+}
+
+impl AgentSender {
+    /// Send `payload` to the agent `recipient_id`, starting a new conversation.
+    pub fn send(&self, recipient_id: u64, payload: impl Serialize) -> Result<(), AgentError> {
+        self.send_message(OutgoingMessage {
+            recipient_id,
+            conversation_id: None,
+            payload: serde_json::to_value(payload)?,
+        })
+    }
+
+    /// Answer `message` with `payload`, in its conversation.
+    pub fn reply(&self, message: &AgentMessage, payload: impl Serialize) -> Result<(), AgentError> {
+        self.send_message(OutgoingMessage {
+            recipient_id: message.sender_id,
+            conversation_id: Some(message.conversation_id.clone()),
+            payload: serde_json::to_value(payload)?,
+        })
+    }
+
+    /// Send a message, failing once the connection is closed.
+    pub fn send_message(&self, message: OutgoingMessage) -> Result<(), AgentError> {
+        self.outgoing.send(message).map_err(|_| AgentError::Closed)
+    }
+}
+
+/// Carry the messages over `transport`, opening it again with `reconnect` when it's lost, until
+/// the connection is closed by either side.
+async fn run<T, F>(
+    mut transport: T,
+    mut reconnect: impl FnMut() -> F,
+    mut outgoing: UnboundedReceiver<OutgoingMessage>,
+    incoming: UnboundedSender<AgentMessage>,
+    closing: CancellationToken,
+    ping_interval: Duration,
+) -> Result<(), AgentError>
+where
+    T: ToolkitTransport,
+    F: Future<Output = Result<T, AgentError>>,
+{
+    loop {
+        tokio::select! {
+            biased;
+
+            Some(message) = outgoing.recv() => {
+                let text = serde_json::to_string(&AgentFrame::SendMessage { data: message })?;
+                if let Err(e) = transport.send(text).await {
+                    tracing::error!(
+                        target: targets::AGENTS,
+                        event = events::SEND_FAILED,
+                        error = %e,
+                        "Failed to send message"
+                    );
+                }
+            }
+
+            _ = closing.cancelled() => {
+                let _ = transport.close().await;
+                return Ok(());
+            }
+
+            _ = sleep(ping_interval) => {
+                if let Err(e) = transport.ping().await {
+                    tracing::error!(
+                        target: targets::AGENTS,
+                        event = events::SEND_FAILED,
+                        error = %e,
+                        "Failed to send ping"
+                    );
+                }
+            }
+
+            received = transport.recv() => match received {
+                Some(Ok(Incoming::Text(text))) => match serde_json::from_slice(&text) {
+                    Ok(AgentFrame::Message { data }) => {
+                        let _ = incoming.send(data);
+                    }
+                    Ok(_) => {}
+                    Err(e) => tracing::warn!(
+                        target: targets::AGENTS,
+                        event = events::UNKNOWN_MESSAGE,
+                        error = %e,
+                        "Received unknown message"
+                    ),
+                },
+                Some(Ok(Incoming::Closed { code, reason })) => {
+                    tracing::info!(
+                        target: targets::AGENTS,
+                        event = events::CONNECTION_CLOSED,
+                        code,
+                        reason = reason.as_deref(),
+                        "Server closed the connection"
+                    );
+                    return Ok(());
+                }
+                Some(Ok(Incoming::Failed(e))) => tracing::error!(
+                    target: targets::AGENTS,
+                    event = events::RECEIVE_FAILED,
+                    error = %e,
+                    "Failed to receive message"
+                ),
+                Some(Ok(Incoming::Pong | Incoming::Ignored)) => {}
+                None | Some(Err(_)) => {
+                    tracing::warn!(
+                        target: targets::AGENTS,
+                        event = events::CONNECTION_LOST,
+                        "Connection lost, connecting again"
+                    );
+                    transport = reconnect().await?;
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toolkit::transport::{ChannelPeer, ChannelTransport};
+    use futures_util::StreamExt;
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    fn message_frame(sender_id: u64, conversation_id: &str, payload: &str) -> String {
+        format!(
+            r#"{{"type":"message","data":{{"senderID":{sender_id},"conversationID":"{conversation_id}","payload":"{payload}","timestamp":5}}}}"#
+        )
+    }
+
+    #[tokio::test]
+    async fn test_send_and_receive() {
+        let (transport, mut peer) = ChannelTransport::new();
+        let reconnect = || async { Err::<ChannelTransport, _>(AgentError::Closed) };
+        let mut connection = AgentConnection::spawn(transport, reconnect, Duration::from_secs(60));
+
+        peer.send(r#"{"type":"presence","data":{}}"#);
+        peer.send(message_frame(1, "c-1", "ping"));
+        let message = connection.next().await.unwrap();
+        assert_eq!(message.sender_id, 1);
+        assert_eq!(message.payload, json!("ping"));
+
+        connection.reply(&message, "pong").unwrap();
+        connection.send(3, json!({ "text": "hi" })).unwrap();
+        assert_eq!(
+            peer.recv().await.unwrap(),
+            r#"{"type":"sendMessage","data":{"recipientID":1,"conversationID":"c-1","payload":"pong"}}"#
+        );
+        assert_eq!(
+            peer.recv().await.unwrap(),
+            r#"{"type":"sendMessage","data":{"recipientID":3,"payload":{"text":"hi"}}}"#
+        );
+
+        let sender = connection.sender();
+        assert!(connection.close().await.is_ok());
+        assert!(matches!(sender.send(1, "late"), Err(AgentError::Closed)));
+    }
+
+    #[tokio::test]
+    async fn test_reconnects_when_lost() {
+        let (transport, peer) = ChannelTransport::new();
+        let (second, second_peer) = ChannelTransport::new();
+        let second = Arc::new(Mutex::new(Some(second)));
+        let reconnect = move || {
+            let next = second.lock().unwrap().take();
+            async move { next.ok_or(AgentError::Closed) }
+        };
+        let mut connection = AgentConnection::spawn(transport, reconnect, Duration::from_secs(60));
+
+        drop(peer);
+        let mut second_peer: ChannelPeer = second_peer;
+        second_peer.send(message_frame(2, "c-2", "again"));
+        assert_eq!(connection.recv().await.unwrap().sender_id, 2);
+
+        connection.send(2, "over the new connection").unwrap();
+        assert!(second_peer.recv().await.is_some());
+
+        // The connection ends once it can't be opened again.
+        drop(second_peer);
+        assert!(connection.recv().await.is_none());
+        assert!(matches!(connection.close().await, Err(AgentError::Closed)));
+    }
+}
@@ -0,0 +1,16 @@
+use crate::toolkit::ToolkitError;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AgentError {
+    /// The connection to the server failed, with the error of the transport it shares with the
+    /// Toolkit service.
+    #[error(transparent)]
+    Connection(#[from] ToolkitError),
+
+    #[error("JsonError: {0}")]
+    JsonError(#[from] serde_json::Error),
+
+    /// The connection was closed, no message can be sent on it anymore.
+    #[error("ConnectionClosed: the agent connection is closed")]
+    Closed,
+}
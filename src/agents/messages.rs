@@ -0,0 +1,85 @@
+//! The messages exchanged between agents through the server.
+//!
+//! # Wire format
+//!
+//! Like the [Toolkit messages](crate::toolkit::messages), every message is a JSON object with a
+//! `type` naming its kind and the `data` of that kind, sent as a WebSocket text frame:
+//!
+//! ```json
+//! {"type":"sendMessage","data":{"recipientID":2,"conversationID":"c-1","payload":{"text":"ping"}}}
+//! {"type":"message","data":{"senderID":1,"conversationID":"c-1","payload":{"text":"ping"},"timestamp":1700000000000}}
+//! ```
+//!
+//! A message sent without a `conversationID` starts a new conversation, whose id the server
+//! assigns.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A message, see the [wire format](self#wire-format).
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+#[non_exhaustive]
+pub enum AgentFrame {
+    /// `"sendMessage"`, from an agent: a message to another agent.
+    SendMessage { data: OutgoingMessage },
+    /// `"message"`, from the server: a message of another agent.
+    Message { data: AgentMessage },
+}
+
+/// A message received from another agent, as in
+/// `{"senderID":1,"conversationID":"c-1","payload":{"text":"ping"},"timestamp":1700000000000}`.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct AgentMessage {
+    #[serde(rename = "senderID")]
+    pub sender_id: u64,
+    #[serde(rename = "conversationID")]
+    pub conversation_id: String,
+    pub payload: Value,
+    /// When the server received the message, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+/// A message to send to another agent, as in
+/// `{"recipientID":2,"conversationID":"c-1","payload":{"text":"ping"}}`.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct OutgoingMessage {
+    #[serde(rename = "recipientID")]
+    pub recipient_id: u64,
+    /// The conversation the message belongs to, `None` to start a new one.
+    #[serde(
+        rename = "conversationID",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub conversation_id: Option<String>,
+    pub payload: Value,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_wire_format() {
+        let frame = r#"{"type":"message","data":{"senderID":1,"conversationID":"c-1","payload":{"text":"ping"},"timestamp":1700000000000}}"#;
+        let AgentFrame::Message { data } = serde_json::from_str(frame).unwrap() else {
+            panic!("expected a message");
+        };
+        assert_eq!(data.sender_id, 1);
+        assert_eq!(data.payload, json!({ "text": "ping" }));
+
+        let frame = AgentFrame::SendMessage {
+            data: OutgoingMessage {
+                recipient_id: 2,
+                conversation_id: None,
+                payload: json!("pong"),
+            },
+        };
+        assert_eq!(
+            serde_json::to_string(&frame).unwrap(),
+            r#"{"type":"sendMessage","data":{"recipientID":2,"payload":"pong"}}"#
+        );
+    }
+}
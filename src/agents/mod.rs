@@ -0,0 +1,19 @@
+//! This module provides the messaging between agents, through the same server and the same kind
+//! of connection as Toolkit services.
+//!
+//! # Example
+//!
+//! See examples/agent_ping_pong.rs
+//!
+//! ```no_run
+#![doc = include_str!("../../examples/agent_ping_pong.rs")]
+//! ```
+
+mod client;
+pub use client::*;
+
+mod errors;
+pub use errors::*;
+
+pub mod messages;
+pub use messages::{AgentMessage, OutgoingMessage};
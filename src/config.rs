@@ -0,0 +1,823 @@
+//! This module provides the configuration shared by the Toolkit service and the tools.
+//!
+//! Every setting is resolved with the following precedence, from highest to lowest:
+//!
+//! 1. Fields set explicitly on a [UnifaiConfig] after loading it.
+//! 2. Values from a TOML file loaded with [UnifaiConfig::from_file].
+//! 3. Environment variables, read once by [UnifaiConfig::from_env] and [UnifaiConfig::from_file].
+//! 4. The built-in defaults of [UnifaiConfig::default].
+//!
+//! # Example
+//! ```no_run
+//! use std::time::Duration;
+//! use unifai_sdk::config::UnifaiConfig;
+//!
+//! let mut config = UnifaiConfig::from_file("unifai.toml").unwrap();
+//! config.request_timeout = Duration::from_secs(5);
+//! ```
+//!
+//! The TOML file accepts the following keys, all optional:
+//!
+//! ```toml
+//! agent_api_key = "..."
+//! toolkit_api_key = "..."
+//! frontend_api_endpoint = "https://api.unifai.network"
+//! backend_api_endpoint = "https://backend.unifai.network/api/v1"
+//! backend_ws_endpoint = "wss://backend.unifai.network/ws"
+//! backend_poll_endpoint = "https://backend.unifai.network/poll"
+//! backend_grpc_endpoint = "https://grpc.backend.unifai.network"
+//! backend_api_fallback_endpoints = ["https://backend-eu.unifai.network/api/v1"]
+//! backend_ws_fallback_endpoints = ["wss://backend-eu.unifai.network/ws"]
+//! ws_api_key_in_query = false
+//! transaction_api_endpoint = "https://txbuilder.unifai.network/api"
+//! connect_timeout_ms = 10000
+//! request_timeout_ms = 15000
+//! call_timeout_ms = 50000
+//! definition_concurrency = 8
+//! definition_timeout_ms = 30000
+//! action_timeout_ms = 30000
+//! cancel_grace_ms = 5000
+//! ping_interval_ms = 30000
+//! missed_pongs = 2
+//! connect_retries = 0
+//! max_concurrent_actions = 64
+//! max_queued_actions = 256
+//! max_result_bytes = 8388608
+//! max_retries = 3
+//! retry_base_delay_ms = 500
+//! failover_threshold = 3
+//! failover_cooldown_ms = 60000
+//!
+//! [client]
+//! connect_timeout_ms = 10000
+//! timeout_ms = 30000
+//! pool_idle_timeout_ms = 90000
+//! tcp_keepalive_ms = 60000
+//! pool_max_idle_per_host = 32
+//! http2_prior_knowledge = false
+//! http2_adaptive_window = true
+//! http2_keep_alive_interval_ms = 30000
+//! decompression = true
+//! max_response_bytes = 16777216
+//! ```
+
+use crate::constants::{
+    DEFAULT_BACKEND_API_ENDPOINT, DEFAULT_BACKEND_GRPC_ENDPOINT, DEFAULT_BACKEND_POLL_ENDPOINT,
+    DEFAULT_BACKEND_WS_ENDPOINT, DEFAULT_CALL_TIMEOUT, DEFAULT_CANCEL_GRACE,
+    DEFAULT_CONNECT_TIMEOUT, DEFAULT_DEFINITION_CONCURRENCY, DEFAULT_DEFINITION_TIMEOUT,
+    DEFAULT_FAILOVER_COOLDOWN, DEFAULT_FAILOVER_THRESHOLD, DEFAULT_FRONTEND_API_ENDPOINT,
+    DEFAULT_HTTP2_KEEP_ALIVE_INTERVAL, DEFAULT_HTTP_CONNECT_TIMEOUT, DEFAULT_HTTP_TIMEOUT,
+    DEFAULT_MAX_RESPONSE_BYTES, DEFAULT_MAX_RETRIES, DEFAULT_MISSED_PONGS, DEFAULT_PING_INTERVAL,
+    DEFAULT_POOL_IDLE_TIMEOUT, DEFAULT_POOL_MAX_IDLE_PER_HOST, DEFAULT_REQUEST_TIMEOUT,
+    DEFAULT_RETRY_BASE_DELAY, DEFAULT_TCP_KEEPALIVE, DEFAULT_TRANSACTION_API_ENDPOINT,
+};
+use serde::Deserialize;
+use std::{
+    env, fmt, fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+#[cfg(feature = "rustls")]
+pub use crate::pinning::{InvalidPin, Sha256Pin};
+
+pub const AGENT_API_KEY_ENV: &str = "UNIFAI_AGENT_API_KEY";
+pub const TOOLKIT_API_KEY_ENV: &str = "UNIFAI_TOOLKIT_API_KEY";
+pub const FRONTEND_API_ENDPOINT_ENV: &str = "UNIFAI_FRONTEND_API_ENDPOINT";
+pub const BACKEND_API_ENDPOINT_ENV: &str = "UNIFAI_BACKEND_API_ENDPOINT";
+pub const BACKEND_WS_ENDPOINT_ENV: &str = "UNIFAI_BACKEND_WS_ENDPOINT";
+pub const BACKEND_POLL_ENDPOINT_ENV: &str = "UNIFAI_BACKEND_POLL_ENDPOINT";
+pub const BACKEND_GRPC_ENDPOINT_ENV: &str = "UNIFAI_BACKEND_GRPC_ENDPOINT";
+pub const TRANSACTION_API_ENDPOINT_ENV: &str = "UNIFAI_TRANSACTION_API_ENDPOINT";
+/// Comma-separated, see [UnifaiConfig::backend_api_fallback_endpoints].
+pub const BACKEND_API_FALLBACK_ENDPOINTS_ENV: &str = "UNIFAI_BACKEND_API_FALLBACK_ENDPOINTS";
+/// Comma-separated, see [UnifaiConfig::backend_ws_fallback_endpoints].
+pub const BACKEND_WS_FALLBACK_ENDPOINTS_ENV: &str = "UNIFAI_BACKEND_WS_FALLBACK_ENDPOINTS";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("Failed to read config file {path:?}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("Failed to parse config file {path:?}: {source}")]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+
+    #[error("Missing {0} in config")]
+    MissingApiKey(&'static str),
+
+    /// An API key that couldn't be read from its source.
+    #[cfg(any(feature = "toolkit", feature = "tools"))]
+    #[error(transparent)]
+    Secret(#[from] crate::secrets::SecretError),
+
+    /// The HTTP client can't be built with the [ClientOptions], such as when the TLS backend fails
+    /// to initialize.
+    #[error("Failed to build the HTTP client: {0}")]
+    Client(reqwest::Error),
+
+    /// A value of the config is invalid, `key` is its path, as in `actions[1].id`.
+    #[error("Invalid {key} in config: {message}")]
+    Invalid { key: String, message: String },
+}
+
+/// Options of the HTTP clients built by the SDK.
+///
+/// Requests that need longer, such as calling an action, override [ClientOptions::timeout] per
+/// request. `None` means no limit, which was the behavior before these options existed.
+///
+/// The defaults suit a long-lived process talking to a few hosts: connections are kept open and
+/// reused, and HTTP/2 connections are kept alive with pings. These options have no effect on wasm32,
+/// where the browser owns the connections.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ClientOptions {
+    /// Timeout of establishing a connection. Defaults to 10 seconds.
+    pub connect_timeout: Option<Duration>,
+    /// Default timeout of a whole request, including reading the response body. Defaults to 30 seconds.
+    pub timeout: Option<Duration>,
+    /// How long an idle connection is kept in the pool. Defaults to 90 seconds.
+    pub pool_idle_timeout: Option<Duration>,
+    /// Interval of TCP keepalive probes. Defaults to 60 seconds.
+    pub tcp_keepalive: Option<Duration>,
+    /// Maximum number of idle connections kept per host. Defaults to 32, `None` means no limit.
+    pub pool_max_idle_per_host: Option<usize>,
+    /// Speak HTTP/2 without negotiating it, which multiplexes every request to a host over one
+    /// connection. Only enable it if all the endpoints support HTTP/2. Defaults to `false`.
+    pub http2_prior_knowledge: bool,
+    /// Size the HTTP/2 flow control windows from the measured bandwidth. Defaults to `true`.
+    pub http2_adaptive_window: bool,
+    /// Interval of the pings keeping HTTP/2 connections alive, even idle ones. Defaults to 30
+    /// seconds.
+    pub http2_keep_alive_interval: Option<Duration>,
+    /// Ask for gzip or brotli compressed responses and decompress them transparently. Turn it off to
+    /// see the raw bytes sent by the server when debugging. Defaults to `true`.
+    pub decompression: bool,
+    /// Maximum size of a tools response body, counted after decompression. Defaults to 16 MiB,
+    /// `None` means no limit.
+    pub max_response_bytes: Option<usize>,
+    /// Certificate authorities trusted on top of the default ones, PEM-encoded, see
+    /// [ClientOptions::add_root_certificate]. Empty by default.
+    pub root_certificates: Vec<Vec<u8>>,
+    /// Keys the HTTPS servers must present, see [ClientOptions::pin_certificates]. Empty by default.
+    #[cfg(feature = "rustls")]
+    pub pinned_certificates: Vec<Sha256Pin>,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Some(DEFAULT_HTTP_CONNECT_TIMEOUT),
+            timeout: Some(DEFAULT_HTTP_TIMEOUT),
+            pool_idle_timeout: Some(DEFAULT_POOL_IDLE_TIMEOUT),
+            tcp_keepalive: Some(DEFAULT_TCP_KEEPALIVE),
+            pool_max_idle_per_host: Some(DEFAULT_POOL_MAX_IDLE_PER_HOST),
+            http2_prior_knowledge: false,
+            http2_adaptive_window: true,
+            http2_keep_alive_interval: Some(DEFAULT_HTTP2_KEEP_ALIVE_INTERVAL),
+            decompression: true,
+            max_response_bytes: Some(DEFAULT_MAX_RESPONSE_BYTES),
+            root_certificates: Vec::new(),
+            #[cfg(feature = "rustls")]
+            pinned_certificates: Vec::new(),
+        }
+    }
+}
+
+impl ClientOptions {
+    /// Trust the certificate authority of the PEM-encoded certificate `pem`, such as the one of a
+    /// corporate TLS-inspecting proxy, failing if it isn't a valid certificate.
+    ///
+    /// Proxies are read from the `HTTPS_PROXY`, `HTTP_PROXY` and `NO_PROXY` environment variables.
+    /// It only applies to the HTTP clients.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn add_root_certificate(mut self, pem: impl Into<Vec<u8>>) -> Result<Self, ConfigError> {
+        let pem = pem.into();
+        reqwest::Certificate::from_pem(&pem).map_err(|e| ConfigError::Invalid {
+            key: "client.root_certificates".to_string(),
+            message: e.to_string(),
+        })?;
+        self.root_certificates.push(pem);
+
+        Ok(self)
+    }
+}
+
+#[cfg(feature = "rustls")]
+impl ClientOptions {
+    /// Reject HTTPS servers whose certificate chain includes none of the keys of `pins`, on top of
+    /// the usual verification against the webpki roots and the
+    /// [root certificates added](ClientOptions::add_root_certificate).
+    ///
+    /// The chain is the one sent by the server, which usually doesn't include the root, so pin the
+    /// key of the leaf or of an intermediate certificate. Requests rejected because of the pins fail
+    /// with a `CertificatePinMismatch` error naming the endpoint.
+    ///
+    /// Pinning is operationally risky: once the server rotates its keys or switches to another
+    /// certificate authority, every request fails until the pins are updated. Always pin a backup
+    /// key as well, and keep a way to update the pins without a release. It only applies to the
+    /// HTTP clients, not to the Toolkit WebSocket connection.
+    pub fn pin_certificates(mut self, pins: Vec<Sha256Pin>) -> Self {
+        self.pinned_certificates = pins;
+        self
+    }
+}
+
+/// Endpoints, API keys, timeouts and retry defaults used by the SDK.
+///
+/// The API keys are never printed by the [Debug] implementation.
+#[derive(Clone)]
+pub struct UnifaiConfig {
+    /// Agent API key, used by the tools.
+    pub agent_api_key: Option<String>,
+    /// Toolkit API key, used by the Toolkit service.
+    pub toolkit_api_key: Option<String>,
+    pub frontend_api_endpoint: String,
+    pub backend_api_endpoint: String,
+    pub backend_ws_endpoint: String,
+    /// Endpoint of the HTTP long-polling transport of the Toolkit, used where WebSocket
+    /// connections can't be opened.
+    pub backend_poll_endpoint: String,
+    /// Endpoint of the gRPC transport of the Toolkit, see
+    /// [TransportPreference::Grpc](crate::toolkit::TransportPreference).
+    pub backend_grpc_endpoint: String,
+    pub transaction_api_endpoint: String,
+    /// Endpoints the tools move to, in order, when `backend_api_endpoint` is unreachable or
+    /// keeps erroring.
+    pub backend_api_fallback_endpoints: Vec<String>,
+    /// Endpoints the Toolkit connects to, in order, when it can't connect to
+    /// `backend_ws_endpoint`.
+    pub backend_ws_fallback_endpoints: Vec<String>,
+    /// Send the API key in the query of the WebSocket URL, as older backends expect, rather than
+    /// in the `Authorization` header of the upgrade request. `false` by default, as URLs end up in
+    /// the logs of proxies.
+    pub ws_api_key_in_query: bool,
+    /// Consecutive connection errors or 5xx responses of a backend API endpoint before the tools
+    /// move to the next one.
+    pub failover_threshold: u32,
+    /// How long the tools stay on a fallback endpoint before trying `backend_api_endpoint` again.
+    pub failover_cooldown: Duration,
+    /// Timeout of establishing the Toolkit WebSocket connection, or long-polling session.
+    pub connect_timeout: Duration,
+    /// Timeout of one-off HTTP requests, such as updating Toolkit info or creating transactions.
+    pub request_timeout: Duration,
+    /// Timeout of calling an action through the tools.
+    pub call_timeout: Duration,
+    /// Maximum number of action definitions evaluated at once when the Toolkit registers its actions.
+    pub definition_concurrency: usize,
+    /// Timeout of evaluating one action definition when the Toolkit registers its actions.
+    pub definition_timeout: Duration,
+    /// Timeout of one action call in the Toolkit, unless the action sets its own with
+    /// [Action::timeout](crate::toolkit::Action::timeout). Unlimited by default.
+    pub action_timeout: Option<Duration>,
+    /// How long a cancelled action call may run to wind down before its task is aborted.
+    pub cancel_grace: Duration,
+    /// Interval of the pings the Toolkit sends to keep its connection alive.
+    pub ping_interval: Duration,
+    /// Pings in a row the server may leave unanswered before the Toolkit deems its WebSocket
+    /// connection dead and stops as if it was lost. Only a pong answers the pings sent before
+    /// it, other frames don't. 0 never deems it dead.
+    pub missed_pongs: u32,
+    /// Times the Toolkit retries connecting to its WebSocket endpoints when none is reachable,
+    /// waiting `retry_base_delay` doubled on every retry. None by default.
+    pub connect_retries: u32,
+    /// Maximum number of action calls the Toolkit executes at once, the others wait in a queue.
+    /// Unlimited by default.
+    pub max_concurrent_actions: Option<usize>,
+    /// Maximum number of action calls waiting for `max_concurrent_actions`, the others are
+    /// answered with a `ToolkitBusy` error. Unlimited by default.
+    pub max_queued_actions: Option<usize>,
+    /// Maximum size of a serialized action result sent by the Toolkit, a larger result is replaced
+    /// with a `PayloadTooLarge` error. Unlimited by default.
+    pub max_result_bytes: Option<usize>,
+    /// Maximum number of retries of a failed request.
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled on every following retry.
+    pub retry_base_delay: Duration,
+    /// Options of the HTTP clients.
+    pub client: ClientOptions,
+}
+
+impl Default for UnifaiConfig {
+    fn default() -> Self {
+        Self {
+            agent_api_key: None,
+            toolkit_api_key: None,
+            frontend_api_endpoint: DEFAULT_FRONTEND_API_ENDPOINT.to_string(),
+            backend_api_endpoint: DEFAULT_BACKEND_API_ENDPOINT.to_string(),
+            backend_ws_endpoint: DEFAULT_BACKEND_WS_ENDPOINT.to_string(),
+            backend_poll_endpoint: DEFAULT_BACKEND_POLL_ENDPOINT.to_string(),
+            backend_grpc_endpoint: DEFAULT_BACKEND_GRPC_ENDPOINT.to_string(),
+            transaction_api_endpoint: DEFAULT_TRANSACTION_API_ENDPOINT.to_string(),
+            backend_api_fallback_endpoints: Vec::new(),
+            backend_ws_fallback_endpoints: Vec::new(),
+            ws_api_key_in_query: false,
+            failover_threshold: DEFAULT_FAILOVER_THRESHOLD,
+            failover_cooldown: DEFAULT_FAILOVER_COOLDOWN,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            call_timeout: DEFAULT_CALL_TIMEOUT,
+            definition_concurrency: DEFAULT_DEFINITION_CONCURRENCY,
+            definition_timeout: DEFAULT_DEFINITION_TIMEOUT,
+            action_timeout: None,
+            cancel_grace: DEFAULT_CANCEL_GRACE,
+            ping_interval: DEFAULT_PING_INTERVAL,
+            missed_pongs: DEFAULT_MISSED_PONGS,
+            connect_retries: 0,
+            max_concurrent_actions: None,
+            max_queued_actions: None,
+            max_result_bytes: None,
+            max_retries: DEFAULT_MAX_RETRIES,
+            retry_base_delay: DEFAULT_RETRY_BASE_DELAY,
+            client: ClientOptions::default(),
+        }
+    }
+}
+
+impl fmt::Debug for UnifaiConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let redact = |key: &Option<String>| key.as_ref().map(|_| "<redacted>");
+
+        f.debug_struct("UnifaiConfig")
+            .field("agent_api_key", &redact(&self.agent_api_key))
+            .field("toolkit_api_key", &redact(&self.toolkit_api_key))
+            .field("frontend_api_endpoint", &self.frontend_api_endpoint)
+            .field("backend_api_endpoint", &self.backend_api_endpoint)
+            .field("backend_ws_endpoint", &self.backend_ws_endpoint)
+            .field("backend_poll_endpoint", &self.backend_poll_endpoint)
+            .field("backend_grpc_endpoint", &self.backend_grpc_endpoint)
+            .field("transaction_api_endpoint", &self.transaction_api_endpoint)
+            .field(
+                "backend_api_fallback_endpoints",
+                &self.backend_api_fallback_endpoints,
+            )
+            .field(
+                "backend_ws_fallback_endpoints",
+                &self.backend_ws_fallback_endpoints,
+            )
+            .field("ws_api_key_in_query", &self.ws_api_key_in_query)
+            .field("failover_threshold", &self.failover_threshold)
+            .field("failover_cooldown", &self.failover_cooldown)
+            .field("connect_timeout", &self.connect_timeout)
+            .field("request_timeout", &self.request_timeout)
+            .field("call_timeout", &self.call_timeout)
+            .field("definition_concurrency", &self.definition_concurrency)
+            .field("definition_timeout", &self.definition_timeout)
+            .field("action_timeout", &self.action_timeout)
+            .field("cancel_grace", &self.cancel_grace)
+            .field("ping_interval", &self.ping_interval)
+            .field("missed_pongs", &self.missed_pongs)
+            .field("connect_retries", &self.connect_retries)
+            .field("max_concurrent_actions", &self.max_concurrent_actions)
+            .field("max_queued_actions", &self.max_queued_actions)
+            .field("max_result_bytes", &self.max_result_bytes)
+            .field("max_retries", &self.max_retries)
+            .field("retry_base_delay", &self.retry_base_delay)
+            .field("client", &self.client)
+            .finish()
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ConfigFile {
+    agent_api_key: Option<String>,
+    toolkit_api_key: Option<String>,
+    frontend_api_endpoint: Option<String>,
+    backend_api_endpoint: Option<String>,
+    backend_ws_endpoint: Option<String>,
+    backend_poll_endpoint: Option<String>,
+    backend_grpc_endpoint: Option<String>,
+    transaction_api_endpoint: Option<String>,
+    backend_api_fallback_endpoints: Option<Vec<String>>,
+    backend_ws_fallback_endpoints: Option<Vec<String>>,
+    ws_api_key_in_query: Option<bool>,
+    failover_threshold: Option<u32>,
+    failover_cooldown_ms: Option<u64>,
+    connect_timeout_ms: Option<u64>,
+    request_timeout_ms: Option<u64>,
+    call_timeout_ms: Option<u64>,
+    definition_concurrency: Option<usize>,
+    definition_timeout_ms: Option<u64>,
+    action_timeout_ms: Option<u64>,
+    cancel_grace_ms: Option<u64>,
+    ping_interval_ms: Option<u64>,
+    missed_pongs: Option<u32>,
+    connect_retries: Option<u32>,
+    max_concurrent_actions: Option<usize>,
+    max_queued_actions: Option<usize>,
+    max_result_bytes: Option<usize>,
+    max_retries: Option<u32>,
+    retry_base_delay_ms: Option<u64>,
+    client: Option<ClientOptionsFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ClientOptionsFile {
+    connect_timeout_ms: Option<u64>,
+    timeout_ms: Option<u64>,
+    pool_idle_timeout_ms: Option<u64>,
+    tcp_keepalive_ms: Option<u64>,
+    pool_max_idle_per_host: Option<usize>,
+    http2_prior_knowledge: Option<bool>,
+    http2_adaptive_window: Option<bool>,
+    http2_keep_alive_interval_ms: Option<u64>,
+    decompression: Option<bool>,
+    max_response_bytes: Option<usize>,
+}
+
+impl ClientOptions {
+    fn merge_file(mut self, file: ClientOptionsFile) -> Self {
+        let millis = |ms: Option<u64>| ms.map(Duration::from_millis);
+
+        self.connect_timeout = millis(file.connect_timeout_ms).or(self.connect_timeout);
+        self.timeout = millis(file.timeout_ms).or(self.timeout);
+        self.pool_idle_timeout = millis(file.pool_idle_timeout_ms).or(self.pool_idle_timeout);
+        self.tcp_keepalive = millis(file.tcp_keepalive_ms).or(self.tcp_keepalive);
+        self.pool_max_idle_per_host = file.pool_max_idle_per_host.or(self.pool_max_idle_per_host);
+        self.http2_prior_knowledge = file
+            .http2_prior_knowledge
+            .unwrap_or(self.http2_prior_knowledge);
+        self.http2_adaptive_window = file
+            .http2_adaptive_window
+            .unwrap_or(self.http2_adaptive_window);
+        self.http2_keep_alive_interval =
+            millis(file.http2_keep_alive_interval_ms).or(self.http2_keep_alive_interval);
+        self.decompression = file.decompression.unwrap_or(self.decompression);
+        self.max_response_bytes = file.max_response_bytes.or(self.max_response_bytes);
+        self
+    }
+}
+
+impl UnifaiConfig {
+    /// Load the config from environment variables, falling back to the defaults.
+    pub fn from_env() -> Self {
+        Self::from_lookup(|key| env::var(key).ok())
+    }
+
+    /// Load the config from a TOML file, falling back to environment variables and the defaults.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path).map_err(|source| ConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        Self::from_env()
+            .merge_toml(&content)
+            .map_err(|source| ConfigError::Parse {
+                path: path.to_path_buf(),
+                source,
+            })
+    }
+
+    /// `backend_api_endpoint` followed by its fallbacks, in the order they're tried.
+    pub fn backend_api_endpoints(&self) -> Vec<&str> {
+        endpoints(
+            &self.backend_api_endpoint,
+            &self.backend_api_fallback_endpoints,
+        )
+    }
+
+    /// `backend_ws_endpoint` followed by its fallbacks, in the order they're tried.
+    pub fn backend_ws_endpoints(&self) -> Vec<&str> {
+        endpoints(
+            &self.backend_ws_endpoint,
+            &self.backend_ws_fallback_endpoints,
+        )
+    }
+
+    /// Return the agent API key, or an error if it's not configured.
+    pub fn require_agent_api_key(&self) -> Result<&str, ConfigError> {
+        let api_key = self
+            .agent_api_key
+            .as_deref()
+            .ok_or(ConfigError::MissingApiKey("agent_api_key"))?;
+
+        check_api_key("agent_api_key", api_key)
+    }
+
+    /// Return the toolkit API key, or an error if it's not configured.
+    pub fn require_toolkit_api_key(&self) -> Result<&str, ConfigError> {
+        let api_key = self
+            .toolkit_api_key
+            .as_deref()
+            .ok_or(ConfigError::MissingApiKey("toolkit_api_key"))?;
+
+        check_api_key("toolkit_api_key", api_key)
+    }
+
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Self {
+        let defaults = Self::default();
+
+        Self {
+            agent_api_key: lookup(AGENT_API_KEY_ENV),
+            toolkit_api_key: lookup(TOOLKIT_API_KEY_ENV),
+            frontend_api_endpoint: lookup(FRONTEND_API_ENDPOINT_ENV)
+                .unwrap_or(defaults.frontend_api_endpoint),
+            backend_api_endpoint: lookup(BACKEND_API_ENDPOINT_ENV)
+                .unwrap_or(defaults.backend_api_endpoint),
+            backend_ws_endpoint: lookup(BACKEND_WS_ENDPOINT_ENV)
+                .unwrap_or(defaults.backend_ws_endpoint),
+            backend_poll_endpoint: lookup(BACKEND_POLL_ENDPOINT_ENV)
+                .unwrap_or(defaults.backend_poll_endpoint),
+            backend_grpc_endpoint: lookup(BACKEND_GRPC_ENDPOINT_ENV)
+                .unwrap_or(defaults.backend_grpc_endpoint),
+            transaction_api_endpoint: lookup(TRANSACTION_API_ENDPOINT_ENV)
+                .unwrap_or(defaults.transaction_api_endpoint),
+            backend_api_fallback_endpoints: lookup(BACKEND_API_FALLBACK_ENDPOINTS_ENV)
+                .map_or(defaults.backend_api_fallback_endpoints, |list| {
+                    split_list(&list)
+                }),
+            backend_ws_fallback_endpoints: lookup(BACKEND_WS_FALLBACK_ENDPOINTS_ENV)
+                .map_or(defaults.backend_ws_fallback_endpoints, |list| {
+                    split_list(&list)
+                }),
+            ..defaults
+        }
+    }
+
+    fn merge_toml(self, content: &str) -> Result<Self, toml::de::Error> {
+        Ok(self.merge_file(toml::from_str(content)?))
+    }
+
+    pub(crate) fn merge_file(self, file: ConfigFile) -> Self {
+        Self {
+            agent_api_key: file.agent_api_key.or(self.agent_api_key),
+            toolkit_api_key: file.toolkit_api_key.or(self.toolkit_api_key),
+            frontend_api_endpoint: file
+                .frontend_api_endpoint
+                .unwrap_or(self.frontend_api_endpoint),
+            backend_api_endpoint: file
+                .backend_api_endpoint
+                .unwrap_or(self.backend_api_endpoint),
+            backend_ws_endpoint: file.backend_ws_endpoint.unwrap_or(self.backend_ws_endpoint),
+            backend_poll_endpoint: file
+                .backend_poll_endpoint
+                .unwrap_or(self.backend_poll_endpoint),
+            backend_grpc_endpoint: file
+                .backend_grpc_endpoint
+                .unwrap_or(self.backend_grpc_endpoint),
+            transaction_api_endpoint: file
+                .transaction_api_endpoint
+                .unwrap_or(self.transaction_api_endpoint),
+            backend_api_fallback_endpoints: file
+                .backend_api_fallback_endpoints
+                .unwrap_or(self.backend_api_fallback_endpoints),
+            backend_ws_fallback_endpoints: file
+                .backend_ws_fallback_endpoints
+                .unwrap_or(self.backend_ws_fallback_endpoints),
+            ws_api_key_in_query: file.ws_api_key_in_query.unwrap_or(self.ws_api_key_in_query),
+            failover_threshold: file.failover_threshold.unwrap_or(self.failover_threshold),
+            failover_cooldown: file
+                .failover_cooldown_ms
+                .map_or(self.failover_cooldown, Duration::from_millis),
+            connect_timeout: file
+                .connect_timeout_ms
+                .map_or(self.connect_timeout, Duration::from_millis),
+            request_timeout: file
+                .request_timeout_ms
+                .map_or(self.request_timeout, Duration::from_millis),
+            call_timeout: file
+                .call_timeout_ms
+                .map_or(self.call_timeout, Duration::from_millis),
+            definition_concurrency: file
+                .definition_concurrency
+                .unwrap_or(self.definition_concurrency),
+            definition_timeout: file
+                .definition_timeout_ms
+                .map_or(self.definition_timeout, Duration::from_millis),
+            action_timeout: file
+                .action_timeout_ms
+                .map(Duration::from_millis)
+                .or(self.action_timeout),
+            cancel_grace: file
+                .cancel_grace_ms
+                .map_or(self.cancel_grace, Duration::from_millis),
+            ping_interval: file
+                .ping_interval_ms
+                .map_or(self.ping_interval, Duration::from_millis),
+            missed_pongs: file.missed_pongs.unwrap_or(self.missed_pongs),
+            connect_retries: file.connect_retries.unwrap_or(self.connect_retries),
+            max_concurrent_actions: file.max_concurrent_actions.or(self.max_concurrent_actions),
+            max_queued_actions: file.max_queued_actions.or(self.max_queued_actions),
+            max_result_bytes: file.max_result_bytes.or(self.max_result_bytes),
+            max_retries: file.max_retries.unwrap_or(self.max_retries),
+            retry_base_delay: file
+                .retry_base_delay_ms
+                .map_or(self.retry_base_delay, Duration::from_millis),
+            client: match file.client {
+                Some(client) => self.client.merge_file(client),
+                None => self.client,
+            },
+        }
+    }
+}
+
+fn endpoints<'a>(primary: &'a str, fallbacks: &'a [String]) -> Vec<&'a str> {
+    std::iter::once(primary)
+        .chain(fallbacks.iter().map(String::as_str))
+        .collect()
+}
+
+/// Fail if `api_key` can't be sent in a header, as with a stray newline.
+pub(crate) fn check_api_key<'a>(
+    key: &'static str,
+    api_key: &'a str,
+) -> Result<&'a str, ConfigError> {
+    match reqwest::header::HeaderValue::from_str(api_key) {
+        Ok(_) => Ok(api_key),
+        Err(_) => Err(ConfigError::Invalid {
+            key: key.to_string(),
+            message: "the API key contains characters not allowed in a header".to_string(),
+        }),
+    }
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env_of(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        move |key| vars.get(key).cloned()
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = UnifaiConfig::from_lookup(env_of(&[]));
+
+        assert_eq!(config.agent_api_key, None);
+        assert_eq!(config.backend_api_endpoint, DEFAULT_BACKEND_API_ENDPOINT);
+        assert_eq!(config.connect_timeout, DEFAULT_CONNECT_TIMEOUT);
+    }
+
+    #[test]
+    fn test_env_overrides_defaults() {
+        let config = UnifaiConfig::from_lookup(env_of(&[
+            (AGENT_API_KEY_ENV, "agent-key"),
+            (BACKEND_WS_ENDPOINT_ENV, "ws://env"),
+            (BACKEND_POLL_ENDPOINT_ENV, "http://env/poll"),
+            (BACKEND_GRPC_ENDPOINT_ENV, "http://env:50051"),
+        ]));
+
+        assert_eq!(config.agent_api_key.as_deref(), Some("agent-key"));
+        assert_eq!(config.backend_ws_endpoint, "ws://env");
+        assert_eq!(config.backend_poll_endpoint, "http://env/poll");
+        assert_eq!(config.backend_grpc_endpoint, "http://env:50051");
+        assert_eq!(config.frontend_api_endpoint, DEFAULT_FRONTEND_API_ENDPOINT);
+    }
+
+    #[test]
+    fn test_file_overrides_env_and_explicit_overrides_file() {
+        let mut config = UnifaiConfig::from_lookup(env_of(&[
+            (BACKEND_WS_ENDPOINT_ENV, "ws://env"),
+            (BACKEND_API_ENDPOINT_ENV, "http://env"),
+        ]))
+        .merge_toml(
+            r#"
+            backend_ws_endpoint = "ws://file"
+            request_timeout_ms = 2000
+            definition_concurrency = 2
+            action_timeout_ms = 500
+            cancel_grace_ms = 100
+            ping_interval_ms = 1000
+            missed_pongs = 3
+            max_concurrent_actions = 4
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.backend_ws_endpoint, "ws://file");
+        assert_eq!(config.backend_api_endpoint, "http://env");
+        assert_eq!(config.request_timeout, Duration::from_secs(2));
+        assert_eq!(config.definition_concurrency, 2);
+        assert_eq!(config.definition_timeout, DEFAULT_DEFINITION_TIMEOUT);
+        assert_eq!(config.action_timeout, Some(Duration::from_millis(500)));
+        assert_eq!(config.cancel_grace, Duration::from_millis(100));
+        assert_eq!(config.ping_interval, Duration::from_secs(1));
+        assert_eq!(config.missed_pongs, 3);
+        assert_eq!(config.connect_retries, 0);
+        assert_eq!(config.max_concurrent_actions, Some(4));
+        assert_eq!(config.max_queued_actions, None);
+        assert_eq!(config.max_result_bytes, None);
+
+        config.backend_ws_endpoint = "ws://explicit".to_string();
+        assert_eq!(config.backend_ws_endpoint, "ws://explicit");
+    }
+
+    #[test]
+    fn test_fallback_endpoints() {
+        let config = UnifaiConfig::from_lookup(env_of(&[
+            (BACKEND_API_ENDPOINT_ENV, "http://primary"),
+            (
+                BACKEND_API_FALLBACK_ENDPOINTS_ENV,
+                "http://eu, http://asia,",
+            ),
+        ]));
+        assert_eq!(
+            config.backend_api_endpoints(),
+            ["http://primary", "http://eu", "http://asia"]
+        );
+        assert_eq!(config.backend_ws_endpoints(), [DEFAULT_BACKEND_WS_ENDPOINT]);
+
+        let config = config
+            .merge_toml(
+                r#"
+                backend_ws_fallback_endpoints = ["ws://eu"]
+                failover_threshold = 5
+                failover_cooldown_ms = 1000
+                "#,
+            )
+            .unwrap();
+        assert_eq!(
+            config.backend_ws_endpoints(),
+            [DEFAULT_BACKEND_WS_ENDPOINT, "ws://eu"]
+        );
+        assert_eq!(config.failover_threshold, 5);
+        assert_eq!(config.failover_cooldown, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_file_client_options() {
+        let config = UnifaiConfig::default()
+            .merge_toml("[client]\ntimeout_ms = 1000\nhttp2_prior_knowledge = true\n")
+            .unwrap();
+
+        assert_eq!(config.client.timeout, Some(Duration::from_secs(1)));
+        assert!(config.client.http2_prior_knowledge);
+        assert_eq!(
+            config.client.connect_timeout,
+            ClientOptions::default().connect_timeout
+        );
+    }
+
+    #[test]
+    fn test_unknown_file_key_is_rejected() {
+        let result = UnifaiConfig::default().merge_toml("backend_endpoint = \"typo\"");
+
+        assert!(result.unwrap_err().to_string().contains("backend_endpoint"));
+    }
+
+    #[test]
+    fn test_invalid_api_key_is_rejected() {
+        let config = UnifaiConfig {
+            agent_api_key: Some("key\n".to_string()),
+            toolkit_api_key: Some("key".to_string()),
+            ..UnifaiConfig::default()
+        };
+
+        assert_eq!(
+            config.require_agent_api_key().unwrap_err().to_string(),
+            "Invalid agent_api_key in config: the API key contains characters not allowed in a \
+             header"
+        );
+        assert_eq!(config.require_toolkit_api_key().unwrap(), "key");
+    }
+
+    #[test]
+    fn test_invalid_root_certificate_is_rejected() {
+        let result = ClientOptions::default().add_root_certificate("not a certificate");
+
+        assert!(matches!(
+            result,
+            Err(ConfigError::Invalid { key, .. }) if key == "client.root_certificates"
+        ));
+    }
+
+    #[test]
+    fn test_from_file() {
+        let path = env::temp_dir().join(format!("unifai-config-{}.toml", std::process::id()));
+        fs::write(&path, "toolkit_api_key = \"file-key\"\nmax_retries = 7\n").unwrap();
+
+        let config = UnifaiConfig::from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.require_toolkit_api_key().unwrap(), "file-key");
+        assert_eq!(config.max_retries, 7);
+        assert!(!format!("{config:?}").contains("file-key"));
+
+        assert!(matches!(
+            UnifaiConfig::from_file(&path),
+            Err(ConfigError::Io { .. })
+        ));
+    }
+}
@@ -1,7 +1,69 @@
+use std::time::Duration;
+
+/// Version of this SDK.
+pub const SDK_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// User-Agent sent with every HTTP request and the Toolkit WebSocket handshake.
+pub const USER_AGENT: &str = concat!(
+    "unifai-sdk-rs/",
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("UNIFAI_SDK_TARGET"),
+    ")"
+);
+
 pub const DEFAULT_FRONTEND_API_ENDPOINT: &str = "https://api.unifai.network";
 
 pub const DEFAULT_BACKEND_API_ENDPOINT: &str = "https://backend.unifai.network/api/v1";
 
 pub const DEFAULT_BACKEND_WS_ENDPOINT: &str = "wss://backend.unifai.network/ws";
 
+pub const DEFAULT_BACKEND_POLL_ENDPOINT: &str = "https://backend.unifai.network/poll";
+
+pub const DEFAULT_BACKEND_GRPC_ENDPOINT: &str = "https://grpc.backend.unifai.network";
+
 pub const DEFAULT_TRANSACTION_API_ENDPOINT: &str = "https://txbuilder.unifai.network/api";
+
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+
+pub const DEFAULT_HTTP_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub const DEFAULT_HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub const DEFAULT_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+pub const DEFAULT_TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+
+pub const DEFAULT_POOL_MAX_IDLE_PER_HOST: usize = 32;
+
+pub const DEFAULT_HTTP2_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+pub const DEFAULT_MAX_RESPONSE_BYTES: usize = 16 * 1024 * 1024;
+
+pub const DEFAULT_CALL_TIMEOUT: Duration = Duration::from_millis(50_000);
+
+pub const DEFAULT_DEFINITION_CONCURRENCY: usize = 8;
+
+#[cfg(feature = "tools")]
+pub const DEFAULT_BATCH_CONCURRENCY: usize = 8;
+
+pub const DEFAULT_DEFINITION_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+
+pub const DEFAULT_RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+
+#[cfg(feature = "tools")]
+pub const DEFAULT_RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+
+pub const DEFAULT_FAILOVER_THRESHOLD: u32 = 3;
+
+pub const DEFAULT_FAILOVER_COOLDOWN: Duration = Duration::from_secs(60);
+
+pub const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(30);
+
+pub const DEFAULT_MISSED_PONGS: u32 = 2;
+
+pub const DEFAULT_CANCEL_GRACE: Duration = Duration::from_secs(5);
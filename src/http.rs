@@ -0,0 +1,504 @@
+//! The HTTP client shared by the SDK.
+//!
+//! Tools and Toolkit services built with the same [ClientOptions] share one [Client], and thus one
+//! connection pool, whatever their API keys, which are sent per request. Constructing tools per
+//! request, as web handlers often do, reuses the open connections instead of opening new ones.
+//!
+//! Use [set_client] to provide your own client instead.
+
+use crate::{
+    config::{ClientOptions, ConfigError},
+    constants::USER_AGENT,
+    logging::{directions, events, outcomes, redact_url, targets, WireLogging},
+    secrets::ApiKeyProvider,
+};
+use reqwest::{
+    header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE},
+    Client, ClientBuilder, IntoUrl, RequestBuilder, Response, StatusCode, Url,
+};
+use serde_json::Value;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock, PoisonError},
+};
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+static CLIENT_OVERRIDE: OnceLock<Client> = OnceLock::new();
+static SHARED_CLIENTS: OnceLock<Mutex<HashMap<ClientOptions, Client>>> = OnceLock::new();
+#[cfg(feature = "toolkit")]
+static PROXY_CLIENTS: OnceLock<Mutex<HashMap<ClientOptions, Client>>> = OnceLock::new();
+
+/// Use `client` for every request of the SDK, ignoring [ClientOptions].
+///
+/// It must be called before creating any tools or Toolkit service, which keep the client they
+/// were created with. It can only be called once, the client is given back if it was already set.
+pub fn set_client(client: Client) -> Result<(), Client> {
+    CLIENT_OVERRIDE.set(client)
+}
+
+/// The client shared by everything using `options`, built on first use, failing if it can't be
+/// built, such as when the TLS backend fails to initialize.
+pub(crate) fn shared_client(options: &ClientOptions) -> Result<Client, reqwest::Error> {
+    if let Some(client) = CLIENT_OVERRIDE.get() {
+        return Ok(client.clone());
+    }
+
+    cached_client(&SHARED_CLIENTS, options, || client_builder(options))
+}
+
+/// The client of outbound requests to third-party hosts, built on first use.
+///
+/// It never follows redirects, so a response can't send a request to another host than the one
+/// it was checked for. It ignores [set_client], whose redirect policy is unknown.
+#[cfg(feature = "toolkit")]
+pub(crate) fn proxy_client(options: &ClientOptions) -> Result<Client, reqwest::Error> {
+    cached_client(&PROXY_CLIENTS, options, || {
+        client_builder(options).redirect(reqwest::redirect::Policy::none())
+    })
+}
+
+/// The client of `clients` for `options`, built with `builder` unless there is one already. A
+/// client that fails to build isn't kept, the next use tries again.
+fn cached_client(
+    clients: &OnceLock<Mutex<HashMap<ClientOptions, Client>>>,
+    options: &ClientOptions,
+    builder: impl FnOnce() -> ClientBuilder,
+) -> Result<Client, reqwest::Error> {
+    let mut clients = clients
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    if let Some(client) = clients.get(options) {
+        return Ok(client.clone());
+    }
+
+    let client = builder().build()?;
+    clients.insert(options.clone(), client.clone());
+
+    Ok(client)
+}
+
+pub(crate) fn client_builder(options: &ClientOptions) -> ClientBuilder {
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+    let builder = Client::builder()
+        .user_agent(USER_AGENT)
+        .default_headers(headers);
+
+    // The wasm client is backed by the browser, which owns connections and timeouts.
+    #[cfg(not(target_arch = "wasm32"))]
+    let builder = {
+        let mut builder = builder
+            .pool_idle_timeout(options.pool_idle_timeout)
+            .tcp_keepalive(options.tcp_keepalive);
+        if let Some(connect_timeout) = options.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        if let Some(timeout) = options.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(max_idle) = options.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max_idle);
+        }
+        if options.http2_prior_knowledge {
+            builder = builder.http2_prior_knowledge();
+        }
+        if let Some(interval) = options.http2_keep_alive_interval {
+            builder = builder
+                .http2_keep_alive_interval(interval)
+                .http2_keep_alive_while_idle(true);
+        }
+        builder = builder
+            .http2_adaptive_window(options.http2_adaptive_window)
+            .gzip(options.decompression)
+            .brotli(options.decompression);
+        for pem in &options.root_certificates {
+            // Checked by `ClientOptions::add_root_certificate`.
+            if let Ok(certificate) = reqwest::Certificate::from_pem(pem) {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        #[cfg(feature = "rustls")]
+        if !options.pinned_certificates.is_empty() {
+            // It replaces the roots added above, they're trusted by the pinning config instead.
+            builder = builder.use_preconfigured_tls(crate::pinning::tls_config(
+                &options.pinned_certificates,
+                crate::pinning::roots(&options.root_certificates),
+            ));
+        }
+        builder
+    };
+    #[cfg(target_arch = "wasm32")]
+    let _ = options;
+
+    builder
+}
+
+/// Who an API key authenticates, as far as the server tells, see
+/// [ToolkitService::verify_credentials](crate::toolkit::ToolkitService::verify_credentials) and
+/// [UnifaiClient::verify_credentials](crate::tools::UnifaiClient::verify_credentials).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Principal {
+    pub toolkit_id: Option<u64>,
+    pub agent_id: Option<u64>,
+}
+
+/// Why an API key couldn't be verified.
+#[derive(Debug, thiserror::Error)]
+pub enum CredentialsError {
+    /// The server rejected the key, with a 401 or 403 status. `key` is the config key it was read
+    /// from, as in `toolkit_api_key`.
+    #[error(
+        "InvalidApiKey: the server rejected the {key} with status {status}, check that it's a valid \
+         key of the right kind"
+    )]
+    InvalidKey { key: &'static str, status: u16 },
+
+    /// The server couldn't be reached, the key is unknown to be valid or not.
+    #[error("NetworkError: {0}")]
+    Network(reqwest::Error),
+
+    /// The server answered with a status saying nothing about the key.
+    #[error("UnexpectedStatus: the server answered with status {0}")]
+    UnexpectedStatus(u16),
+}
+
+/// A handle to the shared client, authorizing its requests with the current API key.
+#[derive(Clone, Debug)]
+pub(crate) struct ApiClient {
+    client: Client,
+    api_key: ApiKeyProvider,
+    wire: WireLogging,
+}
+
+impl ApiClient {
+    /// A handle to the client shared by everything using `options`, failing with a
+    /// [ConfigError::Client] if it can't be built.
+    pub(crate) fn new(
+        api_key: ApiKeyProvider,
+        options: &ClientOptions,
+    ) -> Result<Self, ConfigError> {
+        let client = shared_client(options).map_err(ConfigError::Client)?;
+
+        Ok(Self::with_client(client, api_key))
+    }
+
+    /// A handle to `client` instead of the shared one.
+    pub(crate) fn with_client(client: Client, api_key: ApiKeyProvider) -> Self {
+        Self {
+            client,
+            api_key,
+            wire: WireLogging::default(),
+        }
+    }
+
+    /// Send the requests with `client`, keeping the API key and wire logging.
+    pub(crate) fn set_client(&mut self, client: Client) {
+        self.client = client;
+    }
+
+    #[cfg(feature = "tools")]
+    pub(crate) fn api_key(&self) -> &ApiKeyProvider {
+        &self.api_key
+    }
+
+    pub(crate) fn set_wire_logging(&mut self, wire: WireLogging) {
+        self.wire = wire;
+    }
+
+    pub(crate) fn get(&self, url: impl IntoUrl) -> RequestBuilder {
+        self.client
+            .get(url)
+            .header(AUTHORIZATION, self.api_key.header())
+    }
+
+    pub(crate) fn post(&self, url: impl IntoUrl) -> RequestBuilder {
+        self.client
+            .post(url)
+            .header(AUTHORIZATION, self.api_key.header())
+    }
+
+    #[cfg(feature = "toolkit")]
+    pub(crate) fn delete(&self, url: impl IntoUrl) -> RequestBuilder {
+        self.client
+            .delete(url)
+            .header(AUTHORIZATION, self.api_key.header())
+    }
+
+    /// Send a request, logging it on the wire target.
+    pub(crate) async fn send(&self, request: RequestBuilder) -> reqwest::Result<Response> {
+        let request = request.build()?;
+        let method = request.method().clone();
+        let url = redact_url(request.url().as_str());
+        if let Some(body) = request.body().and_then(|body| body.as_bytes()) {
+            self.wire.http_body(directions::SENT, &url, body);
+        }
+
+        let started = Instant::now();
+        let result = self.client.execute(request).await;
+        let duration_ms = started.elapsed().as_millis() as u64;
+
+        match &result {
+            Ok(response) => tracing::debug!(
+                target: targets::WIRE,
+                event = events::HTTP_REQUEST,
+                method = %method,
+                url,
+                status = response.status().as_u16(),
+                duration_ms,
+                outcome = outcomes::OK,
+                "HTTP request"
+            ),
+            Err(e) => tracing::debug!(
+                target: targets::WIRE,
+                event = events::HTTP_REQUEST,
+                method = %method,
+                url,
+                duration_ms,
+                outcome = outcomes::ERROR,
+                error = %e,
+                "HTTP request"
+            ),
+        }
+
+        result
+    }
+
+    /// Send a request authenticated with the key read from the config key `key`, and tell whether
+    /// the server accepted it.
+    ///
+    /// The principal is read from the `toolkitId` and `agentId` fields of a JSON object response,
+    /// when there are any.
+    pub(crate) async fn verify(
+        &self,
+        request: RequestBuilder,
+        key: &'static str,
+    ) -> Result<Principal, CredentialsError> {
+        let response = self
+            .send(request)
+            .await
+            .map_err(CredentialsError::Network)?;
+        let status = response.status();
+        if matches!(status, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) {
+            return Err(CredentialsError::InvalidKey {
+                key,
+                status: status.as_u16(),
+            });
+        }
+        if !status.is_success() {
+            return Err(CredentialsError::UnexpectedStatus(status.as_u16()));
+        }
+
+        let url = response.url().clone();
+        let body = response.bytes().await.map_err(CredentialsError::Network)?;
+        self.log_response_body(&url, &body);
+        let body: Value = serde_json::from_slice(&body).unwrap_or_default();
+
+        Ok(Principal {
+            toolkit_id: body["toolkitId"].as_u64(),
+            agent_id: body["agentId"].as_u64(),
+        })
+    }
+
+    /// Log the body of a response to a request sent with [ApiClient::send].
+    pub(crate) fn log_response_body(&self, url: &Url, body: &[u8]) {
+        self.wire
+            .http_body(directions::RECEIVED, &redact_url(url.as_str()), body);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bytes::Bytes;
+    use futures_util::future::join_all;
+    use http_body_util::Empty;
+    use hyper::{service::service_fn, Response};
+    use hyper_util::{
+        rt::{TokioExecutor, TokioIo},
+        server::conn::auto,
+    };
+    use std::{
+        convert::Infallible,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant},
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    #[test]
+    fn test_build_api_client() {
+        ApiClient::new(
+            ApiKeyProvider::fixed("test", "api_key").unwrap(),
+            &ClientOptions::default(),
+        )
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_default_request_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                connections.push(stream);
+            }
+        });
+
+        let options = ClientOptions {
+            timeout: Some(Duration::from_millis(200)),
+            ..ClientOptions::default()
+        };
+        let started = Instant::now();
+
+        let api_key = ApiKeyProvider::fixed("test", "api_key").unwrap();
+        let error = ApiClient::new(api_key, &options)
+            .unwrap()
+            .get(format!("http://{addr}"))
+            .send()
+            .await
+            .unwrap_err();
+
+        assert!(error.is_timeout());
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    /// Answer every request on keep-alive connections, counting the connections and recording the
+    /// Authorization headers.
+    async fn counting_server() -> (String, Arc<AtomicUsize>, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let authorizations = Arc::new(Mutex::new(Vec::new()));
+
+        let (count, seen) = (connections.clone(), authorizations.clone());
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                count.fetch_add(1, Ordering::SeqCst);
+                let seen = seen.clone();
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buf = [0; 1024];
+                    loop {
+                        match stream.read(&mut buf).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => request.extend_from_slice(&buf[..n]),
+                        }
+                        if request.ends_with(b"\r\n\r\n") {
+                            let head = String::from_utf8(std::mem::take(&mut request)).unwrap();
+                            let authorization = head
+                                .lines()
+                                .find_map(|line| line.strip_prefix("authorization: "))
+                                .unwrap_or_default()
+                                .to_string();
+                            seen.lock().unwrap().push(authorization);
+                            stream
+                                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                                .await
+                                .unwrap();
+                        }
+                    }
+                });
+            }
+        });
+
+        (format!("http://{addr}"), connections, authorizations)
+    }
+
+    #[tokio::test]
+    async fn test_clients_share_connections() {
+        let (endpoint, connections, authorizations) = counting_server().await;
+        let options = ClientOptions::default();
+
+        // Before the client was shared, each of these opened its own connection.
+        for api_key in ["first", "second", "third"] {
+            let api_key = ApiKeyProvider::fixed(api_key, "api_key").unwrap();
+            ApiClient::new(api_key, &options)
+                .unwrap()
+                .get(&endpoint)
+                .send()
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            *authorizations.lock().unwrap(),
+            ["first", "second", "third"]
+        );
+    }
+
+    /// Serve HTTP/1.1 and HTTP/2 without TLS, answering every request after a short delay, and
+    /// count the connections.
+    async fn hyper_server() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+
+        let count = connections.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                count.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let service = service_fn(|_| async {
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        Ok::<_, Infallible>(Response::new(Empty::<Bytes>::new()))
+                    });
+                    let _ = auto::Builder::new(TokioExecutor::new())
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+
+        (format!("http://{addr}"), connections)
+    }
+
+    /// Send 3 bursts of 8 concurrent requests, like a toolkit creating transactions under load, and
+    /// return the number of connections opened.
+    async fn connections_under_load(options: ClientOptions) -> usize {
+        let (endpoint, connections) = hyper_server().await;
+        let client = client_builder(&options).build().unwrap();
+
+        for _ in 0..3 {
+            let responses = join_all((0..8).map(|_| client.get(&endpoint).send())).await;
+            for response in responses {
+                assert!(response.unwrap().status().is_success());
+            }
+        }
+
+        connections.load(Ordering::SeqCst)
+    }
+
+    #[tokio::test]
+    async fn test_connection_reuse_under_load() {
+        let unpooled = connections_under_load(ClientOptions {
+            pool_max_idle_per_host: Some(0),
+            ..ClientOptions::default()
+        })
+        .await;
+        let defaults = connections_under_load(ClientOptions::default()).await;
+        let http2 = connections_under_load(ClientOptions {
+            http2_prior_knowledge: true,
+            ..ClientOptions::default()
+        })
+        .await;
+
+        // Without pooling every request opens a connection, the defaults keep one per concurrent
+        // request, and HTTP/2 multiplexes them all over one.
+        assert_eq!(unpooled, 24);
+        assert_eq!(defaults, 8);
+        assert_eq!(http2, 1);
+    }
+}
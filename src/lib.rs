@@ -1,14 +1,86 @@
 //! unifai-sdk is the Rust SDK for Unifai, an AI native platform for dynamic tools and agent to agent communication.
 //!
 //! See [modules](#modules) for more details.
+//!
+//! # Features
+//!
+//! - `native-tls` (default): use the platform TLS library (OpenSSL on Linux).
+//! - `rustls`: use rustls with the webpki root certificates.
+//! - `toolkit` (default): the `toolkit` module, for developing Toolkits.
+//! - `http-server`: the `toolkit::http` module, serving the actions of a Toolkit as an axum router.
+//! - `mcp`: the `toolkit::mcp` module, serving the actions of a Toolkit to MCP clients.
+//! - `auto-register`: the `register_action!` macro, registering actions where they're defined.
+//! - `keyring`: `secrets::SecretSource::keyring`, reading API keys from the OS keyring.
+//! - `agents`: the `agents` module, for agents to message each other.
+//! - `schemars`: `toolkit::PayloadSchema::from_type`, the payload schema of an action from a type.
+//! - `tools` (default): the `tools` module, for integrating Unifai into agents.
+//! - `blocking`: the `tools::blocking` module, the tools for synchronous applications.
+//! - `tower`: `tower::Service` implementations of `tools::UnifaiClient`.
+//! - `otel`: propagate the OpenTelemetry trace of tool calls to the actions they call, see `trace`.
+//! - `rig` (default): the [rig](https://docs.rig.rs) integration of the `tools` module.
+//! - `test-utils`: the `toolkit::testing` module, and with `tools`, the `testing` module, to test
+//!   Toolkit services without any network.
+//! - `tokio-full`: re-export tokio with all its features as `unifai_sdk::tokio`.
+//!
+//! The `toolkit` module only enables the `macros`, `net`, `rt`, `sync` and `time` features of tokio,
+//! and runs on both the current-thread and the multi-thread runtime.
+//!
+//! Exactly one TLS backend must be enabled, e.g. `default-features = false, features = ["rustls"]`.
+//! The `rig` dependency brings its own HTTP client with the platform TLS library regardless.
+//!
+//! On wasm32, the browser handles TLS and neither `toolkit` nor `rig` is available. Build with
+//! `default-features = false, features = ["tools"]` and use `tools::UnifaiClient`.
+
+#[cfg(all(feature = "native-tls", feature = "rustls"))]
+compile_error!(
+    "features `native-tls` and `rustls` are mutually exclusive, enable only one of them"
+);
+
+#[cfg(all(
+    not(target_arch = "wasm32"),
+    not(any(feature = "native-tls", feature = "rustls"))
+))]
+compile_error!("a TLS backend is required, enable either the `native-tls` or the `rustls` feature");
+
+#[cfg(all(
+    target_arch = "wasm32",
+    any(feature = "toolkit", feature = "rig", feature = "blocking")
+))]
+compile_error!(
+    "features `toolkit`, `rig` and `blocking` are not available on wasm32, use `default-features = false`"
+);
 
+#[cfg(feature = "agents")]
+pub mod agents;
+pub mod config;
+#[cfg(test)]
+#[path = "../benches/common/mod.rs"]
+mod fixtures;
+#[cfg(any(feature = "toolkit", feature = "tools"))]
+pub mod http;
+pub mod logging;
+pub mod prelude;
+#[cfg(any(feature = "toolkit", feature = "tools"))]
+pub mod secrets;
+#[cfg(all(feature = "test-utils", feature = "tools"))]
+pub mod testing;
+#[cfg(feature = "toolkit")]
 pub mod toolkit;
+#[cfg(feature = "tools")]
 pub mod tools;
+#[cfg(any(feature = "toolkit", feature = "tools"))]
+pub mod trace;
 
 mod constants;
-mod utils;
+#[cfg(any(feature = "toolkit", feature = "rig"))]
+mod payload;
+#[cfg(feature = "rustls")]
+mod pinning;
+pub use constants::{SDK_VERSION, USER_AGENT};
 
+#[cfg(feature = "rig")]
 pub use rig;
 pub use serde;
 pub use serde_json;
+#[cfg(feature = "tokio-full")]
 pub use tokio;
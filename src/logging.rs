@@ -0,0 +1,519 @@
+//! This module documents the [tracing] events emitted by the SDK, so subscribers can filter on them
+//! and build dashboards without matching on log messages.
+//!
+//! Every event is emitted under one of the [targets], carries an `event` field whose value is one of
+//! the [events], and records its data in the [fields]. Payloads are only recorded at `DEBUG` level,
+//! after being passed through a [Redactor].
+//!
+//! # Example
+//! ```no_run
+//! use tracing_subscriber::{filter::Targets, prelude::*};
+//! use unifai_sdk::logging::targets;
+//!
+//! // Action calls and results, without transport noise.
+//! tracing_subscriber::registry()
+//!     .with(tracing_subscriber::fmt::layer())
+//!     .with(Targets::new().with_target(targets::DISPATCH, tracing::Level::INFO))
+//!     .init();
+//! ```
+
+use serde_json::Value;
+use std::{fmt, sync::Arc};
+
+/// Targets of the events.
+pub mod targets {
+    /// Action calls received by a Toolkit and their results.
+    pub const DISPATCH: &str = "unifai::toolkit::dispatch";
+    /// The connection of a Toolkit to the server.
+    pub const TRANSPORT: &str = "unifai::toolkit::transport";
+    /// Requests made by the tools.
+    pub const TOOLS: &str = "unifai::tools";
+    /// Messages exchanged with other agents, and the connection carrying them.
+    pub const AGENTS: &str = "unifai::agents";
+    /// HTTP requests and WebSocket frames exchanged with the server, see [WireLogging](super::WireLogging).
+    pub const WIRE: &str = "unifai::wire";
+}
+
+/// Values of the `event` field.
+pub mod events {
+    /// `INFO`, an action call was received. Fields: `action`, `action_id`, `agent_id`.
+    pub const ACTION_CALL: &str = "action_call";
+    /// `INFO`, an action call completed. Fields: `action`, `action_id`, `agent_id`,
+    /// `duration_ms`, `outcome`.
+    pub const ACTION_RESULT: &str = "action_result";
+    /// `WARN`, an action call named an unknown action. Fields: `action`, `action_id`, `agent_id`.
+    pub const ACTION_NOT_FOUND: &str = "action_not_found";
+    /// `WARN`, an action call was answered with a `ToolkitBusy` error, the queue of calls waiting
+    /// for the concurrency limit being full. Fields: `action`, `action_id`, `agent_id`.
+    pub const TOOLKIT_BUSY: &str = "toolkit_busy";
+    /// `DEBUG`, the payload of an action call or result. Fields: `action_id`, `payload`.
+    pub const ACTION_PAYLOAD: &str = "action_payload";
+    /// `DEBUG`, an inline action call ran past its budget and moved to a task of its own. Fields:
+    /// `action`, `action_id`.
+    pub const INLINE_CALL_DEMOTED: &str = "inline_call_demoted";
+    /// `INFO`, the server cancelled an action call in flight. Fields: `action`, `action_id`,
+    /// `reason`.
+    pub const ACTION_CANCELLED: &str = "action_cancelled";
+    /// `WARN`, a cancelled action call still ran after its grace period and was aborted. Fields:
+    /// `action`, `action_id`.
+    pub const ACTION_ABORTED: &str = "action_aborted";
+    /// `WARN`, a cancellation without an agent ID matched the calls of several agents, and was
+    /// dropped. Fields: `action_id`, `calls`, `reason`.
+    pub const AMBIGUOUS_CANCEL: &str = "ambiguous_cancel";
+    /// `INFO`, a call delivered again was answered with the result of its first delivery, see
+    /// [ToolkitService::with_idempotency](crate::toolkit::ToolkitService::with_idempotency).
+    /// Fields: `action`, `action_id`, `agent_id`.
+    pub const DUPLICATE_CALL: &str = "duplicate_call";
+    /// `WARN`, the progress of an action call was dropped, the call having returned. Fields:
+    /// `action`, `action_id`.
+    pub const PROGRESS_DROPPED: &str = "progress_dropped";
+    /// `WARN`, an action call could not be recorded. Fields: `action_id`, `error`.
+    pub const RECORD_FAILED: &str = "record_failed";
+    /// `INFO`, the actions were registered with the server. Fields: `count`, `bytes`,
+    /// `duration_ms`, and `send_ms` spent serializing and sending the registration.
+    pub const ACTIONS_REGISTERED: &str = "actions_registered";
+    /// `ERROR`, a frame could not be sent. Fields: `error`.
+    pub const SEND_FAILED: &str = "send_failed";
+    /// `ERROR`, a frame could not be received. Fields: `error`.
+    pub const RECEIVE_FAILED: &str = "receive_failed";
+    /// `ERROR`, an outgoing message could not be serialized. Fields: `action_id`, `error`.
+    pub const SERIALIZE_FAILED: &str = "serialize_failed";
+    /// `ERROR`, an action result over the size limit was replaced with an error. Fields:
+    /// `action_id`, `size`, `limit`.
+    pub const RESULT_TOO_LARGE: &str = "result_too_large";
+    /// `WARN`, a message that couldn't be read was received, or `INFO` if only its type is
+    /// unknown. Fields: `error` or `message_type`, and `frame`, the text received, redacted and
+    /// truncated to 1 KiB.
+    pub const UNKNOWN_MESSAGE: &str = "unknown_message";
+    /// `WARN`, the server sent a deprecation notice. Fields: `deprecation`, `minimum_version`,
+    /// `version`.
+    pub const DEPRECATION_NOTICE: &str = "deprecation_notice";
+    /// `INFO`, an MCP server was started, again after it exited if `count` is above 1. Fields:
+    /// `count`.
+    pub const MCP_SERVER_STARTED: &str = "mcp_server_started";
+    /// `INFO`, the server closed the connection. Fields: `code`, `reason`.
+    pub const CONNECTION_CLOSED: &str = "connection_closed";
+    /// `WARN`, the server left `count` pings in a row unanswered, and received nothing for
+    /// `duration_ms`, so the Toolkit deems the connection dead. Fields: `count`, `duration_ms`.
+    pub const CONNECTION_DEAD: &str = "connection_dead";
+    /// `WARN`, the connection of an agent to the server was lost, and is opened again.
+    pub const CONNECTION_LOST: &str = "connection_lost";
+    /// `WARN`, the Toolkit couldn't connect to a WebSocket endpoint and tries the next one. Fields:
+    /// `url`, `error`.
+    pub const ENDPOINT_UNREACHABLE: &str = "endpoint_unreachable";
+    /// `WARN`, the Toolkit couldn't connect to any WebSocket endpoint and tries again after
+    /// `delay_ms`. Fields: `attempt`, `delay_ms`, `error`.
+    pub const CONNECT_RETRY: &str = "connect_retry";
+    /// `WARN`, the Toolkit couldn't open a WebSocket connection and falls back to HTTP
+    /// long-polling. Fields: `url`, `error`.
+    pub const TRANSPORT_FALLBACK: &str = "transport_fallback";
+    /// `WARN`, a Toolkit run by a `ToolkitManager` stopped, or failed to start again, and is
+    /// started again. Fields: `toolkit`, and `reason` or `error`.
+    pub const TOOLKIT_RESTARTING: &str = "toolkit_restarting";
+    /// `ERROR`, a Toolkit run by a `ToolkitManager` failed and isn't started again. Fields:
+    /// `toolkit`, `error`.
+    pub const TOOLKIT_FAILED: &str = "toolkit_failed";
+    /// `INFO` with the `ShutdownReason` of the Toolkit service in `reason`, or `ERROR` with
+    /// `error` when it stopped because of an error.
+    pub const SERVICE_STOPPED: &str = "service_stopped";
+    /// `DEBUG`, an HTTP request completed. Fields: `method`, `url`, `duration_ms`, `outcome`, and
+    /// `status` if a response was received or `error` otherwise.
+    pub const HTTP_REQUEST: &str = "http_request";
+    /// `TRACE`, the body of an HTTP request or response, only if [WireLogging::log_bodies](super::WireLogging::log_bodies)
+    /// is set. Fields: `direction`, `url`, `body`.
+    pub const HTTP_BODY: &str = "http_body";
+    /// `DEBUG`, a WebSocket connection is opened, with the secrets in its URL redacted. Fields:
+    /// `url`.
+    pub const WS_CONNECT: &str = "ws_connect";
+    /// `TRACE`, a WebSocket text frame, only if [WireLogging::log_bodies](super::WireLogging::log_bodies)
+    /// is set. Fields: `direction`, `bytes`, `body`.
+    pub const WS_FRAME: &str = "ws_frame";
+    /// `DEBUG`, a tools request completed. Fields: `operation`, `duration_ms`, `outcome`, and
+    /// `error` if it failed.
+    pub const TOOL_REQUEST: &str = "tool_request";
+    /// `WARN`, a tools request failed and is sent again after `delay_ms`, see
+    /// [RetryPolicy](crate::tools::RetryPolicy). Fields: `operation`, `attempt`, `delay_ms`, and
+    /// `status` if a response was received or `error` otherwise.
+    pub const TOOL_RETRY: &str = "tool_retry";
+    /// `WARN`, the tools moved to another backend API endpoint, see
+    /// [UnifaiConfig::backend_api_fallback_endpoints](crate::config::UnifaiConfig::backend_api_fallback_endpoints).
+    /// Fields: `from`, `to`.
+    pub const ENDPOINT_SWITCHED: &str = "endpoint_switched";
+    /// `INFO`, the action embeddings of a [DynamicToolSet](crate::tools::DynamicToolSet) were
+    /// loaded. Fields: `reused`, `embedded`, `evicted`.
+    pub const TOOL_EMBEDDINGS_LOADED: &str = "tool_embeddings_loaded";
+    /// `WARN`, the action embeddings cache could not be read and is rebuilt. Fields: `path`,
+    /// `error`.
+    pub const TOOL_EMBEDDINGS_CACHE_IGNORED: &str = "tool_embeddings_cache_ignored";
+}
+
+/// Names of the fields.
+pub mod fields {
+    pub const EVENT: &str = "event";
+    pub const ACTION: &str = "action";
+    pub const ACTION_ID: &str = "action_id";
+    pub const AGENT_ID: &str = "agent_id";
+    pub const ATTEMPT: &str = "attempt";
+    pub const BODY: &str = "body";
+    pub const BYTES: &str = "bytes";
+    pub const COUNT: &str = "count";
+    pub const DELAY_MS: &str = "delay_ms";
+    pub const DEPRECATION: &str = "deprecation";
+    pub const DIRECTION: &str = "direction";
+    pub const DURATION_MS: &str = "duration_ms";
+    pub const EMBEDDED: &str = "embedded";
+    pub const ERROR: &str = "error";
+    pub const EVICTED: &str = "evicted";
+    pub const FRAME: &str = "frame";
+    pub const FROM: &str = "from";
+    pub const METHOD: &str = "method";
+    pub const MINIMUM_VERSION: &str = "minimum_version";
+    pub const OPERATION: &str = "operation";
+    pub const OUTCOME: &str = "outcome";
+    pub const PATH: &str = "path";
+    pub const PAYLOAD: &str = "payload";
+    pub const REASON: &str = "reason";
+    pub const REUSED: &str = "reused";
+    pub const STATUS: &str = "status";
+    pub const TO: &str = "to";
+    pub const TOOLKIT: &str = "toolkit";
+    pub const URL: &str = "url";
+    pub const VERSION: &str = "version";
+}
+
+/// Values of the `outcome` field.
+pub mod outcomes {
+    pub const OK: &str = "ok";
+    pub const ERROR: &str = "error";
+    pub const EVICTED: &str = "evicted";
+}
+
+/// Values of the `direction` field.
+pub mod directions {
+    pub const SENT: &str = "sent";
+    pub const RECEIVED: &str = "received";
+}
+
+/// A hook applied to every payload before it's logged.
+pub type Redactor = Arc<dyn Fn(&Value) -> Value + Send + Sync>;
+
+/// The default [Redactor], see [redact_secrets].
+pub fn default_redactor() -> Redactor {
+    Arc::new(redact_secrets)
+}
+
+/// Replace the values of object keys that look like secrets, such as `apiKey`, `password` or
+/// `token`, with `"<redacted>"`, at any depth.
+pub fn redact_secrets(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| {
+                    if is_secret_key(key) {
+                        (key.clone(), Value::String("<redacted>".to_string()))
+                    } else {
+                        (key.clone(), redact_secrets(value))
+                    }
+                })
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.iter().map(redact_secrets).collect()),
+        value => value.clone(),
+    }
+}
+
+/// Options of the logging of the exchanges with the server, on the [WIRE](targets::WIRE) target.
+///
+/// The method, URL, status and duration of every HTTP request are logged at `DEBUG`. Bodies and
+/// WebSocket frames are only logged at `TRACE` once [WireLogging::log_bodies] is set, after going
+/// through the redactor and being truncated. Headers are never logged, and the values of
+/// secret-looking query parameters are redacted from URLs.
+#[derive(Clone)]
+pub struct WireLogging {
+    log_bodies: bool,
+    max_body_bytes: usize,
+    redactor: Redactor,
+}
+
+impl Default for WireLogging {
+    fn default() -> Self {
+        Self {
+            log_bodies: false,
+            max_body_bytes: 4096,
+            redactor: default_redactor(),
+        }
+    }
+}
+
+impl fmt::Debug for WireLogging {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WireLogging")
+            .field("log_bodies", &self.log_bodies)
+            .field("max_body_bytes", &self.max_body_bytes)
+            .finish_non_exhaustive()
+    }
+}
+
+impl WireLogging {
+    /// Log the bodies of HTTP requests and responses, and WebSocket frames. Defaults to `false`.
+    pub fn log_bodies(mut self, log_bodies: bool) -> Self {
+        self.log_bodies = log_bodies;
+        self
+    }
+
+    /// Truncate logged bodies to this many bytes. Defaults to 4096.
+    pub fn max_body_bytes(mut self, max_body_bytes: usize) -> Self {
+        self.max_body_bytes = max_body_bytes;
+        self
+    }
+
+    /// Set the hook applied to JSON bodies before they're logged. Defaults to [redact_secrets].
+    /// Bodies that aren't JSON are never logged, only their size.
+    pub fn redactor(mut self, redactor: impl Fn(&Value) -> Value + Send + Sync + 'static) -> Self {
+        self.redactor = Arc::new(redactor);
+        self
+    }
+
+    /// Log a body of an HTTP exchange, if enabled.
+    #[cfg_attr(not(any(feature = "toolkit", feature = "tools")), allow(dead_code))]
+    pub(crate) fn http_body(&self, direction: &'static str, url: &str, body: &[u8]) {
+        if self.bodies_enabled() {
+            tracing::trace!(
+                target: targets::WIRE,
+                event = events::HTTP_BODY,
+                direction,
+                url,
+                body = %self.render(body),
+                "HTTP body"
+            );
+        }
+    }
+
+    /// Log a WebSocket text frame, if enabled.
+    #[cfg_attr(not(feature = "toolkit"), allow(dead_code))]
+    pub(crate) fn ws_frame(&self, direction: &'static str, frame: &str) {
+        if self.bodies_enabled() {
+            tracing::trace!(
+                target: targets::WIRE,
+                event = events::WS_FRAME,
+                direction,
+                bytes = frame.len(),
+                body = %self.render(frame.as_bytes()),
+                "WebSocket frame"
+            );
+        }
+    }
+
+    #[cfg_attr(not(any(feature = "toolkit", feature = "tools")), allow(dead_code))]
+    fn bodies_enabled(&self) -> bool {
+        self.log_bodies && tracing::enabled!(target: targets::WIRE, tracing::Level::TRACE)
+    }
+
+    /// Redact and truncate a body.
+    #[cfg_attr(not(any(feature = "toolkit", feature = "tools")), allow(dead_code))]
+    fn render(&self, body: &[u8]) -> String {
+        let Ok(value) = serde_json::from_slice::<Value>(body) else {
+            return format!("<{} bytes, not JSON>", body.len());
+        };
+
+        truncate((self.redactor)(&value).to_string(), self.max_body_bytes)
+    }
+}
+
+/// A frame received from the server for a warning: redacted with [redact_secrets] if it's JSON,
+/// and truncated to `max_bytes`.
+#[cfg_attr(not(feature = "toolkit"), allow(dead_code))]
+pub(crate) fn frame_excerpt(frame: &[u8], max_bytes: usize) -> String {
+    let text = match serde_json::from_slice::<Value>(frame) {
+        Ok(value) => redact_secrets(&value).to_string(),
+        Err(_) => String::from_utf8_lossy(frame).into_owned(),
+    };
+    truncate(text, max_bytes)
+}
+
+#[cfg_attr(not(any(feature = "toolkit", feature = "tools")), allow(dead_code))]
+fn truncate(mut text: String, max_bytes: usize) -> String {
+    if text.len() > max_bytes {
+        let mut end = max_bytes;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+        text.push_str("...<truncated>");
+    }
+    text
+}
+
+/// A URL with the values of its secret-looking query parameters redacted, down to their last 4
+/// characters if they're long enough for those not to give them away.
+#[cfg_attr(not(any(feature = "toolkit", feature = "tools")), allow(dead_code))]
+pub(crate) fn redact_url(url: &str) -> String {
+    let Some((base, query)) = url.split_once('?') else {
+        return url.to_string();
+    };
+
+    let query: Vec<String> = query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) if is_secret_key(key) && value.len() >= 16 => {
+                let last = value.char_indices().nth_back(3).map_or(0, |(i, _)| i);
+                format!("{key}=<redacted>{}", &value[last..])
+            }
+            Some((key, _)) if is_secret_key(key) => format!("{key}=<redacted>"),
+            _ => pair.to_string(),
+        })
+        .collect();
+
+    format!("{base}?{}", query.join("&"))
+}
+
+fn is_secret_key(key: &str) -> bool {
+    const SECRET_WORDS: &[&str] = &[
+        "apikey",
+        "api_key",
+        "api-key",
+        "authorization",
+        "password",
+        "passwd",
+        "privatekey",
+        "private_key",
+        "secret",
+        "token",
+    ];
+
+    let key = key.to_lowercase();
+    SECRET_WORDS.iter().any(|word| key.contains(word))
+}
+
+/// A layer capturing events, for asserting on them in tests.
+#[cfg(all(test, any(feature = "toolkit", feature = "tools")))]
+pub(crate) mod capture {
+    use std::{
+        collections::HashMap,
+        fmt::Debug,
+        sync::{Arc, Mutex},
+    };
+    use tracing::{
+        field::{Field, Visit},
+        Event, Subscriber,
+    };
+    use tracing_subscriber::{layer::Context, prelude::*, Layer};
+
+    #[derive(Clone, Debug)]
+    pub(crate) struct CapturedEvent {
+        pub target: String,
+        pub level: tracing::Level,
+        pub fields: HashMap<String, String>,
+    }
+
+    #[derive(Clone, Default)]
+    pub(crate) struct Captured(Arc<Mutex<Vec<CapturedEvent>>>);
+
+    impl Captured {
+        /// Capture the events of the current thread until the guard is dropped.
+        pub(crate) fn install(&self) -> tracing::subscriber::DefaultGuard {
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(self.clone()))
+        }
+
+        /// All the captured events.
+        pub(crate) fn all(&self) -> Vec<CapturedEvent> {
+            self.0.lock().unwrap().clone()
+        }
+
+        /// The captured events whose `event` field is `name`.
+        pub(crate) fn events(&self, name: &str) -> Vec<CapturedEvent> {
+            self.0
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|event| event.fields.get("event").map(String::as_str) == Some(name))
+                .cloned()
+                .collect()
+        }
+    }
+
+    struct Visitor(HashMap<String, String>);
+
+    impl Visit for Visitor {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name().to_string(), value.to_string());
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            self.0
+                .insert(field.name().to_string(), format!("{value:?}"));
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for Captured {
+        fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+            let mut visitor = Visitor(HashMap::new());
+            event.record(&mut visitor);
+
+            self.0.lock().unwrap().push(CapturedEvent {
+                target: event.metadata().target().to_string(),
+                level: *event.metadata().level(),
+                fields: visitor.0,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_redact_secrets() {
+        let value = json!({
+            "content": "hello",
+            "apiKey": "k",
+            "nested": [{ "accessToken": "t", "amount": 1 }],
+        });
+
+        assert_eq!(
+            redact_secrets(&value),
+            json!({
+                "content": "hello",
+                "apiKey": "<redacted>",
+                "nested": [{ "accessToken": "<redacted>", "amount": 1 }],
+            })
+        );
+    }
+
+    #[test]
+    fn test_redact_url() {
+        assert_eq!(
+            redact_url("wss://example.com/ws?type=toolkit&api-key=k"),
+            "wss://example.com/ws?type=toolkit&api-key=<redacted>"
+        );
+        assert_eq!(
+            redact_url("wss://example.com/ws?api-key=0123456789abcdef"),
+            "wss://example.com/ws?api-key=<redacted>cdef"
+        );
+        assert_eq!(redact_url("https://example.com/a"), "https://example.com/a");
+    }
+
+    #[test]
+    fn test_wire_logging_truncates_redacted_bodies() {
+        let wire = WireLogging::default().max_body_bytes(16);
+
+        let body = wire.render(br#"{"apiKey":"k","content":"a long enough content"}"#);
+        assert_eq!(body, r#"{"apiKey":"<reda...<truncated>"#);
+        assert_eq!(wire.render(b"k=v"), "<3 bytes, not JSON>");
+    }
+
+    #[test]
+    fn test_frame_excerpt() {
+        let frame = br#"{"type":"nope","data":{"token":"t"}}"#;
+        assert_eq!(
+            frame_excerpt(frame, 1024),
+            r#"{"data":{"token":"<redacted>"},"type":"nope"}"#
+        );
+
+        let long = "é".repeat(1000);
+        let excerpt = frame_excerpt(long.as_bytes(), 1024);
+        assert_eq!(excerpt, format!("{}...<truncated>", "é".repeat(512)));
+    }
+}
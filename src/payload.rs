@@ -0,0 +1,38 @@
+//! The payloads of Toolkit actions, as described to agents.
+
+use serde_json::{json, Map, Value};
+
+/// `payload` as a JSON Schema object, see
+/// [ActionDefinition::input_schema](crate::toolkit::ActionDefinition::input_schema).
+pub(crate) fn input_schema(payload: &Value) -> Value {
+    let Some(fields) = payload.as_object() else {
+        return json!({ "type": "object", "properties": {} });
+    };
+    if fields.get("type").is_some_and(Value::is_string) {
+        return payload.clone();
+    }
+
+    let mut properties = Map::new();
+    let mut required = Vec::new();
+    for (name, field) in fields {
+        let field = match field {
+            Value::Object(field) => {
+                let mut field = field.clone();
+                if field.remove("required") == Some(Value::Bool(true)) {
+                    required.push(Value::String(name.clone()));
+                }
+                Value::Object(field)
+            }
+            // A field described in words only.
+            Value::String(description) => json!({ "description": description }),
+            _ => json!({}),
+        };
+        properties.insert(name.clone(), field);
+    }
+
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+    })
+}
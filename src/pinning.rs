@@ -0,0 +1,392 @@
+//! Pinning of the keys of the servers behind the HTTP clients, see
+//! [ClientOptions::pin_certificates](crate::config::ClientOptions::pin_certificates).
+
+// Only the tools and the Toolkit make HTTP requests.
+#![cfg_attr(not(any(feature = "toolkit", feature = "tools")), allow(dead_code))]
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::digest::{digest, SHA256};
+use rustls::{
+    client::{
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        WebPkiServerVerifier,
+    },
+    crypto::ring::default_provider,
+    pki_types::{pem::PemObject, CertificateDer, ServerName, UnixTime},
+    CertificateError, ClientConfig, DigitallySignedStruct, OtherError, RootCertStore,
+    SignatureScheme,
+};
+use std::{error::Error as StdError, fmt, str::FromStr, sync::Arc};
+
+/// The SHA-256 digest of a DER-encoded SubjectPublicKeyInfo, as used by HPKP and `curl --pinnedpubkey`.
+///
+/// Get it from a certificate with:
+///
+/// ```sh
+/// openssl x509 -in cert.pem -pubkey -noout | openssl pkey -pubin -outform der \
+///     | openssl dgst -sha256 -binary | base64
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Sha256Pin([u8; 32]);
+
+impl Sha256Pin {
+    /// A pin from the raw digest.
+    pub fn new(digest: [u8; 32]) -> Self {
+        Self(digest)
+    }
+
+    /// Compute the pin of a DER-encoded SubjectPublicKeyInfo.
+    pub fn from_spki_der(spki: &[u8]) -> Self {
+        let mut pin = [0; 32];
+        pin.copy_from_slice(digest(&SHA256, spki).as_ref());
+        Self(pin)
+    }
+
+    /// Parse a base64-encoded pin.
+    pub fn from_base64(pin: &str) -> Result<Self, InvalidPin> {
+        let bytes = STANDARD.decode(pin).map_err(|_| InvalidPin)?;
+        Ok(Self(bytes.try_into().map_err(|_| InvalidPin)?))
+    }
+}
+
+impl FromStr for Sha256Pin {
+    type Err = InvalidPin;
+
+    fn from_str(pin: &str) -> Result<Self, Self::Err> {
+        Self::from_base64(pin)
+    }
+}
+
+impl fmt::Debug for Sha256Pin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Sha256Pin({})", STANDARD.encode(self.0))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("a pin must be the base64 encoding of a 32 bytes SHA-256 digest")]
+pub struct InvalidPin;
+
+/// The error of a server whose certificates include none of the pinned keys.
+#[derive(Debug, thiserror::Error)]
+#[error("none of the server certificates matches a pinned key")]
+struct PinMismatch;
+
+/// A TLS config which verifies certificates against `roots` as usual, then rejects servers
+/// sending no certificate with one of the `pins`.
+pub(crate) fn tls_config(pins: &[Sha256Pin], roots: RootCertStore) -> ClientConfig {
+    let provider = Arc::new(default_provider());
+    let inner = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+        .build()
+        .unwrap();
+    let verifier = PinningVerifier {
+        inner,
+        pins: pins.to_vec(),
+    };
+
+    let mut config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    config
+}
+
+/// The roots trusted by the HTTP clients: the webpki roots, and the certificates of the
+/// PEM-encoded `extra` ones, see
+/// [ClientOptions::add_root_certificate](crate::config::ClientOptions::add_root_certificate).
+pub(crate) fn roots(extra: &[Vec<u8>]) -> RootCertStore {
+    let mut roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    // Checked by `ClientOptions::add_root_certificate`.
+    let certificates = extra
+        .iter()
+        .flat_map(|pem| CertificateDer::pem_slice_iter(pem))
+        .filter_map(Result::ok);
+    roots.add_parsable_certificates(certificates);
+
+    roots
+}
+
+/// Whether the request failed because the server didn't match the pinned keys.
+pub(crate) fn is_pin_mismatch(e: &reqwest::Error) -> bool {
+    let mut source: Option<&(dyn StdError + 'static)> = Some(e);
+
+    while let Some(mut e) = source {
+        // The io errors wrapping the TLS error skip it in their `source`.
+        while let Some(inner) = e.downcast_ref::<std::io::Error>().and_then(|e| e.get_ref()) {
+            e = inner;
+        }
+        if let Some(rustls::Error::InvalidCertificate(CertificateError::Other(other))) =
+            e.downcast_ref::<rustls::Error>()
+        {
+            return other.0.is::<PinMismatch>();
+        }
+        source = e.source();
+    }
+
+    false
+}
+
+/// The URL of a failed request, without its query which may hold secrets.
+pub(crate) fn endpoint(e: &reqwest::Error) -> String {
+    match e.url() {
+        Some(url) => {
+            let mut url = url.clone();
+            url.set_query(None);
+            url.to_string()
+        }
+        None => "unknown endpoint".to_string(),
+    }
+}
+
+#[derive(Debug)]
+struct PinningVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pins: Vec<Sha256Pin>,
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+
+        let pinned = std::iter::once(end_entity)
+            .chain(intermediates)
+            .filter_map(|cert| spki_der(cert))
+            .any(|spki| self.pins.contains(&Sha256Pin::from_spki_der(spki)));
+
+        if pinned {
+            Ok(verified)
+        } else {
+            Err(rustls::Error::InvalidCertificate(CertificateError::Other(
+                OtherError(Arc::new(PinMismatch)),
+            )))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// Extract the DER-encoded SubjectPublicKeyInfo of a certificate which already passed verification.
+fn spki_der(cert: &[u8]) -> Option<&[u8]> {
+    // Certificate ::= SEQUENCE { tbsCertificate, ... }
+    let (certificate, _) = der_element(cert)?;
+    let (tbs, _) = der_element(der_contents(certificate)?)?;
+    let mut fields = der_contents(tbs)?;
+
+    // version [0] is optional, then serialNumber, signature, issuer, validity and subject.
+    if fields.first() == Some(&0xa0) {
+        fields = der_element(fields)?.1;
+    }
+    for _ in 0..5 {
+        fields = der_element(fields)?.1;
+    }
+
+    Some(der_element(fields)?.0)
+}
+
+/// Split the first DER element, tag and length included, from the rest of `input`.
+fn der_element(input: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (header, length) = der_header(input)?;
+    let end = header.checked_add(length)?;
+
+    (end <= input.len()).then(|| input.split_at(end))
+}
+
+/// The contents of a DER element, without its tag and length.
+fn der_contents(element: &[u8]) -> Option<&[u8]> {
+    let (header, length) = der_header(element)?;
+    element.get(header..header.checked_add(length)?)
+}
+
+/// The size of the tag and length of a DER element, and the size of its contents.
+fn der_header(input: &[u8]) -> Option<(usize, usize)> {
+    let first = *input.get(1)?;
+    if first < 0x80 {
+        return Some((2, first as usize));
+    }
+
+    let size = (first & 0x7f) as usize;
+    if size == 0 || size > std::mem::size_of::<usize>() {
+        return None;
+    }
+    let length = input
+        .get(2..2 + size)?
+        .iter()
+        .fold(0, |length, &byte| length << 8 | byte as usize);
+
+    Some((2 + size, length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{CertifiedKey, KeyPair};
+    use reqwest::Client;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+    use tokio_rustls::TlsAcceptor;
+
+    /// Serve HTTPS for `localhost` with a new self-signed certificate, returning its URL, the
+    /// certificate and the pin of its key.
+    async fn tls_server() -> (String, CertificateDer<'static>, Sha256Pin) {
+        let CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let pin = Sha256Pin::from_spki_der(&key_pair.public_key_der());
+        let cert = cert.der().clone();
+
+        let config = rustls::ServerConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![cert.clone()],
+                key_pair.serialize_der().try_into().unwrap(),
+            )
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let Ok(mut stream) = acceptor.accept(stream).await else {
+                        return;
+                    };
+                    let mut buf = [0; 1024];
+                    let _ = stream.read(&mut buf).await;
+                    let _ = stream
+                        .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                        .await;
+                });
+            }
+        });
+
+        (
+            format!("https://localhost:{port}/toolkits/fields/"),
+            cert,
+            pin,
+        )
+    }
+
+    fn pinned_client(pins: &[Sha256Pin], cert: CertificateDer<'static>) -> Client {
+        let mut roots = RootCertStore::empty();
+        roots.add(cert).unwrap();
+
+        Client::builder()
+            .use_preconfigured_tls(tls_config(pins, roots))
+            .build()
+            .unwrap()
+    }
+
+    #[cfg(any(feature = "toolkit", feature = "tools"))]
+    #[tokio::test]
+    async fn test_pins_apply_with_added_roots() {
+        use crate::{config::ClientOptions, http::client_builder};
+
+        let (url, cert, pin) = tls_server().await;
+        let pem = format!(
+            "-----BEGIN CERTIFICATE-----\n{}\n-----END CERTIFICATE-----\n",
+            STANDARD.encode(&cert)
+        );
+        let options = ClientOptions::default().add_root_certificate(pem).unwrap();
+
+        let client = client_builder(&options.clone().pin_certificates(vec![pin]))
+            .build()
+            .unwrap();
+        let response = client.get(&url).send().await.unwrap();
+        assert!(response.status().is_success());
+
+        let client = client_builder(&options.pin_certificates(vec![Sha256Pin::new([0; 32])]))
+            .build()
+            .unwrap();
+        assert!(is_pin_mismatch(&client.get(&url).send().await.unwrap_err()));
+    }
+
+    #[test]
+    fn test_spki_der() {
+        let key_pair = KeyPair::generate().unwrap();
+        let params = rcgen::CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+        let cert = params.self_signed(&key_pair).unwrap();
+
+        assert_eq!(spki_der(cert.der()), Some(&key_pair.public_key_der()[..]));
+    }
+
+    #[test]
+    fn test_parse_pin() {
+        let pin = Sha256Pin::new([7; 32]);
+        let encoded = STANDARD.encode([7; 32]);
+
+        assert_eq!(encoded.parse::<Sha256Pin>().unwrap(), pin);
+        assert!("not a pin".parse::<Sha256Pin>().is_err());
+        assert!(STANDARD.encode([7; 16]).parse::<Sha256Pin>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_matching_pin_is_accepted() {
+        let (url, cert, pin) = tls_server().await;
+
+        let response = pinned_client(&[Sha256Pin::new([0; 32]), pin], cert)
+            .get(url)
+            .send()
+            .await
+            .unwrap();
+
+        assert!(response.status().is_success());
+    }
+
+    #[tokio::test]
+    async fn test_pin_mismatch_is_rejected() {
+        let (url, cert, _) = tls_server().await;
+
+        let error = pinned_client(&[Sha256Pin::new([0; 32])], cert)
+            .get(format!("{url}?apiKey=secret"))
+            .send()
+            .await
+            .unwrap_err();
+
+        assert!(is_pin_mismatch(&error));
+        assert_eq!(endpoint(&error), url);
+    }
+}
@@ -0,0 +1,19 @@
+//! Commonly needed imports, `use unifai_sdk::prelude::*;` is enough to write a Toolkit or an agent.
+//!
+//! It also brings the `serde` crate into scope, so serde derives work with
+//! `#[serde(crate = "serde")]` without depending on serde directly.
+
+pub use crate::config::{ClientOptions, ConfigError, UnifaiConfig};
+#[cfg(feature = "toolkit")]
+pub use crate::toolkit::{
+    Action, ActionContext, ActionDefinition, ActionParams, ActionResult, FunctionAction,
+    PaymentDefinition, ToolkitError, ToolkitHandle, ToolkitInfo, ToolkitRuntimeEvent,
+    ToolkitService,
+};
+#[cfg(feature = "tools")]
+pub use crate::tools::{
+    get_tools, get_tools_from_config, CallTool, CallToolArgs, SearchTools, SearchToolsArgs,
+    ToolsError, UnifaiClient,
+};
+pub use serde::{self, Deserialize, Serialize};
+pub use serde_json::{self, json, Value};
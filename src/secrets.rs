@@ -0,0 +1,313 @@
+//! Where API keys come from, when they can't be hard-coded in an environment variable read once.
+//!
+//! A [SecretSource] is resolved into an [ApiKeyProvider] when the tools client or the Toolkit
+//! service is created, failing right away if the key can't be read. The provider hands the
+//! current key to every request, and [ApiKeyProvider::rotate] reads the source again, so a key
+//! mounted as a file can be rotated without restarting:
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! use unifai_sdk::{secrets::SecretSource, toolkit::ToolkitService};
+//!
+//! let service = ToolkitService::from_secret(SecretSource::file("/run/secrets/unifai")).await?;
+//! let api_key = service.api_key().clone();
+//!
+//! // On the rotation signal of the platform, e.g. SIGHUP or a file watcher:
+//! api_key.rotate().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Keys are never logged, the [Debug] output of sources and providers only names where the key
+//! comes from.
+
+use crate::config::{check_api_key, ConfigError};
+use reqwest::header::HeaderValue;
+use std::{
+    env,
+    error::Error,
+    fmt,
+    future::Future,
+    path::PathBuf,
+    pin::Pin,
+    sync::{Arc, PoisonError, RwLock},
+};
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+type CustomSource =
+    Arc<dyn Fn() -> Pin<Box<dyn Future<Output = Result<String, BoxError>> + Send>> + Send + Sync>;
+
+/// Where to read an API key from. Surrounding whitespace, such as the trailing newline of a file,
+/// is trimmed.
+#[derive(Clone)]
+pub struct SecretSource(Source);
+
+#[derive(Clone)]
+enum Source {
+    Env(String),
+    File(PathBuf),
+    #[cfg(feature = "keyring")]
+    Keyring {
+        service: String,
+        user: String,
+    },
+    Custom(CustomSource),
+}
+
+impl SecretSource {
+    /// The environment variable `name`.
+    pub fn env(name: impl Into<String>) -> Self {
+        Self(Source::Env(name.into()))
+    }
+
+    /// The content of the file at `path`, as mounted by Kubernetes secrets.
+    pub fn file(path: impl Into<PathBuf>) -> Self {
+        Self(Source::File(path.into()))
+    }
+
+    /// The password of the OS keyring entry of `service` and `user`.
+    #[cfg(feature = "keyring")]
+    pub fn keyring(service: impl Into<String>, user: impl Into<String>) -> Self {
+        Self(Source::Keyring {
+            service: service.into(),
+            user: user.into(),
+        })
+    }
+
+    /// The key returned by `resolve`, called again on every rotation.
+    pub fn custom<F, Fut, E>(resolve: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<String, E>> + Send + 'static,
+        E: Into<BoxError>,
+    {
+        Self(Source::Custom(Arc::new(move || {
+            let future = resolve();
+            Box::pin(async move { future.await.map_err(Into::into) })
+        })))
+    }
+
+    /// Read the key from the source.
+    pub async fn resolve(&self) -> Result<String, SecretError> {
+        self.read()
+            .await
+            .and_then(|key| {
+                let key = key.trim();
+                if key.is_empty() {
+                    Err("the secret is empty".into())
+                } else {
+                    Ok(key.to_string())
+                }
+            })
+            .map_err(|error| SecretError {
+                source_name: self.to_string(),
+                error,
+            })
+    }
+
+    async fn read(&self) -> Result<String, BoxError> {
+        match &self.0 {
+            Source::Env(name) => Ok(env::var(name)?),
+            Source::File(path) => Ok(std::fs::read_to_string(path)?),
+            #[cfg(feature = "keyring")]
+            Source::Keyring { service, user } => {
+                Ok(keyring::Entry::new(service, user)?.get_password()?)
+            }
+            Source::Custom(resolve) => resolve().await,
+        }
+    }
+}
+
+impl fmt::Display for SecretSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Source::Env(name) => write!(f, "env var {name}"),
+            Source::File(path) => write!(f, "file {}", path.display()),
+            #[cfg(feature = "keyring")]
+            Source::Keyring { service, user } => write!(f, "keyring entry {service}/{user}"),
+            Source::Custom(_) => write!(f, "custom source"),
+        }
+    }
+}
+
+impl fmt::Debug for SecretSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SecretSource")
+            .field(&format_args!("{self}"))
+            .finish()
+    }
+}
+
+/// An API key that couldn't be read from its source.
+#[derive(Debug, thiserror::Error)]
+#[error("SecretError: couldn't read the API key from {source_name}: {error}")]
+pub struct SecretError {
+    /// The source, as in `file /run/secrets/unifai`.
+    pub source_name: String,
+    pub error: BoxError,
+}
+
+/// The API key of a client or a service, read by each request. Clones share the key, so rotating
+/// one rotates all of them.
+#[derive(Clone)]
+pub struct ApiKeyProvider {
+    source: Option<SecretSource>,
+    key: Arc<RwLock<HeaderValue>>,
+}
+
+impl ApiKeyProvider {
+    /// Resolve the key from `source`, failing if it can't be read or isn't a valid header value.
+    pub async fn new(source: SecretSource) -> Result<Self, SecretError> {
+        let key = resolve_header(&source).await?;
+
+        Ok(Self {
+            source: Some(source),
+            key: Arc::new(RwLock::new(key)),
+        })
+    }
+
+    /// A key that never changes, failing with a [ConfigError::Invalid] naming `name` if it isn't a
+    /// valid header value.
+    pub(crate) fn fixed(api_key: &str, name: &'static str) -> Result<Self, ConfigError> {
+        let mut key = HeaderValue::from_str(check_api_key(name, api_key)?)
+            .expect("checked to be a valid header value");
+        key.set_sensitive(true);
+
+        Ok(Self {
+            source: None,
+            key: Arc::new(RwLock::new(key)),
+        })
+    }
+
+    /// Read the key from its source again, for the requests sent from now on. The current key is
+    /// kept if it can't be read. Does nothing for a key given as a string.
+    pub async fn rotate(&self) -> Result<(), SecretError> {
+        let Some(source) = &self.source else {
+            return Ok(());
+        };
+
+        let key = resolve_header(source).await?;
+        *self.key.write().unwrap_or_else(PoisonError::into_inner) = key;
+
+        Ok(())
+    }
+
+    /// The current key, as an `Authorization` header.
+    pub(crate) fn header(&self) -> HeaderValue {
+        self.key
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// The current key, for the URLs carrying it.
+    #[cfg(feature = "toolkit")]
+    pub(crate) fn expose(&self) -> String {
+        // Only valid strings are stored.
+        self.header().to_str().unwrap_or_default().to_string()
+    }
+}
+
+impl fmt::Debug for ApiKeyProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ApiKeyProvider")
+            .field("source", &self.source)
+            .finish_non_exhaustive()
+    }
+}
+
+async fn resolve_header(source: &SecretSource) -> Result<HeaderValue, SecretError> {
+    let key = source.resolve().await?;
+    let mut key = HeaderValue::from_str(&key).map_err(|e| SecretError {
+        source_name: source.to_string(),
+        error: e.into(),
+    })?;
+    key.set_sensitive(true);
+
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_resolve_sources() {
+        let dir = env::temp_dir().join(format!("unifai-secrets-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("key");
+        std::fs::write(&path, "from-file\n").unwrap();
+
+        env::set_var("UNIFAI_TEST_SECRET_SOURCE", " from-env ");
+
+        assert_eq!(
+            SecretSource::env("UNIFAI_TEST_SECRET_SOURCE")
+                .resolve()
+                .await
+                .unwrap(),
+            "from-env"
+        );
+        assert_eq!(
+            SecretSource::file(&path).resolve().await.unwrap(),
+            "from-file"
+        );
+        let custom = SecretSource::custom(|| async { Ok::<_, std::io::Error>("custom".into()) });
+        assert_eq!(custom.resolve().await.unwrap(), "custom");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_errors_name_the_source() {
+        let error = SecretSource::env("UNIFAI_TEST_SECRET_UNSET")
+            .resolve()
+            .await
+            .unwrap_err();
+        assert_eq!(error.source_name, "env var UNIFAI_TEST_SECRET_UNSET");
+
+        let error = SecretSource::file("/nonexistent/unifai/key")
+            .resolve()
+            .await
+            .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("SecretError: couldn't read the API key from file /nonexistent"),
+            "{error}"
+        );
+
+        let error = ApiKeyProvider::new(SecretSource::custom(|| async {
+            Ok::<_, std::io::Error>("  ".into())
+        }))
+        .await
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "SecretError: couldn't read the API key from custom source: the secret is empty"
+        );
+
+        let error = ApiKeyProvider::new(SecretSource::custom(|| async {
+            Ok::<_, std::io::Error>("line\nbreak".into())
+        }))
+        .await
+        .unwrap_err();
+        assert_eq!(error.source_name, "custom source");
+    }
+
+    #[test]
+    fn test_debug_never_shows_the_key() {
+        let provider = ApiKeyProvider::fixed("super-secret", "api_key").unwrap();
+        assert!(!format!("{provider:?}").contains("super-secret"));
+        assert!(provider.header().is_sensitive());
+    }
+
+    #[test]
+    fn test_fixed_key_must_fit_a_header() {
+        let error = ApiKeyProvider::fixed("super-secret\n", "toolkit_api_key").unwrap_err();
+        assert!(matches!(
+            error,
+            ConfigError::Invalid { key, .. } if key == "toolkit_api_key"
+        ));
+    }
+}
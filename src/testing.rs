@@ -0,0 +1,97 @@
+//! Test the actions of a Toolkit through the agent tools, without any Unifai backend, see
+//! [loopback], or with toolkits connected to a simulated one, see [MockBackend].
+//!
+//! ```
+//! use serde_json::{json, Value};
+//! use unifai_sdk::{config::UnifaiConfig, testing::loopback, toolkit::*, tools::*};
+//!
+//! struct Echo;
+//!
+//! impl Action for Echo {
+//!     const NAME: &'static str = "echo";
+//!
+//!     type Error = std::convert::Infallible;
+//!     type Args = Value;
+//!     type Output = Value;
+//!
+//!     async fn definition(&self) -> ActionDefinition {
+//!         ActionDefinition {
+//!             description: "Echo the payload".to_string(),
+//!             payload: json!({}),
+//!             payment: None,
+//!             ..Default::default()
+//!         }
+//!     }
+//!
+//!     async fn call(
+//!         &self,
+//!         _ctx: ActionContext,
+//!         params: ActionParams<Self::Args>,
+//!     ) -> Result<ActionResult<Self::Output>, Self::Error> {
+//!         Ok(ActionResult {
+//!             payload: params.payload,
+//!             payment: None,
+//!         })
+//!     }
+//! }
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let mut service = ToolkitService::new("test");
+//! service.add_action(Echo);
+//!
+//! let (search_tools, call_tool) = loopback(service, &UnifaiConfig::default()).await;
+//!
+//! let args = SearchToolsArgs { query: "echo".to_string(), ..Default::default() };
+//! let found = search_tools.search(&args).await.unwrap();
+//! assert_eq!(found[0].action, "echo");
+//!
+//! let args = CallToolArgs { action: "echo".to_string(), payload: json!({ "n": 1 }), payment: None, ..Default::default() };
+//! let response = call_tool.call_typed(&args).await.unwrap();
+//! assert_eq!(response.payload, json!({ "n": 1 }));
+//! # }
+//! ```
+
+use crate::{
+    config::UnifaiConfig,
+    toolkit::{loopback, ToolkitService},
+    tools::{CallTool, SearchTools, UnifaiClient},
+};
+use std::sync::Arc;
+
+pub use crate::toolkit::mock_backend::{MockBackend, RecordedFrame};
+
+/// Serve the actions of `service` on a loopback port, in place of the Unifai backend, and return
+/// the agent tools built from `tools_config` pointed at it.
+///
+/// Searches match the words of the query against the names and descriptions of the actions, and
+/// return their plain names, which calls expect. Calls go through the same dispatch as the ones
+/// received from Unifai, with an `agent_id` of `0`. The agent API key of the config is optional.
+///
+/// The server runs until the runtime shuts down.
+///
+/// # Panics
+///
+/// If no loopback port can be bound.
+pub async fn loopback(
+    service: ToolkitService,
+    tools_config: &UnifaiConfig,
+) -> (SearchTools, CallTool) {
+    let addr = loopback::serve(Arc::new(service))
+        .await
+        .expect("a loopback port is bound");
+
+    let config = UnifaiConfig {
+        agent_api_key: Some(
+            tools_config
+                .agent_api_key
+                .clone()
+                .unwrap_or_else(|| "loopback".to_string()),
+        ),
+        backend_api_endpoint: format!("http://{addr}"),
+        ..tools_config.clone()
+    };
+    let client = UnifaiClient::from_config(&config).expect("the config has an agent API key");
+
+    (client.clone().into(), client.into())
+}
@@ -1,14 +1,153 @@
 use super::{context::ActionContext, errors::ToolkitError};
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use std::{error::Error, future::Future, pin::Pin};
+use serde::{
+    de::{DeserializeOwned, IgnoredAny},
+    Deserialize, Serialize,
+};
+use serde_json::{
+    value::{to_raw_value, RawValue},
+    Value,
+};
+use std::{borrow::Cow, error::Error, future::Future, pin::Pin, time::Duration};
 
 /// A struct used to define an action.
-#[derive(Clone, Debug, Deserialize, Serialize)]
+///
+/// The optional fields are shown by the Unifai UI, and only sent when set.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
 pub struct ActionDefinition {
     pub description: String,
     pub payload: Value,
+    /// The payment the action asks for, usually a [PaymentDefinition] converted with `into()`.
     pub payment: Option<Value>,
+    /// The name of the action shown to humans, such as `Echo Slam`.
+    #[serde(
+        rename = "displayName",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub display_name: Option<String>,
+    /// Example payloads of the action, checked by [verify_examples](super::verify_examples).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub examples: Option<Vec<Value>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+}
+
+/// The payment a paid action asks for, as in
+/// `{"amount":100,"token":"USDC","description":"per call","required":true}`.
+///
+/// # Example
+/// ```
+/// # use unifai_sdk::toolkit::PaymentDefinition;
+/// let payment = PaymentDefinition::fixed(100).with_description("per call");
+/// let range = PaymentDefinition::range(10, 100).with_token("USDC").optional();
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PaymentDefinition {
+    #[serde(flatten)]
+    pub price: PaymentPrice,
+    /// The currency of the price, the platform's default if `None`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Whether a call must authorize a payment, rather than pay only for extras.
+    pub required: bool,
+}
+
+/// The price of a paid action, in the smallest unit of its token, as the payments of
+/// [ActionParams] and [ActionResult].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum PaymentPrice {
+    /// `{"amount":100}`, the same for every call.
+    Fixed { amount: u64 },
+    /// `{"minAmount":10,"maxAmount":100}`, depending on the call.
+    Range {
+        #[serde(rename = "minAmount")]
+        min_amount: u64,
+        #[serde(rename = "maxAmount")]
+        max_amount: u64,
+    },
+}
+
+impl PaymentDefinition {
+    /// A required payment of `amount` per call.
+    pub fn fixed(amount: u64) -> Self {
+        Self::new(PaymentPrice::Fixed { amount })
+    }
+
+    /// A required payment between `min_amount` and `max_amount` per call.
+    pub fn range(min_amount: u64, max_amount: u64) -> Self {
+        Self::new(PaymentPrice::Range {
+            min_amount,
+            max_amount,
+        })
+    }
+
+    fn new(price: PaymentPrice) -> Self {
+        Self {
+            price,
+            token: None,
+            description: None,
+            required: true,
+        }
+    }
+
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Let calls authorize no payment.
+    pub fn optional(mut self) -> Self {
+        self.required = false;
+        self
+    }
+}
+
+impl From<PaymentDefinition> for Value {
+    fn from(payment: PaymentDefinition) -> Self {
+        serde_json::to_value(payment).expect("a payment definition always serializes")
+    }
+}
+
+impl ActionDefinition {
+    /// The payload as a JSON Schema object.
+    ///
+    /// Payloads describe each field with a `required` flag, as in `{"content": {"type": "string",
+    /// "required": true}}`; they are turned into an object schema listing the required fields.
+    /// A payload that already is a schema, with a top-level `type`, is returned as is.
+    pub fn input_schema(&self) -> Value {
+        crate::payload::input_schema(&self.payload)
+    }
+
+    /// The example payloads, those of the `examples` field if set, or else the `examples` of a
+    /// payload that is a schema, see [verify_examples](super::verify_examples).
+    pub fn examples(&self) -> &[Value] {
+        if let Some(examples) = &self.examples {
+            return examples;
+        }
+        match self.payload.get("examples") {
+            Some(Value::Array(examples))
+                if self.payload.get("type").is_some_and(Value::is_string) =>
+            {
+                examples
+            }
+            _ => &[],
+        }
+    }
+
+    /// Whether calling the action changes nothing, from a `side_effect_free: true` flag next to
+    /// the `examples` of a payload that is a schema, so its examples can be run.
+    pub fn is_side_effect_free(&self) -> bool {
+        self.payload.get("type").is_some_and(Value::is_string)
+            && self.payload.get("side_effect_free") == Some(&Value::Bool(true))
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -23,6 +162,27 @@ pub struct ActionResult<T> {
     pub payment: Option<u64>,
 }
 
+/// Whether an action can be called, see [Action::available].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Availability {
+    #[default]
+    Available,
+    /// Calls are answered at once with an
+    /// [ActionUnavailable](ToolkitError::ActionUnavailable) error, telling the agent when to try
+    /// again if `retry_after` is known.
+    Unavailable { retry_after: Option<Duration> },
+}
+
+impl From<bool> for Availability {
+    fn from(available: bool) -> Self {
+        if available {
+            Self::Available
+        } else {
+            Self::Unavailable { retry_after: None }
+        }
+    }
+}
+
 /// Trait that represents an action of Toolkit
 ///
 /// # Example
@@ -61,6 +221,7 @@ pub struct ActionResult<T> {
 ///                 }
 ///             }),
 ///             payment: None,
+///             ..Default::default()
 ///         }
 ///     }
 ///
@@ -84,6 +245,10 @@ pub struct ActionResult<T> {
 /// ```
 pub trait Action: Sized + Send + Sync {
     /// The name of the action. This name should be unique.
+    ///
+    /// [ToolkitService::add_action](super::ToolkitService::add_action) checks it against the
+    /// [grammar](super::InvalidActionName) at compile time. Leave it empty for an action that
+    /// overrides [Action::name] instead.
     const NAME: &'static str;
 
     /// The error type of the action.
@@ -91,6 +256,9 @@ pub trait Action: Sized + Send + Sync {
     /// The arguments type of the action.
     type Args: for<'a> Deserialize<'a> + Send + Sync;
     /// The output type of the action.
+    ///
+    /// It's serialized straight into the result message, its object keys keep the order of its
+    /// `Serialize` implementation.
     type Output: Serialize;
 
     /// A method returning the name of the action.
@@ -107,18 +275,187 @@ pub trait Action: Sized + Send + Sync {
         ctx: ActionContext,
         params: ActionParams<Self::Args>,
     ) -> impl Future<Output = Result<ActionResult<Self::Output>, Self::Error>> + Send + Sync;
+
+    /// The timeout of a call, overriding the one of the service, see
+    /// [ToolkitService::with_action_timeout](super::ToolkitService::with_action_timeout).
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Whether the action can be called, checked before each call, such as to report a
+    /// maintenance window of the API it depends on rather than time out. It should return
+    /// quickly. See also [ToolkitHandle::set_availability](super::ToolkitHandle::set_availability).
+    fn available(&self) -> impl Future<Output = Availability> + Send + Sync {
+        async { Availability::Available }
+    }
 }
 
+/// The future of [ActionDyn::call_raw].
+pub(crate) type RawCallFuture<'a> = Pin<
+    Box<dyn Future<Output = Result<ActionResult<Box<RawValue>>, ToolkitError>> + Send + Sync + 'a>,
+>;
+
 pub(crate) trait ActionDyn: Send + Sync {
     fn name(&self) -> String;
 
     fn definition(&self) -> Pin<Box<dyn Future<Output = ActionDefinition> + Send + Sync + '_>>;
 
+    /// The timeout of a call, if it overrides the one of the service.
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    fn available(&self) -> Pin<Box<dyn Future<Output = Availability> + Send + Sync + '_>> {
+        Box::pin(std::future::ready(Availability::Available))
+    }
+
+    /// Deserialize a payload into the arguments of the action like a call would, without calling
+    /// it. Actions taking any JSON only decode string-encoded payloads.
+    fn check_payload(&self, payload: &RawValue) -> Result<(), PayloadError> {
+        check_payload::<Value>(payload)
+    }
+
     fn call(
         &self,
         ctx: ActionContext,
-        params: ActionParams<Value>,
+        params: ActionParams<Box<RawValue>>,
     ) -> Pin<Box<dyn Future<Output = Result<ActionResult<Value>, ToolkitError>> + Send + Sync + '_>>;
+
+    /// Like [ActionDyn::call], with the result payload serialized to JSON text, as sent over the
+    /// wire.
+    ///
+    /// Actions implementing [Action] serialize their output directly, instead of building a
+    /// [Value] to serialize it again, which matters for large results. Object keys then come in
+    /// the order of the output's `Serialize` implementation, where a [Value] sorts them.
+    fn call_raw(
+        &self,
+        ctx: ActionContext,
+        params: ActionParams<Box<RawValue>>,
+    ) -> RawCallFuture<'_> {
+        into_raw_call(self.call(ctx, params))
+    }
+}
+
+/// Serialize the result payload of an [ActionDyn::call] to JSON text.
+pub(crate) fn into_raw_call<'a>(
+    call: Pin<
+        Box<dyn Future<Output = Result<ActionResult<Value>, ToolkitError>> + Send + Sync + 'a>,
+    >,
+) -> RawCallFuture<'a> {
+    Box::pin(async move {
+        let result = call.await?;
+
+        Ok(ActionResult {
+            payload: to_raw_value(&result.payload)?,
+            payment: result.payment,
+        })
+    })
+}
+
+/// The stages of decoding a payload: the `value` as is, the `json_string` it encodes, and the
+/// `double_json_string` that one encodes.
+const DECODING_STAGES: [&str; 3] = ["value", "json_string", "double_json_string"];
+
+/// The forms of a payload to deserialize, with their decoding stage, in the order to try them.
+///
+/// A string whose content is JSON is encoded JSON, as agents often send, so it's only taken as a
+/// string, such as for a `String` argument, once the forms it decodes to have failed.
+fn payload_forms(payload: &RawValue) -> Vec<(&'static str, Cow<'_, str>)> {
+    let mut forms = Vec::new();
+    let mut text = Cow::Borrowed(payload.get());
+    for stage in DECODING_STAGES {
+        let decoded = text
+            .starts_with('"')
+            .then(|| serde_json::from_str::<String>(&text).ok())
+            .flatten()
+            .filter(|decoded| serde_json::from_str::<IgnoredAny>(decoded).is_ok());
+        let encoded = decoded.is_some();
+        forms.push((stage, text, encoded));
+        match decoded {
+            Some(decoded) => text = Cow::Owned(decoded),
+            None => break,
+        }
+    }
+
+    forms.sort_by_key(|(_, _, encoded)| *encoded);
+    forms
+        .into_iter()
+        .map(|(stage, text, _)| (stage, text))
+        .collect()
+}
+
+/// Deserialize the payload of an action call, which may also be encoded as a JSON string, or
+/// twice, failing with [ToolkitError::PayloadDecode] and the stages tried.
+///
+/// The payload is read straight from its JSON text, without building a [Value] first.
+pub(crate) fn parse_payload<T: DeserializeOwned>(payload: &RawValue) -> Result<T, ToolkitError> {
+    let mut stages = Vec::new();
+    let mut first_error = None;
+    for (stage, text) in payload_forms(payload) {
+        stages.push(stage);
+        match serde_json::from_str(&text) {
+            Ok(payload) => return Ok(payload),
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+
+    Err(ToolkitError::PayloadDecode {
+        stages,
+        error: first_error.expect("a payload has a form"),
+    })
+}
+
+/// A payload that doesn't deserialize, with the path of the offending value, `.` for the whole
+/// payload.
+#[derive(Debug)]
+pub(crate) struct PayloadError {
+    pub(crate) path: String,
+    pub(crate) error: serde_json::Error,
+}
+
+impl From<serde_path_to_error::Error<serde_json::Error>> for PayloadError {
+    fn from(e: serde_path_to_error::Error<serde_json::Error>) -> Self {
+        Self {
+            path: e.path().to_string(),
+            error: e.into_inner(),
+        }
+    }
+}
+
+/// Deserialize a payload like [parse_payload], tracking the path of the first error.
+pub(crate) fn check_payload<T: DeserializeOwned>(payload: &RawValue) -> Result<(), PayloadError> {
+    let mut first_error = None;
+    for (_, text) in payload_forms(payload) {
+        match check_text::<T>(&text) {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+
+    Err(first_error.expect("a payload has a form"))
+}
+
+fn check_text<T: DeserializeOwned>(text: &str) -> Result<(), PayloadError> {
+    let mut deserializer = serde_json::Deserializer::from_str(text);
+    serde_path_to_error::deserialize::<_, T>(&mut deserializer)?;
+    deserializer.end().map_err(|error| PayloadError {
+        path: ".".to_string(),
+        error,
+    })
+}
+
+/// Deserialize the arguments of an action from a payload of any shape.
+fn parse_params<T: Action>(
+    params: ActionParams<Box<RawValue>>,
+) -> Result<ActionParams<T::Args>, ToolkitError> {
+    Ok(ActionParams {
+        payload: parse_payload(&params.payload)?,
+        payment: params.payment,
+    })
 }
 
 impl<T: Action> ActionDyn for T {
@@ -130,34 +467,134 @@ impl<T: Action> ActionDyn for T {
         Box::pin(<Self as Action>::definition(self))
     }
 
+    fn timeout(&self) -> Option<Duration> {
+        <Self as Action>::timeout(self)
+    }
+
+    fn available(&self) -> Pin<Box<dyn Future<Output = Availability> + Send + Sync + '_>> {
+        Box::pin(<Self as Action>::available(self))
+    }
+
+    fn check_payload(&self, payload: &RawValue) -> Result<(), PayloadError> {
+        check_payload::<T::Args>(payload)
+    }
+
     fn call(
         &self,
         ctx: ActionContext,
-        params: ActionParams<Value>,
+        params: ActionParams<Box<RawValue>>,
     ) -> Pin<Box<dyn Future<Output = Result<ActionResult<Value>, ToolkitError>> + Send + Sync + '_>>
     {
         Box::pin(async move {
-            let payload: <Self as Action>::Args = if let Some(payload_str) = params.payload.as_str()
-            {
-                serde_json::from_str(payload_str)?
-            } else {
-                serde_json::from_value(params.payload)?
-            };
+            let params = parse_params::<Self>(params)?;
+            let result = <Self as Action>::call(self, ctx, params)
+                .await
+                .map_err(|e| ToolkitError::ActionCallError(Box::new(e)))?;
 
-            let params = ActionParams {
-                payload,
-                payment: params.payment,
-            };
+            Ok(ActionResult {
+                payload: serde_json::to_value(result.payload)?,
+                payment: result.payment,
+            })
+        })
+    }
 
-            <Self as Action>::call(self, ctx, params)
+    fn call_raw(
+        &self,
+        ctx: ActionContext,
+        params: ActionParams<Box<RawValue>>,
+    ) -> RawCallFuture<'_> {
+        Box::pin(async move {
+            let params = parse_params::<Self>(params)?;
+            let result = <Self as Action>::call(self, ctx, params)
                 .await
-                .map_err(|e| ToolkitError::ActionCallError(Box::new(e)))
-                .and_then(|result| {
-                    Ok(ActionResult {
-                        payload: serde_json::to_value(result.payload)?,
-                        payment: result.payment,
-                    })
-                })
+                .map_err(|e| ToolkitError::ActionCallError(Box::new(e)))?;
+
+            Ok(ActionResult {
+                payload: to_raw_value(&result.payload)?,
+                payment: result.payment,
+            })
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toolkit::ActionBytes;
+    use serde_json::json;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Args {
+        content: String,
+    }
+
+    fn raw(payload: Value) -> Box<RawValue> {
+        to_raw_value(&payload).unwrap()
+    }
+
+    #[test]
+    fn test_parse_payload_shapes() {
+        let expected = Args {
+            content: "hi".to_string(),
+        };
+        let object = json!({ "content": "hi" });
+        let encoded = json!(object.to_string());
+        let double = json!(encoded.to_string());
+
+        for payload in [&object, &encoded, &double] {
+            assert_eq!(
+                parse_payload::<Args>(&raw(payload.clone())).unwrap(),
+                expected
+            );
+            assert_eq!(
+                parse_payload::<Value>(&raw(payload.clone())).unwrap(),
+                object
+            );
+            assert!(check_payload::<Args>(&raw(payload.clone())).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_parse_raw_string_payload() {
+        assert_eq!(
+            parse_payload::<String>(&raw(json!("hello"))).unwrap(),
+            "hello"
+        );
+        // Encoded JSON that isn't a string is still a string argument.
+        assert_eq!(parse_payload::<String>(&raw(json!("42"))).unwrap(), "42");
+        assert_eq!(
+            parse_payload::<Value>(&raw(json!("hello"))).unwrap(),
+            json!("hello")
+        );
+    }
+
+    #[test]
+    fn test_parse_bytes_payload() {
+        let bytes = parse_payload::<ActionBytes>(&raw(json!("aGk="))).unwrap();
+        assert_eq!(&bytes[..], b"hi");
+
+        let bytes = parse_payload::<ActionBytes>(&raw(json!({ "$bytes": "aGk=" }))).unwrap();
+        assert_eq!(&bytes[..], b"hi");
+    }
+
+    #[test]
+    fn test_decode_error_names_stages() {
+        let error = parse_payload::<Args>(&raw(json!({ "text": "hi" }))).unwrap_err();
+        assert!(
+            matches!(&error, ToolkitError::PayloadDecode { stages, .. } if stages == &["value"])
+        );
+
+        let encoded = json!(json!({ "text": "hi" }).to_string());
+        let error = parse_payload::<Args>(&raw(json!(encoded.to_string()))).unwrap_err();
+        let ToolkitError::PayloadDecode { stages, .. } = &error else {
+            panic!("{error}");
+        };
+        assert_eq!(stages, &["double_json_string", "value", "json_string"]);
+        assert!(error
+            .to_string()
+            .starts_with("PayloadDecodeError: missing field `content`"));
+        assert!(error
+            .to_string()
+            .ends_with("(tried double_json_string, value, json_string)"));
+    }
+}
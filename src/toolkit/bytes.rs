@@ -0,0 +1,148 @@
+//! Binary data in the payloads of actions, see [ActionBytes].
+
+use base64::{
+    alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    Engine,
+};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, ops::Deref};
+
+/// Standard base64, with or without padding.
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Binary data, such as an image, in the arguments or output of an action.
+///
+/// Agents send it as a base64 string, or wrapped as `{"$bytes": "..."}`; it's serialized back as
+/// a base64 string.
+///
+/// # Example
+/// ```
+/// # use serde::Deserialize;
+/// # use unifai_sdk::toolkit::ActionBytes;
+/// #[derive(Deserialize)]
+/// struct Args {
+///     caption: String,
+///     image: ActionBytes,
+/// }
+///
+/// let args: Args = serde_json::from_str(r#"{"caption":"hi","image":"iVBORw=="}"#).unwrap();
+/// assert_eq!(&args.image[..4], b"\x89PNG");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ActionBytes(pub Vec<u8>);
+
+impl ActionBytes {
+    pub fn into_inner(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+impl Deref for ActionBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<Vec<u8>> for ActionBytes {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+}
+
+impl Serialize for ActionBytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&BASE64.encode(&self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for ActionBytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(BytesVisitor)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct BytesVisitor;
+
+impl<'de> de::Visitor<'de> for BytesVisitor {
+    type Value = ActionBytes;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(r#"a base64 string or {"$bytes": "<base64>"}"#)
+    }
+
+    fn visit_str<E: de::Error>(self, encoded: &str) -> Result<Self::Value, E> {
+        BASE64
+            .decode(encoded)
+            .map(ActionBytes)
+            .map_err(|e| E::custom(format_args!("invalid base64: {e}")))
+    }
+
+    fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut bytes = None;
+        while let Some(key) = map.next_key::<String>()? {
+            if key != "$bytes" {
+                return Err(de::Error::unknown_field(&key, &["$bytes"]));
+            }
+            if bytes.is_some() {
+                return Err(de::Error::duplicate_field("$bytes"));
+            }
+            let encoded: String = map.next_value()?;
+            bytes = Some(self.visit_str::<A::Error>(&encoded)?);
+        }
+
+        bytes.ok_or_else(|| de::Error::missing_field("$bytes"))
+    }
+}
+
+#[cfg(feature = "schemars")]
+impl schemars::JsonSchema for ActionBytes {
+    fn schema_name() -> String {
+        "ActionBytes".to_string()
+    }
+
+    fn json_schema(_: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        schemars::schema::SchemaObject {
+            instance_type: Some(schemars::schema::InstanceType::String.into()),
+            format: Some("byte".to_string()),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_bytes_shapes() {
+        let bytes: ActionBytes = serde_json::from_value(json!("aGVsbG8=")).unwrap();
+        assert_eq!(bytes, ActionBytes(b"hello".to_vec()));
+
+        let bytes: ActionBytes = serde_json::from_value(json!("aGVsbG8")).unwrap();
+        assert_eq!(&bytes[..], b"hello");
+
+        let bytes: ActionBytes = serde_json::from_value(json!({ "$bytes": "aGk=" })).unwrap();
+        assert_eq!(&bytes[..], b"hi");
+
+        assert_eq!(serde_json::to_value(&bytes).unwrap(), json!("aGk="));
+    }
+
+    #[test]
+    fn test_invalid_bytes() {
+        let error = serde_json::from_value::<ActionBytes>(json!("not base64!")).unwrap_err();
+        assert!(error.to_string().contains("invalid base64"), "{error}");
+
+        assert!(serde_json::from_value::<ActionBytes>(json!({ "bytes": "aGk=" })).is_err());
+        assert!(serde_json::from_value::<ActionBytes>(json!({})).is_err());
+        assert!(serde_json::from_value::<ActionBytes>(json!(1)).is_err());
+    }
+}
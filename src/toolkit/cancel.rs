@@ -0,0 +1,282 @@
+//! The cancellation of action calls in flight by the server, see
+//! [ActionContext::cancelled](super::ActionContext::cancelled).
+//!
+//! A cancelled call first has its token cancelled, for the action to stop cleanly, and is answered
+//! with an [ActionCancelled](super::ToolkitError::ActionCancelled) error once it returns. If it's
+//! still running after the grace period, its task is aborted and the call is answered right away.
+
+use super::idempotency::CallKey;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use tokio::task::AbortHandle;
+use tokio_util::sync::CancellationToken;
+
+/// The calls running on tasks of their own, which the server may cancel.
+#[derive(Default)]
+pub(super) struct InFlightCalls {
+    calls: HashMap<CallKey, InFlightCall>,
+    /// The number of calls past which the finished ones are forgotten.
+    prune_at: usize,
+}
+
+struct InFlightCall {
+    action: String,
+    cancellation: CancellationToken,
+    abort: AbortHandle,
+    answered: Answered,
+}
+
+/// Whether a call was answered, by its task or when aborted, so that it's answered only once.
+#[derive(Clone, Default)]
+pub(super) struct Answered(Arc<AtomicBool>);
+
+impl Answered {
+    /// Claim the answer of the call, `false` if it was already.
+    pub(super) fn claim(&self) -> bool {
+        !self.0.swap(true, Ordering::AcqRel)
+    }
+}
+
+/// A cancellation without an agent ID, of an action ID the calls of several agents have.
+#[derive(Debug, PartialEq)]
+pub(super) struct AmbiguousCancel {
+    pub(super) calls: usize,
+}
+
+/// A call aborted after its grace period, to answer.
+pub(super) struct AbortedCall {
+    pub(super) action: String,
+    pub(super) key: CallKey,
+}
+
+impl InFlightCalls {
+    pub(super) fn insert(
+        &mut self,
+        key: CallKey,
+        action: String,
+        cancellation: CancellationToken,
+        abort: AbortHandle,
+        answered: Answered,
+    ) {
+        // Calls are only removed when cancelled, forget the finished ones every now and then.
+        if self.calls.len() >= self.prune_at {
+            self.calls.retain(|_, call| !call.abort.is_finished());
+            self.prune_at = (self.calls.len() * 2).max(64);
+        }
+
+        self.calls.insert(
+            key,
+            InFlightCall {
+                action,
+                cancellation,
+                abort,
+                answered,
+            },
+        );
+    }
+
+    /// Whether the call `key` is still running.
+    pub(super) fn contains(&self, key: CallKey) -> bool {
+        self.calls
+            .get(&key)
+            .is_some_and(|call| !call.abort.is_finished())
+    }
+
+    /// Cancel the token of a call, returning its key and action, `None` if it's not in flight.
+    ///
+    /// Without `agent_id`, the call in flight with `action_id` is cancelled, and none if the
+    /// calls of several agents have it.
+    pub(super) fn cancel(
+        &mut self,
+        action_id: u64,
+        agent_id: Option<u64>,
+    ) -> Result<Option<(CallKey, &str)>, AmbiguousCancel> {
+        let key = match agent_id {
+            Some(agent_id) => CallKey {
+                agent_id,
+                action_id,
+            },
+            None => {
+                let mut keys = self
+                    .calls
+                    .iter()
+                    .filter(|(key, call)| key.action_id == action_id && !call.abort.is_finished())
+                    .map(|(key, _)| *key);
+                let Some(key) = keys.next() else {
+                    return Ok(None);
+                };
+                let others = keys.count();
+                if others > 0 {
+                    return Err(AmbiguousCancel { calls: others + 1 });
+                }
+                key
+            }
+        };
+        let Some(call) = self
+            .calls
+            .get(&key)
+            .filter(|call| !call.abort.is_finished())
+        else {
+            return Ok(None);
+        };
+        call.cancellation.cancel();
+
+        Ok(Some((key, &call.action)))
+    }
+
+    /// Abort a cancelled call at the end of its grace period, `None` if it answered on its own
+    /// meanwhile.
+    pub(super) fn abort(&mut self, key: CallKey) -> Option<AbortedCall> {
+        let call = self.calls.remove(&key)?;
+        if !call.answered.claim() {
+            return None;
+        }
+        call.abort.abort();
+
+        Some(AbortedCall {
+            action: call.action,
+            key,
+        })
+    }
+}
+
+impl Drop for InFlightCalls {
+    /// The connection is gone, and with it whoever waits for the results.
+    fn drop(&mut self) {
+        for call in self.calls.values() {
+            call.cancellation.cancel();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::pending;
+    use tokio::spawn;
+
+    const KEY: CallKey = CallKey {
+        agent_id: 2,
+        action_id: 1,
+    };
+
+    #[tokio::test]
+    async fn test_cancel_then_abort() {
+        let mut calls = InFlightCalls::default();
+        let (token, answered) = (CancellationToken::new(), Answered::default());
+        let task = spawn(pending::<()>());
+        calls.insert(
+            KEY,
+            "sleep".to_string(),
+            token.clone(),
+            task.abort_handle(),
+            answered.clone(),
+        );
+
+        assert_eq!(calls.cancel(1, None), Ok(Some((KEY, "sleep"))));
+        assert!(token.is_cancelled());
+        assert_eq!(calls.cancel(7, None), Ok(None));
+
+        let aborted = calls.abort(KEY).unwrap();
+        assert_eq!((aborted.action.as_str(), aborted.key), ("sleep", KEY));
+        assert!(task.await.unwrap_err().is_cancelled());
+        assert!(calls.abort(KEY).is_none());
+
+        // The aborted call was answered, its task can't answer it again.
+        assert!(!answered.claim());
+    }
+
+    #[tokio::test]
+    async fn test_finished_calls_answer_on_their_own() {
+        let mut calls = InFlightCalls::default();
+        let answered = Answered::default();
+        let task = spawn({
+            let answered = answered.clone();
+            async move { assert!(answered.claim()) }
+        });
+        calls.insert(
+            KEY,
+            "echo".to_string(),
+            Default::default(),
+            task.abort_handle(),
+            answered,
+        );
+        task.await.unwrap();
+
+        assert_eq!(calls.cancel(1, None), Ok(None));
+        assert!(calls.abort(KEY).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_calls_are_keyed_by_agent() {
+        let mut calls = InFlightCalls::default();
+        let other = CallKey { agent_id: 3, ..KEY };
+        let tasks = [spawn(pending::<()>()), spawn(pending::<()>())];
+        for (key, task) in [KEY, other].into_iter().zip(&tasks) {
+            calls.insert(
+                key,
+                "sleep".to_string(),
+                Default::default(),
+                task.abort_handle(),
+                Default::default(),
+            );
+        }
+
+        assert!(calls.abort(other).is_some());
+        assert!(calls.abort(other).is_none());
+        assert!(calls.abort(KEY).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_cancels_the_call_of_the_agent() {
+        let mut calls = InFlightCalls::default();
+        let other = CallKey { agent_id: 3, ..KEY };
+        let tokens = [CancellationToken::new(), CancellationToken::new()];
+        let tasks = [spawn(pending::<()>()), spawn(pending::<()>())];
+        for ((key, token), task) in [KEY, other].into_iter().zip(&tokens).zip(&tasks) {
+            calls.insert(
+                key,
+                "sleep".to_string(),
+                token.clone(),
+                task.abort_handle(),
+                Default::default(),
+            );
+        }
+
+        // Without an agent, either call could be meant.
+        assert_eq!(calls.cancel(1, None), Err(AmbiguousCancel { calls: 2 }));
+        assert!(!tokens[0].is_cancelled() && !tokens[1].is_cancelled());
+
+        assert_eq!(calls.cancel(1, Some(3)), Ok(Some((other, "sleep"))));
+        assert!(!tokens[0].is_cancelled() && tokens[1].is_cancelled());
+        assert_eq!(calls.cancel(1, Some(4)), Ok(None));
+
+        // Once the other call is done, the call left is the one meant.
+        calls.abort(other).unwrap();
+        assert_eq!(calls.cancel(1, None), Ok(Some((KEY, "sleep"))));
+        assert!(tokens[0].is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn test_dropping_cancels_every_call() {
+        let mut calls = InFlightCalls::default();
+        let token = CancellationToken::new();
+        let task = spawn(pending::<()>());
+        calls.insert(
+            KEY,
+            "sleep".to_string(),
+            token.clone(),
+            task.abort_handle(),
+            Default::default(),
+        );
+
+        drop(calls);
+        assert!(token.is_cancelled());
+        task.abort();
+    }
+}
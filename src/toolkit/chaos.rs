@@ -0,0 +1,535 @@
+//! Fault injection between a Toolkit service and its transport, to test how the service behaves
+//! when frames are lost, delayed, duplicated or corrupted, and when the connection drops.
+
+use super::{
+    errors::Result,
+    transport::{Incoming, ToolkitTransport},
+};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::time::{sleep, sleep_until, Instant};
+use tokio_tungstenite::tungstenite::Bytes;
+
+/// Which way a frame goes, seen from the service.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// Received from the server, such as action calls.
+    Incoming,
+    /// Sent to the server, such as action results.
+    Outgoing,
+}
+
+/// A fault injected on a frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// The frame is lost.
+    Drop,
+    /// The frame is delivered twice.
+    Duplicate,
+    /// The frame is truncated to half its length, so it's no longer valid JSON.
+    Corrupt,
+    /// The frame is delivered after this long, holding back the ones after it.
+    Delay(Duration),
+    /// The frame is lost and the connection is closed, which stops the service.
+    Disconnect,
+}
+
+/// The faults a [ChaosTransport] injects.
+///
+/// Faults scheduled with [FaultPlan::with_fault_at] apply to the given frames. Every other frame
+/// draws its fault from the probabilities, with an RNG seeded by [FaultPlan::seeded], so a plan
+/// injects the same faults for the same traffic.
+#[derive(Clone, Debug, Default)]
+pub struct FaultPlan {
+    seed: u64,
+    disconnect: f64,
+    drop: f64,
+    corrupt: f64,
+    duplicate: f64,
+    delay: f64,
+    max_delay: Duration,
+    schedule: Vec<(Direction, usize, Fault)>,
+}
+
+impl FaultPlan {
+    /// A plan injecting no faults, drawing from an RNG seeded with `seed`.
+    pub fn seeded(seed: u64) -> Self {
+        Self {
+            seed,
+            ..Self::default()
+        }
+    }
+
+    /// Lose each frame with probability `p`.
+    pub fn with_drop(mut self, p: f64) -> Self {
+        self.drop = p;
+        self
+    }
+
+    /// Deliver each frame twice with probability `p`.
+    pub fn with_duplicate(mut self, p: f64) -> Self {
+        self.duplicate = p;
+        self
+    }
+
+    /// Truncate each frame with probability `p`.
+    pub fn with_corrupt(mut self, p: f64) -> Self {
+        self.corrupt = p;
+        self
+    }
+
+    /// Delay each frame with probability `p`, by up to `max_delay`.
+    pub fn with_delay(mut self, p: f64, max_delay: Duration) -> Self {
+        self.delay = p;
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Close the connection on each frame with probability `p`.
+    pub fn with_disconnect(mut self, p: f64) -> Self {
+        self.disconnect = p;
+        self
+    }
+
+    /// Inject `fault` on the `frame`-th frame going in `direction`, from 0. The registration isn't
+    /// counted, see [ChaosTransport].
+    pub fn with_fault_at(mut self, direction: Direction, frame: usize, fault: Fault) -> Self {
+        self.schedule.push((direction, frame, fault));
+        self
+    }
+}
+
+/// The number of faults injected by a [ChaosTransport] so far.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FaultCounts {
+    pub dropped: usize,
+    pub duplicated: usize,
+    pub corrupted: usize,
+    pub delayed: usize,
+    pub disconnects: usize,
+}
+
+impl FaultCounts {
+    /// The number of faults of any kind.
+    pub fn total(&self) -> usize {
+        self.dropped + self.duplicated + self.corrupted + self.delayed + self.disconnects
+    }
+
+    fn count(&mut self, fault: Fault) {
+        match fault {
+            Fault::Drop => self.dropped += 1,
+            Fault::Duplicate => self.duplicated += 1,
+            Fault::Corrupt => self.corrupted += 1,
+            Fault::Delay(_) => self.delayed += 1,
+            Fault::Disconnect => self.disconnects += 1,
+        }
+    }
+}
+
+/// A handle on the [FaultCounts] of a [ChaosTransport], which stays readable after the service
+/// stopped.
+#[derive(Clone, Debug, Default)]
+pub struct FaultCounters(Arc<Mutex<FaultCounts>>);
+
+impl FaultCounters {
+    /// The faults injected so far.
+    pub fn get(&self) -> FaultCounts {
+        self.0.lock().unwrap().clone()
+    }
+
+    fn count(&self, fault: Fault) {
+        self.0.lock().unwrap().count(fault);
+    }
+}
+
+/// A transport injecting the faults of a [FaultPlan] into the frames of another one, any
+/// [ToolkitTransport] such as a [WebSocketTransport](super::WebSocketTransport) or one of your
+/// own. Start the service over it with
+/// [ToolkitService::start_over](super::ToolkitService::start_over).
+///
+/// The first frame sent, the registration of the actions, goes through untouched so the service
+/// always starts. See [ToolkitTestHarness::start_with_faults](super::testing::ToolkitTestHarness::start_with_faults)
+/// to inject faults between a service and the harness.
+pub struct ChaosTransport<T> {
+    inner: T,
+    plan: FaultPlan,
+    rng: SplitMix64,
+    counters: FaultCounters,
+    registered: bool,
+    frames: [usize; 2],
+    held: Option<(Instant, Bytes)>,
+    replayed: VecDeque<Bytes>,
+    closing: bool,
+}
+
+impl<T> ChaosTransport<T> {
+    /// Wrap `inner`, injecting the faults of `plan`.
+    pub fn new(inner: T, plan: FaultPlan) -> Self {
+        Self {
+            inner,
+            rng: SplitMix64(plan.seed),
+            plan,
+            counters: FaultCounters::default(),
+            registered: false,
+            frames: [0; 2],
+            held: None,
+            replayed: VecDeque::new(),
+            closing: false,
+        }
+    }
+
+    /// The counters of the injected faults.
+    pub fn counters(&self) -> FaultCounters {
+        self.counters.clone()
+    }
+
+    /// Draw the fault of the next frame going in `direction`, if any, and count it.
+    fn next_fault(&mut self, direction: Direction) -> Option<Fault> {
+        let frame = &mut self.frames[direction as usize];
+        let index = *frame;
+        *frame += 1;
+
+        let scheduled = self
+            .plan
+            .schedule
+            .iter()
+            .find(|(d, i, _)| *d == direction && *i == index)
+            .map(|(_, _, fault)| *fault);
+
+        let fault = scheduled.or_else(|| {
+            let plan = &self.plan;
+            let rng = &mut self.rng;
+            if rng.chance(plan.disconnect) {
+                Some(Fault::Disconnect)
+            } else if rng.chance(plan.drop) {
+                Some(Fault::Drop)
+            } else if rng.chance(plan.corrupt) {
+                Some(Fault::Corrupt)
+            } else if rng.chance(plan.duplicate) {
+                Some(Fault::Duplicate)
+            } else if rng.chance(plan.delay) {
+                Some(Fault::Delay(plan.max_delay.mul_f64(rng.next_f64())))
+            } else {
+                None
+            }
+        });
+
+        if let Some(fault) = fault {
+            self.counters.count(fault);
+        }
+
+        fault
+    }
+}
+
+impl<T: ToolkitTransport> ToolkitTransport for ChaosTransport<T> {
+    async fn send(&mut self, text: String) -> Result<()> {
+        if !self.registered {
+            self.registered = true;
+            return self.inner.send(text).await;
+        }
+
+        match self.next_fault(Direction::Outgoing) {
+            None => self.inner.send(text).await,
+            Some(Fault::Drop) => Ok(()),
+            Some(Fault::Duplicate) => {
+                self.inner.send(text.clone()).await?;
+                self.inner.send(text).await
+            }
+            Some(Fault::Corrupt) => self.inner.send(truncate(text)).await,
+            Some(Fault::Delay(delay)) => {
+                sleep(delay).await;
+                self.inner.send(text).await
+            }
+            Some(Fault::Disconnect) => {
+                self.closing = true;
+                Ok(())
+            }
+        }
+    }
+
+    async fn ping(&mut self) -> Result<()> {
+        self.inner.ping().await
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.inner.close().await
+    }
+
+    // Cancel safe like the inner transport: a delayed frame is held until its deadline across
+    // calls, so the dispatch loop can keep selecting on it.
+    async fn recv(&mut self) -> Option<Result<Incoming>> {
+        loop {
+            if let Some((deadline, _)) = &self.held {
+                sleep_until(*deadline).await;
+                let (_, text) = self.held.take()?;
+                return Some(Ok(Incoming::Text(text)));
+            }
+
+            if let Some(text) = self.replayed.pop_front() {
+                return Some(Ok(Incoming::Text(text)));
+            }
+
+            if self.closing {
+                return Some(Ok(Incoming::Closed {
+                    code: None,
+                    reason: None,
+                }));
+            }
+
+            let text = match self.inner.recv().await? {
+                Ok(Incoming::Text(text)) => text,
+                other => return Some(other),
+            };
+
+            match self.next_fault(Direction::Incoming) {
+                None => return Some(Ok(Incoming::Text(text))),
+                Some(Fault::Drop) => {}
+                Some(Fault::Duplicate) => {
+                    self.replayed.push_back(text.clone());
+                    return Some(Ok(Incoming::Text(text)));
+                }
+                Some(Fault::Corrupt) => {
+                    let text = text.slice(..text.len() / 2);
+                    return Some(Ok(Incoming::Text(text)));
+                }
+                Some(Fault::Delay(delay)) => self.held = Some((Instant::now() + delay, text)),
+                Some(Fault::Disconnect) => self.closing = true,
+            }
+        }
+    }
+}
+
+/// Truncate `text` to about half its length.
+fn truncate(mut text: String) -> String {
+    let mut len = text.len() / 2;
+    while !text.is_char_boundary(len) {
+        len -= 1;
+    }
+    text.truncate(len);
+
+    text
+}
+
+/// A small seedable RNG, good enough to draw faults.
+#[derive(Clone, Debug)]
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `[0, 1)`.
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, p: f64) -> bool {
+        p > 0.0 && self.next_f64() < p
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toolkit::{
+        messages::{ActionCallParams, ToolkitMessage},
+        testing::ToolkitTestHarness,
+        Action, ActionContext, ActionDefinition, ActionParams, ActionResult, ToolkitService,
+    };
+    use serde_json::{json, Value};
+    use std::{collections::HashMap, convert::Infallible};
+    use tokio::time::timeout;
+
+    struct Echo;
+
+    impl Action for Echo {
+        const NAME: &'static str = "echo";
+
+        type Error = Infallible;
+        type Args = Value;
+        type Output = Value;
+
+        async fn definition(&self) -> ActionDefinition {
+            ActionDefinition {
+                description: "Echo the payload".to_string(),
+                payload: json!({}),
+                payment: None,
+                ..Default::default()
+            }
+        }
+
+        async fn call(
+            &self,
+            _ctx: ActionContext,
+            params: ActionParams<Self::Args>,
+        ) -> std::result::Result<ActionResult<Self::Output>, Self::Error> {
+            Ok(ActionResult {
+                payload: params.payload,
+                payment: None,
+            })
+        }
+    }
+
+    fn echo_service() -> ToolkitService {
+        let mut service = ToolkitService::new("test");
+        service.add_action(Echo);
+
+        service
+    }
+
+    fn call(action_id: u64) -> ToolkitMessage {
+        ToolkitMessage::Action {
+            data: ActionCallParams::new("echo")
+                .with_action_id(action_id)
+                .with_payload(json!({ "n": action_id })),
+        }
+    }
+
+    /// The results received until the service stopped or went quiet, by action ID, with the number
+    /// of times each was received.
+    async fn collect_results(harness: &mut ToolkitTestHarness) -> HashMap<u64, (Value, usize)> {
+        let mut results = HashMap::new();
+
+        while let Ok(Some(frame)) = timeout(Duration::from_millis(50), harness.next_frame()).await {
+            if let Ok(ToolkitMessage::ActionResult { data }) =
+                ToolkitMessage::from_frame(frame.as_bytes())
+            {
+                let payload = serde_json::from_str(data.payload.get()).unwrap();
+                results.entry(data.action_id).or_insert((payload, 0)).1 += 1;
+            }
+        }
+
+        results
+    }
+
+    /// The delivery guarantee of the service: every call it receives gets a result, once per time
+    /// it's received. There is no retry nor deduplication in the service, so a call or result lost
+    /// on the way is the backend's to send again, and a duplicated call gets two results with the
+    /// same action ID, for the backend to keep exactly one.
+    #[tokio::test]
+    async fn test_every_call_eventually_gets_a_result() {
+        const CALLS: u64 = 30;
+        let mut injected = FaultCounts::default();
+
+        for seed in 0..8 {
+            let mut pending: Vec<u64> = (1..=CALLS).collect();
+            let mut answered = HashMap::new();
+
+            // Like the backend, reconnect and send the calls without a result again, until they
+            // all have one.
+            for connection in 0.. {
+                assert!(
+                    connection < 50,
+                    "seed {seed}: calls still pending {pending:?}"
+                );
+                if pending.is_empty() {
+                    break;
+                }
+
+                let plan = FaultPlan::seeded(seed * 1000 + connection)
+                    .with_drop(0.05)
+                    .with_duplicate(0.05)
+                    .with_corrupt(0.02)
+                    .with_delay(0.1, Duration::from_millis(5))
+                    .with_disconnect(0.03);
+                let (mut harness, counters) =
+                    ToolkitTestHarness::start_with_faults(echo_service(), plan)
+                        .await
+                        .unwrap();
+
+                for action_id in &pending {
+                    harness.send_raw(call(*action_id));
+                }
+
+                for (action_id, (payload, _)) in collect_results(&mut harness).await {
+                    assert_eq!(payload, json!({ "n": action_id }), "seed {seed}");
+                    answered.entry(action_id).or_insert(payload);
+                }
+                pending.retain(|action_id| !answered.contains_key(action_id));
+
+                let counts = counters.get();
+                injected.dropped += counts.dropped;
+                injected.duplicated += counts.duplicated;
+                injected.corrupted += counts.corrupted;
+                injected.delayed += counts.delayed;
+                injected.disconnects += counts.disconnects;
+            }
+
+            assert_eq!(answered.len(), CALLS as usize, "seed {seed}");
+        }
+
+        assert!(injected.dropped > 0, "{injected:?}");
+        assert!(injected.duplicated > 0, "{injected:?}");
+        assert!(injected.corrupted > 0, "{injected:?}");
+        assert!(injected.delayed > 0, "{injected:?}");
+        assert!(injected.disconnects > 0, "{injected:?}");
+    }
+
+    #[tokio::test]
+    async fn test_results_match_deliveries() {
+        for seed in 0..8 {
+            // Without disconnects, and with duplicates and delays only, nothing is lost.
+            let plan = FaultPlan::seeded(seed)
+                .with_duplicate(0.2)
+                .with_delay(0.2, Duration::from_millis(5));
+            let (mut harness, counters) =
+                ToolkitTestHarness::start_with_faults(echo_service(), plan)
+                    .await
+                    .unwrap();
+
+            for action_id in 1..=20 {
+                harness.send_raw(call(action_id));
+            }
+            let results = collect_results(&mut harness).await;
+
+            assert_eq!(results.len(), 20, "seed {seed}");
+            let received: usize = results.values().map(|(_, count)| count).sum();
+            assert_eq!(received, 20 + counters.get().duplicated, "seed {seed}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scheduled_faults() {
+        let plan = FaultPlan::seeded(0)
+            .with_fault_at(Direction::Incoming, 0, Fault::Corrupt)
+            .with_fault_at(Direction::Incoming, 1, Fault::Drop)
+            .with_fault_at(Direction::Outgoing, 0, Fault::Duplicate)
+            .with_fault_at(Direction::Incoming, 3, Fault::Disconnect);
+        let (mut harness, counters) = ToolkitTestHarness::start_with_faults(echo_service(), plan)
+            .await
+            .unwrap();
+
+        for action_id in 1..=3 {
+            harness.send_raw(call(action_id));
+        }
+        let results = collect_results(&mut harness).await;
+
+        // The corrupted call is skipped as an unknown message, and the service keeps running.
+        assert_eq!(results.keys().copied().collect::<Vec<_>>(), [3]);
+        assert_eq!(results[&3].1, 2);
+
+        harness.send_raw(call(4));
+        harness.send_raw(call(5));
+        assert_eq!(harness.next_frame().await, None);
+        assert_eq!(
+            counters.get(),
+            FaultCounts {
+                dropped: 1,
+                duplicated: 1,
+                corrupted: 1,
+                delayed: 0,
+                disconnects: 1,
+            }
+        );
+        assert!(harness.shutdown().await.is_ok());
+    }
+}
@@ -1,45 +1,482 @@
-use super::Result;
-use crate::constants::DEFAULT_TRANSACTION_API_ENDPOINT;
-use reqwest::Client;
-use serde::Serialize;
-use serde_json::{json, Value};
-use std::env;
+use super::{
+    messages::{ActionCallProgress, ToolkitMessage},
+    Result, ToolkitError,
+};
+use crate::{
+    config::UnifaiConfig,
+    http::ApiClient,
+    logging::{events, targets},
+    trace::TraceContext,
+};
+use reqwest::RequestBuilder;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, value::to_raw_value, Map, Value};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use tokio::sync::mpsc::WeakUnboundedSender;
+use tokio_util::sync::CancellationToken;
 
 #[derive(Clone, Debug)]
 pub struct ActionContext {
-    pub(crate) api_client: Client,
-    pub action: String,
+    pub(crate) api_client: ApiClient,
+    pub(crate) config: Arc<UnifaiConfig>,
+    pub action: Arc<str>,
     pub action_id: u64,
     pub agent_id: u64,
+    pub(crate) metadata: CallMetadata,
+    pub(crate) link: CallLink,
+}
+
+/// What the server sent with a call besides its payload.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CallMetadata {
+    pub(crate) trace_context: Option<TraceContext>,
+    /// The [extra](super::messages::ActionCallParams::extra) fields of the call.
+    pub(crate) extra: Arc<Map<String, Value>>,
+}
+
+/// What ties a call to the connection it was received on. Calls not received over a Toolkit
+/// connection, such as over HTTP or MCP, have no progress and are never cancelled.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CallLink {
+    pub(crate) progress: Option<ProgressSender>,
+    /// Cancelled when the server cancels the call, see [ActionContext::cancelled].
+    pub(crate) cancellation: CancellationToken,
+}
+
+/// Where the progress of a call goes, see [ActionContext::send_progress].
+#[derive(Clone, Debug)]
+pub(crate) struct ProgressSender {
+    /// Weak, so a context kept after its call doesn't hold the service back from stopping.
+    sender: WeakUnboundedSender<ToolkitMessage>,
+    /// Set once the call returned.
+    done: Arc<AtomicBool>,
+}
+
+impl ProgressSender {
+    pub(crate) fn new(sender: WeakUnboundedSender<ToolkitMessage>) -> Self {
+        Self {
+            sender,
+            done: Arc::default(),
+        }
+    }
+
+    /// Drop the progress sent from now on, the result of the call being on its way.
+    pub(crate) fn finish(&self) {
+        self.done.store(true, Ordering::Release);
+    }
 }
 
 impl ActionContext {
+    /// The id of the trace of the agent's tool call, if it was sent with the call, see
+    /// [crate::trace].
+    pub fn trace_id(&self) -> Option<&str> {
+        self.metadata.trace_context.as_ref()?.trace_id()
+    }
+
+    /// The fields of the call the SDK doesn't know, as sent by the server, such as a conversation
+    /// id or the locale of the caller. Empty for calls not received over a Toolkit connection.
+    pub fn metadata(&self) -> &Map<String, Value> {
+        &self.metadata.extra
+    }
+
+    /// Whether the server cancelled the call, see [ActionContext::cancelled].
+    pub fn is_cancelled(&self) -> bool {
+        self.link.cancellation.is_cancelled()
+    }
+
+    /// Wait until the server cancels the call, never for calls not received over a Toolkit
+    /// connection.
+    ///
+    /// A cancelled call is answered with an
+    /// [ActionCancelled](ToolkitError::ActionCancelled) error whatever the action returns, and
+    /// its task is aborted if it still runs after
+    /// [UnifaiConfig::cancel_grace](crate::config::UnifaiConfig::cancel_grace). Long-running
+    /// actions select on this future to stop cleanly instead, releasing what they hold.
+    pub async fn cancelled(&self) {
+        self.link.cancellation.cancelled().await;
+    }
+
+    /// The token cancelled with the call, for the tasks the action spawns.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.link.cancellation.clone()
+    }
+
+    /// Send the agent an intermediate result of the call, as an `actionProgress` message, see
+    /// [messages](super::messages). The result the action returns still ends the call.
+    ///
+    /// Progress sent once the action returned is dropped with a warning. So is, silently, the
+    /// progress of calls not received over a Toolkit connection, such as over HTTP or MCP.
+    pub fn send_progress(&self, payload: impl Serialize) -> Result<()> {
+        let Some(progress) = &self.link.progress else {
+            return Ok(());
+        };
+        if progress.done.load(Ordering::Acquire) {
+            tracing::warn!(
+                target: targets::DISPATCH,
+                event = events::PROGRESS_DROPPED,
+                action = &*self.action,
+                action_id = self.action_id,
+                "Progress sent after the action call returned, dropped"
+            );
+            return Ok(());
+        }
+
+        let message = ToolkitMessage::ActionProgress {
+            data: ActionCallProgress {
+                action: self.action.to_string(),
+                action_id: self.action_id,
+                agent_id: self.agent_id,
+                payload: to_raw_value(&payload)?,
+            },
+        };
+        // The service may have stopped, with no connection to send it on.
+        if let Some(sender) = progress.sender.upgrade() {
+            let _ = sender.send(message);
+        }
+
+        Ok(())
+    }
+
+    /// Create a transaction of type `tx_type` for the agent to sign. A failing status is a
+    /// [Rejected](ToolkitError::Rejected) error, with the message of the server.
     pub async fn create_transaction(
         &self,
         tx_type: &str,
         payload: impl Serialize,
-    ) -> Result<Value> {
-        let endpoint = env::var("UNIFAI_TRANSACTION_API_ENDPOINT")
-            .unwrap_or(DEFAULT_TRANSACTION_API_ENDPOINT.to_string());
-        let url = format!("{endpoint}/tx/create");
-
-        let args = json!({
+    ) -> Result<Transaction> {
+        let url = format!("{}/tx/create", self.config.transaction_api_endpoint);
+        let request = self.api_client.post(url).json(&json!({
             "agentId": self.agent_id,
             "actionId": self.action_id,
-            "actionName": self.action,
+            "actionName": &*self.action,
             "type": tx_type,
             "payload": payload,
-        });
+        }));
+
+        self.send_checked_tx("create_transaction", request).await
+    }
+
+    /// The transaction `id`, such as to poll the status of one created by
+    /// [ActionContext::create_transaction].
+    pub async fn get_transaction(&self, id: &str) -> Result<Transaction> {
+        let url = format!("{}/tx/get", self.config.transaction_api_endpoint);
+        let request = self.api_client.get(url).query(&[("id", id)]);
+
+        self.send_checked_tx("get_transaction", request).await
+    }
+
+    /// The transactions matching `filter`.
+    pub async fn list_transactions(&self, filter: &TransactionFilter) -> Result<Vec<Transaction>> {
+        let url = format!("{}/tx/list", self.config.transaction_api_endpoint);
+        let request = self.api_client.get(url).query(filter);
+
+        self.send_checked_tx("list_transactions", request).await
+    }
 
-        let result = self
+    /// The payment of the call, as the transaction API knows it.
+    pub async fn get_payment_info(&self) -> Result<PaymentInfo> {
+        let url = format!("{}/tx/payment", self.config.transaction_api_endpoint);
+        let request = self
             .api_client
-            .post(url)
-            .json(&args)
-            .send()
-            .await?
-            .json()
-            .await?;
-
-        Ok(result)
+            .get(url)
+            .query(&[("agentId", self.agent_id), ("actionId", self.action_id)]);
+
+        self.send_tx("get_payment_info", request).await
+    }
+
+    /// Charge the agent `amount` for the call, at most the amount it authorized, returning the
+    /// payment as settled.
+    pub async fn settle_payment(&self, amount: u64) -> Result<PaymentInfo> {
+        let url = format!("{}/tx/payment/settle", self.config.transaction_api_endpoint);
+        let request = self.api_client.post(url).json(&json!({
+            "agentId": self.agent_id,
+            "actionId": self.action_id,
+            "actionName": &*self.action,
+            "amount": amount,
+        }));
+
+        self.send_tx("settle_payment", request).await
+    }
+
+    /// Give the payment of the call back to the agent, returning the payment as refunded.
+    pub async fn refund_payment(&self, reason: &str) -> Result<PaymentInfo> {
+        let url = format!("{}/tx/payment/refund", self.config.transaction_api_endpoint);
+        let request = self.api_client.post(url).json(&json!({
+            "agentId": self.agent_id,
+            "actionId": self.action_id,
+            "actionName": &*self.action,
+            "reason": reason,
+        }));
+
+        self.send_tx("refund_payment", request).await
+    }
+
+    /// Send a request to the transaction API and parse its response. Failing statuses are
+    /// [ToolkitError::ApiError] errors, with the status.
+    async fn send_tx<T: DeserializeOwned>(
+        &self,
+        operation: &'static str,
+        request: RequestBuilder,
+    ) -> Result<T> {
+        let timeout = self.config.request_timeout;
+        let into_error = |e| ToolkitError::from_request(e, operation, timeout);
+
+        let request = request.timeout(timeout);
+        let response = self.api_client.send(request).await.map_err(into_error)?;
+        let response = response.error_for_status().map_err(into_error)?;
+        let url = response.url().clone();
+        let body = response.bytes().await.map_err(into_error)?;
+        self.api_client.log_response_body(&url, &body);
+
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Like [ActionContext::send_tx], failing statuses being [ToolkitError::Rejected] errors, with
+    /// the message of the server.
+    async fn send_checked_tx<T: DeserializeOwned>(
+        &self,
+        operation: &'static str,
+        request: RequestBuilder,
+    ) -> Result<T> {
+        let timeout = self.config.request_timeout;
+        let into_error = |e| ToolkitError::from_request(e, operation, timeout);
+
+        let request = request.timeout(timeout);
+        let response = self.api_client.send(request).await.map_err(into_error)?;
+        let status = response.status();
+        let url = response.url().clone();
+        let body = response.bytes().await.map_err(into_error)?;
+        self.api_client.log_response_body(&url, &body);
+
+        if !status.is_success() {
+            return Err(ToolkitError::from_status(operation, status, &body));
+        }
+        Ok(serde_json::from_slice(&body)?)
+    }
+}
+
+/// A transaction created for the agent to sign, as in
+/// `{"id":"tx-1","status":"pending","type":"transfer","payload":{},"createdAt":"..."}`.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Transaction {
+    pub id: String,
+    /// The status of the transaction, such as `pending` until the agent signs it.
+    pub status: String,
+    #[serde(rename = "type")]
+    pub tx_type: String,
+    #[serde(default)]
+    pub payload: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+}
+
+/// Which transactions [ActionContext::list_transactions] returns, all of them by default.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionFilter {
+    /// Only the transactions of this agent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub agent_id: Option<u64>,
+    /// Only the transactions of this type.
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub tx_type: Option<String>,
+    /// Only the transactions with this status.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    /// At most this many transactions.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+}
+
+/// The payment of an action call, as in `{"authorized":100,"settled":40,"refunded":false}`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PaymentInfo {
+    /// The amount the agent authorized for the call, `None` if it authorized none.
+    pub authorized: Option<u64>,
+    /// The amount charged so far.
+    #[serde(default)]
+    pub settled: u64,
+    /// Whether the payment was given back to the agent.
+    #[serde(default)]
+    pub refunded: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::ClientOptions, secrets::ApiKeyProvider};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        task::JoinHandle,
+    };
+
+    /// Answer one request with `status` and `body`, returning the raw request.
+    async fn serve_once(status: &str, body: &str) -> (String, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let response = format!(
+            "HTTP/1.1 {status}\r\ncontent-length: {}\r\n\r\n{body}",
+            body.len()
+        );
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            loop {
+                let n = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request).to_lowercase();
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length = head
+                        .lines()
+                        .find_map(|line| line.strip_prefix("content-length: "))
+                        .map_or(0, |length| length.parse().unwrap());
+                    if body.len() >= length {
+                        break;
+                    }
+                }
+            }
+            stream.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        (endpoint, server)
+    }
+
+    fn context(transaction_api_endpoint: String) -> ActionContext {
+        ActionContext {
+            api_client: ApiClient::new(
+                ApiKeyProvider::fixed("test", "toolkit_api_key").unwrap(),
+                &ClientOptions::default(),
+            )
+            .unwrap(),
+            config: Arc::new(UnifaiConfig {
+                transaction_api_endpoint,
+                ..UnifaiConfig::default()
+            }),
+            action: "transact".into(),
+            action_id: 7,
+            agent_id: 3,
+            metadata: Default::default(),
+            link: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_payment_info() {
+        let (endpoint, server) = serve_once(
+            "200 OK",
+            r#"{"authorized":100,"settled":0,"refunded":false}"#,
+        )
+        .await;
+
+        let payment = context(endpoint).get_payment_info().await.unwrap();
+
+        assert_eq!(payment.authorized, Some(100));
+        let request = server.await.unwrap();
+        assert!(
+            request.starts_with("GET /tx/payment?agentId=3&actionId=7 "),
+            "{request}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_settle_payment() {
+        let (endpoint, server) = serve_once(
+            "200 OK",
+            r#"{"authorized":100,"settled":40,"refunded":false}"#,
+        )
+        .await;
+
+        let payment = context(endpoint).settle_payment(40).await.unwrap();
+
+        assert_eq!(payment.settled, 40);
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /tx/payment/settle "), "{request}");
+        assert!(
+            request.ends_with(r#"{"actionId":7,"actionName":"transact","agentId":3,"amount":40}"#),
+            "{request}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_transaction() {
+        let (endpoint, server) = serve_once(
+            "200 OK",
+            r#"{"id":"tx-1","status":"pending","type":"transfer","payload":{"to":"abc"}}"#,
+        )
+        .await;
+
+        let transaction = context(endpoint)
+            .create_transaction("transfer", json!({ "to": "abc" }))
+            .await
+            .unwrap();
+
+        assert_eq!(transaction.id, "tx-1");
+        assert_eq!(transaction.status, "pending");
+        assert_eq!(transaction.tx_type, "transfer");
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /tx/create "), "{request}");
+    }
+
+    #[tokio::test]
+    async fn test_rejected_transaction_keeps_the_message() {
+        let (endpoint, _server) =
+            serve_once("400 Bad Request", r#"{"error":"unknown type"}"#).await;
+
+        let error = context(endpoint)
+            .create_transaction("nope", json!({}))
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "RequestRejected: create_transaction failed with status 400: unknown type"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_transactions() {
+        let (endpoint, server) = serve_once(
+            "200 OK",
+            r#"[{"id":"tx-1","status":"signed","type":"transfer","createdAt":"2025-01-01"}]"#,
+        )
+        .await;
+
+        let filter = TransactionFilter {
+            agent_id: Some(3),
+            status: Some("signed".to_string()),
+            ..Default::default()
+        };
+        let transactions = context(endpoint).list_transactions(&filter).await.unwrap();
+
+        assert_eq!(transactions[0].created_at.as_deref(), Some("2025-01-01"));
+        let request = server.await.unwrap();
+        assert!(
+            request.starts_with("GET /tx/list?agentId=3&status=signed "),
+            "{request}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_payment_failure_keeps_the_status() {
+        let (endpoint, _server) =
+            serve_once("409 Conflict", r#"{"error":"already settled"}"#).await;
+
+        let error = context(endpoint)
+            .refund_payment("no result")
+            .await
+            .unwrap_err();
+
+        let ToolkitError::ApiError(e) = error else {
+            panic!("expected an API error, got {error}");
+        };
+        assert_eq!(e.status(), Some(reqwest::StatusCode::CONFLICT));
     }
 }
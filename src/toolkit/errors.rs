@@ -1,3 +1,10 @@
+use super::PayloadViolation;
+use crate::{config::ConfigError, http::CredentialsError};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{error::Error, time::Duration};
+
 #[derive(Debug, thiserror::Error)]
 pub enum ToolkitError {
     #[error("ActionCallError: {0}")]
@@ -6,11 +13,287 @@ pub enum ToolkitError {
     #[error("JsonError: {0}")]
     JsonError(#[from] serde_json::Error),
 
+    #[error("ConfigError: {0}")]
+    ConfigError(#[from] ConfigError),
+
     #[error("ApiError: {0}")]
-    ApiError(#[from] reqwest::Error),
+    ApiError(reqwest::Error),
+
+    #[error("IoError: {0}")]
+    IoError(#[from] std::io::Error),
 
     #[error("WebSocketError: {0}")]
-    WebSocketError(#[from] tokio_tungstenite::tungstenite::Error),
+    WebSocketError(Box<tokio_tungstenite::tungstenite::Error>),
+
+    /// The gRPC stream failed or couldn't be opened, see
+    /// [TransportPreference::Grpc](super::TransportPreference::Grpc).
+    #[cfg(feature = "grpc")]
+    #[error("GrpcError: {0}")]
+    GrpcError(Box<tonic::Status>),
+
+    #[error("TimeoutError: {operation} timed out after {timeout:?}")]
+    Timeout {
+        operation: &'static str,
+        timeout: Duration,
+    },
+
+    /// The definition of an action took longer than
+    /// [UnifaiConfig::definition_timeout](crate::config::UnifaiConfig) to evaluate.
+    #[error("DefinitionTimeout: definition of action {action} timed out after {timeout:?}")]
+    DefinitionTimeout { action: String, timeout: Duration },
+
+    /// A call of an action took longer than its timeout, see
+    /// [ToolkitService::with_action_timeout](super::ToolkitService::with_action_timeout).
+    #[error("ActionTimeout: action {action} timed out after {timeout:?}")]
+    ActionTimeout { action: String, timeout: Duration },
+
+    /// A call of an action the service doesn't serve, or no longer does, see
+    /// [ToolkitHandle::remove_action](super::ToolkitHandle::remove_action). The actions it serves
+    /// are listed when there are few enough of them to tell the agent.
+    #[error("ActionNotFound: no action named {action}")]
+    ActionNotFound {
+        action: String,
+        available_actions: Option<Vec<String>>,
+    },
+
+    /// An action panicked during a call, with the message of the panic.
+    #[error("ActionPanicked: action {action} panicked: {message}")]
+    ActionPanicked { action: String, message: String },
+
+    /// A call of an action that is unavailable, see [Action::available](super::Action::available),
+    /// with when to try again if known.
+    #[error("ActionUnavailable: action {action} is unavailable")]
+    ActionUnavailable {
+        action: String,
+        retry_after: Option<Duration>,
+    },
+
+    /// The server cancelled a call in flight, see
+    /// [ActionContext::cancelled](super::ActionContext::cancelled).
+    #[error("ActionCancelled: call of action {action} was cancelled")]
+    ActionCancelled { action: String },
+
+    /// The payload of a call doesn't deserialize into the arguments of its action, as a value or
+    /// as the JSON it encodes, with the error of the first of the decoding `stages` tried.
+    #[error("PayloadDecodeError: {error} (tried {})", stages.join(", "))]
+    PayloadDecode {
+        stages: Vec<&'static str>,
+        error: serde_json::Error,
+    },
+
+    /// The payload of a call doesn't match the definition of its action, see
+    /// [ToolkitService::with_payload_validation](super::ToolkitService::with_payload_validation).
+    #[error("InvalidPayload: {}", join(violations))]
+    InvalidPayload { violations: Vec<PayloadViolation> },
+
+    /// The serialized result of a call is `size` bytes, over the `limit` of
+    /// [ToolkitService::with_max_result_bytes](super::ToolkitService::with_max_result_bytes).
+    #[error("PayloadTooLarge: result of {size} bytes is over the limit of {limit} bytes")]
+    PayloadTooLarge { size: usize, limit: usize },
+
+    /// A call arrived while `queued` calls were already waiting for the limit of concurrent calls,
+    /// see [ToolkitService::with_max_concurrent_actions](super::ToolkitService::with_max_concurrent_actions).
+    #[error("ToolkitBusy: {queued} action calls already waiting")]
+    ToolkitBusy { queued: usize },
+
+    /// No toolkit has the id, or it isn't registered with the API key, see
+    /// [ToolkitService::get_toolkit](super::ToolkitService::get_toolkit).
+    #[error("ToolkitNotFound: no toolkit with id {id}")]
+    ToolkitNotFound { id: u64 },
+
+    /// The server rejected the toolkit API key, with a 401 or 403 status.
+    #[error("Unauthorized: the server rejected the toolkit API key with status {status}")]
+    Unauthorized { status: u16 },
+
+    /// The server refused a request with a failing status, such as a validation failure, and the
+    /// error message it gave.
+    #[error("RequestRejected: {operation} failed with status {status}: {message}")]
+    Rejected {
+        operation: &'static str,
+        status: u16,
+        message: String,
+    },
+
+    /// The server presented none of the pinned keys, see
+    /// [ClientOptions::pin_certificates](crate::config::ClientOptions).
+    #[error("CertificatePinMismatch: {endpoint} presented none of the pinned keys")]
+    CertificatePinMismatch { endpoint: String },
+
+    /// The toolkit API key was checked before starting and couldn't be verified, see
+    /// [ToolkitService::with_credentials_check](super::ToolkitService::with_credentials_check).
+    #[error(transparent)]
+    Credentials(#[from] CredentialsError),
+}
+
+impl ToolkitError {
+    /// Convert a request error, naming the operation if the request timed out.
+    pub(crate) fn from_request(
+        e: reqwest::Error,
+        operation: &'static str,
+        timeout: Duration,
+    ) -> Self {
+        if e.is_timeout() {
+            Self::Timeout { operation, timeout }
+        } else {
+            e.into()
+        }
+    }
+
+    /// Convert a failing response, keeping the error message of its `body`.
+    pub(crate) fn from_status(operation: &'static str, status: StatusCode, body: &[u8]) -> Self {
+        if matches!(status, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) {
+            return Self::Unauthorized {
+                status: status.as_u16(),
+            };
+        }
+
+        let json: Value = serde_json::from_slice(body).unwrap_or_default();
+        let message = ["message", "error", "detail"]
+            .into_iter()
+            .find_map(|key| json[key].as_str())
+            .map(str::to_string)
+            .unwrap_or_else(|| String::from_utf8_lossy(body).trim().to_string());
+
+        Self::Rejected {
+            operation,
+            status: status.as_u16(),
+            message,
+        }
+    }
+
+    /// Turn a 404 status of a request about the toolkit `id` into [ToolkitError::ToolkitNotFound].
+    pub(crate) fn not_found(self, id: u64) -> Self {
+        match self {
+            Self::Rejected { status: 404, .. } => Self::ToolkitNotFound { id },
+            e => e,
+        }
+    }
+
+    /// The HTTP status and `code` of a failed call, the `code` also sent in its error payload.
+    pub(crate) fn http_status(&self) -> (u16, &'static str) {
+        match self {
+            Self::JsonError(_) | Self::PayloadDecode { .. } | Self::InvalidPayload { .. } => {
+                (422, "invalid_payload")
+            }
+            Self::ActionCallError(_) | Self::ActionPanicked { .. } => (500, "action_error"),
+            Self::Timeout { .. } | Self::ActionTimeout { .. } => (504, "timeout"),
+            Self::ToolkitBusy { .. } => (503, "toolkit_busy"),
+            Self::ActionUnavailable { .. } => (503, "unavailable"),
+            Self::PayloadTooLarge { .. } => (500, "payload_too_large"),
+            Self::ActionNotFound { .. } => (404, "not_found"),
+            Self::ActionCancelled { .. } => (500, "cancelled"),
+            _ => (500, "internal_error"),
+        }
+    }
+}
+
+impl From<reqwest::Error> for ToolkitError {
+    fn from(e: reqwest::Error) -> Self {
+        #[cfg(feature = "rustls")]
+        if crate::pinning::is_pin_mismatch(&e) {
+            return Self::CertificatePinMismatch {
+                endpoint: crate::pinning::endpoint(&e),
+            };
+        }
+
+        Self::ApiError(e)
+    }
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for ToolkitError {
+    fn from(e: tokio_tungstenite::tungstenite::Error) -> Self {
+        Self::WebSocketError(Box::new(e))
+    }
+}
+
+#[cfg(feature = "grpc")]
+impl From<tonic::Status> for ToolkitError {
+    fn from(status: tonic::Status) -> Self {
+        Self::GrpcError(Box::new(status))
+    }
+}
+
+#[cfg(feature = "grpc")]
+impl From<tonic::transport::Error> for ToolkitError {
+    fn from(e: tonic::transport::Error) -> Self {
+        tonic::Status::from_error(Box::new(e)).into()
+    }
+}
+
+/// The error of an action as sent to agents, who can branch on its `code`.
+///
+/// An action opts in by returning it as its error, or by carrying it as the source of its error.
+/// Other errors are sent with the `action_error` code and their message, not retryable, see
+/// [IntoActionError].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, thiserror::Error)]
+#[error("{code}: {message}")]
+pub struct ActionError {
+    pub code: String,
+    pub message: String,
+    /// Whether the agent may call the action again with the same payload.
+    pub retryable: bool,
+    pub details: Value,
+}
+
+impl ActionError {
+    /// The code of the errors that aren't an [ActionError].
+    pub const DEFAULT_CODE: &'static str = "action_error";
+
+    pub fn new(code: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            code: code.into(),
+            message: message.into(),
+            retryable: false,
+            details: Value::Null,
+        }
+    }
+
+    pub fn with_retryable(mut self, retryable: bool) -> Self {
+        self.retryable = retryable;
+        self
+    }
+
+    pub fn with_details(mut self, details: Value) -> Self {
+        self.details = details;
+        self
+    }
+}
+
+/// Turning the error of an action into the [ActionError] sent to agents.
+///
+/// Every error implements it: the first [ActionError] in its chain of sources is used as is, and
+/// other errors become an `action_error` with their message.
+pub trait IntoActionError {
+    fn to_action_error(&self) -> ActionError;
+}
+
+impl<E: Error + 'static> IntoActionError for E {
+    fn to_action_error(&self) -> ActionError {
+        find_action_error(self)
+    }
+}
+
+impl IntoActionError for dyn Error + Send + Sync {
+    fn to_action_error(&self) -> ActionError {
+        find_action_error(self)
+    }
+}
+
+fn find_action_error(error: &(dyn Error + 'static)) -> ActionError {
+    let mut source = Some(error);
+    while let Some(error) = source {
+        if let Some(action_error) = error.downcast_ref::<ActionError>() {
+            return action_error.clone();
+        }
+        source = error.source();
+    }
+
+    ActionError::new(ActionError::DEFAULT_CODE, error.to_string())
+}
+
+fn join(violations: &[PayloadViolation]) -> String {
+    let violations: Vec<_> = violations.iter().map(ToString::to_string).collect();
+    violations.join("; ")
 }
 
 pub(crate) type Result<T> = std::result::Result<T, ToolkitError>;
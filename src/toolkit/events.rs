@@ -0,0 +1,160 @@
+use super::ToolkitObserver;
+use serde_json::Value;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::broadcast;
+
+/// Capacity of the runtime event channel returned by [ToolkitHandle::errors](super::ToolkitHandle::errors).
+///
+/// The channel is a bounded [broadcast](tokio::sync::broadcast) channel: a receiver that falls more than
+/// this many events behind gets [RecvError::Lagged](tokio::sync::broadcast::error::RecvError::Lagged) on its
+/// next `recv()` and resumes from the oldest event still buffered. The running service never waits for
+/// slow receivers.
+pub const RUNTIME_EVENT_CAPACITY: usize = 64;
+
+/// An event emitted by a running Toolkit service.
+///
+/// Events are cheap to clone, every payload is reference counted.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum ToolkitRuntimeEvent {
+    /// A frame could not be sent to the server. The service keeps running.
+    SendFailed { message: Arc<str> },
+
+    /// A call delivered again was answered with the result of its first delivery, without running
+    /// its action again, see
+    /// [ToolkitService::with_idempotency](super::ToolkitService::with_idempotency).
+    DuplicateCall {
+        action: Arc<str>,
+        agent_id: u64,
+        action_id: u64,
+    },
+
+    /// An outgoing message could not be serialized. If it was an action result, an
+    /// `internal_error` result was sent in its place. The service keeps running.
+    SerializationFailed {
+        action_id: Option<u64>,
+        message: Arc<str>,
+    },
+
+    /// The server warned that this SDK relies on a deprecated behavior, or is older than the
+    /// minimum version it supports. The service keeps running.
+    DeprecationNotice {
+        message: Option<Arc<str>>,
+        minimum_version: Option<Arc<str>>,
+    },
+
+    /// The service stopped because of an error. This is the last event of the service.
+    Fatal { message: Arc<str> },
+
+    /// The service stopped without an error. This is the last event of the service.
+    Stopped { reason: ShutdownReason },
+}
+
+impl ToolkitRuntimeEvent {
+    /// Whether the service stopped because of this event.
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, Self::Fatal { .. })
+    }
+
+    /// Whether this is the last event of the service, either [ToolkitRuntimeEvent::Fatal] or
+    /// [ToolkitRuntimeEvent::Stopped].
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, Self::Fatal { .. } | Self::Stopped { .. })
+    }
+}
+
+/// A change in the lifecycle of a Toolkit service, such as to keep a status page up to date, see
+/// [ToolkitService::events](super::ToolkitService::events).
+///
+/// Like the runtime events, they go on a bounded channel of [RUNTIME_EVENT_CAPACITY] events,
+/// where receivers falling behind lose the oldest, so the service never waits for them.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum ToolkitEvent {
+    /// The service connected to the server, before registering its actions.
+    Connected,
+
+    /// The service stopped, because of `reason`, or because of an error if `None`, reported
+    /// with [ToolkitRuntimeEvent::Fatal]. A service restarted by a
+    /// [ToolkitManager](super::ToolkitManager) is then [ToolkitEvent::Connected] again.
+    Disconnected { reason: Option<ShutdownReason> },
+
+    /// The definitions of `count` actions were sent to the server, on start and whenever the
+    /// actions change.
+    ActionsRegistered { count: usize },
+
+    /// An action call started.
+    ActionStarted { action: Arc<str>, action_id: u64 },
+
+    /// An action call finished, successfully if `ok`.
+    ActionFinished {
+        action: Arc<str>,
+        action_id: u64,
+        ok: bool,
+        duration: Duration,
+    },
+
+    /// A message from the server couldn't be read, and was skipped.
+    ProtocolError { detail: Arc<str> },
+
+    /// The server sent a message of a type this SDK doesn't know, which was skipped.
+    UnknownMessage {
+        message_type: Arc<str>,
+        data: Arc<Value>,
+    },
+}
+
+/// Why a Toolkit service stopped without an error, the output of its
+/// [ToolkitHandle](super::ToolkitHandle) on success.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ShutdownReason {
+    /// The service was stopped with [ToolkitHandle::shutdown](super::ToolkitHandle::shutdown).
+    /// `drained` tells whether the results of the calls in flight were all sent before the
+    /// connection was closed.
+    GracefulRequested { drained: bool },
+
+    /// The server closed the connection, with the code and the reason of its close frame, if any.
+    ServerClosed {
+        code: Option<u16>,
+        reason: Option<Arc<str>>,
+    },
+
+    /// The connection ended without being closed, such as after a network failure.
+    ConnectionLost,
+}
+
+impl ShutdownReason {
+    /// Whether a supervisor should start the service again, for every reason but
+    /// [ShutdownReason::GracefulRequested].
+    pub fn should_restart(&self) -> bool {
+        !matches!(self, Self::GracefulRequested { .. })
+    }
+}
+
+/// Emits the runtime events of a service, to the receivers of its handle and to its observer.
+#[derive(Clone)]
+pub(super) struct EventSender {
+    sender: broadcast::Sender<ToolkitRuntimeEvent>,
+    observer: Option<Arc<dyn ToolkitObserver>>,
+}
+
+impl EventSender {
+    pub(super) fn new(observer: Option<Arc<dyn ToolkitObserver>>) -> Self {
+        let (sender, _) = broadcast::channel(RUNTIME_EVENT_CAPACITY);
+
+        Self { sender, observer }
+    }
+
+    /// Emit `event`, whether or not anyone receives it.
+    pub(super) fn send(&self, event: ToolkitRuntimeEvent) {
+        if let Some(observer) = &self.observer {
+            observer.on_connection_event(&event);
+        }
+        let _ = self.sender.send(event);
+    }
+
+    pub(super) fn subscribe(&self) -> broadcast::Receiver<ToolkitRuntimeEvent> {
+        self.sender.subscribe()
+    }
+}
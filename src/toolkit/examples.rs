@@ -0,0 +1,331 @@
+//! Check that the example payloads declared in the definitions of the actions still deserialize
+//! into their arguments, so examples can't drift away from the actions they document.
+//!
+//! Examples are declared with the `examples` keyword of a payload that is a JSON Schema, next to
+//! an optional `side_effect_free: true` flag allowing them to be run:
+//!
+//! ```
+//! # use serde_json::json;
+//! # use unifai_sdk::toolkit::ActionDefinition;
+//! let definition = ActionDefinition {
+//!     description: "Get the balance of an account".to_string(),
+//!     payload: json!({
+//!         "type": "object",
+//!         "properties": { "account": { "type": "string" } },
+//!         "required": ["account"],
+//!         "examples": [{ "account": "alice" }],
+//!         "side_effect_free": true,
+//!     }),
+//!     payment: None,
+//!     ..Default::default()
+//! };
+//!
+//! assert_eq!(definition.examples(), [json!({ "account": "alice" })]);
+//! assert!(definition.is_side_effect_free());
+//! ```
+
+use super::{
+    action::ActionDefinition,
+    errors::ToolkitError,
+    messages::ActionCallParams,
+    service::{dispatch_action, ToolkitService},
+};
+use serde_json::value::to_raw_value;
+use std::{collections::BTreeMap, fmt};
+
+/// What an example failed at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExampleStage {
+    /// The example doesn't deserialize into the arguments of the action.
+    Deserialize,
+    /// The action returned an error when called with the example.
+    Call,
+    /// The output of the action doesn't serialize.
+    Serialize,
+}
+
+/// An example of an action that failed, see [verify_examples].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExampleFailure {
+    /// The index of the example in [ActionDefinition::examples].
+    pub example: usize,
+    pub stage: ExampleStage,
+    /// The path of the first value that didn't deserialize, such as `items[2].id`, or `.` for
+    /// the whole payload.
+    pub path: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for ExampleFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "example {}: ", self.example)?;
+        match (&self.path, self.stage) {
+            (Some(path), _) => write!(f, "at {path}: ")?,
+            (None, ExampleStage::Call) => write!(f, "call failed: ")?,
+            (None, ExampleStage::Serialize) => write!(f, "output doesn't serialize: ")?,
+            (None, ExampleStage::Deserialize) => {}
+        }
+
+        write!(f, "{}", self.message)
+    }
+}
+
+/// The failed examples, by action name.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ExampleErrors(pub BTreeMap<String, Vec<ExampleFailure>>);
+
+impl fmt::Display for ExampleErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid action examples:")?;
+        for (action, failures) in &self.0 {
+            for failure in failures {
+                write!(f, "\n  {action}, {failure}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for ExampleErrors {}
+
+/// Check that the examples of every action of the service deserialize into its arguments, the
+/// way calls do, string-encoded payloads included.
+///
+/// Meant to run at startup, before [ToolkitService::start], or in tests.
+pub async fn verify_examples(service: &ToolkitService) -> Result<(), ExampleErrors> {
+    check(service, false).await
+}
+
+/// Like [verify_examples], also calling the actions flagged as side effect free with their
+/// examples, and checking that their outputs serialize.
+///
+/// The calls go through the same dispatch as the ones received from Unifai, with an `action_id`
+/// and an `agent_id` of `0`.
+pub async fn verify_and_run_examples(service: &ToolkitService) -> Result<(), ExampleErrors> {
+    check(service, true).await
+}
+
+async fn check(service: &ToolkitService, run: bool) -> Result<(), ExampleErrors> {
+    let mut errors = BTreeMap::new();
+    let actions = service.actions().entries();
+    for (name, action) in actions {
+        let definition = action.definition().await;
+        let failures = check_action(service, &name, &definition, run).await;
+        if !failures.is_empty() {
+            errors.insert(name.to_string(), failures);
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ExampleErrors(errors))
+    }
+}
+
+async fn check_action(
+    service: &ToolkitService,
+    name: &str,
+    definition: &ActionDefinition,
+    run: bool,
+) -> Vec<ExampleFailure> {
+    let action = service.actions().get(name).expect("the action was listed");
+    let mut failures = Vec::new();
+
+    for (i, example) in definition.examples().iter().enumerate() {
+        let payload = to_raw_value(example).expect("a Value always serializes");
+
+        if let Err(e) = action.check_payload(&payload) {
+            failures.push(ExampleFailure {
+                example: i,
+                stage: ExampleStage::Deserialize,
+                path: Some(e.path),
+                message: e.error.to_string(),
+            });
+            continue;
+        }
+
+        if !(run && definition.is_side_effect_free()) {
+            continue;
+        }
+
+        let params = ActionCallParams {
+            action: name.to_string(),
+            action_id: 0,
+            agent_id: 0,
+            payload,
+            payment: None,
+            trace_context: None,
+            extra: Default::default(),
+        };
+        // The payload deserializes, a JSON error can only come from the output.
+        let stage = match dispatch_action(service, params).await {
+            Some(Err(ToolkitError::JsonError(e))) => Some((ExampleStage::Serialize, e.to_string())),
+            Some(Err(e)) => Some((ExampleStage::Call, e.to_string())),
+            Some(Ok(_)) | None => None,
+        };
+        if let Some((stage, message)) = stage {
+            failures.push(ExampleFailure {
+                example: i,
+                stage,
+                path: None,
+                message,
+            });
+        }
+    }
+
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toolkit::{Action, ActionContext, ActionParams, ActionResult};
+    use serde::Deserialize;
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+
+    #[derive(Deserialize)]
+    struct TransferArgs {
+        #[allow(dead_code)]
+        to: String,
+        amount: f64,
+    }
+
+    /// Fails on negative amounts, and returns an output that doesn't serialize on zero.
+    struct Transfer {
+        examples: Value,
+        side_effect_free: bool,
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("negative amount")]
+    struct NegativeAmount;
+
+    impl Action for Transfer {
+        const NAME: &'static str = "transfer";
+
+        type Error = NegativeAmount;
+        type Args = TransferArgs;
+        type Output = HashMap<Vec<u8>, f64>;
+
+        async fn definition(&self) -> ActionDefinition {
+            ActionDefinition {
+                description: "Transfer tokens".to_string(),
+                payload: json!({
+                    "type": "object",
+                    "properties": {
+                        "to": { "type": "string" },
+                        "amount": { "type": "number" },
+                    },
+                    "examples": self.examples,
+                    "side_effect_free": self.side_effect_free,
+                }),
+                payment: None,
+                ..Default::default()
+            }
+        }
+
+        async fn call(
+            &self,
+            _ctx: ActionContext,
+            params: ActionParams<Self::Args>,
+        ) -> std::result::Result<ActionResult<Self::Output>, Self::Error> {
+            let amount = params.payload.amount;
+            if amount < 0.0 {
+                return Err(NegativeAmount);
+            }
+
+            let mut payload = HashMap::new();
+            if amount == 0.0 {
+                // JSON object keys must be strings.
+                payload.insert(vec![0], amount);
+            }
+
+            Ok(ActionResult {
+                payload,
+                payment: None,
+            })
+        }
+    }
+
+    fn service(examples: Value, side_effect_free: bool) -> ToolkitService {
+        let mut service = ToolkitService::new("test");
+        service.add_action(Transfer {
+            examples,
+            side_effect_free,
+        });
+
+        service
+    }
+
+    fn failures(result: Result<(), ExampleErrors>) -> Vec<ExampleFailure> {
+        result.unwrap_err().0.remove("transfer").unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_valid_examples() {
+        let examples = json!([
+            { "to": "alice", "amount": 1.5 },
+            // String-encoded, like some agents send them.
+            r#"{"to":"bob","amount":2}"#,
+        ]);
+
+        assert_eq!(
+            verify_examples(&service(examples.clone(), false)).await,
+            Ok(())
+        );
+        assert_eq!(
+            verify_and_run_examples(&service(examples, true)).await,
+            Ok(())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_examples_that_dont_deserialize() {
+        let examples = json!([
+            { "to": "alice", "amount": 1.5 },
+            { "to": "alice", "amount": "lots" },
+            { "amount": 1 },
+        ]);
+
+        let failures = failures(verify_examples(&service(examples, false)).await);
+
+        assert_eq!(failures.len(), 2);
+        assert_eq!(failures[0].example, 1);
+        assert_eq!(failures[0].stage, ExampleStage::Deserialize);
+        assert_eq!(failures[0].path.as_deref(), Some("amount"));
+        assert!(failures[0].message.contains("invalid type"), "{failures:?}");
+        assert_eq!(failures[1].example, 2);
+        assert_eq!(failures[1].path.as_deref(), Some("."));
+        assert!(failures[1].message.contains("missing field `to`"));
+    }
+
+    #[tokio::test]
+    async fn test_run_side_effect_free_examples() {
+        let examples = json!([
+            { "to": "alice", "amount": -1 },
+            { "to": "alice", "amount": 0 },
+        ]);
+
+        // Not run without the flag.
+        assert_eq!(
+            verify_and_run_examples(&service(examples.clone(), false)).await,
+            Ok(())
+        );
+
+        let result = verify_and_run_examples(&service(examples, true)).await;
+        let report = result.as_ref().unwrap_err().to_string();
+        let failures = failures(result);
+
+        assert_eq!(
+            failures.iter().map(|f| f.stage).collect::<Vec<_>>(),
+            [ExampleStage::Call, ExampleStage::Serialize]
+        );
+        assert!(
+            report.contains("transfer, example 0: call failed: ActionCallError: negative amount"),
+            "{report}"
+        );
+    }
+}
@@ -0,0 +1,164 @@
+//! Actions defined by an async closure, without a type of their own, see [FunctionAction].
+
+use super::{Action, ActionContext, ActionDefinition, ActionParams, ActionResult};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{error::Error, fmt, future::Future, marker::PhantomData};
+
+/// The error of the closure of a [FunctionAction], any error boxed by `?`.
+type BoxError = Box<dyn Error + Send + Sync>;
+
+/// An action calling an async closure with the context of the call and its arguments.
+///
+/// The arguments are deserialized from the payload into the type of the second parameter of the
+/// closure, and the output it returns is the result payload, never charging a payment. Actions
+/// setting a payment implement [Action] instead.
+///
+/// # Example
+/// ```no_run
+/// use serde::Deserialize;
+/// use serde_json::json;
+/// use unifai_sdk::toolkit::{ActionDefinition, FunctionAction, ToolkitService};
+///
+/// #[derive(Deserialize)]
+/// struct EchoArgs {
+///     content: String,
+/// }
+///
+/// let definition = ActionDefinition {
+///     description: "Echo the message".to_string(),
+///     payload: json!({ "content": { "type": "string", "required": true } }),
+///     payment: None,
+///     ..Default::default()
+/// };
+///
+/// let mut service = ToolkitService::new("UNIFAI_TOOLKIT_API_KEY");
+/// let echo = FunctionAction::new("echo", definition, |ctx, args: EchoArgs| async move {
+///     Ok(format!("Agent <${}> said \"{}\".", ctx.agent_id, args.content))
+/// });
+/// service.add_action(echo);
+/// ```
+pub struct FunctionAction<F, A> {
+    name: String,
+    definition: ActionDefinition,
+    function: F,
+    args: PhantomData<fn(A)>,
+}
+
+impl<F, A> FunctionAction<F, A> {
+    /// An action named `name`, calling `function`.
+    pub fn new<Fut, O>(name: impl Into<String>, definition: ActionDefinition, function: F) -> Self
+    where
+        F: Fn(ActionContext, A) -> Fut,
+        Fut: Future<Output = Result<O, BoxError>>,
+    {
+        Self {
+            name: name.into(),
+            definition,
+            function,
+            args: PhantomData,
+        }
+    }
+}
+
+impl<F, A, Fut, O> Action for FunctionAction<F, A>
+where
+    F: Fn(ActionContext, A) -> Fut + Send + Sync,
+    A: DeserializeOwned + Send + Sync,
+    Fut: Future<Output = Result<O, BoxError>> + Send + Sync,
+    O: Serialize,
+{
+    /// Unused, the name is given to [FunctionAction::new].
+    const NAME: &'static str = "";
+
+    type Error = FunctionError;
+    type Args = A;
+    type Output = O;
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    async fn definition(&self) -> ActionDefinition {
+        self.definition.clone()
+    }
+
+    async fn call(
+        &self,
+        ctx: ActionContext,
+        params: ActionParams<Self::Args>,
+    ) -> Result<ActionResult<Self::Output>, Self::Error> {
+        Ok(ActionResult {
+            payload: (self.function)(ctx, params.payload)
+                .await
+                .map_err(FunctionError)?,
+            payment: None,
+        })
+    }
+}
+
+/// The error returned by the closure of a [FunctionAction], displayed as it is.
+#[derive(Debug)]
+pub struct FunctionError(pub BoxError);
+
+impl fmt::Display for FunctionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Error for FunctionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.0.source()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toolkit::{testing::ToolkitTestHarness, ToolkitService};
+    use serde::Deserialize;
+    use serde_json::json;
+
+    #[derive(Deserialize)]
+    struct DivideArgs {
+        dividend: i64,
+        divisor: i64,
+    }
+
+    #[tokio::test]
+    async fn test_function_action() {
+        let definition = ActionDefinition {
+            description: "Divide two numbers".to_string(),
+            payload: json!({}),
+            payment: None,
+            ..Default::default()
+        };
+        let mut service = ToolkitService::new("test");
+        service.add_action(FunctionAction::new(
+            "divide",
+            definition.clone(),
+            |_ctx, args: DivideArgs| async move {
+                if args.divisor == 0 {
+                    return Err("division by zero".into());
+                }
+                Ok(args.dividend / args.divisor)
+            },
+        ));
+        let mut harness = ToolkitTestHarness::start(service).await.unwrap();
+        assert_eq!(harness.registered_actions()["divide"], definition);
+
+        let payload = json!({ "dividend": 7, "divisor": 2 });
+        let result = harness.send_action_call("divide", payload, 1).await;
+        assert_eq!(result.payload.get(), "3");
+
+        let payload = json!({ "dividend": 7, "divisor": 0 });
+        let result = harness.send_action_call("divide", payload, 1).await;
+        assert!(
+            result.payload.get().contains("division by zero"),
+            "{}",
+            result.payload
+        );
+
+        harness.shutdown().await.unwrap();
+    }
+}
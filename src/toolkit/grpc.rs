@@ -0,0 +1,375 @@
+//! The gRPC transport, for infrastructures that terminate and inspect gRPC better than WebSocket
+//! connections.
+//!
+//! It opens the `Connect` stream of `proto/toolkit.proto` at
+//! [UnifaiConfig::backend_grpc_endpoint], with the API key in the `authorization` metadata and
+//! the kind of client in `x-unifai-client-type`. The stream carries the same
+//! [messages](super::messages) as the WebSocket, as JSON in its frames, along with the pings, the
+//! pongs, and the close of the server. `https` endpoints are verified with the root certificates
+//! of the OS.
+
+use super::{
+    errors::{Result, ToolkitError},
+    transport::{Incoming, ToolkitTransport},
+};
+use crate::{
+    config::UnifaiConfig,
+    constants::USER_AGENT,
+    logging::{directions, WireLogging},
+    secrets::ApiKeyProvider,
+};
+use futures_util::{stream, StreamExt};
+use proto::{Frame, Kind, Ping, Pong};
+use tokio::{
+    sync::mpsc::{unbounded_channel, UnboundedSender},
+    time::timeout,
+};
+use tonic::{
+    client::Grpc,
+    codegen::http::uri::PathAndQuery,
+    metadata::MetadataValue,
+    transport::{ClientTlsConfig, Endpoint},
+    Request, Status, Streaming,
+};
+use tonic_prost::ProstCodec;
+
+const CONNECT_PATH: &str = "/unifai.toolkit.v1.Toolkit/Connect";
+
+/// The messages of `proto/toolkit.proto`, written out as `prost` would generate them.
+pub(crate) mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Frame {
+        #[prost(oneof = "Kind", tags = "1, 2, 3, 4")]
+        pub kind: Option<Kind>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Kind {
+        /// A toolkit message, as the JSON sent over the WebSocket.
+        #[prost(string, tag = "1")]
+        Message(String),
+        #[prost(message, tag = "2")]
+        Ping(Ping),
+        #[prost(message, tag = "3")]
+        Pong(Pong),
+        #[prost(message, tag = "4")]
+        Close(Close),
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Ping {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Pong {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Close {
+        #[prost(uint32, optional, tag = "1")]
+        pub code: Option<u32>,
+        #[prost(string, tag = "2")]
+        pub reason: String,
+    }
+}
+
+/// A `Connect` stream.
+pub(crate) struct GrpcTransport {
+    /// The frames to send, `None` once closed, which ends the stream on the service side.
+    outgoing: Option<UnboundedSender<Frame>>,
+    incoming: Streaming<Frame>,
+    wire: WireLogging,
+}
+
+impl GrpcTransport {
+    /// Open a stream at [UnifaiConfig::backend_grpc_endpoint], as a client of `client_type`
+    /// authenticated with `api_key`.
+    pub(crate) async fn connect(
+        config: &UnifaiConfig,
+        wire: &WireLogging,
+        client_type: &str,
+        api_key: &ApiKeyProvider,
+    ) -> Result<Self> {
+        let connect_timeout = config.connect_timeout;
+        let open = Self::open(config, client_type, api_key);
+        let (outgoing, incoming) =
+            timeout(connect_timeout, open)
+                .await
+                .map_err(|_| ToolkitError::Timeout {
+                    operation: "gRPC connect",
+                    timeout: connect_timeout,
+                })??;
+
+        Ok(Self {
+            outgoing: Some(outgoing),
+            incoming,
+            wire: wire.clone(),
+        })
+    }
+
+    async fn open(
+        config: &UnifaiConfig,
+        client_type: &str,
+        api_key: &ApiKeyProvider,
+    ) -> Result<(UnboundedSender<Frame>, Streaming<Frame>)> {
+        let url = config.backend_grpc_endpoint.clone();
+        let mut endpoint = Endpoint::from_shared(url.clone())?
+            .user_agent(USER_AGENT)?
+            .connect_timeout(config.connect_timeout);
+        if url.starts_with("https://") {
+            endpoint = endpoint.tls_config(ClientTlsConfig::new().with_native_roots())?;
+        }
+        let mut grpc = Grpc::new(endpoint.connect().await?);
+        grpc.ready().await?;
+
+        let (outgoing, mut frames) = unbounded_channel();
+        let mut request = Request::new(stream::poll_fn(move |cx| frames.poll_recv(cx)));
+        let mut key = MetadataValue::try_from(api_key.expose())
+            .map_err(|_| Status::unauthenticated("the API key isn't valid metadata"))?;
+        key.set_sensitive(true);
+        let metadata = request.metadata_mut();
+        metadata.insert("authorization", key);
+        metadata.insert(
+            "x-unifai-client-type",
+            MetadataValue::try_from(client_type)
+                .map_err(|_| Status::invalid_argument("invalid client type"))?,
+        );
+
+        let path = PathAndQuery::from_static(CONNECT_PATH);
+        let response = grpc.streaming(request, path, ProstCodec::default()).await?;
+
+        Ok((outgoing, response.into_inner()))
+    }
+
+    fn push(&self, kind: Kind) -> Result<()> {
+        let frame = Frame { kind: Some(kind) };
+        match &self.outgoing {
+            Some(outgoing) if outgoing.send(frame).is_ok() => Ok(()),
+            _ => Err(Status::unavailable("the gRPC stream is closed").into()),
+        }
+    }
+}
+
+impl ToolkitTransport for GrpcTransport {
+    async fn send(&mut self, text: String) -> Result<()> {
+        self.wire.ws_frame(directions::SENT, &text);
+        self.push(Kind::Message(text))
+    }
+
+    async fn ping(&mut self) -> Result<()> {
+        self.push(Kind::Ping(Ping {}))
+    }
+
+    fn answers_pings(&self) -> bool {
+        true
+    }
+
+    async fn recv(&mut self) -> Option<Result<Incoming>> {
+        let frame = match self.incoming.next().await? {
+            Ok(frame) => frame,
+            Err(status) => return Some(Err(status.into())),
+        };
+
+        let incoming = match frame.kind {
+            Some(Kind::Message(text)) => {
+                self.wire.ws_frame(directions::RECEIVED, &text);
+                Incoming::Text(text.into())
+            }
+            Some(Kind::Ping(_)) => {
+                if let Err(e) = self.push(Kind::Pong(Pong {})) {
+                    return Some(Err(e));
+                }
+                Incoming::Ignored
+            }
+            Some(Kind::Close(close)) => Incoming::Closed {
+                code: close.code.and_then(|code| u16::try_from(code).ok()),
+                reason: (!close.reason.is_empty()).then(|| close.reason.into()),
+            },
+            Some(Kind::Pong(_)) => Incoming::Pong,
+            None => Incoming::Ignored,
+        };
+
+        Some(Ok(incoming))
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.outgoing = None;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{proto::Close, *};
+    use crate::toolkit::{
+        messages::{ActionCallParams, ToolkitMessage},
+        ActionDefinition, FunctionAction, ShutdownReason, ToolkitService, TransportPreference,
+    };
+    use futures_util::{
+        future::{ready, Ready},
+        stream::BoxStream,
+    };
+    use hyper::{body::Incoming as Body, service::service_fn};
+    use hyper_util::{
+        rt::{TokioExecutor, TokioIo},
+        server::conn::auto,
+    };
+    use serde_json::{json, Value};
+    use std::convert::Infallible;
+    use tokio::{net::TcpListener, spawn, sync::mpsc::UnboundedReceiver};
+    use tonic::{
+        codegen::http,
+        metadata::MetadataMap,
+        server::{self, StreamingService},
+        Response,
+    };
+
+    /// A `Connect` stream opened by the toolkit, as the in-process server sees it.
+    struct Connection {
+        path: String,
+        metadata: MetadataMap,
+        received: Streaming<Frame>,
+        sender: UnboundedSender<Frame>,
+    }
+
+    impl Connection {
+        fn send(&self, kind: Kind) {
+            self.sender.send(Frame { kind: Some(kind) }).unwrap();
+        }
+
+        async fn next_frame(&mut self) -> Kind {
+            self.received.next().await.unwrap().unwrap().kind.unwrap()
+        }
+
+        async fn next_message(&mut self) -> ToolkitMessage {
+            match self.next_frame().await {
+                Kind::Message(text) => ToolkitMessage::from_frame(text.as_bytes()).unwrap(),
+                kind => panic!("expected a message, got {kind:?}"),
+            }
+        }
+    }
+
+    /// Hands the streams over to the test.
+    struct Connect {
+        path: String,
+        connections: UnboundedSender<Connection>,
+    }
+
+    impl StreamingService<Frame> for Connect {
+        type Response = Frame;
+        type ResponseStream = BoxStream<'static, std::result::Result<Frame, Status>>;
+        type Future = Ready<std::result::Result<Response<Self::ResponseStream>, Status>>;
+
+        fn call(&mut self, request: Request<Streaming<Frame>>) -> Self::Future {
+            let (sender, mut frames) = unbounded_channel();
+            let (metadata, _, received) = request.into_parts();
+            let _ = self.connections.send(Connection {
+                path: self.path.clone(),
+                metadata,
+                received,
+                sender,
+            });
+
+            let frames = stream::poll_fn(move |cx| frames.poll_recv(cx)).map(Ok);
+            ready(Ok(Response::new(frames.boxed())))
+        }
+    }
+
+    /// Serve the `Connect` stream on a local port, returning its URL and the streams opened.
+    async fn serve() -> (String, UnboundedReceiver<Connection>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (connections, opened) = unbounded_channel();
+
+        spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let connections = connections.clone();
+                let service = service_fn(move |request: http::Request<Body>| {
+                    let connect = Connect {
+                        path: request.uri().path().to_string(),
+                        connections: connections.clone(),
+                    };
+                    async move {
+                        let mut grpc = server::Grpc::new(ProstCodec::<Frame, Frame>::default());
+                        Ok::<_, Infallible>(grpc.streaming(connect, request).await)
+                    }
+                });
+                spawn(async move {
+                    let _ = auto::Builder::new(TokioExecutor::new())
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+
+        (format!("http://{addr}"), opened)
+    }
+
+    #[tokio::test]
+    async fn test_interop() {
+        let (endpoint, mut opened) = serve().await;
+        let mut service = ToolkitService::from_config(UnifaiConfig {
+            toolkit_api_key: Some("test-key".to_string()),
+            backend_grpc_endpoint: endpoint.clone(),
+            ..UnifaiConfig::default()
+        })
+        .unwrap()
+        .with_transport(TransportPreference::Grpc);
+        let definition = ActionDefinition {
+            description: "Echo the payload".to_string(),
+            payload: json!({}),
+            payment: None,
+            ..Default::default()
+        };
+        service.add_action(FunctionAction::new(
+            "echo",
+            definition,
+            |_ctx, payload: Value| async move { Ok(payload) },
+        ));
+        let handle = service.start().await.unwrap();
+        assert_eq!(handle.endpoint(), Some(endpoint.as_str()));
+
+        let mut connection = opened.recv().await.unwrap();
+        assert_eq!(connection.path, CONNECT_PATH);
+        assert_eq!(
+            connection.metadata.get("authorization").unwrap(),
+            "test-key"
+        );
+        assert_eq!(
+            connection.metadata.get("x-unifai-client-type").unwrap(),
+            "toolkit"
+        );
+        assert!(matches!(
+            connection.next_message().await,
+            ToolkitMessage::RegisterActions { data } if data.actions.contains_key("echo")
+        ));
+
+        let call = ToolkitMessage::Action {
+            data: ActionCallParams::new("echo")
+                .with_action_id(1)
+                .with_payload(json!({ "n": 1 })),
+        };
+        connection.send(Kind::Message(serde_json::to_string(&call).unwrap()));
+        match connection.next_message().await {
+            ToolkitMessage::ActionResult { data } => {
+                assert_eq!(data.action_id, 1);
+                assert_eq!(data.payload.get(), r#"{"n":1}"#);
+            }
+            message => panic!("expected a result, got {message:?}"),
+        }
+
+        connection.send(Kind::Ping(Ping {}));
+        assert_eq!(connection.next_frame().await, Kind::Pong(Pong {}));
+
+        connection.send(Kind::Close(Close {
+            code: Some(1000),
+            reason: "bye".to_string(),
+        }));
+        assert_eq!(
+            handle.await.unwrap().unwrap(),
+            ShutdownReason::ServerClosed {
+                code: Some(1000),
+                reason: Some("bye".into()),
+            }
+        );
+    }
+}
@@ -0,0 +1,331 @@
+//! Serve the actions of a Toolkit over plain HTTP, as an [axum] router.
+//!
+//! - `GET /actions` returns the definitions of the actions, by name.
+//! - `POST /actions/{name}` calls an action with a body of `{"payload": ..., "payment": ...}`, as
+//!   [ActionParams], and returns its result payload.
+//!
+//! Calls go through the same dispatch as the ones received from Unifai, with the same logging and
+//! redaction. They are made with an `agent_id` of `0`, and an `action_id` counting the calls of
+//! the router.
+//!
+//! Failures return `{"error": ..., "code": ...}` with one of these statuses, and the other fields
+//! of the payload sent to Unifai, such as the `message`, `retryable` and `details` of an
+//! [ActionError](super::ActionError):
+//!
+//! | Status | `code`            | When                                             |
+//! |--------|-------------------|--------------------------------------------------|
+//! | 400    | `invalid_request` | The body is not a JSON object with a `payload`.  |
+//! | 401    | `unauthorized`    | The bearer token was missing or rejected.        |
+//! | 404    | `not_found`       | There is no such action.                         |
+//! | 422    | `invalid_payload` | The payload does not match the action arguments. |
+//! | 500    | `action_error`    | The action returned an error, or its own `code`. |
+//! | 503    | `unavailable`     | The action is unavailable.                       |
+//! | 504    | `timeout`         | The action timed out.                            |
+//! | 500    | `internal_error`  | Anything else.                                   |
+//!
+//! # Example
+//!
+//! ```ignore
+//! let mut service = ToolkitService::new("UNIFAI_TOOLKIT_API_KEY");
+//! service.add_action(EchoSlam);
+//!
+//! let app = unifai_sdk::toolkit::http::router_with_auth(service, bearer_token("internal-token"));
+//! let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await.unwrap();
+//! axum::serve(listener, app).await.unwrap();
+//! ```
+
+use super::{
+    action::{ActionDefinition, ActionParams},
+    messages::ActionCallParams,
+    service::{dispatch_action, error_payload, ToolkitService},
+};
+use crate::trace::TraceContext;
+use axum::{
+    body::Bytes,
+    extract::{Path, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use futures_util::future::join_all;
+use serde_json::{json, value::RawValue};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+/// A hook deciding whether a bearer token is allowed to call the actions.
+pub type AuthHook = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+/// An [AuthHook] allowing exactly `token`.
+pub fn bearer_token(token: impl Into<String>) -> AuthHook {
+    let token = token.into();
+
+    Arc::new(move |candidate| {
+        // Compare every byte, so the time taken doesn't tell how much of the token matched.
+        candidate.len() == token.len()
+            && candidate
+                .bytes()
+                .zip(token.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    })
+}
+
+/// A router serving the actions of `service` to the requests whose bearer token `auth` allows.
+pub fn router_with_auth(service: ToolkitService, auth: AuthHook) -> Router {
+    router(service).route_layer(middleware::from_fn(move |request, next| {
+        authorize(auth.clone(), request, next)
+    }))
+}
+
+#[derive(Clone)]
+struct HttpState {
+    service: Arc<ToolkitService>,
+    next_action_id: Arc<AtomicU64>,
+}
+
+/// A router serving the actions of `service` to anyone who can reach it.
+pub fn router(service: ToolkitService) -> Router {
+    let state = HttpState {
+        service: Arc::new(service),
+        next_action_id: Arc::new(AtomicU64::new(1)),
+    };
+
+    Router::new()
+        .route("/actions", get(list_actions))
+        .route("/actions/{name}", post(call_action))
+        .with_state(state)
+}
+
+async fn authorize(auth: AuthHook, request: Request, next: Next) -> Response {
+    let allowed = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| auth(token));
+
+    if !allowed {
+        let mut response = error_response(
+            StatusCode::UNAUTHORIZED,
+            "unauthorized",
+            "Missing or invalid bearer token",
+        );
+        response
+            .headers_mut()
+            .insert(header::WWW_AUTHENTICATE, "Bearer".parse().unwrap());
+        return response;
+    }
+
+    next.run(request).await
+}
+
+async fn list_actions(State(state): State<HttpState>) -> Json<HashMap<String, ActionDefinition>> {
+    let actions = state.service.actions().entries();
+    let definitions = join_all(
+        actions
+            .into_iter()
+            .map(|(name, action)| async move { (name.to_string(), action.definition().await) }),
+    )
+    .await;
+
+    Json(definitions.into_iter().collect())
+}
+
+async fn call_action(
+    State(state): State<HttpState>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let params: ActionParams<Box<RawValue>> = match serde_json::from_slice(&body) {
+        Ok(params) => params,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, "invalid_request", e),
+    };
+
+    let call = ActionCallParams {
+        action: name.clone(),
+        action_id: state.next_action_id.fetch_add(1, Ordering::Relaxed),
+        agent_id: 0,
+        payload: params.payload,
+        payment: params.payment,
+        trace_context: TraceContext::from_headers(&headers),
+        extra: Default::default(),
+    };
+
+    match dispatch_action(&state.service, call).await {
+        None => error_response(
+            StatusCode::NOT_FOUND,
+            "not_found",
+            format!("Action not found: {name}"),
+        ),
+        Some(Ok(result)) => Json(result.payload).into_response(),
+        Some(Err(e)) => {
+            let (status, _) = e.http_status();
+            let status = StatusCode::from_u16(status).expect("a valid status");
+            (status, Json(error_payload(&e))).into_response()
+        }
+    }
+}
+
+fn error_response(status: StatusCode, code: &str, error: impl ToString) -> Response {
+    let body = json!({
+        "error": error.to_string(),
+        "code": code,
+    });
+
+    (status, Json(body)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toolkit::{Action, ActionContext, ActionError, ActionResult};
+    use axum::body::{to_bytes, Body};
+    use serde::Deserialize;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    struct Echo;
+
+    #[derive(Deserialize)]
+    struct EchoArgs {
+        content: String,
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("echo refused {0:?}")]
+    struct EchoError(String, #[source] ActionError);
+
+    impl Action for Echo {
+        const NAME: &'static str = "echo";
+
+        type Error = EchoError;
+        type Args = EchoArgs;
+        type Output = String;
+
+        async fn definition(&self) -> ActionDefinition {
+            ActionDefinition {
+                description: "Echo the message".to_string(),
+                payload: json!({ "content": { "type": "string", "required": true } }),
+                payment: None,
+                ..Default::default()
+            }
+        }
+
+        async fn call(
+            &self,
+            ctx: ActionContext,
+            params: ActionParams<Self::Args>,
+        ) -> Result<ActionResult<Self::Output>, Self::Error> {
+            if params.payload.content == "fail" {
+                let error = ActionError::new("echo_refused", "try again")
+                    .with_retryable(true)
+                    .with_details(json!({ "content": params.payload.content }));
+                return Err(EchoError(params.payload.content, error));
+            }
+
+            Ok(ActionResult {
+                payload: format!("{} #{}", params.payload.content, ctx.action_id),
+                payment: None,
+            })
+        }
+    }
+
+    fn service() -> ToolkitService {
+        let mut service = ToolkitService::new("test");
+        service.add_action(Echo);
+        service
+    }
+
+    async fn send(router: &Router, request: axum::http::Request<Body>) -> (StatusCode, Value) {
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn call(action: &str, body: &str) -> axum::http::Request<Body> {
+        axum::http::Request::post(format!("/actions/{action}"))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_list_actions() {
+        let router = router(service());
+
+        let request = axum::http::Request::get("/actions")
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = send(&router, request).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["echo"]["description"], "Echo the message");
+        assert_eq!(body["echo"]["payload"]["content"]["required"], true);
+    }
+
+    #[tokio::test]
+    async fn test_call_action() {
+        let router = router(service());
+
+        let (status, body) = send(&router, call("echo", r#"{"payload":{"content":"hi"}}"#)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "hi #1");
+
+        let (status, body) = send(&router, call("echo", r#"{"payload":{"content":"fail"}}"#)).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body["code"], "echo_refused");
+        assert!(body["error"].as_str().unwrap().contains("echo refused"));
+        assert_eq!(body["message"], "try again");
+        assert_eq!(body["retryable"], true);
+        assert_eq!(body["details"], json!({ "content": "fail" }));
+
+        let (status, body) = send(&router, call("echo", r#"{"payload":{"other":1}}"#)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "invalid_payload");
+
+        let (status, body) = send(&router, call("echo", "not json")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "invalid_request");
+
+        let (status, body) = send(&router, call("missing", r#"{"payload":{}}"#)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["code"], "not_found");
+    }
+
+    #[tokio::test]
+    async fn test_bearer_token_auth() {
+        let router = router_with_auth(service(), bearer_token("internal-token"));
+        let body = r#"{"payload":{"content":"hi"}}"#;
+
+        let (status, response) = send(&router, call("echo", body)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(response["code"], "unauthorized");
+
+        let mut request = call("echo", body);
+        request.headers_mut().insert(
+            header::AUTHORIZATION,
+            "Bearer internal-tokem".parse().unwrap(),
+        );
+        let (status, _) = send(&router, request).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let mut request = call("echo", body);
+        request.headers_mut().insert(
+            header::AUTHORIZATION,
+            "Bearer internal-token".parse().unwrap(),
+        );
+        let (status, response) = send(&router, request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response, "hi #1");
+    }
+}
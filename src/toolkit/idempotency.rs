@@ -0,0 +1,321 @@
+//! Answering the calls the server delivers again with the result of their first delivery, see
+//! [ToolkitService::with_idempotency](super::ToolkitService::with_idempotency).
+//!
+//! The server may deliver a call again after a reconnect. The results are stored by
+//! [CallKey], and a call already answered gets its stored result instead of running the action a
+//! second time. A call delivered again while its first delivery still runs on the same connection
+//! is left to it, and one still running from a lost connection is waited for.
+//! The calls cancelled because the connection was lost aren't stored, so they run again when
+//! delivered again.
+
+use super::{
+    events::{EventSender, ToolkitRuntimeEvent},
+    messages::ActionCallResult,
+};
+use crate::logging::{events, targets};
+use futures_util::future::BoxFuture;
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+/// The identity of an action call, the same in every delivery of the call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CallKey {
+    pub agent_id: u64,
+    pub action_id: u64,
+}
+
+/// Where the results of the calls are kept, such as a Redis server shared by the replicas of a
+/// toolkit, see
+/// [ToolkitService::with_idempotency_store](super::ToolkitService::with_idempotency_store).
+///
+/// The store decides how long results are kept. A store that fails should act as if it had no
+/// result, so calls run rather than go unanswered.
+pub trait IdempotencyStore: Send + Sync {
+    /// The result of the call `key`, if it's stored.
+    fn get(&self, key: CallKey) -> BoxFuture<'_, Option<ActionCallResult>>;
+
+    /// Store the result of the call `key`.
+    fn put(&self, key: CallKey, result: ActionCallResult) -> BoxFuture<'_, ()>;
+}
+
+/// An [IdempotencyStore] in memory, keeping the results of the last `capacity` calls for `ttl`.
+/// The oldest results are dropped first.
+pub struct MemoryIdempotencyStore {
+    capacity: usize,
+    ttl: Duration,
+    entries: Mutex<Entries>,
+}
+
+#[derive(Default)]
+struct Entries {
+    results: HashMap<CallKey, (Instant, ActionCallResult)>,
+    /// The keys of `results`, oldest first.
+    order: VecDeque<CallKey>,
+}
+
+impl MemoryIdempotencyStore {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            entries: Mutex::default(),
+        }
+    }
+
+    fn get_now(&self, key: CallKey, now: Instant) -> Option<ActionCallResult> {
+        let entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        let (stored, result) = entries.results.get(&key)?;
+
+        (now.duration_since(*stored) < self.ttl).then(|| result.clone())
+    }
+
+    fn put_now(&self, key: CallKey, result: ActionCallResult, now: Instant) {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        if entries.results.insert(key, (now, result)).is_none() {
+            entries.order.push_back(key);
+        }
+
+        while let Some(oldest) = entries.order.front().copied() {
+            let expired = entries.results[&oldest].0 + self.ttl <= now;
+            if !expired && entries.results.len() <= self.capacity {
+                break;
+            }
+            entries.order.pop_front();
+            entries.results.remove(&oldest);
+        }
+    }
+}
+
+impl IdempotencyStore for MemoryIdempotencyStore {
+    fn get(&self, key: CallKey) -> BoxFuture<'_, Option<ActionCallResult>> {
+        let result = self.get_now(key, Instant::now());
+        Box::pin(async move { result })
+    }
+
+    fn put(&self, key: CallKey, result: ActionCallResult) -> BoxFuture<'_, ()> {
+        self.put_now(key, result, Instant::now());
+        Box::pin(async {})
+    }
+}
+
+/// The store of a service, and the calls it's running, shared with the services started again.
+pub(super) struct Idempotency {
+    store: Arc<dyn IdempotencyStore>,
+    running: Mutex<HashMap<CallKey, watch::Receiver<Option<ActionCallResult>>>>,
+}
+
+/// The right to answer a call, released when dropped.
+struct Claim<'a> {
+    idempotency: &'a Idempotency,
+    key: CallKey,
+    sender: watch::Sender<Option<ActionCallResult>>,
+}
+
+impl Drop for Claim<'_> {
+    fn drop(&mut self) {
+        self.idempotency
+            .running
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.key);
+    }
+}
+
+impl Idempotency {
+    pub(super) fn new(store: Arc<dyn IdempotencyStore>) -> Self {
+        Self {
+            store,
+            running: Mutex::default(),
+        }
+    }
+
+    /// Answer the call `key` with `answer`, unless another delivery of the call answers it.
+    /// `cancellation` is the token of the call, whose result isn't stored if it's cancelled.
+    pub(super) async fn answer_once(
+        &self,
+        key: CallKey,
+        cancellation: &CancellationToken,
+        answer: impl Future<Output = ActionCallResult>,
+        event_sender: &EventSender,
+    ) -> ActionCallResult {
+        let claim = loop {
+            let mut waiting = {
+                let mut running = self.running.lock().unwrap_or_else(PoisonError::into_inner);
+                match running.get(&key) {
+                    Some(waiting) => waiting.clone(),
+                    None => {
+                        let (sender, receiver) = watch::channel(None);
+                        running.insert(key, receiver);
+                        break Claim {
+                            idempotency: self,
+                            key,
+                            sender,
+                        };
+                    }
+                }
+            };
+            // Claim the call if the other delivery ends unanswered.
+            let answered = waiting
+                .wait_for(Option::is_some)
+                .await
+                .map(|result| result.clone().expect("waited for a result"));
+            if let Ok(result) = answered {
+                report_duplicate(&result.action, key, event_sender);
+                return result;
+            }
+        };
+
+        if let Some(result) = self.store.get(key).await {
+            report_duplicate(&result.action, key, event_sender);
+            return result;
+        }
+
+        let result = answer.await;
+        if !cancellation.is_cancelled() {
+            self.store.put(key, result.clone()).await;
+            claim.sender.send_replace(Some(result.clone()));
+        }
+
+        result
+    }
+}
+
+/// Report a call delivered again, which its first delivery answers.
+pub(super) fn report_duplicate(action: &str, key: CallKey, event_sender: &EventSender) {
+    tracing::info!(
+        target: targets::DISPATCH,
+        event = events::DUPLICATE_CALL,
+        action,
+        action_id = key.action_id,
+        agent_id = key.agent_id,
+        "Action call delivered again, answered with its first result"
+    );
+
+    event_sender.send(ToolkitRuntimeEvent::DuplicateCall {
+        action: action.into(),
+        agent_id: key.agent_id,
+        action_id: key.action_id,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::value::to_raw_value;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn key(action_id: u64) -> CallKey {
+        CallKey {
+            agent_id: 1,
+            action_id,
+        }
+    }
+
+    fn result(action_id: u64) -> ActionCallResult {
+        ActionCallResult {
+            action: "echo".to_string(),
+            action_id,
+            agent_id: 1,
+            payload: to_raw_value(&action_id).unwrap(),
+            payment: None,
+            extra: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_memory_store_is_bounded() {
+        let store = MemoryIdempotencyStore::new(2, Duration::from_secs(60));
+        let now = Instant::now();
+        for action_id in 1..=3 {
+            store.put_now(key(action_id), result(action_id), now);
+        }
+
+        assert!(store.get_now(key(1), now).is_none());
+        assert_eq!(store.get_now(key(2), now).unwrap().action_id, 2);
+        assert_eq!(store.get_now(key(3), now).unwrap().action_id, 3);
+    }
+
+    #[test]
+    fn test_memory_store_expires() {
+        let store = MemoryIdempotencyStore::new(10, Duration::from_secs(60));
+        let now = Instant::now();
+        store.put_now(key(1), result(1), now);
+
+        let later = now + Duration::from_secs(60);
+        assert!(store.get_now(key(1), later).is_none());
+        store.put_now(key(2), result(2), later);
+        assert_eq!(store.entries.lock().unwrap().order, [key(2)]);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_deliveries_run_once() {
+        let idempotency = Idempotency::new(Arc::new(MemoryIdempotencyStore::new(
+            10,
+            Duration::from_secs(60),
+        )));
+        let calls = AtomicUsize::new(0);
+        let token = CancellationToken::new();
+        let answer = || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            tokio::task::yield_now().await;
+            result(1)
+        };
+
+        let event_sender = EventSender::new(None);
+        let mut events = event_sender.subscribe();
+
+        let (first, second) = tokio::join!(
+            idempotency.answer_once(key(1), &token, answer(), &event_sender),
+            idempotency.answer_once(key(1), &token, answer(), &event_sender),
+        );
+        let third = idempotency
+            .answer_once(key(1), &token, answer(), &event_sender)
+            .await;
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        for answered in [first, second, third] {
+            assert_eq!(answered.payload.get(), "1");
+        }
+        // Every delivery but the one that ran is reported.
+        for _ in 0..2 {
+            let event = events.try_recv().unwrap();
+            assert!(matches!(
+                event,
+                ToolkitRuntimeEvent::DuplicateCall { action_id: 1, .. }
+            ));
+        }
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_calls_run_again() {
+        let idempotency = Idempotency::new(Arc::new(MemoryIdempotencyStore::new(
+            10,
+            Duration::from_secs(60),
+        )));
+        let cancelled = CancellationToken::new();
+        cancelled.cancel();
+
+        let event_sender = EventSender::new(None);
+
+        idempotency
+            .answer_once(key(1), &cancelled, async { result(0) }, &event_sender)
+            .await;
+        let answered = idempotency
+            .answer_once(
+                key(1),
+                &CancellationToken::new(),
+                async { result(1) },
+                &event_sender,
+            )
+            .await;
+
+        assert_eq!(answered.payload.get(), "1");
+    }
+}
@@ -0,0 +1,142 @@
+//! The limit of the action calls a running service executes at once, see
+//! [ToolkitService::with_max_concurrent_actions](super::ToolkitService::with_max_concurrent_actions).
+//!
+//! Calls over the limit wait in a queue, in the order they arrived, until a call in flight
+//! finishes. Calls arriving while the queue is full are answered right away with a
+//! [ToolkitBusy](super::ToolkitError::ToolkitBusy) error.
+
+use futures_util::future::BoxFuture;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+#[derive(Debug)]
+pub(super) struct CallLimiter {
+    /// `None` without a limit.
+    permits: Option<Arc<Semaphore>>,
+    max_queued: Option<usize>,
+    in_flight: AtomicUsize,
+    queued: AtomicUsize,
+}
+
+/// What to do with an incoming call.
+pub(super) enum Admission {
+    /// Run the call now.
+    Run(Slot),
+    /// Run the call once the slot is given.
+    Queue(BoxFuture<'static, Slot>),
+    /// Answer the call with an error, the queue is full.
+    Busy,
+}
+
+/// The right of a call to execute, held until it finishes.
+pub(super) struct Slot {
+    limiter: Arc<CallLimiter>,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl CallLimiter {
+    pub(super) fn new(max_concurrent: Option<usize>, max_queued: Option<usize>) -> Arc<Self> {
+        Arc::new(Self {
+            permits: max_concurrent.map(|max| Arc::new(Semaphore::new(max.max(1)))),
+            max_queued,
+            in_flight: AtomicUsize::new(0),
+            queued: AtomicUsize::new(0),
+        })
+    }
+
+    /// Admit an incoming call.
+    ///
+    /// Calls are admitted one at a time from the dispatch loop, so the queue never goes over its
+    /// limit.
+    pub(super) fn admit(self: &Arc<Self>) -> Admission {
+        let Some(permits) = &self.permits else {
+            return Admission::Run(self.slot(None));
+        };
+
+        if let Ok(permit) = permits.clone().try_acquire_owned() {
+            return Admission::Run(self.slot(Some(permit)));
+        }
+        if self
+            .max_queued
+            .is_some_and(|max| self.queued.load(Ordering::Relaxed) >= max)
+        {
+            return Admission::Busy;
+        }
+
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        let (limiter, permits) = (self.clone(), permits.clone());
+        Admission::Queue(Box::pin(async move {
+            let permit = permits.acquire_owned().await.expect("never closed");
+            limiter.queued.fetch_sub(1, Ordering::Relaxed);
+            limiter.slot(Some(permit))
+        }))
+    }
+
+    fn slot(self: &Arc<Self>, permit: Option<OwnedSemaphorePermit>) -> Slot {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+
+        Slot {
+            limiter: self.clone(),
+            _permit: permit,
+        }
+    }
+
+    pub(super) fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    pub(super) fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.limiter.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_queues_then_rejects_calls_over_the_limit() {
+        let limiter = CallLimiter::new(Some(1), Some(1));
+
+        let Admission::Run(first) = limiter.admit() else {
+            panic!("the first call should run");
+        };
+        let Admission::Queue(second) = limiter.admit() else {
+            panic!("the second call should be queued");
+        };
+        assert!(matches!(limiter.admit(), Admission::Busy));
+        assert_eq!((limiter.in_flight(), limiter.queued()), (1, 1));
+
+        drop(first);
+        let second = second.await;
+        assert_eq!((limiter.in_flight(), limiter.queued()), (1, 0));
+
+        drop(second);
+        assert_eq!(limiter.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_unlimited() {
+        let limiter = CallLimiter::new(None, Some(0));
+
+        let slots: Vec<_> = (0..100)
+            .map(|_| match limiter.admit() {
+                Admission::Run(slot) => slot,
+                _ => panic!("calls should run right away"),
+            })
+            .collect();
+        assert_eq!(limiter.in_flight(), 100);
+
+        drop(slots);
+        assert_eq!(limiter.in_flight(), 0);
+    }
+}
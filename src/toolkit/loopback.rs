@@ -0,0 +1,421 @@
+//! A local stand-in for the tools API of the Unifai backend, serving the actions of one service,
+//! see [crate::testing::loopback], or of the toolkits connected to a
+//! [MockBackend](crate::testing::MockBackend), to the agent tools.
+//!
+//! - `GET /actions/search?query=...&limit=...` returns the actions whose name or description
+//!   contain words of the query, with the most matching first.
+//! - `POST /actions/call` calls an action with a body of [CallToolArgs], and returns its result
+//!   like the backend, as in `{"payload": ..., "payment": null}`, or `{"error": ..., "code": ...}`
+//!   with the statuses of the `http-server` router.
+
+use super::{
+    errors::{Result, ToolkitError},
+    messages::ActionCallParams,
+    service::{dispatch_action, ToolkitService},
+    ActionDefinition, ActionResult,
+};
+use crate::{tools::CallToolArgs, trace::TraceContext};
+use bytes::Bytes;
+use futures_util::future::BoxFuture;
+use http_body_util::{BodyExt, Full};
+use hyper::{body::Incoming, header, Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde_json::{json, value::to_raw_value, Value};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tokio::{net::TcpListener, spawn};
+
+/// The number of actions returned by a search without a limit.
+const DEFAULT_SEARCH_LIMIT: usize = 10;
+
+/// The actions a loopback server serves.
+pub(crate) trait Served: Send + Sync + 'static {
+    /// The definitions of the actions, by name.
+    fn definitions(&self) -> BoxFuture<'_, Result<HashMap<String, ActionDefinition>>>;
+
+    /// Call an action, `None` if there is no such action.
+    fn call(&self, params: ActionCallParams) -> BoxFuture<'_, Option<Result<ActionResult<Value>>>>;
+}
+
+impl Served for ToolkitService {
+    fn definitions(&self) -> BoxFuture<'_, Result<HashMap<String, ActionDefinition>>> {
+        Box::pin(ToolkitService::definitions(self))
+    }
+
+    fn call(&self, params: ActionCallParams) -> BoxFuture<'_, Option<Result<ActionResult<Value>>>> {
+        Box::pin(dispatch_action(self, params))
+    }
+}
+
+struct Backend<S> {
+    served: Arc<S>,
+    next_action_id: AtomicU64,
+}
+
+/// Serve the actions of `served` on a loopback port, until the runtime shuts down.
+pub(crate) async fn serve<S: Served>(served: Arc<S>) -> std::io::Result<SocketAddr> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let backend = Arc::new(Backend {
+        served,
+        next_action_id: AtomicU64::new(1),
+    });
+
+    spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let backend = backend.clone();
+            spawn(async move {
+                let service = hyper::service::service_fn(move |request| {
+                    handle_http(backend.clone(), request)
+                });
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+
+    Ok(addr)
+}
+
+async fn handle_http<S: Served>(
+    backend: Arc<Backend<S>>,
+    request: Request<Incoming>,
+) -> std::result::Result<Response<Full<Bytes>>, Infallible> {
+    let path = request.uri().path().trim_end_matches('/').to_string();
+    let response = match (request.method(), path.as_str()) {
+        (&Method::GET, "/actions/search") => {
+            let (query, limit) = search_args(request.uri().query().unwrap_or_default());
+            match search(&backend, &query, limit).await {
+                Ok(actions) => json_response(StatusCode::OK, actions),
+                Err(e) => failure_response(e),
+            }
+        }
+        (&Method::POST, "/actions/call") => {
+            let trace_context = TraceContext::from_headers(request.headers());
+            match request.into_body().collect().await {
+                Ok(body) => call(&backend, &body.to_bytes(), trace_context).await,
+                Err(e) => error_response(StatusCode::BAD_REQUEST, "invalid_request", e),
+            }
+        }
+        _ => error_response(StatusCode::NOT_FOUND, "not_found", "No such endpoint"),
+    };
+
+    Ok(response)
+}
+
+/// The query and limit of a search, as encoded by [UnifaiClient](crate::tools::UnifaiClient).
+fn search_args(query: &str) -> (String, usize) {
+    let url = reqwest::Url::parse(&format!("http://loopback/?{query}")).expect("a valid URL");
+    let mut args = (String::new(), DEFAULT_SEARCH_LIMIT);
+
+    for (key, value) in url.query_pairs() {
+        match &*key {
+            "query" => args.0 = value.into_owned(),
+            "limit" => args.1 = value.parse().unwrap_or(DEFAULT_SEARCH_LIMIT),
+            _ => {}
+        }
+    }
+
+    args
+}
+
+async fn search<S: Served>(backend: &Backend<S>, query: &str, limit: usize) -> Result<Value> {
+    let words: Vec<_> = query.split_whitespace().map(str::to_lowercase).collect();
+    let mut matches: Vec<_> = backend
+        .served
+        .definitions()
+        .await?
+        .into_iter()
+        .filter_map(|(name, definition)| {
+            let text = format!("{name} {}", definition.description).to_lowercase();
+            let score = words.iter().filter(|word| text.contains(*word)).count();
+            (score > 0 || words.is_empty()).then_some((score, name, definition))
+        })
+        .collect();
+    matches.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+
+    Ok(matches
+        .into_iter()
+        .take(limit)
+        .map(|(_, name, definition)| {
+            json!({
+                "action": name,
+                "description": definition.description,
+                "payload": definition.payload,
+                "payment": definition.payment,
+            })
+        })
+        .collect())
+}
+
+async fn call<S: Served>(
+    backend: &Backend<S>,
+    body: &[u8],
+    trace_context: Option<TraceContext>,
+) -> Response<Full<Bytes>> {
+    let args: CallToolArgs = match serde_json::from_slice(body) {
+        Ok(args) => args,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, "invalid_request", e),
+    };
+
+    let params = ActionCallParams {
+        action: args.action.clone(),
+        action_id: backend.next_action_id.fetch_add(1, Ordering::Relaxed),
+        agent_id: 0,
+        payload: to_raw_value(&args.payload).expect("a Value always serializes"),
+        payment: args.payment,
+        trace_context,
+        extra: Default::default(),
+    };
+
+    match backend.served.call(params).await {
+        None => error_response(
+            StatusCode::NOT_FOUND,
+            "not_found",
+            format!("Action not found: {}", args.action),
+        ),
+        Some(Ok(result)) => json_response(
+            StatusCode::OK,
+            json!({
+                "payload": result.payload,
+                "payment": result.payment,
+            }),
+        ),
+        Some(Err(e)) => failure_response(e),
+    }
+}
+
+fn json_response(status: StatusCode, body: Value) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(body.to_string())))
+        .unwrap()
+}
+
+fn failure_response(e: ToolkitError) -> Response<Full<Bytes>> {
+    let (status, code) = e.http_status();
+    let status = StatusCode::from_u16(status).expect("a valid status");
+    error_response(status, code, e)
+}
+
+fn error_response(status: StatusCode, code: &str, error: impl ToString) -> Response<Full<Bytes>> {
+    let body = json!({
+        "error": error.to_string(),
+        "code": code,
+    });
+
+    json_response(status, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::UnifaiConfig,
+        toolkit::{Action, ActionContext, ActionDefinition, ActionParams, ActionResult},
+        tools::{SearchToolsArgs, ToolsError, UnifaiClient},
+    };
+
+    struct Described(&'static str, &'static str);
+
+    impl Action for Described {
+        const NAME: &'static str = "";
+
+        type Error = std::convert::Infallible;
+        type Args = Value;
+        type Output = Value;
+
+        fn name(&self) -> String {
+            self.0.to_string()
+        }
+
+        async fn definition(&self) -> ActionDefinition {
+            ActionDefinition {
+                description: self.1.to_string(),
+                payload: json!({}),
+                payment: None,
+                ..Default::default()
+            }
+        }
+
+        async fn call(
+            &self,
+            _ctx: ActionContext,
+            params: ActionParams<Self::Args>,
+        ) -> std::result::Result<ActionResult<Self::Output>, Self::Error> {
+            Ok(ActionResult {
+                payload: params.payload,
+                payment: None,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_search_ranks_and_limits() {
+        let mut service = ToolkitService::new("test");
+        service.add_action(Described("weather", "Get the weather in a city"));
+        service.add_action(Described("forecast", "Forecast the weather for a week"));
+        service.add_action(Described("balance", "Get the balance of a Solana account"));
+        let addr = serve(Arc::new(service)).await.unwrap();
+        let client = UnifaiClient::from_config(&UnifaiConfig {
+            agent_api_key: Some("test".to_string()),
+            backend_api_endpoint: format!("http://{addr}"),
+            ..UnifaiConfig::default()
+        })
+        .unwrap();
+
+        let search = |query: &str, limit| {
+            let args = SearchToolsArgs {
+                query: query.to_string(),
+                limit,
+                ..Default::default()
+            };
+            let client = client.clone();
+            async move {
+                let tools = client.search_tools_typed(&args).await.unwrap();
+                tools
+                    .into_iter()
+                    .map(|tool| tool.action)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(
+            search("Weather forecast", None).await,
+            ["forecast", "weather"]
+        );
+        assert_eq!(search("weather", Some(1)).await, ["forecast"]);
+        assert!(search("unknown", None).await.is_empty());
+        assert_eq!(search("", None).await.len(), 3);
+
+        let args = CallToolArgs {
+            action: "missing".to_string(),
+            payload: json!({}),
+            payment: None,
+            ..Default::default()
+        };
+        let body: Value = serde_json::from_str(&client.call_tool(&args).await.unwrap()).unwrap();
+        assert_eq!(body["code"], "not_found");
+        assert!(matches!(
+            client.call_tool_typed(&args).await,
+            Err(ToolsError::Status { status: 404, .. })
+        ));
+    }
+
+    /// Returns the trace id of its call.
+    struct Traced;
+
+    impl Action for Traced {
+        const NAME: &'static str = "traced";
+
+        type Error = std::convert::Infallible;
+        type Args = Value;
+        type Output = Option<String>;
+
+        async fn definition(&self) -> ActionDefinition {
+            ActionDefinition {
+                description: "Trace id".to_string(),
+                payload: json!({}),
+                payment: None,
+                ..Default::default()
+            }
+        }
+
+        async fn call(
+            &self,
+            ctx: ActionContext,
+            _params: ActionParams<Self::Args>,
+        ) -> std::result::Result<ActionResult<Self::Output>, Self::Error> {
+            Ok(ActionResult {
+                payload: ctx.trace_id().map(str::to_string),
+                payment: None,
+            })
+        }
+    }
+
+    #[cfg(feature = "otel")]
+    #[tokio::test]
+    async fn test_call_tool_propagates_the_trace() {
+        use opentelemetry::trace::TracerProvider;
+        use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+        use tracing::Instrument;
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let mut service = ToolkitService::new("test");
+        service.add_action(Traced);
+        let addr = serve(Arc::new(service)).await.unwrap();
+        let client = UnifaiClient::from_config(&UnifaiConfig {
+            agent_api_key: Some("test".to_string()),
+            backend_api_endpoint: format!("http://{addr}"),
+            ..UnifaiConfig::default()
+        })
+        .unwrap();
+
+        let args = CallToolArgs {
+            action: "traced".to_string(),
+            payload: json!({}),
+            payment: None,
+            ..Default::default()
+        };
+        let response = client
+            .call_tool_typed(&args)
+            .instrument(tracing::info_span!("agent"))
+            .await
+            .unwrap();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let span = |name: &str| {
+            spans
+                .iter()
+                .find(|span| span.name == name)
+                .unwrap_or_else(|| panic!("no {name} span"))
+        };
+        let (agent, call_tool, action_call) =
+            (span("agent"), span("call_tool"), span("action_call"));
+
+        let agent_trace = agent.span_context.trace_id();
+        assert_eq!(call_tool.span_context.trace_id(), agent_trace);
+        assert_eq!(action_call.span_context.trace_id(), agent_trace);
+        assert_eq!(action_call.parent_span_id, call_tool.span_context.span_id());
+        assert_eq!(response.payload, json!(agent_trace.to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_call_without_trace() {
+        let mut service = ToolkitService::new("test");
+        service.add_action(Traced);
+        let addr = serve(Arc::new(service)).await.unwrap();
+        let client = UnifaiClient::from_config(&UnifaiConfig {
+            agent_api_key: Some("test".to_string()),
+            backend_api_endpoint: format!("http://{addr}"),
+            ..UnifaiConfig::default()
+        })
+        .unwrap();
+
+        let args = CallToolArgs {
+            action: "traced".to_string(),
+            payload: json!({}),
+            payment: None,
+            ..Default::default()
+        };
+        let response = client.call_tool_typed(&args).await.unwrap();
+        assert_eq!(response.payload, Value::Null);
+    }
+}
@@ -0,0 +1,388 @@
+//! Several Toolkits run in one process, each with its own API key, such as the Toolkits of the
+//! customers of a hosting service.
+
+use super::{
+    errors::ToolkitError, CancellationToken, ShutdownReason, ToolkitHandle, ToolkitService,
+};
+use crate::logging::{events, targets};
+use futures_util::future::join_all;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, PoisonError, RwLock},
+    time::Duration,
+};
+use tokio::{spawn, task::JoinHandle, time::sleep};
+
+/// How long a Toolkit waits before starting again, by default.
+const DEFAULT_RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// Runs several Toolkit services, each on its own connection, and starts again the ones that
+/// stop, so a failing Toolkit doesn't take down the others.
+///
+/// # Example
+/// ```ignore
+/// let mut manager = ToolkitManager::new();
+/// for customer in customers {
+///     manager.add_toolkit(customer.name, ToolkitService::new(&customer.api_key));
+/// }
+///
+/// let handle = manager.start();
+/// println!("{:?}", handle.statuses());
+///
+/// for (name, result) in handle.shutdown().await {
+///     println!("{name}: {result:?}");
+/// }
+/// ```
+pub struct ToolkitManager {
+    toolkits: BTreeMap<String, ToolkitService>,
+    restart_delay: Duration,
+}
+
+impl Default for ToolkitManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ToolkitManager {
+    pub fn new() -> Self {
+        Self {
+            toolkits: BTreeMap::new(),
+            restart_delay: DEFAULT_RESTART_DELAY,
+        }
+    }
+
+    /// Add a Toolkit, named `name` in its statuses, errors and logs, replacing any Toolkit of the
+    /// same name.
+    pub fn add_toolkit(&mut self, name: impl Into<String>, service: ToolkitService) {
+        self.toolkits.insert(name.into(), service);
+    }
+
+    /// Set how long a Toolkit waits before starting again, after it stopped or failed to start
+    /// again. Defaults to 1 second.
+    pub fn with_restart_delay(mut self, restart_delay: Duration) -> Self {
+        self.restart_delay = restart_delay;
+        self
+    }
+
+    /// Start the Toolkits concurrently, each on a task of its own, returning a handle to query and
+    /// stop them.
+    ///
+    /// A Toolkit failing to start the first time, such as with an invalid API key, is
+    /// [ToolkitStatus::Failed] and isn't started again. Once started, a Toolkit is started again
+    /// whenever it stops, until it's shut down.
+    pub fn start(self) -> ToolkitManagerHandle {
+        let statuses = Arc::new(RwLock::new(
+            self.toolkits
+                .keys()
+                .map(|name| (name.clone(), ToolkitStatus::Starting))
+                .collect(),
+        ));
+        let shutdown = CancellationToken::new();
+        let runners = self
+            .toolkits
+            .into_iter()
+            .map(|(name, service)| {
+                let supervisor = Supervisor {
+                    name: name.clone(),
+                    statuses: statuses.clone(),
+                    shutdown: shutdown.clone(),
+                    restart_delay: self.restart_delay,
+                };
+                (name, spawn(supervisor.run(service)))
+            })
+            .collect();
+
+        ToolkitManagerHandle {
+            statuses,
+            shutdown,
+            runners,
+        }
+    }
+}
+
+/// The status of a Toolkit run by a [ToolkitManager].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ToolkitStatus {
+    /// Connecting for the first time.
+    Starting,
+    /// Connected and serving its actions.
+    Connected,
+    /// Connecting again, after it stopped.
+    Reconnecting,
+    /// Shut down with the other Toolkits.
+    Stopped,
+    /// Failed and not started again, with the error.
+    Failed { error: Arc<str> },
+}
+
+/// An error of a Toolkit run by a [ToolkitManager], labeled with its name.
+#[derive(Debug, thiserror::Error)]
+pub enum ToolkitManagerError {
+    #[error("ToolkitFailed: toolkit {toolkit} failed: {source}")]
+    Failed {
+        toolkit: String,
+        #[source]
+        source: Box<ToolkitError>,
+    },
+
+    #[error("ToolkitPanicked: toolkit {toolkit} panicked")]
+    Panicked { toolkit: String },
+}
+
+/// The result of a Toolkit once it stopped for good.
+pub type ToolkitOutcome = Result<ShutdownReason, ToolkitManagerError>;
+
+/// A handle to the Toolkits started by [ToolkitManager::start].
+///
+/// Dropping the handle leaves the Toolkits running.
+pub struct ToolkitManagerHandle {
+    statuses: Arc<RwLock<BTreeMap<String, ToolkitStatus>>>,
+    shutdown: CancellationToken,
+    runners: Vec<(String, JoinHandle<ToolkitOutcome>)>,
+}
+
+impl ToolkitManagerHandle {
+    /// The status of the Toolkit `name`, `None` if there is no such Toolkit.
+    pub fn status(&self, name: &str) -> Option<ToolkitStatus> {
+        self.statuses().remove(name)
+    }
+
+    /// The statuses of all the Toolkits, by name.
+    pub fn statuses(&self) -> BTreeMap<String, ToolkitStatus> {
+        self.statuses
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Ask every Toolkit to stop, like [ToolkitHandle::request_shutdown].
+    pub fn request_shutdown(&self) {
+        self.shutdown.cancel();
+    }
+
+    /// Ask every Toolkit to stop, and wait for them, see [ToolkitManagerHandle::join].
+    pub async fn shutdown(self) -> Vec<(String, ToolkitOutcome)> {
+        self.request_shutdown();
+        self.join().await
+    }
+
+    /// Wait for every Toolkit to stop for good, returning how each one did, by name.
+    pub async fn join(self) -> Vec<(String, ToolkitOutcome)> {
+        let (names, runners): (Vec<_>, Vec<_>) = self.runners.into_iter().unzip();
+        let outcomes = join_all(runners).await;
+
+        names
+            .into_iter()
+            .zip(outcomes)
+            .map(|(name, outcome)| {
+                let outcome = outcome.unwrap_or_else(|_| {
+                    Err(ToolkitManagerError::Panicked {
+                        toolkit: name.clone(),
+                    })
+                });
+                (name, outcome)
+            })
+            .collect()
+    }
+}
+
+/// Runs one Toolkit, starting it again whenever it stops.
+struct Supervisor {
+    name: String,
+    statuses: Arc<RwLock<BTreeMap<String, ToolkitStatus>>>,
+    shutdown: CancellationToken,
+    restart_delay: Duration,
+}
+
+impl Supervisor {
+    async fn run(self, service: ToolkitService) -> ToolkitOutcome {
+        let mut started_once = false;
+
+        loop {
+            let started = tokio::select! {
+                _ = self.shutdown.cancelled() => return Ok(self.stopped(None)),
+                started = service.to_restart().start() => started,
+            };
+            let handle = match started {
+                Ok(handle) => handle,
+                Err(e) if started_once => {
+                    tracing::warn!(
+                        target: targets::TRANSPORT,
+                        event = events::TOOLKIT_RESTARTING,
+                        toolkit = self.name,
+                        error = %e,
+                        "Toolkit failed to start again, retrying"
+                    );
+                    if self.wait_restart_delay().await {
+                        return Ok(self.stopped(None));
+                    }
+                    continue;
+                }
+                Err(e) => return Err(self.failed(e)),
+            };
+            started_once = true;
+            self.set_status(ToolkitStatus::Connected);
+
+            let reason = match self.serve(handle).await {
+                Ok(reason) if reason.should_restart() => reason,
+                Ok(reason) => return Ok(self.stopped(Some(reason))),
+                Err(e) => return Err(e),
+            };
+            tracing::warn!(
+                target: targets::TRANSPORT,
+                event = events::TOOLKIT_RESTARTING,
+                toolkit = self.name,
+                reason = ?reason,
+                "Toolkit stopped, starting it again"
+            );
+            self.set_status(ToolkitStatus::Reconnecting);
+            if self.wait_restart_delay().await {
+                return Ok(self.stopped(Some(reason)));
+            }
+        }
+    }
+
+    /// Wait for the started Toolkit to stop, shutting it down with the others.
+    async fn serve(&self, mut handle: ToolkitHandle) -> ToolkitOutcome {
+        let stopped = tokio::select! {
+            stopped = &mut handle => Some(stopped),
+            _ = self.shutdown.cancelled() => None,
+        };
+        let stopped = match stopped {
+            Some(stopped) => stopped,
+            None => handle.shutdown().await,
+        };
+
+        match stopped {
+            Ok(Ok(reason)) => Ok(reason),
+            Ok(Err(e)) => Err(self.failed(e)),
+            Err(_) => {
+                self.set_status(ToolkitStatus::Failed {
+                    error: "panicked".into(),
+                });
+                Err(ToolkitManagerError::Panicked {
+                    toolkit: self.name.clone(),
+                })
+            }
+        }
+    }
+
+    /// Wait before starting again, returning whether the Toolkits were shut down meanwhile.
+    async fn wait_restart_delay(&self) -> bool {
+        tokio::select! {
+            _ = self.shutdown.cancelled() => true,
+            _ = sleep(self.restart_delay) => false,
+        }
+    }
+
+    fn stopped(&self, reason: Option<ShutdownReason>) -> ShutdownReason {
+        self.set_status(ToolkitStatus::Stopped);
+        reason.unwrap_or(ShutdownReason::GracefulRequested { drained: true })
+    }
+
+    fn failed(&self, e: ToolkitError) -> ToolkitManagerError {
+        tracing::error!(
+            target: targets::TRANSPORT,
+            event = events::TOOLKIT_FAILED,
+            toolkit = self.name,
+            error = %e,
+            "Toolkit failed"
+        );
+        self.set_status(ToolkitStatus::Failed {
+            error: e.to_string().into(),
+        });
+
+        ToolkitManagerError::Failed {
+            toolkit: self.name.clone(),
+            source: Box::new(e),
+        }
+    }
+
+    fn set_status(&self, status: ToolkitStatus) {
+        self.statuses
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(self.name.clone(), status);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::UnifaiConfig,
+        toolkit::testing::{ScriptedServer, Step},
+    };
+    use tokio::time::timeout;
+
+    fn service_at(url: String) -> ToolkitService {
+        ToolkitService::from_config(UnifaiConfig {
+            toolkit_api_key: Some("test".to_string()),
+            backend_ws_endpoint: url,
+            ..UnifaiConfig::default()
+        })
+        .unwrap()
+    }
+
+    async fn wait_for(handle: &ToolkitManagerHandle, name: &str, status: ToolkitStatus) {
+        timeout(Duration::from_secs(5), async {
+            while handle.status(name).as_ref() != Some(&status) {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("{name} is {:?}", handle.status(name)));
+    }
+
+    #[tokio::test]
+    async fn test_runs_toolkits_apart() {
+        let server = ScriptedServer::start(vec![
+            Step::Accept,
+            Step::expect_message("registerActions"),
+            Step::Close,
+            Step::Accept,
+            Step::expect_message("registerActions"),
+            Step::Stall(Duration::MAX),
+        ])
+        .await;
+        let mut manager = ToolkitManager::new().with_restart_delay(Duration::from_millis(10));
+        manager.add_toolkit("healthy", service_at(server.url()));
+        // Nothing listens there.
+        manager.add_toolkit("broken", service_at("ws://127.0.0.1:1".to_string()));
+        let handle = manager.start();
+
+        // The broken Toolkit doesn't stop the healthy one, which is started again once closed.
+        timeout(Duration::from_secs(5), async {
+            while server.received_frames().len() < 2 {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        wait_for(&handle, "healthy", ToolkitStatus::Connected).await;
+        assert!(matches!(
+            handle.status("broken"),
+            Some(ToolkitStatus::Failed { .. })
+        ));
+        assert_eq!(handle.status("missing"), None);
+
+        let outcomes = handle.shutdown().await;
+        assert_eq!(outcomes[0].0, "broken");
+        let Err(error) = &outcomes[0].1 else {
+            panic!("the broken Toolkit stopped");
+        };
+        assert!(
+            error
+                .to_string()
+                .starts_with("ToolkitFailed: toolkit broken failed: "),
+            "{error}"
+        );
+        assert_eq!(outcomes[1].0, "healthy");
+        assert!(matches!(
+            outcomes[1].1,
+            Ok(ShutdownReason::GracefulRequested { .. })
+        ));
+    }
+}
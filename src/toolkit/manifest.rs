@@ -0,0 +1,630 @@
+//! Assemble and run a Toolkit described by a TOML file, see [run_from_config].
+//!
+//! The file names the registered actions to mount, with their settings:
+//!
+//! ```toml
+//! [toolkit]
+//! name = "Echo Slam"
+//! description = "What's in, what's out."
+//! # Environment variables that must be set before starting, for the actions to read.
+//! secrets = ["ECHO_UPSTREAM_API_KEY"]
+//!
+//! # The keys of the file read by UnifaiConfig::from_file, all optional.
+//! [config]
+//! toolkit_api_key = "..."
+//! request_timeout_ms = 15000
+//!
+//! [[actions]]
+//! # The identifier the action was registered with in the ActionRegistry.
+//! id = "echo"
+//! # Fail the calls taking longer than this, with a timeout error. Unlimited by default.
+//! timeout_ms = 5000
+//! # Queue the calls beyond this many at once. Unlimited by default.
+//! max_concurrency = 4
+//! # Answer the calls with the same payload and payment from the last successful result, for this
+//! # long. Off by default, only enable it for actions without side effects.
+//! cache_ttl_ms = 60000
+//!
+//! # Passed to the constructor registered with ActionRegistry::register_with_settings.
+//! [actions.settings]
+//! prefix = ">"
+//! ```
+
+use super::{
+    action::{into_raw_call, ActionDefinition, ActionDyn, PayloadError, RawCallFuture},
+    errors::{Result, ToolkitError},
+    Action, ActionContext, ActionParams, ActionResult, ExecutionMode, ShutdownReason, ToolkitInfo,
+    ToolkitService,
+};
+use crate::config::{ConfigError, ConfigFile, UnifaiConfig};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{value::RawValue, Value};
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs,
+    future::Future,
+    path::Path,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{sync::Semaphore, time::timeout};
+
+type Constructor = Box<
+    dyn Fn(Option<toml::Value>) -> std::result::Result<Box<dyn ActionDyn>, String> + Send + Sync,
+>;
+
+/// The actions a config file can mount, by identifier.
+///
+/// # Example
+/// ```ignore
+/// let mut registry = ActionRegistry::new();
+/// registry.register("echo", || EchoSlam);
+/// registry.register_with_settings("greet", |settings: GreetSettings| Greet::new(settings.prefix));
+///
+/// unifai_sdk::toolkit::run_from_config("toolkit.toml", registry).await?;
+/// ```
+#[derive(Default)]
+pub struct ActionRegistry {
+    constructors: HashMap<String, Constructor>,
+}
+
+impl ActionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register an action taking no settings. A config giving it `settings` is rejected.
+    pub fn register<A: Action + 'static>(
+        &mut self,
+        id: impl Into<String>,
+        constructor: impl Fn() -> A + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.insert(id, move |settings| match settings {
+            None => Ok(Box::new(constructor()) as Box<dyn ActionDyn>),
+            Some(_) => Err("this action takes no settings".to_string()),
+        })
+    }
+
+    /// Register an action built from the `settings` table of its entry in the config, which is
+    /// deserialized as `S`. An entry without settings is deserialized from an empty table.
+    pub fn register_with_settings<S: DeserializeOwned, A: Action + 'static>(
+        &mut self,
+        id: impl Into<String>,
+        constructor: impl Fn(S) -> A + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.insert(id, move |settings| {
+            let settings = settings.unwrap_or_else(|| toml::Value::Table(toml::Table::new()));
+            let settings = S::deserialize(settings).map_err(|e| e.to_string())?;
+
+            Ok(Box::new(constructor(settings)) as Box<dyn ActionDyn>)
+        })
+    }
+
+    fn insert(
+        &mut self,
+        id: impl Into<String>,
+        constructor: impl Fn(Option<toml::Value>) -> std::result::Result<Box<dyn ActionDyn>, String>
+            + Send
+            + Sync
+            + 'static,
+    ) -> &mut Self {
+        self.constructors.insert(id.into(), Box::new(constructor));
+        self
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestFile {
+    toolkit: ToolkitSection,
+    #[serde(default)]
+    config: ConfigFile,
+    #[serde(default)]
+    actions: Vec<ActionEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ToolkitSection {
+    name: String,
+    description: String,
+    #[serde(default)]
+    secrets: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ActionEntry {
+    id: String,
+    timeout_ms: Option<u64>,
+    max_concurrency: Option<usize>,
+    cache_ttl_ms: Option<u64>,
+    settings: Option<toml::Value>,
+}
+
+/// Build the service described by the config file at `path`, with the actions of `registry` it
+/// mounts, and the info to update it with.
+///
+/// Besides the syntax, the config is checked for unknown action identifiers, actions mounted twice,
+/// invalid settings and unset secrets, reported as [ConfigError::Invalid] with the offending key.
+pub fn service_from_config(
+    path: impl AsRef<Path>,
+    registry: &ActionRegistry,
+) -> std::result::Result<(ToolkitService, ToolkitInfo), ConfigError> {
+    let path = path.as_ref();
+    let content = fs::read_to_string(path).map_err(|source| ConfigError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let manifest: ManifestFile = toml::from_str(&content).map_err(|source| ConfigError::Parse {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    build(manifest, registry, |name| {
+        env::var_os(name).is_some_and(|v| !v.is_empty())
+    })
+}
+
+/// Build the service described by the config file at `path`, update its info, and run it until it
+/// stops, see [service_from_config]. Returns why it stopped.
+pub async fn run_from_config(
+    path: impl AsRef<Path>,
+    registry: ActionRegistry,
+) -> Result<ShutdownReason> {
+    let (service, info) = service_from_config(path, &registry)?;
+
+    service.update_info(info).await?;
+
+    match service.start().await?.await {
+        Ok(result) => result,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+fn build(
+    manifest: ManifestFile,
+    registry: &ActionRegistry,
+    is_set: impl Fn(&str) -> bool,
+) -> std::result::Result<(ToolkitService, ToolkitInfo), ConfigError> {
+    let invalid = |key: String, message: String| ConfigError::Invalid { key, message };
+
+    for (i, secret) in manifest.toolkit.secrets.iter().enumerate() {
+        if !is_set(secret) {
+            return Err(invalid(
+                format!("toolkit.secrets[{i}]"),
+                format!("environment variable {secret} is not set"),
+            ));
+        }
+    }
+
+    let config = UnifaiConfig::from_env().merge_file(manifest.config);
+    let service = ToolkitService::from_config(config)?;
+
+    let mut ids = HashSet::new();
+    for (i, entry) in manifest.actions.into_iter().enumerate() {
+        let key = |field: &str| format!("actions[{i}].{field}");
+
+        let Some(constructor) = registry.constructors.get(&entry.id) else {
+            return Err(invalid(
+                key("id"),
+                format!("no action registered as {:?}", entry.id),
+            ));
+        };
+        if !ids.insert(entry.id.clone()) {
+            return Err(invalid(
+                key("id"),
+                format!("{:?} is mounted twice", entry.id),
+            ));
+        }
+
+        let positive = |field: &str, value: Option<u64>| match value {
+            Some(0) => Err(invalid(key(field), "must be above 0".to_string())),
+            value => Ok(value.map(Duration::from_millis)),
+        };
+        let timeout = positive("timeout_ms", entry.timeout_ms)?;
+        let cache_ttl = positive("cache_ttl_ms", entry.cache_ttl_ms)?;
+        let permits = match entry.max_concurrency {
+            Some(0) => {
+                return Err(invalid(
+                    key("max_concurrency"),
+                    "must be above 0".to_string(),
+                ))
+            }
+            max => max.map(Semaphore::new),
+        };
+
+        let action = constructor(entry.settings).map_err(|e| invalid(key("settings"), e))?;
+        let name = action.name();
+        let name = service
+            .checked_name(&name)
+            .map_err(|e| invalid(key("id"), e.to_string()))?;
+        if service.actions().contains(&name) {
+            return Err(invalid(
+                key("id"),
+                format!("action {name:?} is already mounted"),
+            ));
+        }
+
+        let action = ConfiguredAction {
+            action,
+            timeout,
+            permits,
+            cache: cache_ttl.map(|ttl| ResultCache {
+                ttl,
+                entries: Mutex::default(),
+            }),
+        };
+        service
+            .actions_mut()
+            .insert(name, Arc::new(action), ExecutionMode::Spawned);
+    }
+
+    let info = ToolkitInfo {
+        name: manifest.toolkit.name,
+        description: manifest.toolkit.description,
+    };
+
+    Ok((service, info))
+}
+
+/// An action with the settings of its config entry applied around its calls.
+struct ConfiguredAction {
+    action: Box<dyn ActionDyn>,
+    timeout: Option<Duration>,
+    permits: Option<Semaphore>,
+    cache: Option<ResultCache>,
+}
+
+struct ResultCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (Instant, ActionResult<Value>)>>,
+}
+
+impl ResultCache {
+    fn get(&self, key: &str) -> Option<ActionResult<Value>> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (stored, _)| stored.elapsed() < self.ttl);
+        entries.get(key).map(|(_, result)| result.clone())
+    }
+
+    fn insert(&self, key: String, result: ActionResult<Value>) {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(key, (Instant::now(), result));
+    }
+}
+
+impl ActionDyn for ConfiguredAction {
+    fn name(&self) -> String {
+        self.action.name()
+    }
+
+    fn definition(&self) -> Pin<Box<dyn Future<Output = ActionDefinition> + Send + Sync + '_>> {
+        self.action.definition()
+    }
+
+    fn check_payload(&self, payload: &RawValue) -> std::result::Result<(), PayloadError> {
+        self.action.check_payload(payload)
+    }
+
+    fn call(
+        &self,
+        ctx: ActionContext,
+        params: ActionParams<Box<RawValue>>,
+    ) -> Pin<Box<dyn Future<Output = Result<ActionResult<Value>>> + Send + Sync + '_>> {
+        Box::pin(async move {
+            let key = match &self.cache {
+                Some(cache) => {
+                    // Keyed by value, so payloads only differing in their formatting share results.
+                    let payload: Value = serde_json::from_str(params.payload.get())?;
+                    let key = serde_json::to_string(&(payload, params.payment))?;
+                    if let Some(result) = cache.get(&key) {
+                        return Ok(result);
+                    }
+                    Some(key)
+                }
+                None => None,
+            };
+
+            let result = self.limit(self.action.call(ctx, params)).await?;
+
+            if let (Some(cache), Some(key)) = (&self.cache, key) {
+                cache.insert(key, result.clone());
+            }
+
+            Ok(result)
+        })
+    }
+
+    fn call_raw(
+        &self,
+        ctx: ActionContext,
+        params: ActionParams<Box<RawValue>>,
+    ) -> RawCallFuture<'_> {
+        // The cache keeps results as values.
+        if self.cache.is_some() {
+            return into_raw_call(self.call(ctx, params));
+        }
+
+        Box::pin(self.limit(self.action.call_raw(ctx, params)))
+    }
+}
+
+impl ConfiguredAction {
+    /// Apply the concurrency limit and timeout around a call.
+    async fn limit<T>(&self, call: impl Future<Output = Result<T>>) -> Result<T> {
+        let _permit = match &self.permits {
+            Some(permits) => Some(
+                permits
+                    .acquire()
+                    .await
+                    .expect("the semaphore is never closed"),
+            ),
+            None => None,
+        };
+
+        match self.timeout {
+            Some(limit) => timeout(limit, call)
+                .await
+                .map_err(|_| ToolkitError::Timeout {
+                    operation: "action call",
+                    timeout: limit,
+                })?,
+            None => call.await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toolkit::{
+        messages::ActionCallParams,
+        service::{dispatch_action, handle_action_call},
+    };
+    use serde_json::{json, value::to_raw_value};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[derive(Default)]
+    struct Counter {
+        calls: Arc<AtomicUsize>,
+        running: Arc<AtomicUsize>,
+        max_running: Arc<AtomicUsize>,
+        delay: Duration,
+    }
+
+    #[derive(Deserialize)]
+    struct CounterSettings {
+        delay_ms: u64,
+    }
+
+    impl Action for Counter {
+        const NAME: &'static str = "count";
+
+        type Error = std::convert::Infallible;
+        type Args = Value;
+        type Output = usize;
+
+        async fn definition(&self) -> ActionDefinition {
+            ActionDefinition {
+                description: "Count the calls".to_string(),
+                payload: json!({}),
+                payment: None,
+                ..Default::default()
+            }
+        }
+
+        async fn call(
+            &self,
+            _ctx: ActionContext,
+            _params: ActionParams<Self::Args>,
+        ) -> std::result::Result<ActionResult<Self::Output>, Self::Error> {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_running.fetch_max(running, Ordering::SeqCst);
+            tokio::time::sleep(self.delay).await;
+            self.running.fetch_sub(1, Ordering::SeqCst);
+
+            Ok(ActionResult {
+                payload: self.calls.fetch_add(1, Ordering::SeqCst) + 1,
+                payment: None,
+            })
+        }
+    }
+
+    fn manifest(content: &str) -> ManifestFile {
+        toml::from_str(content).unwrap()
+    }
+
+    fn registry(counter: Arc<Counter>) -> ActionRegistry {
+        let mut registry = ActionRegistry::new();
+        registry.register_with_settings("counter", move |settings: CounterSettings| Counter {
+            delay: Duration::from_millis(settings.delay_ms),
+            calls: counter.calls.clone(),
+            running: counter.running.clone(),
+            max_running: counter.max_running.clone(),
+        });
+        registry
+    }
+
+    fn invalid_key(
+        result: std::result::Result<(ToolkitService, ToolkitInfo), ConfigError>,
+    ) -> String {
+        match result {
+            Err(ConfigError::Invalid { key, .. }) => key,
+            Err(e) => panic!("unexpected error: {e}"),
+            Ok(_) => panic!("the config was accepted"),
+        }
+    }
+
+    async fn call(service: &ToolkitService) -> Result<ActionResult<Value>> {
+        let params = ActionCallParams {
+            action: "count".to_string(),
+            action_id: 1,
+            agent_id: 1,
+            payload: to_raw_value(&json!({ "n": 1 })).unwrap(),
+            payment: None,
+            trace_context: None,
+            extra: Default::default(),
+        };
+
+        dispatch_action(service, params).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_builds_service() {
+        let counter = Arc::new(Counter::default());
+        let (service, info) = build(
+            manifest(
+                r#"
+                [toolkit]
+                name = "Counter"
+                description = "Counts"
+                secrets = ["COUNTER_KEY"]
+
+                [config]
+                toolkit_api_key = "key"
+                call_timeout_ms = 1000
+
+                [[actions]]
+                id = "counter"
+                max_concurrency = 2
+                cache_ttl_ms = 60000
+
+                [actions.settings]
+                delay_ms = 50
+                "#,
+            ),
+            &registry(counter.clone()),
+            |name| name == "COUNTER_KEY",
+        )
+        .unwrap();
+
+        assert_eq!(info.name, "Counter");
+        assert_eq!(service.config().call_timeout, Duration::from_secs(1));
+        assert_eq!(service.actions().len(), 1);
+
+        // Identical calls are answered from the cache.
+        assert_eq!(call(&service).await.unwrap().payload, 1);
+        assert_eq!(call(&service).await.unwrap().payload, 1);
+        assert_eq!(counter.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_limits_concurrency_and_time() {
+        let counter = Arc::new(Counter::default());
+        let (service, _) = build(
+            manifest(
+                r#"
+                toolkit = { name = "Counter", description = "Counts" }
+                config = { toolkit_api_key = "key" }
+
+                [[actions]]
+                id = "counter"
+                max_concurrency = 2
+                timeout_ms = 1000
+                settings = { delay_ms = 20 }
+                "#,
+            ),
+            &registry(counter.clone()),
+            |_| true,
+        )
+        .unwrap();
+
+        let results = futures_util::future::join_all((0..6).map(|_| call(&service))).await;
+        assert!(results.iter().all(|result| result.is_ok()));
+        assert_eq!(counter.max_running.load(Ordering::SeqCst), 2);
+
+        let (service, _) = build(
+            manifest(
+                r#"
+                toolkit = { name = "Counter", description = "Counts" }
+                config = { toolkit_api_key = "key" }
+
+                [[actions]]
+                id = "counter"
+                timeout_ms = 10
+                settings = { delay_ms = 1000 }
+                "#,
+            ),
+            &registry(counter),
+            |_| true,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            call(&service).await,
+            Err(ToolkitError::Timeout {
+                operation: "action call",
+                ..
+            })
+        ));
+
+        // Results serialized for the wire go through the same limits.
+        let params = ActionCallParams {
+            action: "count".to_string(),
+            action_id: 2,
+            agent_id: 1,
+            payload: to_raw_value(&json!({ "n": 1 })).unwrap(),
+            payment: None,
+            trace_context: None,
+            extra: Default::default(),
+        };
+        let result = handle_action_call(Arc::new(service), params, Default::default())
+            .await
+            .unwrap();
+        assert!(result.payload.get().contains("action call timed out"));
+    }
+
+    #[test]
+    fn test_errors_point_at_key() {
+        let registry = registry(Arc::default());
+        let build = |content: &str| build(manifest(content), &registry, |_| false);
+        let header = "toolkit = { name = \"n\", description = \"d\" }\nconfig = { toolkit_api_key = \"key\" }\n";
+
+        let key = invalid_key(build(&format!(
+            "{header}[[actions]]\nid = \"counter\"\nsettings = {{ delay_ms = 1 }}\n[[actions]]\nid = \"missing\"\n"
+        )));
+        assert_eq!(key, "actions[1].id");
+
+        let key = invalid_key(build(&format!(
+            "{header}[[actions]]\nid = \"counter\"\nsettings = {{ delay_ms = \"soon\" }}\n"
+        )));
+        assert_eq!(key, "actions[0].settings");
+
+        let key = invalid_key(build(&format!(
+            "{header}[[actions]]\nid = \"counter\"\nmax_concurrency = 0\nsettings = {{ delay_ms = 1 }}\n"
+        )));
+        assert_eq!(key, "actions[0].max_concurrency");
+
+        let key = invalid_key(build(
+            "[toolkit]\nname = \"n\"\ndescription = \"d\"\nsecrets = [\"A\"]\n",
+        ));
+        assert_eq!(key, "toolkit.secrets[0]");
+    }
+
+    #[test]
+    fn test_service_from_config_reports_syntax_errors() {
+        let path = env::temp_dir().join(format!("unifai-toolkit-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            "[toolkit]\nname = \"n\"\ndescription = \"d\"\ntimeout = 1\n",
+        )
+        .unwrap();
+
+        let result = service_from_config(&path, &ActionRegistry::new());
+        fs::remove_file(&path).unwrap();
+
+        match result {
+            Err(ConfigError::Parse { source, .. }) => {
+                assert!(source.to_string().contains("timeout"))
+            }
+            _ => panic!("the config was accepted"),
+        }
+    }
+}
@@ -0,0 +1,867 @@
+use super::{METHOD_NOT_FOUND, PROTOCOL_VERSION};
+use crate::{
+    config::ClientOptions,
+    constants::SDK_VERSION,
+    http::shared_client,
+    logging::{events, targets},
+    toolkit::{
+        action::{parse_payload, ActionDefinition, ActionDyn, ActionParams, ActionResult},
+        ActionContext, InvalidActionName, ToolkitError,
+    },
+};
+use reqwest::{header::ACCEPT, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, value::RawValue, Value};
+use std::{
+    collections::HashMap,
+    fmt,
+    future::{ready, Future},
+    pin::Pin,
+    process::Stdio,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    process::{Child, Command},
+    spawn,
+    sync::{
+        mpsc::{unbounded_channel, UnboundedSender},
+        oneshot,
+    },
+    time::timeout,
+};
+
+/// Default timeout of the requests to an MCP server, see [McpClient::with_request_timeout].
+pub const DEFAULT_MCP_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// An error talking to an MCP server.
+#[derive(Debug, thiserror::Error)]
+pub enum McpError {
+    #[error("InvalidUrl: {0}")]
+    InvalidUrl(String),
+
+    #[error("IoError: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("HttpError: {0}")]
+    HttpError(#[from] reqwest::Error),
+
+    #[error("ProtocolError: {0}")]
+    ProtocolError(String),
+
+    /// The server exited or closed the connection. It is restarted on the next request.
+    #[error("Closed: the MCP server closed the connection")]
+    Closed,
+
+    #[error("TimeoutError: {method} timed out after {timeout:?}")]
+    Timeout { method: String, timeout: Duration },
+
+    /// The server answered with a JSON-RPC error.
+    #[error("RpcError: {message} ({code})")]
+    RpcError { code: i64, message: String },
+
+    /// The tool reported a failure, with the text of its content blocks.
+    #[error("ToolError: {0}")]
+    ToolError(String),
+
+    /// A tool can't be mounted under its name, see
+    /// [ToolkitService::mount_mcp](crate::toolkit::ToolkitService::mount_mcp).
+    #[error(transparent)]
+    InvalidActionName(#[from] InvalidActionName),
+}
+
+/// A tool advertised by an MCP server.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct McpTool {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub input_schema: Value,
+}
+
+/// A client of an MCP server, started as a child process or reached over HTTP with Server-Sent
+/// Events.
+///
+/// The server is connected to on the first request. If it exits or closes the connection, the
+/// pending requests fail with [McpError::Closed] and it is started again on the next request;
+/// failed tool calls are not retried, since they may have had side effects.
+///
+/// Clones share the connection.
+#[derive(Clone)]
+pub struct McpClient {
+    inner: Arc<Inner>,
+    request_timeout: Duration,
+}
+
+struct Inner {
+    transport: Transport,
+    connection: tokio::sync::Mutex<Option<Arc<Connection>>>,
+    next_id: AtomicU64,
+    starts: AtomicU64,
+}
+
+enum Transport {
+    Stdio(Mutex<Command>),
+    Sse {
+        client: reqwest::Client,
+        url: Url,
+    },
+    #[cfg(test)]
+    Io(Box<dyn Fn() -> (tokio::io::DuplexStream, tokio::io::DuplexStream) + Send + Sync>),
+}
+
+impl McpClient {
+    /// Talk to the server started by `command` over its stdin and stdout.
+    ///
+    /// Its stderr is inherited, and it is killed when the client and its clones are dropped.
+    pub fn stdio(mut command: Command) -> Self {
+        command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true);
+
+        Self::new(Transport::Stdio(Mutex::new(command)))
+    }
+
+    /// Talk to the server whose event stream is at `url`, such as `http://127.0.0.1:8000/sse`.
+    pub fn sse(url: &str) -> Result<Self, McpError> {
+        let url = Url::parse(url).map_err(|e| McpError::InvalidUrl(e.to_string()))?;
+        // The event stream stays open, it must not be cut by the default request timeout.
+        let client = shared_client(&ClientOptions {
+            timeout: None,
+            ..ClientOptions::default()
+        })?;
+
+        Ok(Self::new(Transport::Sse { client, url }))
+    }
+
+    fn new(transport: Transport) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                transport,
+                connection: tokio::sync::Mutex::new(None),
+                next_id: AtomicU64::new(1),
+                starts: AtomicU64::new(0),
+            }),
+            request_timeout: DEFAULT_MCP_REQUEST_TIMEOUT,
+        }
+    }
+
+    /// Set the timeout of each request, including tool calls.
+    pub fn with_request_timeout(mut self, request_timeout: Duration) -> Self {
+        self.request_timeout = request_timeout;
+        self
+    }
+
+    /// List the tools of the server.
+    pub async fn list_tools(&self) -> Result<Vec<McpTool>, McpError> {
+        let mut tools = Vec::new();
+        let mut cursor = None;
+
+        loop {
+            let params = match cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+            let mut result = self.request("tools/list", params).await?;
+
+            let page: Vec<McpTool> = serde_json::from_value(result["tools"].take())
+                .map_err(|e| McpError::ProtocolError(e.to_string()))?;
+            tools.extend(page);
+
+            cursor = result["nextCursor"].as_str().map(str::to_string);
+            if cursor.is_none() {
+                return Ok(tools);
+            }
+        }
+    }
+
+    /// Call a tool, returning its content blocks.
+    ///
+    /// A result flagged with `isError` is returned as [McpError::ToolError].
+    pub async fn call_tool(&self, name: &str, arguments: Value) -> Result<Value, McpError> {
+        let mut result = self
+            .request(
+                "tools/call",
+                json!({ "name": name, "arguments": arguments }),
+            )
+            .await?;
+        let content = result["content"].take();
+
+        if result["isError"] == true {
+            let text = content
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|block| block["text"].as_str())
+                .collect::<Vec<_>>()
+                .join("\n");
+            return Err(McpError::ToolError(text));
+        }
+
+        Ok(content)
+    }
+
+    async fn request(&self, method: &str, params: Value) -> Result<Value, McpError> {
+        let connection = self.connection().await?;
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+
+        connection
+            .request(id, method, params, self.request_timeout)
+            .await
+    }
+
+    /// The open connection, (re)starting the server if there is none.
+    async fn connection(&self) -> Result<Arc<Connection>, McpError> {
+        let mut current = self.inner.connection.lock().await;
+        if let Some(connection) = current.as_ref().filter(|c| !c.is_closed()) {
+            return Ok(connection.clone());
+        }
+
+        let connection = Arc::new(self.connect().await?);
+        *current = Some(connection.clone());
+
+        Ok(connection)
+    }
+
+    async fn connect(&self) -> Result<Connection, McpError> {
+        let connection = match &self.inner.transport {
+            Transport::Stdio(command) => {
+                let mut child = command.lock().unwrap().spawn()?;
+                let stdin = child.stdin.take().unwrap();
+                let stdout = child.stdout.take().unwrap();
+                Connection::over_lines(stdout, stdin, Some(child))
+            }
+            Transport::Sse { client, url } => Connection::over_sse(client, url).await?,
+            #[cfg(test)]
+            Transport::Io(connect) => {
+                let (reader, writer) = connect();
+                Connection::over_lines(reader, writer, None)
+            }
+        };
+
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        let params = json!({
+            "protocolVersion": PROTOCOL_VERSION,
+            "capabilities": {},
+            "clientInfo": { "name": "unifai-sdk", "version": SDK_VERSION },
+        });
+        connection
+            .request(id, "initialize", params, self.request_timeout)
+            .await?;
+        connection.notify("notifications/initialized");
+
+        let count = self.inner.starts.fetch_add(1, Ordering::Relaxed) + 1;
+        tracing::info!(
+            target: targets::TRANSPORT,
+            event = events::MCP_SERVER_STARTED,
+            count,
+            "MCP server started"
+        );
+
+        Ok(connection)
+    }
+}
+
+impl fmt::Debug for McpClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let transport = match &self.inner.transport {
+            Transport::Stdio(command) => format!("{:?}", command.lock().unwrap().as_std()),
+            Transport::Sse { url, .. } => url.to_string(),
+            #[cfg(test)]
+            Transport::Io(_) => "io".to_string(),
+        };
+
+        f.debug_struct("McpClient")
+            .field("transport", &transport)
+            .field("request_timeout", &self.request_timeout)
+            .finish()
+    }
+}
+
+#[derive(Default)]
+struct State {
+    pending: HashMap<u64, oneshot::Sender<Result<Value, McpError>>>,
+    closed: bool,
+}
+
+impl State {
+    /// Mark the connection closed, failing the pending requests.
+    fn close(state: &Mutex<State>) {
+        let mut state = state.lock().unwrap();
+        state.closed = true;
+        state.pending.clear();
+    }
+}
+
+struct Connection {
+    outgoing: UnboundedSender<String>,
+    state: Arc<Mutex<State>>,
+    _child: Option<Child>,
+}
+
+impl Connection {
+    /// Exchange newline-delimited messages, as over the stdio of a child process.
+    fn over_lines(
+        reader: impl AsyncRead + Unpin + Send + 'static,
+        mut writer: impl AsyncWrite + Unpin + Send + 'static,
+        child: Option<Child>,
+    ) -> Self {
+        let (outgoing, mut rx) = unbounded_channel::<String>();
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let (writer_state, reader_state) = (state.clone(), state.clone());
+        spawn(async move {
+            while let Some(mut message) = rx.recv().await {
+                message.push('\n');
+                if writer.write_all(message.as_bytes()).await.is_err()
+                    || writer.flush().await.is_err()
+                {
+                    break;
+                }
+            }
+            State::close(&writer_state);
+        });
+
+        let replies = outgoing.clone();
+        spawn(async move {
+            let mut lines = BufReader::new(reader).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                receive(&reader_state, &replies, &line);
+            }
+            State::close(&reader_state);
+        });
+
+        Self {
+            outgoing,
+            state,
+            _child: child,
+        }
+    }
+
+    /// Open the event stream at `url`, and post messages to the endpoint it announces.
+    async fn over_sse(client: &reqwest::Client, url: &Url) -> Result<Self, McpError> {
+        let mut response = client
+            .get(url.clone())
+            .header(ACCEPT, "text/event-stream")
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let mut parser = SseParser::default();
+        let mut received = Vec::new();
+        let endpoint = loop {
+            let chunk = response.chunk().await?.ok_or(McpError::Closed)?;
+            received.extend(parser.feed(&chunk));
+            if let Some(i) = received.iter().position(|(event, _)| event == "endpoint") {
+                let (_, endpoint) = received.remove(i);
+                break url
+                    .join(&endpoint)
+                    .map_err(|e| McpError::InvalidUrl(e.to_string()))?;
+            }
+        };
+
+        let (outgoing, mut rx) = unbounded_channel::<String>();
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let (writer_state, reader_state) = (state.clone(), state.clone());
+        let client = client.clone();
+        spawn(async move {
+            while let Some(message) = rx.recv().await {
+                let sent = client.post(endpoint.clone()).body(message).send().await;
+                if !sent.is_ok_and(|response| response.status().is_success()) {
+                    break;
+                }
+            }
+            State::close(&writer_state);
+        });
+
+        let replies = outgoing.clone();
+        spawn(async move {
+            let mut received = received;
+            loop {
+                for (event, data) in received.drain(..) {
+                    if event == "message" {
+                        receive(&reader_state, &replies, &data);
+                    }
+                }
+                match response.chunk().await {
+                    Ok(Some(chunk)) => received = parser.feed(&chunk),
+                    _ => break,
+                }
+            }
+            State::close(&reader_state);
+        });
+
+        Ok(Self {
+            outgoing,
+            state,
+            _child: None,
+        })
+    }
+
+    fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().closed || self.outgoing.is_closed()
+    }
+
+    async fn request(
+        &self,
+        id: u64,
+        method: &str,
+        params: Value,
+        request_timeout: Duration,
+    ) -> Result<Value, McpError> {
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.closed {
+                return Err(McpError::Closed);
+            }
+            state.pending.insert(id, tx);
+        }
+
+        let message = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        if self.outgoing.send(message.to_string()).is_err() {
+            self.state.lock().unwrap().pending.remove(&id);
+            return Err(McpError::Closed);
+        }
+
+        match timeout(request_timeout, rx).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(McpError::Closed),
+            Err(_) => {
+                self.state.lock().unwrap().pending.remove(&id);
+                Err(McpError::Timeout {
+                    method: method.to_string(),
+                    timeout: request_timeout,
+                })
+            }
+        }
+    }
+
+    fn notify(&self, method: &str) {
+        let message = json!({ "jsonrpc": "2.0", "method": method });
+        let _ = self.outgoing.send(message.to_string());
+    }
+}
+
+/// Handle a message from the server: resolve the pending request it answers, or answer its own
+/// request.
+fn receive(state: &Mutex<State>, replies: &UnboundedSender<String>, message: &str) {
+    let message: Value = match serde_json::from_str(message) {
+        Ok(message) => message,
+        Err(e) => {
+            tracing::warn!(
+                target: targets::TRANSPORT,
+                event = events::UNKNOWN_MESSAGE,
+                error = %e,
+                "Received an invalid message from the MCP server"
+            );
+            return;
+        }
+    };
+
+    if let Some(method) = message["method"].as_str() {
+        // Only requests, which carry an id, are answered. Notifications are ignored.
+        if let Some(id) = message.get("id") {
+            let reply = if method == "ping" {
+                json!({ "jsonrpc": "2.0", "id": id, "result": {} })
+            } else {
+                json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": METHOD_NOT_FOUND, "message": format!("Method not found: {method}") },
+                })
+            };
+            let _ = replies.send(reply.to_string());
+        }
+        return;
+    }
+
+    let Some(tx) = message["id"]
+        .as_u64()
+        .and_then(|id| state.lock().unwrap().pending.remove(&id))
+    else {
+        return;
+    };
+
+    let result = match message.get("error") {
+        Some(error) => Err(McpError::RpcError {
+            code: error["code"].as_i64().unwrap_or_default(),
+            message: error["message"].as_str().unwrap_or_default().to_string(),
+        }),
+        None => Ok(message.get("result").cloned().unwrap_or_default()),
+    };
+    let _ = tx.send(result);
+}
+
+/// Split a stream of Server-Sent Events into `(event, data)` pairs.
+#[derive(Default)]
+struct SseParser {
+    buffer: String,
+}
+
+impl SseParser {
+    fn feed(&mut self, chunk: &[u8]) -> Vec<(String, String)> {
+        self.buffer.push_str(&String::from_utf8_lossy(chunk));
+        if self.buffer.contains('\r') {
+            self.buffer = self.buffer.replace("\r\n", "\n");
+        }
+
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.find("\n\n") {
+            let block: String = self.buffer.drain(..end + 2).collect();
+            let mut event = "message".to_string();
+            let mut data = Vec::new();
+            for line in block.lines() {
+                if let Some(value) = line.strip_prefix("event:") {
+                    event = value.trim().to_string();
+                } else if let Some(value) = line.strip_prefix("data:") {
+                    data.push(value.strip_prefix(' ').unwrap_or(value));
+                }
+            }
+            events.push((event, data.join("\n")));
+        }
+
+        events
+    }
+}
+
+/// A tool of an MCP server, called as an action.
+pub(crate) struct McpAction {
+    client: McpClient,
+    tool: McpTool,
+}
+
+impl McpAction {
+    pub(crate) fn new(client: McpClient, tool: McpTool) -> Self {
+        Self { client, tool }
+    }
+}
+
+impl ActionDyn for McpAction {
+    fn name(&self) -> String {
+        self.tool.name.clone()
+    }
+
+    fn definition(&self) -> Pin<Box<dyn Future<Output = ActionDefinition> + Send + Sync + '_>> {
+        Box::pin(ready(ActionDefinition {
+            description: self.tool.description.clone().unwrap_or_default(),
+            payload: self.tool.input_schema.clone(),
+            payment: None,
+            ..Default::default()
+        }))
+    }
+
+    fn call(
+        &self,
+        _ctx: ActionContext,
+        params: ActionParams<Box<RawValue>>,
+    ) -> Pin<Box<dyn Future<Output = Result<ActionResult<Value>, ToolkitError>> + Send + Sync + '_>>
+    {
+        let (client, name) = (self.client.clone(), self.tool.name.clone());
+
+        Box::pin(async move {
+            let arguments: Value = parse_payload(&params.payload)?;
+
+            // The call runs on its own task, as the HTTP futures of the SSE transport are not Sync.
+            let content = spawn(async move { client.call_tool(&name, arguments).await })
+                .await
+                .map_err(|e| ToolkitError::ActionCallError(Box::new(e)))?
+                .map_err(|e| ToolkitError::ActionCallError(Box::new(e)))?;
+
+            Ok(ActionResult {
+                payload: content,
+                payment: None,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toolkit::{
+        mcp::serve_sse,
+        messages::ActionCallParams,
+        service::{dispatch_action, error_payload},
+        Action, ToolkitService,
+    };
+    use serde_json::value::to_raw_value;
+    use tokio::{
+        io::{duplex, DuplexStream},
+        net::TcpListener,
+    };
+
+    /// Answer like an MCP server with the tools `echo`, `fail`, `crash` and `hang`, the last two
+    /// listed on a second page.
+    async fn fake_server(reader: DuplexStream, mut writer: DuplexStream) {
+        let mut lines = BufReader::new(reader).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let request: Value = serde_json::from_str(&line).unwrap();
+            let Some(id) = request.get("id").cloned() else {
+                continue;
+            };
+
+            let result = match (request["method"].as_str().unwrap(), &request["params"]) {
+                ("initialize", _) => json!({
+                    "protocolVersion": PROTOCOL_VERSION,
+                    "capabilities": { "tools": {} },
+                    "serverInfo": { "name": "fake", "version": "1.0.0" },
+                }),
+                ("tools/list", params) if params["cursor"].is_null() => json!({
+                    "tools": [
+                        {
+                            "name": "echo",
+                            "description": "Echo the text",
+                            "inputSchema": {
+                                "type": "object",
+                                "properties": { "text": { "type": "string" } },
+                                "required": ["text"],
+                            },
+                        },
+                        { "name": "fail", "inputSchema": { "type": "object" } },
+                    ],
+                    "nextCursor": "2",
+                }),
+                ("tools/list", _) => json!({
+                    "tools": [
+                        { "name": "crash", "inputSchema": { "type": "object" } },
+                        { "name": "hang", "inputSchema": { "type": "object" } },
+                    ],
+                }),
+                ("tools/call", params) => match params["name"].as_str().unwrap() {
+                    "echo" => json!({
+                        "content": [{ "type": "text", "text": params["arguments"]["text"] }],
+                    }),
+                    "fail" => json!({
+                        "content": [{ "type": "text", "text": "disk full" }],
+                        "isError": true,
+                    }),
+                    "crash" => return,
+                    _ => continue,
+                },
+                (method, _) => {
+                    let error = json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": { "code": METHOD_NOT_FOUND, "message": method },
+                    });
+                    writer
+                        .write_all(format!("{error}\n").as_bytes())
+                        .await
+                        .unwrap();
+                    continue;
+                }
+            };
+
+            let response = json!({ "jsonrpc": "2.0", "id": id, "result": result });
+            writer
+                .write_all(format!("{response}\n").as_bytes())
+                .await
+                .unwrap();
+        }
+    }
+
+    /// A client of fake servers, counting how many were started.
+    fn fake_client() -> (McpClient, Arc<AtomicU64>) {
+        let started = Arc::new(AtomicU64::new(0));
+        let count = started.clone();
+
+        let client = McpClient::new(Transport::Io(Box::new(move || {
+            count.fetch_add(1, Ordering::SeqCst);
+            let (client_writer, server_reader) = duplex(4096);
+            let (server_writer, client_reader) = duplex(4096);
+            spawn(fake_server(server_reader, server_writer));
+            (client_reader, client_writer)
+        })));
+
+        (client, started)
+    }
+
+    async fn call_action(service: &Arc<ToolkitService>, action: &str, payload: Value) -> Value {
+        let params = ActionCallParams {
+            action: action.to_string(),
+            action_id: 1,
+            agent_id: 1,
+            payload: to_raw_value(&payload).unwrap(),
+            payment: None,
+            trace_context: None,
+            extra: Default::default(),
+        };
+
+        match dispatch_action(service, params).await.unwrap() {
+            Ok(result) => result.payload,
+            Err(e) => error_payload(&e),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mount_mcp_tools() {
+        let (client, _) = fake_client();
+        let mut service = ToolkitService::new("test");
+        service.mount_mcp(client).await.unwrap();
+
+        let entries = service.actions().entries();
+        let mut names: Vec<_> = entries.iter().map(|(name, _)| &**name).collect();
+        names.sort();
+        assert_eq!(names, ["crash", "echo", "fail", "hang"]);
+
+        let echo = service.actions().get("echo").unwrap();
+        let definition = echo.definition().await;
+        assert_eq!(definition.description, "Echo the text");
+        assert_eq!(definition.input_schema()["required"], json!(["text"]));
+
+        let service = Arc::new(service);
+        assert_eq!(
+            call_action(&service, "echo", json!({ "text": "hello" })).await,
+            json!([{ "type": "text", "text": "hello" }])
+        );
+        assert_eq!(
+            call_action(&service, "echo", json!("{\"text\":\"as a string\"}")).await,
+            json!([{ "type": "text", "text": "as a string" }])
+        );
+        assert_eq!(
+            call_action(&service, "fail", json!({})).await,
+            json!({
+                "error": "ActionCallError: ToolError: disk full",
+                "code": "action_error",
+                "message": "ToolError: disk full",
+                "retryable": false,
+                "details": null,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_restarts_crashed_server() {
+        let (client, started) = fake_client();
+
+        let error = client.call_tool("crash", json!({})).await.unwrap_err();
+        assert!(matches!(error, McpError::Closed), "{error:?}");
+        assert_eq!(started.load(Ordering::SeqCst), 1);
+
+        let content = client
+            .call_tool("echo", json!({ "text": "again" }))
+            .await
+            .unwrap();
+        assert_eq!(content[0]["text"], "again");
+        assert_eq!(started.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_error_translation() {
+        let (client, _) = fake_client();
+        let client = client.with_request_timeout(Duration::from_millis(100));
+
+        let error = client.call_tool("hang", json!({})).await.unwrap_err();
+        assert!(
+            matches!(&error, McpError::Timeout { method, .. } if method == "tools/call"),
+            "{error:?}"
+        );
+
+        let error = client
+            .request("resources/list", json!({}))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                error,
+                McpError::RpcError {
+                    code: METHOD_NOT_FOUND,
+                    ..
+                }
+            ),
+            "{error:?}"
+        );
+
+        // A timed out call leaves the server running.
+        assert!(client
+            .call_tool("echo", json!({ "text": "ok" }))
+            .await
+            .is_ok());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_stdio_server_exiting() {
+        let client = McpClient::stdio(Command::new("true"));
+
+        let error = client.list_tools().await.unwrap_err();
+
+        assert!(matches!(error, McpError::Closed), "{error:?}");
+    }
+
+    struct Echo;
+
+    impl Action for Echo {
+        const NAME: &'static str = "echo";
+
+        type Error = std::convert::Infallible;
+        type Args = Value;
+        type Output = Value;
+
+        async fn definition(&self) -> ActionDefinition {
+            ActionDefinition {
+                description: "Echo the payload".to_string(),
+                payload: json!({ "text": { "type": "string", "required": true } }),
+                payment: None,
+                ..Default::default()
+            }
+        }
+
+        async fn call(
+            &self,
+            _ctx: ActionContext,
+            params: ActionParams<Self::Args>,
+        ) -> Result<ActionResult<Self::Output>, Self::Error> {
+            Ok(ActionResult {
+                payload: params.payload,
+                payment: None,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sse_client() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let mut served = ToolkitService::new("test");
+        served.add_action(Echo);
+        spawn(serve_sse(served, addr));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let client = McpClient::sse(&format!("http://{addr}/sse")).unwrap();
+        let tools = client.list_tools().await.unwrap();
+        assert_eq!(tools.len(), 1);
+        assert_eq!(tools[0].input_schema["required"], json!(["text"]));
+
+        let content = client
+            .call_tool("echo", json!({ "text": "over sse" }))
+            .await
+            .unwrap();
+        assert_eq!(content[0]["text"], "{\"text\":\"over sse\"}");
+    }
+
+    #[test]
+    fn test_sse_parser() {
+        let mut parser = SseParser::default();
+
+        assert!(parser.feed(b"event: endpoint\r\ndata: /message").is_empty());
+        assert_eq!(
+            parser.feed(b"?sessionId=1\r\n\r\ndata: {}\n\n"),
+            [
+                ("endpoint".to_string(), "/message?sessionId=1".to_string()),
+                ("message".to_string(), "{}".to_string()),
+            ]
+        );
+    }
+}
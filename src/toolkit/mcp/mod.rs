@@ -0,0 +1,48 @@
+//! Bridge Toolkits and the [Model Context Protocol](https://modelcontextprotocol.io), both ways.
+//!
+//! # Serving actions to MCP clients
+//!
+//! [serve_stdio] and [serve_sse] advertise each action of a Toolkit service as an MCP tool, with its
+//! payload definition as the input schema, so MCP clients such as Claude Desktop can call it like
+//! the Unifai agents do. The service does not connect to Unifai: actions are called with a context
+//! whose `agent_id` is `0` and whose `action_id` counts the calls of the bridge.
+//!
+//! Results carrying an `error`, including the ones of failed actions, are reported as MCP tool
+//! errors.
+//!
+//! ```ignore
+//! let mut service = ToolkitService::new("UNIFAI_TOOLKIT_API_KEY");
+//! service.add_action(EchoSlam);
+//!
+//! unifai_sdk::toolkit::mcp::serve_stdio(service).await.unwrap();
+//! ```
+//!
+//! # Publishing the tools of an MCP server
+//!
+//! An [McpClient] connects to an MCP server, and [ToolkitService::mount_mcp](super::ToolkitService::mount_mcp)
+//! adds each of its tools as an action, whose payload definition is the tool's input schema. Calls
+//! are forwarded to the server and return its content blocks as the result payload.
+//!
+//! ```ignore
+//! let mut command = tokio::process::Command::new("npx");
+//! command.args(["-y", "@modelcontextprotocol/server-filesystem", "/srv/files"]);
+//!
+//! let mut service = ToolkitService::new("UNIFAI_TOOLKIT_API_KEY");
+//! service.mount_mcp(McpClient::stdio(command)).await.unwrap();
+//! ```
+
+mod client;
+pub use client::*;
+
+mod server;
+pub use server::*;
+
+/// The latest protocol version supported, used by the client and answered by the server to clients
+/// requesting an unknown one.
+const PROTOCOL_VERSION: &str = "2024-11-05";
+const SUPPORTED_PROTOCOL_VERSIONS: &[&str] = &["2024-11-05", "2025-03-26"];
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
@@ -0,0 +1,570 @@
+use super::{
+    INVALID_PARAMS, INVALID_REQUEST, METHOD_NOT_FOUND, PARSE_ERROR, PROTOCOL_VERSION,
+    SUPPORTED_PROTOCOL_VERSIONS,
+};
+use crate::{
+    constants::SDK_VERSION,
+    toolkit::{
+        errors::{Result, ToolkitError},
+        messages::ActionCallParams,
+        service::{dispatch_action, error_payload, ToolkitService},
+    },
+};
+use bytes::Bytes;
+use futures_util::{future::join_all, stream};
+use http_body_util::{combinators::BoxBody, BodyExt, Full, StreamBody};
+use hyper::{
+    body::{Frame, Incoming},
+    header, Method, Request, Response, StatusCode,
+};
+use hyper_util::rt::TokioIo;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, value::RawValue, Value};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    hash::{BuildHasher, RandomState},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpListener, ToSocketAddrs},
+    spawn,
+    sync::mpsc::{unbounded_channel, UnboundedSender},
+};
+
+/// Serve the actions over stdin and stdout, until stdin is closed.
+///
+/// This is the transport MCP clients use for local servers. Nothing else must be written to
+/// stdout, log to stderr instead.
+pub async fn serve_stdio(service: ToolkitService) -> Result<()> {
+    serve(service, tokio::io::stdin(), tokio::io::stdout()).await
+}
+
+/// Serve the actions over newline-delimited JSON-RPC messages, until `reader` is closed.
+///
+/// Requests are handled concurrently, responses are written as they complete.
+pub async fn serve<R, W>(service: ToolkitService, reader: R, mut writer: W) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let bridge = Arc::new(Bridge::new(service));
+    let (tx, mut rx) = unbounded_channel::<String>();
+
+    let write_loop = spawn(async move {
+        while let Some(mut response) = rx.recv().await {
+            response.push('\n');
+            writer.write_all(response.as_bytes()).await?;
+            writer.flush().await?;
+        }
+        Ok::<_, std::io::Error>(())
+    });
+
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (bridge, tx) = (bridge.clone(), tx.clone());
+        spawn(async move {
+            if let Some(response) = bridge.handle(&line).await {
+                let _ = tx.send(response);
+            }
+        });
+    }
+    drop(tx);
+
+    write_loop
+        .await
+        .map_err(|e| ToolkitError::IoError(std::io::Error::other(e)))??;
+
+    Ok(())
+}
+
+/// Serve the actions over the HTTP with Server-Sent Events transport, until the task is dropped.
+///
+/// Clients open the event stream with `GET /sse`, which announces the endpoint to `POST` their
+/// messages to, and receive the responses on the stream. The server is not authenticated: bind it
+/// to a loopback address.
+pub async fn serve_sse(service: ToolkitService, addr: impl ToSocketAddrs) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    let bridge = Arc::new(Bridge::new(service));
+    let sessions = Sessions::default();
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let (bridge, sessions) = (bridge.clone(), sessions.clone());
+        spawn(async move {
+            let service = hyper::service::service_fn(move |request| {
+                handle_http(bridge.clone(), sessions.clone(), request)
+            });
+            let _ = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+        });
+    }
+}
+
+type Body = BoxBody<Bytes, Infallible>;
+type Sessions = Arc<Mutex<HashMap<String, UnboundedSender<Bytes>>>>;
+
+async fn handle_http(
+    bridge: Arc<Bridge>,
+    sessions: Sessions,
+    request: Request<Incoming>,
+) -> std::result::Result<Response<Body>, Infallible> {
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/sse") => {
+            let session_id = format!("{:016x}", RandomState::new().hash_one(SDK_VERSION));
+            let (tx, rx) = unbounded_channel();
+            let _ = tx.send(sse_event(
+                "endpoint",
+                &format!("/message?sessionId={session_id}"),
+            ));
+            sessions.lock().unwrap().insert(session_id, tx);
+
+            let events = stream::unfold(rx, |mut rx| async move {
+                let event = rx.recv().await?;
+                Some((Ok(Frame::data(event)), rx))
+            });
+
+            Ok(Response::builder()
+                .header(header::CONTENT_TYPE, "text/event-stream")
+                .header(header::CACHE_CONTROL, "no-cache")
+                .body(StreamBody::new(events).boxed())
+                .unwrap())
+        }
+        (&Method::POST, "/message") => {
+            let session_id = request
+                .uri()
+                .query()
+                .unwrap_or_default()
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("sessionId="))
+                .unwrap_or_default()
+                .to_string();
+            let Some(tx) = sessions.lock().unwrap().get(&session_id).cloned() else {
+                return Ok(status(StatusCode::NOT_FOUND));
+            };
+            let Ok(body) = request.into_body().collect().await else {
+                return Ok(status(StatusCode::BAD_REQUEST));
+            };
+            let Ok(message) = String::from_utf8(body.to_bytes().to_vec()) else {
+                return Ok(status(StatusCode::BAD_REQUEST));
+            };
+
+            spawn(async move {
+                if let Some(response) = bridge.handle(&message).await {
+                    if tx.send(sse_event("message", &response)).is_err() {
+                        sessions.lock().unwrap().remove(&session_id);
+                    }
+                }
+            });
+
+            Ok(status(StatusCode::ACCEPTED))
+        }
+        _ => Ok(status(StatusCode::NOT_FOUND)),
+    }
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Full::new(Bytes::new()).boxed())
+        .unwrap()
+}
+
+fn sse_event(event: &str, data: &str) -> Bytes {
+    Bytes::from(format!("event: {event}\ndata: {data}\n\n"))
+}
+
+#[derive(Deserialize)]
+struct JsonRpcRequest {
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Serialize)]
+struct JsonRpcResponse {
+    jsonrpc: &'static str,
+    id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<JsonRpcError>,
+}
+
+#[derive(Serialize)]
+struct JsonRpcError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct ToolCallParams {
+    name: String,
+    #[serde(default)]
+    arguments: Box<RawValue>,
+}
+
+struct Bridge {
+    service: Arc<ToolkitService>,
+    next_action_id: AtomicU64,
+}
+
+impl Bridge {
+    fn new(service: ToolkitService) -> Self {
+        Self {
+            service: Arc::new(service),
+            next_action_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Handle a JSON-RPC message, returning the response unless it was a notification.
+    async fn handle(&self, message: &str) -> Option<String> {
+        let response = match serde_json::from_str::<Value>(message) {
+            Err(e) => error_response(Value::Null, PARSE_ERROR, e.to_string()),
+            Ok(message) => match serde_json::from_value::<JsonRpcRequest>(message) {
+                Err(e) => error_response(Value::Null, INVALID_REQUEST, e.to_string()),
+                Ok(request) => {
+                    let id = request.id?;
+                    match self.dispatch(&request.method, request.params).await {
+                        Ok(result) => JsonRpcResponse {
+                            jsonrpc: "2.0",
+                            id,
+                            result: Some(result),
+                            error: None,
+                        },
+                        Err((code, message)) => error_response(id, code, message),
+                    }
+                }
+            },
+        };
+
+        serde_json::to_string(&response).ok()
+    }
+
+    async fn dispatch(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> std::result::Result<Value, (i64, String)> {
+        match method {
+            "initialize" => {
+                let requested = params["protocolVersion"].as_str().unwrap_or_default();
+                let version = SUPPORTED_PROTOCOL_VERSIONS
+                    .iter()
+                    .find(|version| **version == requested)
+                    .unwrap_or(&PROTOCOL_VERSION);
+
+                Ok(json!({
+                    "protocolVersion": version,
+                    "capabilities": { "tools": {} },
+                    "serverInfo": { "name": "unifai-sdk", "version": SDK_VERSION },
+                }))
+            }
+            "ping" => Ok(json!({})),
+            "tools/list" => {
+                let actions = self.service.actions().entries();
+                let tools = join_all(actions.into_iter().map(|(name, action)| async move {
+                    let definition = action.definition().await;
+                    json!({
+                        "name": &*name,
+                        "description": definition.description,
+                        "inputSchema": definition.input_schema(),
+                    })
+                }))
+                .await;
+
+                Ok(json!({ "tools": tools }))
+            }
+            "tools/call" => {
+                let params: ToolCallParams =
+                    serde_json::from_value(params).map_err(|e| (INVALID_PARAMS, e.to_string()))?;
+                if !self.service.actions().contains(&params.name) {
+                    return Err((INVALID_PARAMS, format!("Unknown tool: {}", params.name)));
+                }
+
+                let call = ActionCallParams {
+                    action: params.name,
+                    action_id: self.next_action_id.fetch_add(1, Ordering::Relaxed),
+                    agent_id: 0,
+                    payload: params.arguments,
+                    payment: None,
+                    trace_context: None,
+                    extra: Default::default(),
+                };
+                let payload = match dispatch_action(&self.service, call).await {
+                    Some(Ok(result)) => result.payload,
+                    Some(Err(e)) => error_payload(&e),
+                    None => return Err((INVALID_PARAMS, "Unknown tool".to_string())),
+                };
+
+                Ok(tool_result(payload))
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("Method not found: {method}"))),
+        }
+    }
+}
+
+/// Wrap an action result as MCP tool content, flagging results carrying an `error`.
+fn tool_result(payload: Value) -> Value {
+    let is_error = payload.get("error").is_some();
+    let text = match payload {
+        Value::String(text) => text,
+        payload => payload.to_string(),
+    };
+
+    json!({
+        "content": [{ "type": "text", "text": text }],
+        "isError": is_error,
+    })
+}
+
+fn error_response(id: Value, code: i64, message: String) -> JsonRpcResponse {
+    JsonRpcResponse {
+        jsonrpc: "2.0",
+        id,
+        result: None,
+        error: Some(JsonRpcError { code, message }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toolkit::{Action, ActionContext, ActionDefinition, ActionParams, ActionResult};
+    use tokio::io::{duplex, AsyncBufReadExt, DuplexStream, Lines};
+
+    struct Echo;
+
+    #[derive(Deserialize)]
+    struct EchoArgs {
+        content: String,
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("echo refused {0:?}")]
+    struct EchoError(String);
+
+    impl Action for Echo {
+        const NAME: &'static str = "echo";
+
+        type Error = EchoError;
+        type Args = EchoArgs;
+        type Output = String;
+
+        async fn definition(&self) -> ActionDefinition {
+            ActionDefinition {
+                description: "Echo the message".to_string(),
+                payload: json!({
+                    "content": {
+                        "type": "string",
+                        "description": "The content to echo.",
+                        "required": true
+                    }
+                }),
+                payment: None,
+                ..Default::default()
+            }
+        }
+
+        async fn call(
+            &self,
+            ctx: ActionContext,
+            params: ActionParams<Self::Args>,
+        ) -> std::result::Result<ActionResult<Self::Output>, Self::Error> {
+            if params.payload.content == "fail" {
+                return Err(EchoError(params.payload.content));
+            }
+
+            Ok(ActionResult {
+                payload: format!("{} #{}", params.payload.content, ctx.action_id),
+                payment: None,
+            })
+        }
+    }
+
+    struct Client {
+        writer: DuplexStream,
+        lines: Lines<BufReader<DuplexStream>>,
+    }
+
+    impl Client {
+        async fn request(&mut self, request: Value) -> Value {
+            self.writer
+                .write_all(format!("{request}\n").as_bytes())
+                .await
+                .unwrap();
+            let line = self.lines.next_line().await.unwrap().unwrap();
+            serde_json::from_str(&line).unwrap()
+        }
+    }
+
+    fn start() -> Client {
+        let (writer, server_reader) = duplex(4096);
+        let (server_writer, reader) = duplex(4096);
+
+        let mut service = ToolkitService::new("test");
+        service.add_action(Echo);
+        spawn(serve(service, server_reader, server_writer));
+
+        Client {
+            writer,
+            lines: BufReader::new(reader).lines(),
+        }
+    }
+
+    fn call(id: u64, arguments: Value) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "tools/call",
+            "params": { "name": "echo", "arguments": arguments },
+        })
+    }
+
+    #[tokio::test]
+    async fn test_initialize() {
+        let mut client = start();
+
+        let response = client
+            .request(json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "initialize",
+                "params": { "protocolVersion": "2024-11-05", "capabilities": {} },
+            }))
+            .await;
+        assert_eq!(response["id"], 1);
+        assert_eq!(response["result"]["protocolVersion"], "2024-11-05");
+        assert!(response["result"]["capabilities"]["tools"].is_object());
+
+        let response = client
+            .request(json!({
+                "jsonrpc": "2.0",
+                "id": "next",
+                "method": "initialize",
+                "params": { "protocolVersion": "1999-01-01" },
+            }))
+            .await;
+        assert_eq!(response["id"], "next");
+        assert_eq!(response["result"]["protocolVersion"], PROTOCOL_VERSION);
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let mut client = start();
+
+        client
+            .writer
+            .write_all(b"{\"jsonrpc\":\"2.0\",\"method\":\"notifications/initialized\"}\n")
+            .await
+            .unwrap();
+
+        let response = client
+            .request(json!({ "jsonrpc": "2.0", "id": 1, "method": "tools/list" }))
+            .await;
+        assert_eq!(
+            response["result"]["tools"],
+            json!([{
+                "name": "echo",
+                "description": "Echo the message",
+                "inputSchema": {
+                    "type": "object",
+                    "properties": {
+                        "content": { "type": "string", "description": "The content to echo." }
+                    },
+                    "required": ["content"],
+                },
+            }])
+        );
+
+        let response = client.request(call(2, json!({ "content": "hello" }))).await;
+        assert_eq!(response["id"], 2);
+        assert_eq!(
+            response["result"],
+            json!({ "content": [{ "type": "text", "text": "hello #1" }], "isError": false })
+        );
+
+        let response = client.request(call(3, json!({ "content": "fail" }))).await;
+        assert_eq!(response["result"]["isError"], true);
+        let text = response["result"]["content"][0]["text"].as_str().unwrap();
+        assert!(text.contains("echo refused \\\"fail\\\""), "{text}");
+
+        let response = client.request(call(4, json!({ "other": 1 }))).await;
+        assert_eq!(response["result"]["isError"], true);
+
+        let response = client
+            .request(json!({
+                "jsonrpc": "2.0",
+                "id": 5,
+                "method": "tools/call",
+                "params": { "name": "missing", "arguments": {} },
+            }))
+            .await;
+        assert_eq!(response["error"]["code"], INVALID_PARAMS);
+
+        let response = client
+            .request(json!({ "jsonrpc": "2.0", "id": 6, "method": "resources/list" }))
+            .await;
+        assert_eq!(response["error"]["code"], METHOD_NOT_FOUND);
+
+        client.writer.write_all(b"not json\n").await.unwrap();
+        let line = client.lines.next_line().await.unwrap().unwrap();
+        let response: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(response["error"]["code"], PARSE_ERROR);
+        assert!(response["id"].is_null());
+    }
+
+    #[tokio::test]
+    async fn test_serve_sse() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let mut service = ToolkitService::new("test");
+        service.add_action(Echo);
+        spawn(serve_sse(service, addr));
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        let client = reqwest::Client::new();
+        let mut events = client
+            .get(format!("http://{addr}/sse"))
+            .send()
+            .await
+            .unwrap();
+        let endpoint = String::from_utf8(events.chunk().await.unwrap().unwrap().to_vec()).unwrap();
+        let path = endpoint
+            .strip_prefix("event: endpoint\ndata: ")
+            .unwrap()
+            .trim_end();
+
+        let accepted = client
+            .post(format!("http://{addr}{path}"))
+            .body(call(1, json!({ "content": "hi" })).to_string())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(accepted.status(), StatusCode::ACCEPTED);
+
+        let event = String::from_utf8(events.chunk().await.unwrap().unwrap().to_vec()).unwrap();
+        let data = event.strip_prefix("event: message\ndata: ").unwrap();
+        let response: Value = serde_json::from_str(data.trim_end()).unwrap();
+        assert_eq!(response["result"]["content"][0]["text"], "hi #1");
+
+        let unknown = client
+            .post(format!("http://{addr}/message?sessionId=unknown"))
+            .body("{}")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(unknown.status(), StatusCode::NOT_FOUND);
+    }
+}
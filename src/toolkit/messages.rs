@@ -1,16 +1,143 @@
+//! The messages exchanged between a Toolkit service and the server, for custom transports,
+//! recordings of the traffic, or emulators of the server.
+//!
+//! # Wire format
+//!
+//! Every message is a JSON object with a `type` naming its kind and the `data` of that kind, sent
+//! as a WebSocket text frame, or in the body of an HTTP request with
+//! [long-polling](super::TransportPreference::Polling). The names of the `type`s and fields are stable: they only change
+//! with the protocol, and new kinds of messages may be added, so [ToolkitMessage] is
+//! `#[non_exhaustive]`. Messages of unknown types are read as [ToolkitMessage::Unknown], and
+//! unknown fields of known messages are ignored.
+//!
+//! ```json
+//! {"type":"registerActions","data":{"actions":{"echo":{"description":"...","payload":{},"payment":null}},"clientInfo":{"sdk":"rust","version":"0.1.0","protocolFeatures":["errorCode"]}}}
+//! {"type":"registerActionsAck","data":{"deprecation":null,"minimumVersion":null}}
+//! {"type":"action","data":{"action":"echo","actionID":1,"agentID":2,"payload":{"content":"hi"},"payment":null}}
+//! {"type":"actionCancel","data":{"actionID":1,"agentID":2,"reason":"agent disconnected"}}
+//! {"type":"actionProgress","data":{"action":"echo","actionID":1,"agentID":2,"payload":{"done":0.5}}}
+//! {"type":"actionResult","data":{"action":"echo","actionID":1,"agentID":2,"payload":"hi","payment":null}}
+//! ```
+//!
+//! Note the upper case `ID` of `actionID` and `agentID`, where other fields are camel case.
+//! An `action` may also carry the `traceContext` of the agent's call, as in
+//! `"traceContext":{"traceparent":"00-...-01"}`, see [crate::trace]. Other fields of an `action`,
+//! such as the locale of the caller, are kept in [ActionCallParams::extra] and sent back unchanged
+//! in its `actionResult`.
+
 use super::ActionDefinition;
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use std::collections::HashMap;
+use crate::trace::TraceContext;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use serde_json::{
+    value::{to_raw_value, RawValue},
+    Map, Value,
+};
+use std::{borrow::Cow, collections::HashMap};
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+/// A message, see the [wire format](self#wire-format).
+#[derive(Clone, Debug, PartialEq, Serialize)]
 #[serde(tag = "type", rename_all = "camelCase")]
+#[non_exhaustive]
 pub enum ToolkitMessage {
+    /// `"action"`, from the server: call an action.
     Action { data: ActionCallParams },
+    /// `"actionResult"`, from the service: the result of an action call, with the same
+    /// `actionID`.
     ActionResult { data: ActionCallResult },
+    /// `"actionProgress"`, from the service: an intermediate result of an action call, with the
+    /// same `actionID`, sent before its `actionResult`, see
+    /// [ActionContext::send_progress](super::ActionContext::send_progress).
+    ActionProgress { data: ActionCallProgress },
+    /// `"actionCancel"`, from the server: stop an action call in flight, which is answered with an
+    /// `ActionCancelled` error, see [ActionContext::cancelled](super::ActionContext::cancelled).
+    ActionCancel { data: ActionCallCancel },
+    /// `"registerActions"`, from the service: the actions it serves, sent first on every
+    /// connection.
     RegisterActions { data: ActionsRegisterParams },
+    /// `"registerActionsAck"`, from the server: the registration was received.
+    RegisterActionsAck { data: ActionsRegisterAck },
+    /// A message of a `type` this SDK doesn't know, such as one added to the protocol since, with
+    /// its `data` as is. The service skips it, see
+    /// [ToolkitEvent::UnknownMessage](super::ToolkitEvent::UnknownMessage).
+    #[serde(untagged)]
+    Unknown {
+        #[serde(rename = "type")]
+        r#type: String,
+        data: Value,
+    },
+}
+
+impl<'de> Deserialize<'de> for ToolkitMessage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Frame {
+            #[serde(rename = "type")]
+            kind: String,
+            #[serde(default)]
+            data: Value,
+        }
+
+        // Raw values can't be read from a buffered frame, the data is read as a value instead.
+        fn data<'de, D: Deserializer<'de>, T: DeserializeOwned>(
+            data: Value,
+        ) -> Result<T, D::Error> {
+            serde_json::from_value(data).map_err(serde::de::Error::custom)
+        }
+
+        let Frame { kind, data: value } = Frame::deserialize(deserializer)?;
+        Ok(match kind.as_str() {
+            "action" => Self::Action {
+                data: data::<D, _>(value)?,
+            },
+            "actionResult" => Self::ActionResult {
+                data: data::<D, _>(value)?,
+            },
+            "actionProgress" => Self::ActionProgress {
+                data: data::<D, _>(value)?,
+            },
+            "actionCancel" => Self::ActionCancel {
+                data: data::<D, _>(value)?,
+            },
+            "registerActions" => Self::RegisterActions {
+                data: data::<D, _>(value)?,
+            },
+            "registerActionsAck" => Self::RegisterActionsAck {
+                data: data::<D, _>(value)?,
+            },
+            _ => Self::Unknown {
+                r#type: kind,
+                data: value,
+            },
+        })
+    }
+}
+
+/// An action call, read from its frame in a single pass.
+#[derive(Deserialize)]
+struct ActionFrame<'a> {
+    #[serde(rename = "type", borrow)]
+    kind: Cow<'a, str>,
+    data: ActionCallParams,
+}
+
+impl ToolkitMessage {
+    /// Parse a message received from the server.
+    ///
+    /// Action calls are read straight from the frame, their payload is only copied as JSON text.
+    pub fn from_frame(frame: &[u8]) -> serde_json::Result<Self> {
+        // Action calls are by far the most frequent messages, try them first.
+        match serde_json::from_slice::<ActionFrame>(frame) {
+            Ok(ActionFrame { kind, data }) if kind == "action" => Ok(Self::Action { data }),
+            _ => serde_json::from_slice(frame),
+        }
+    }
 }
 
+/// An action call sent by the server, as in
+/// `{"action":"echo","actionID":1,"agentID":2,"payload":{},"payment":null}`.
+///
+/// `payload` is any JSON value, objects are sometimes encoded as JSON strings. `payment` is the
+/// amount the agent agreed to pay, if any. Equal calls have the same payload as JSON values, whatever its formatting.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ActionCallParams {
     pub action: String,
@@ -18,10 +145,27 @@ pub struct ActionCallParams {
     pub action_id: u64,
     #[serde(rename = "agentID")]
     pub agent_id: u64,
-    pub payload: Value,
+    /// Parsed by the action, see `parse_payload`.
+    pub payload: Box<RawValue>,
     pub payment: Option<u64>,
+    /// The trace of the agent's tool call, when the server forwards it, see [crate::trace].
+    #[serde(
+        rename = "traceContext",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub trace_context: Option<TraceContext>,
+    /// The fields of the call the SDK doesn't know, such as a conversation id, see
+    /// [ActionContext::metadata](super::ActionContext::metadata).
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
+/// The result of an action call, sent back to the server, with the `action`, `actionID` and
+/// `agentID` of the call.
+///
+/// `payload` is the output of the action, or `{"error": ..., "code": ...}` if the call failed.
+/// `payment` is the amount charged for the call, if any. Equal results have the same payload as JSON values, whatever its formatting.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ActionCallResult {
     pub action: String,
@@ -29,11 +173,602 @@ pub struct ActionCallResult {
     pub action_id: u64,
     #[serde(rename = "agentID")]
     pub agent_id: u64,
-    pub payload: Value,
+    /// Serialized by the action, see `ActionDyn::call_raw`.
+    pub payload: Box<RawValue>,
     pub payment: Option<u64>,
+    /// The [extra](ActionCallParams::extra) fields of the call, sent back unchanged.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
+/// The progress of an action call, with the `action`, `actionID` and `agentID` of the call, as in
+/// `{"action":"render","actionID":1,"agentID":2,"payload":{"done":0.5}}`.
 #[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ActionCallProgress {
+    pub action: String,
+    #[serde(rename = "actionID")]
+    pub action_id: u64,
+    #[serde(rename = "agentID")]
+    pub agent_id: u64,
+    pub payload: Box<RawValue>,
+}
+
+impl PartialEq for ActionCallProgress {
+    fn eq(&self, other: &Self) -> bool {
+        self.action == other.action
+            && self.action_id == other.action_id
+            && self.agent_id == other.agent_id
+            && same_json(&self.payload, &other.payload)
+    }
+}
+
+/// The cancellation of an action call by the server, as in
+/// `{"actionID":1,"agentID":2,"reason":"agent disconnected"}`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ActionCallCancel {
+    #[serde(rename = "actionID")]
+    pub action_id: u64,
+    /// The agent of the call, as action IDs are only unique by agent. Without it, the call is
+    /// cancelled only if no other agent has one with the same action ID in flight.
+    #[serde(rename = "agentID", default, skip_serializing_if = "Option::is_none")]
+    pub agent_id: Option<u64>,
+    /// Why the call was cancelled, for the logs.
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+/// Implement the constructor, setters and equality shared by the call and result messages.
+macro_rules! action_message {
+    ($message:ident $(, $field:ident)*) => {
+        impl $message {
+            /// A message for `action` with an empty object as payload, and 1 as action and agent
+            /// IDs, to customize with the `with_*` setters.
+            pub fn new(action: &str) -> Self {
+                Self {
+                    action: action.to_string(),
+                    action_id: 1,
+                    agent_id: 1,
+                    payload: RawValue::from_string("{}".to_string()).unwrap(),
+                    payment: None,
+                    extra: Map::new(),
+                    $($field: None,)*
+                }
+            }
+
+            pub fn with_action_id(mut self, action_id: u64) -> Self {
+                self.action_id = action_id;
+                self
+            }
+
+            pub fn with_agent_id(mut self, agent_id: u64) -> Self {
+                self.agent_id = agent_id;
+                self
+            }
+
+            /// Set the payload.
+            ///
+            /// # Panics
+            ///
+            /// If the payload fails to serialize, like a map with non-string keys.
+            pub fn with_payload(mut self, payload: impl Serialize) -> Self {
+                self.payload = to_raw_value(&payload).expect("the payload serializes");
+                self
+            }
+
+            pub fn with_payment(mut self, payment: u64) -> Self {
+                self.payment = Some(payment);
+                self
+            }
+
+            /// Set an [extra](ActionCallParams::extra) field.
+            ///
+            /// # Panics
+            ///
+            /// If the value fails to serialize.
+            pub fn with_extra(mut self, key: &str, value: impl Serialize) -> Self {
+                let value = serde_json::to_value(value).expect("the value serializes");
+                self.extra.insert(key.to_string(), value);
+                self
+            }
+        }
+
+        impl PartialEq for $message {
+            fn eq(&self, other: &Self) -> bool {
+                self.action == other.action
+                    && self.action_id == other.action_id
+                    && self.agent_id == other.agent_id
+                    && self.payment == other.payment
+                    && self.extra == other.extra
+                    && same_json(&self.payload, &other.payload)
+            }
+        }
+    };
+}
+
+action_message!(ActionCallParams, trace_context);
+action_message!(ActionCallResult);
+
+fn same_json(a: &RawValue, b: &RawValue) -> bool {
+    a.get() == b.get()
+        || serde_json::from_str::<Value>(a.get()).ok() == serde_json::from_str(b.get()).ok()
+}
+
+/// The actions of a service by name, and the SDK registering them, as in
+/// `{"actions":{"echo":{...}},"clientInfo":{...}}`. `clientInfo` is left out by the legacy
+/// registration.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct ActionsRegisterParams {
     pub actions: HashMap<String, ActionDefinition>,
+    #[serde(
+        rename = "clientInfo",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub client_info: Option<ClientInfo>,
+}
+
+/// Protocol behaviors supported by this SDK, reported on registration.
+///
+/// - `errorCode`: results may carry a `code` next to the `error`, such as `internal_error`.
+pub const PROTOCOL_FEATURES: &[&str] = &["errorCode"];
+
+/// Identifies the SDK to the server, so it can warn deployments relying on deprecated behaviors.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientInfo {
+    pub sdk: String,
+    pub version: String,
+    pub protocol_features: Vec<String>,
+}
+
+impl ClientInfo {
+    pub fn current() -> Self {
+        Self {
+            sdk: "rust".to_string(),
+            version: crate::SDK_VERSION.to_string(),
+            protocol_features: PROTOCOL_FEATURES.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+}
+
+/// The server's acknowledgement of the registration, which may carry deprecation hints, as in
+/// `{"deprecation":"...","minimumVersion":"0.3.0"}`.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActionsRegisterAck {
+    #[serde(default)]
+    pub deprecation: Option<String>,
+    #[serde(default)]
+    pub minimum_version: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::action_frame,
+        toolkit::action::{parse_payload, PaymentDefinition},
+    };
+    use serde_json::json;
+
+    fn params(frame: &[u8]) -> ActionCallParams {
+        match ToolkitMessage::from_frame(frame).unwrap() {
+            ToolkitMessage::Action { data } => data,
+            message => panic!("unexpected message: {message:?}"),
+        }
+    }
+
+    #[test]
+    fn test_from_frame_keeps_payload_text() {
+        let frame = r#"{"data":{"action":"echo","actionID":1,"agentID":2,"payload":{"b": [1, 2], "a": "é"},"payment":null},"type":"action"}"#
+            .as_bytes();
+
+        let params = params(frame);
+        assert_eq!(params.action, "echo");
+        assert_eq!((params.action_id, params.agent_id), (1, 2));
+        assert_eq!(params.payload.get(), r#"{"b": [1, 2], "a": "é"}"#);
+
+        // Parsed like the whole message would be.
+        let ToolkitMessage::Action { data } = serde_json::from_slice(frame).unwrap() else {
+            unreachable!()
+        };
+        let expected: Value = serde_json::from_str(data.payload.get()).unwrap();
+        assert_eq!(parse_payload::<Value>(&params.payload).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_payment_definition_wire_format() {
+        let fixed = PaymentDefinition::fixed(100).with_description("per call");
+        assert_eq!(
+            Value::from(fixed.clone()),
+            json!({ "amount": 100, "description": "per call", "required": true })
+        );
+
+        let range = PaymentDefinition::range(10, 100)
+            .with_token("USDC")
+            .optional();
+        assert_eq!(
+            Value::from(range.clone()),
+            json!({ "minAmount": 10, "maxAmount": 100, "token": "USDC", "required": false })
+        );
+
+        for payment in [fixed, range] {
+            let value = Value::from(payment.clone());
+            assert_eq!(
+                serde_json::from_value::<PaymentDefinition>(value).unwrap(),
+                payment
+            );
+        }
+    }
+
+    #[test]
+    fn test_from_frame_string_encoded_payload() {
+        let payload = json!({ "content": "say \"hi\"" });
+        let frame = action_frame("echo", 1, &Value::String(payload.to_string()));
+
+        let encoded = params(&frame);
+        assert_eq!(encoded.action_id, 1);
+        assert_eq!(parse_payload::<Value>(&encoded.payload).unwrap(), payload);
+
+        let plain = params(&action_frame("echo", 1, &payload));
+        assert_eq!(parse_payload::<Value>(&plain.payload).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_from_frame_other_messages() {
+        let ack = br#"{"type":"registerActionsAck","data":{"deprecation":"old"}}"#;
+        match ToolkitMessage::from_frame(ack).unwrap() {
+            ToolkitMessage::RegisterActionsAck { data } => {
+                assert_eq!(data.deprecation.as_deref(), Some("old"))
+            }
+            message => panic!("unexpected message: {message:?}"),
+        }
+
+        // New kinds of messages are kept as is, new fields of known ones are ignored.
+        assert_eq!(
+            ToolkitMessage::from_frame(br#"{"type":"agentJoined","data":{"agentID":2}}"#).unwrap(),
+            ToolkitMessage::Unknown {
+                r#type: "agentJoined".to_string(),
+                data: json!({ "agentID": 2 }),
+            }
+        );
+        let cancel = br#"{"type":"actionCancel","data":{"actionID":1,"graceMs":10},"seq":4}"#;
+        assert!(matches!(
+            ToolkitMessage::from_frame(cancel).unwrap(),
+            ToolkitMessage::ActionCancel { data } if data.action_id == 1
+        ));
+        let ack = br#"{"type":"registerActionsAck","data":{"toolkitID":7}}"#;
+        assert!(matches!(
+            ToolkitMessage::from_frame(ack).unwrap(),
+            ToolkitMessage::RegisterActionsAck { .. }
+        ));
+
+        assert!(ToolkitMessage::from_frame(br#"{"data":{}}"#).is_err());
+        assert!(ToolkitMessage::from_frame(br#"{"type":"action","data":{"action":1}}"#).is_err());
+        assert!(ToolkitMessage::from_frame(b"not json").is_err());
+    }
+
+    #[test]
+    fn test_action_messages_wire_names() {
+        let call = ActionCallParams::new("echo")
+            .with_action_id(5)
+            .with_agent_id(7);
+        let message = ToolkitMessage::Action { data: call };
+        let text = serde_json::to_string(&message).unwrap();
+        assert_eq!(
+            text,
+            r#"{"type":"action","data":{"action":"echo","actionID":5,"agentID":7,"payload":{},"payment":null}}"#
+        );
+        assert_eq!(
+            ToolkitMessage::from_frame(text.as_bytes()).unwrap(),
+            message
+        );
+        assert_eq!(
+            serde_json::from_str::<ToolkitMessage>(&text).unwrap(),
+            message
+        );
+
+        let result = ActionCallResult::new("echo")
+            .with_action_id(5)
+            .with_payload(json!({ "n": 1 }))
+            .with_payment(3);
+        let message = ToolkitMessage::ActionResult { data: result };
+        let text = serde_json::to_string(&message).unwrap();
+        assert_eq!(
+            text,
+            r#"{"type":"actionResult","data":{"action":"echo","actionID":5,"agentID":1,"payload":{"n":1},"payment":3}}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<ToolkitMessage>(&text).unwrap(),
+            message
+        );
+    }
+
+    #[test]
+    fn test_action_extra_fields() {
+        let frame = br#"{"type":"action","data":{"action":"echo","actionID":1,"agentID":2,"payload":{"a":1},"payment":null,"conversationID":"c1","locale":"fr"}}"#;
+
+        for message in [
+            ToolkitMessage::from_frame(frame).unwrap(),
+            serde_json::from_slice(frame).unwrap(),
+        ] {
+            let ToolkitMessage::Action { data } = &message else {
+                panic!("not an action: {message:?}");
+            };
+            assert_eq!(data.payload.get(), r#"{"a":1}"#);
+            assert_eq!(data.extra["locale"], "fr");
+            assert_eq!(data.extra["conversationID"], "c1");
+            assert_eq!(
+                serde_json::to_vec(&message).unwrap(),
+                frame.to_vec(),
+                "the extra fields round-trip"
+            );
+        }
+    }
+
+    #[test]
+    fn test_action_trace_context() {
+        let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let frame = format!(
+            r#"{{"type":"action","data":{{"action":"echo","actionID":1,"agentID":2,"payload":{{}},"payment":null,"traceContext":{{"traceparent":"{traceparent}"}}}}}}"#
+        );
+
+        let call = params(frame.as_bytes());
+        let trace_context = call.trace_context.as_ref().unwrap();
+        assert_eq!(trace_context.traceparent, traceparent);
+        assert_eq!(trace_context.tracestate, None);
+        assert_eq!(
+            serde_json::to_string(&ToolkitMessage::Action { data: call }).unwrap(),
+            frame
+        );
+    }
+
+    #[test]
+    fn test_registration_wire_json() {
+        let definition = ActionDefinition {
+            description: "Echo".to_string(),
+            payload: json!({}),
+            payment: None,
+            ..Default::default()
+        };
+        let detailed = ActionDefinition {
+            display_name: Some("Echo Slam".to_string()),
+            examples: Some(vec![json!({ "content": "hi" })]),
+            tags: Some(vec!["demo".to_string()]),
+            ..definition.clone()
+        };
+        let registration = |definition| {
+            serde_json::to_string(&ActionsRegisterParams {
+                actions: HashMap::from([("echo".to_string(), definition)]),
+                client_info: None,
+            })
+            .unwrap()
+        };
+
+        assert_eq!(
+            registration(definition),
+            r#"{"actions":{"echo":{"description":"Echo","payload":{},"payment":null}}}"#
+        );
+        assert_eq!(
+            registration(detailed),
+            concat!(
+                r#"{"actions":{"echo":{"description":"Echo","payload":{},"payment":null,"#,
+                r#""displayName":"Echo Slam","examples":[{"content":"hi"}],"tags":["demo"]}}}"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_registration_messages_wire_names() {
+        let definition = ActionDefinition {
+            description: "Echo".to_string(),
+            payload: json!({}),
+            payment: None,
+            ..Default::default()
+        };
+        let message = ToolkitMessage::RegisterActions {
+            data: ActionsRegisterParams {
+                actions: HashMap::from([("echo".to_string(), definition)]),
+                client_info: Some(ClientInfo::current()),
+            },
+        };
+        let value = serde_json::to_value(&message).unwrap();
+        assert_eq!(value["type"], "registerActions");
+        assert_eq!(value["data"]["clientInfo"]["sdk"], "rust");
+        assert_eq!(
+            value["data"]["clientInfo"]["protocolFeatures"],
+            json!(PROTOCOL_FEATURES)
+        );
+        assert_eq!(
+            serde_json::from_value::<ToolkitMessage>(value).unwrap(),
+            message
+        );
+
+        let message = ToolkitMessage::RegisterActionsAck {
+            data: ActionsRegisterAck {
+                deprecation: None,
+                minimum_version: Some("0.3.0".to_string()),
+            },
+        };
+        let text = serde_json::to_string(&message).unwrap();
+        assert_eq!(
+            text,
+            r#"{"type":"registerActionsAck","data":{"deprecation":null,"minimumVersion":"0.3.0"}}"#
+        );
+        assert_eq!(
+            ToolkitMessage::from_frame(text.as_bytes()).unwrap(),
+            message
+        );
+    }
+
+    /// One message of every kind, with every optional field set. The match fails to compile when
+    /// a kind is added, so it gets pinned too.
+    fn every_message() -> Vec<ToolkitMessage> {
+        let messages = vec![
+            ToolkitMessage::Action {
+                data: ActionCallParams::new("echo")
+                    .with_action_id(1)
+                    .with_agent_id(2)
+                    .with_payload(json!({ "content": "hi" }))
+                    .with_payment(3),
+            },
+            ToolkitMessage::ActionResult {
+                data: ActionCallResult::new("echo")
+                    .with_action_id(1)
+                    .with_agent_id(2)
+                    .with_payload("hi")
+                    .with_payment(3),
+            },
+            ToolkitMessage::ActionProgress {
+                data: ActionCallProgress {
+                    action: "echo".to_string(),
+                    action_id: 1,
+                    agent_id: 2,
+                    payload: to_raw_value(&json!({ "done": 0.5 })).unwrap(),
+                },
+            },
+            ToolkitMessage::ActionCancel {
+                data: ActionCallCancel {
+                    action_id: 1,
+                    agent_id: Some(2),
+                    reason: Some("agent disconnected".to_string()),
+                },
+            },
+            ToolkitMessage::RegisterActions {
+                data: ActionsRegisterParams {
+                    actions: HashMap::from([(
+                        "echo".to_string(),
+                        ActionDefinition {
+                            description: "Echo".to_string(),
+                            payload: json!({ "content": { "type": "string" } }),
+                            payment: Some(PaymentDefinition::range(1, 5).into()),
+                            ..Default::default()
+                        },
+                    )]),
+                    client_info: Some(ClientInfo::current()),
+                },
+            },
+            ToolkitMessage::RegisterActionsAck {
+                data: ActionsRegisterAck {
+                    deprecation: Some("old".to_string()),
+                    minimum_version: Some("0.3.0".to_string()),
+                },
+            },
+            ToolkitMessage::Unknown {
+                r#type: "agentJoined".to_string(),
+                data: json!({ "agentID": 2 }),
+            },
+        ];
+
+        for message in &messages {
+            match message {
+                ToolkitMessage::Action { .. }
+                | ToolkitMessage::ActionResult { .. }
+                | ToolkitMessage::ActionProgress { .. }
+                | ToolkitMessage::ActionCancel { .. }
+                | ToolkitMessage::RegisterActions { .. }
+                | ToolkitMessage::RegisterActionsAck { .. }
+                | ToolkitMessage::Unknown { .. } => {}
+            }
+        }
+
+        messages
+    }
+
+    #[test]
+    fn test_every_message_round_trips() {
+        for message in every_message() {
+            let text = serde_json::to_string(&message).unwrap();
+
+            assert_eq!(
+                ToolkitMessage::from_frame(text.as_bytes()).unwrap(),
+                message
+            );
+            assert_eq!(
+                serde_json::from_str::<ToolkitMessage>(&text).unwrap(),
+                message
+            );
+            let value = serde_json::to_value(&message).unwrap();
+            assert_eq!(
+                serde_json::from_value::<ToolkitMessage>(value).unwrap(),
+                message
+            );
+        }
+    }
+
+    #[test]
+    fn test_wire_names_are_pinned() {
+        let keys = |value: &Value| {
+            let mut keys: Vec<_> = value.as_object().unwrap().keys().cloned().collect();
+            keys.sort();
+            keys
+        };
+
+        let names: Vec<_> = every_message()
+            .iter()
+            .map(|message| {
+                let value = serde_json::to_value(message).unwrap();
+                assert_eq!(keys(&value), ["data", "type"]);
+                (
+                    value["type"].as_str().unwrap().to_string(),
+                    keys(&value["data"]),
+                )
+            })
+            .collect();
+
+        let call = ["action", "actionID", "agentID", "payload", "payment"];
+        assert_eq!(
+            names,
+            [
+                ("action".to_string(), call.map(String::from).to_vec()),
+                ("actionResult".to_string(), call.map(String::from).to_vec()),
+                (
+                    "actionProgress".to_string(),
+                    call[..4].iter().map(|key| key.to_string()).collect()
+                ),
+                (
+                    "actionCancel".to_string(),
+                    vec![
+                        "actionID".to_string(),
+                        "agentID".to_string(),
+                        "reason".to_string()
+                    ]
+                ),
+                (
+                    "registerActions".to_string(),
+                    vec!["actions".to_string(), "clientInfo".to_string()]
+                ),
+                (
+                    "registerActionsAck".to_string(),
+                    vec!["deprecation".to_string(), "minimumVersion".to_string()]
+                ),
+                ("agentJoined".to_string(), vec!["agentID".to_string()]),
+            ]
+        );
+
+        let registration = serde_json::to_value(&every_message()[4]).unwrap();
+        assert_eq!(
+            keys(&registration["data"]["clientInfo"]),
+            ["protocolFeatures", "sdk", "version"]
+        );
+        assert_eq!(
+            keys(&registration["data"]["actions"]["echo"]),
+            ["description", "payload", "payment"]
+        );
+
+        // The server's casing of the IDs, anything else is rejected.
+        let frame = br#"{"type":"action","data":{"action":"echo","actionId":1,"agentId":2,"payload":{},"payment":null}}"#;
+        assert!(ToolkitMessage::from_frame(frame).is_err());
+    }
+
+    #[test]
+    fn test_payload_equality_ignores_formatting() {
+        let call = ActionCallParams::new("echo").with_payload(json!({ "a": 1, "b": [1, 2] }));
+        let formatted = params(
+            br#"{"type":"action","data":{"action":"echo","actionID":1,"agentID":1,"payload":{ "b": [1, 2], "a": 1 },"payment":null}}"#,
+        );
+        assert_eq!(formatted, call);
+        assert_ne!(call.clone().with_payload(json!({ "a": 2 })), call);
+        assert_ne!(call.clone().with_agent_id(2), call);
+    }
 }
@@ -0,0 +1,171 @@
+//! Logic wrapping the calls of every action, such as checking who calls them, see
+//! [ToolkitService::with_middleware](super::ToolkitService::with_middleware).
+
+use super::{
+    action::{ActionDyn, ActionParams, ActionResult},
+    context::ActionContext,
+    errors::{ActionError, ToolkitError},
+};
+use serde_json::{value::RawValue, Value};
+use std::{collections::HashSet, future::Future, pin::Pin, sync::Arc};
+
+/// The result of an action call going through an [ActionMiddleware].
+pub type MiddlewareFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<ActionResult<Value>, ToolkitError>> + Send + Sync + 'a>>;
+
+/// Logic run around the calls of every action of a service, set with
+/// [ToolkitService::with_middleware](super::ToolkitService::with_middleware).
+///
+/// A middleware passes the call on with `next.run(ctx, params)`, possibly changing the params
+/// first, or answers it itself, such as with an error. Middlewares run in the order they were
+/// added, the first one seeing the call first and the result last.
+///
+/// # Example
+/// ```
+/// use unifai_sdk::toolkit::{
+///     ActionContext, ActionMiddleware, ActionParams, MiddlewareFuture, Next,
+/// };
+/// use serde_json::value::RawValue;
+///
+/// struct LogCalls;
+///
+/// impl ActionMiddleware for LogCalls {
+///     fn handle<'a>(
+///         &'a self,
+///         ctx: ActionContext,
+///         params: ActionParams<Box<RawValue>>,
+///         next: Next<'a>,
+///     ) -> MiddlewareFuture<'a> {
+///         Box::pin(async move {
+///             println!("agent {} calls {}", ctx.agent_id, ctx.action);
+///             next.run(ctx, params).await
+///         })
+///     }
+/// }
+/// ```
+pub trait ActionMiddleware: Send + Sync {
+    fn handle<'a>(
+        &'a self,
+        ctx: ActionContext,
+        params: ActionParams<Box<RawValue>>,
+        next: Next<'a>,
+    ) -> MiddlewareFuture<'a>;
+}
+
+/// The rest of the middlewares of a call, then its action.
+pub struct Next<'a> {
+    middlewares: &'a [Arc<dyn ActionMiddleware>],
+    action: &'a dyn ActionDyn,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(
+        middlewares: &'a [Arc<dyn ActionMiddleware>],
+        action: &'a dyn ActionDyn,
+    ) -> Self {
+        Self {
+            middlewares,
+            action,
+        }
+    }
+
+    /// Pass the call on to the next middleware, or to the action after the last one.
+    pub fn run(
+        self,
+        ctx: ActionContext,
+        params: ActionParams<Box<RawValue>>,
+    ) -> MiddlewareFuture<'a> {
+        match self.middlewares.split_first() {
+            Some((middleware, middlewares)) => {
+                middleware.handle(ctx, params, Next::new(middlewares, self.action))
+            }
+            None => self.action.call(ctx, params),
+        }
+    }
+}
+
+/// Answers the calls of the agents not in its list with a `forbidden` [ActionError].
+pub struct AllowedAgents {
+    agent_ids: HashSet<u64>,
+}
+
+impl AllowedAgents {
+    pub fn new(agent_ids: impl IntoIterator<Item = u64>) -> Self {
+        Self {
+            agent_ids: agent_ids.into_iter().collect(),
+        }
+    }
+}
+
+impl ActionMiddleware for AllowedAgents {
+    fn handle<'a>(
+        &'a self,
+        ctx: ActionContext,
+        params: ActionParams<Box<RawValue>>,
+        next: Next<'a>,
+    ) -> MiddlewareFuture<'a> {
+        if self.agent_ids.contains(&ctx.agent_id) {
+            return next.run(ctx, params);
+        }
+
+        let error = ActionError::new(
+            "forbidden",
+            format!("agent {} may not call {}", ctx.agent_id, ctx.action),
+        );
+        Box::pin(async move { Err(ToolkitError::ActionCallError(Box::new(error))) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toolkit::{
+        testing::ToolkitTestHarness, ActionDefinition, FunctionAction, ToolkitService,
+    };
+    use serde_json::{json, value::to_raw_value};
+
+    /// Wraps the payload of the calls as `{"sanitized": payload}`.
+    struct Sanitize;
+
+    impl ActionMiddleware for Sanitize {
+        fn handle<'a>(
+            &'a self,
+            ctx: ActionContext,
+            mut params: ActionParams<Box<RawValue>>,
+            next: Next<'a>,
+        ) -> MiddlewareFuture<'a> {
+            let payload: Value = serde_json::from_str(params.payload.get()).unwrap();
+            params.payload = to_raw_value(&json!({ "sanitized": payload })).unwrap();
+            next.run(ctx, params)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_middlewares() {
+        let definition = ActionDefinition {
+            description: "Echo the payload".to_string(),
+            payload: json!({}),
+            payment: None,
+            ..Default::default()
+        };
+        let mut service = ToolkitService::new("test")
+            .with_middleware(AllowedAgents::new([1]))
+            .with_middleware(Sanitize);
+        service.add_action(FunctionAction::new(
+            "echo",
+            definition,
+            |_ctx, payload: Value| async move { Ok(payload) },
+        ));
+        let mut harness = ToolkitTestHarness::start(service).await.unwrap();
+
+        let result = harness.send_action_call("echo", json!("hi"), 1).await;
+        assert_eq!(result.payload.get(), r#"{"sanitized":"hi"}"#);
+
+        let result = harness.send_action_call("echo", json!("hi"), 2).await;
+        let payload: Value = serde_json::from_str(result.payload.get()).unwrap();
+        assert_eq!(payload["code"], "forbidden");
+        assert_eq!(payload["message"], "agent 2 may not call echo");
+
+        assert!(harness.shutdown().await.is_ok());
+    }
+}
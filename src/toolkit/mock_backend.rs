@@ -0,0 +1,450 @@
+//! A local stand-in for the whole Unifai backend, see [MockBackend].
+
+use super::{
+    chaos::Direction,
+    errors::{Result, ToolkitError},
+    loopback::{self, Served},
+    messages::{ActionCallParams, ActionCallResult, ActionsRegisterAck, ToolkitMessage},
+    ActionDefinition, ActionResult,
+};
+use crate::{
+    config::UnifaiConfig,
+    tools::{CallTool, SearchTools, UnifaiClient},
+};
+use futures_util::{future::BoxFuture, SinkExt, StreamExt};
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    spawn,
+    sync::{
+        mpsc::{unbounded_channel, UnboundedSender},
+        oneshot, Notify,
+    },
+};
+use tokio_tungstenite::{accept_async, tungstenite::Message};
+
+/// The Unifai backend simulated on local ports, to test toolkits and agents together without
+/// credentials or network access.
+///
+/// Toolkits connect to its WebSocket endpoint and register their actions, which the agent tools
+/// then search and call through its tools API, as they would through Unifai. Tests can also call
+/// the actions directly, and read every frame exchanged with the toolkits.
+///
+/// ```
+/// # use unifai_sdk::{testing::MockBackend, toolkit::ToolkitService};
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let backend = MockBackend::start().await;
+/// let service = ToolkitService::from_config(backend.config()).unwrap();
+/// // Add the actions to test, then:
+/// let _handle = service.start().await.unwrap();
+///
+/// let (search_tools, call_tool) = backend.tools();
+/// # }
+/// ```
+///
+/// The servers run until the runtime shuts down.
+pub struct MockBackend {
+    state: Arc<State>,
+    api_addr: SocketAddr,
+    ws_addr: SocketAddr,
+}
+
+/// A frame exchanged between a [MockBackend] and a toolkit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordedFrame {
+    /// The index of the toolkit, in the order they connected.
+    pub toolkit: usize,
+    /// [Direction::Outgoing] for the frames sent by the toolkit.
+    pub direction: Direction,
+    pub text: String,
+}
+
+#[derive(Default)]
+struct State {
+    toolkits: Mutex<Vec<Toolkit>>,
+    /// The calls waiting for their result, with the toolkit answering them.
+    pending: Mutex<HashMap<u64, (usize, oneshot::Sender<ActionCallResult>)>>,
+    frames: Mutex<Vec<RecordedFrame>>,
+    next_action_id: AtomicU64,
+    /// Notified when a toolkit registers its actions.
+    registered: Notify,
+}
+
+struct Toolkit {
+    actions: HashMap<String, ActionDefinition>,
+    /// `None` once the toolkit disconnected.
+    sender: Option<UnboundedSender<String>>,
+}
+
+impl MockBackend {
+    /// Start the tools API and the WebSocket endpoint on loopback ports.
+    ///
+    /// # Panics
+    ///
+    /// If no loopback port can be bound.
+    pub async fn start() -> Self {
+        let state = Arc::new(State::default());
+        let api_addr = loopback::serve(state.clone())
+            .await
+            .expect("a loopback port is bound");
+
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("a loopback port is bound");
+        let ws_addr = listener.local_addr().expect("the listener has an address");
+        let accepting = state.clone();
+        spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                spawn(serve_toolkit(accepting.clone(), stream));
+            }
+        });
+
+        Self {
+            state,
+            api_addr,
+            ws_addr,
+        }
+    }
+
+    /// A config pointing toolkits and tools at the backend, with placeholder API keys.
+    pub fn config(&self) -> UnifaiConfig {
+        UnifaiConfig {
+            agent_api_key: Some("mock".to_string()),
+            toolkit_api_key: Some("mock".to_string()),
+            backend_api_endpoint: format!("http://{}", self.api_addr),
+            backend_ws_endpoint: format!("ws://{}/ws", self.ws_addr),
+            ..UnifaiConfig::default()
+        }
+    }
+
+    /// The agent tools, searching and calling the actions of the connected toolkits.
+    pub fn tools(&self) -> (SearchTools, CallTool) {
+        let client = UnifaiClient::from_config(&self.config()).expect("the config has an API key");
+
+        (client.clone().into(), client.into())
+    }
+
+    /// The actions registered by the connected toolkits, by name.
+    pub fn registered_actions(&self) -> HashMap<String, ActionDefinition> {
+        self.state.registered_actions()
+    }
+
+    /// Wait until a connected toolkit registered `action`.
+    pub async fn wait_for_action(&self, action: &str) {
+        loop {
+            let registered = self.state.registered.notified();
+            if self.state.toolkit_serving(action).is_some() {
+                return;
+            }
+            registered.await;
+        }
+    }
+
+    /// Call an action as the server would, and wait for its result.
+    ///
+    /// # Panics
+    ///
+    /// If no connected toolkit serves the action, or it disconnected before answering.
+    pub async fn send_action_call(
+        &self,
+        action: &str,
+        payload: impl Serialize,
+        agent_id: u64,
+    ) -> ActionCallResult {
+        let params = ActionCallParams::new(action)
+            .with_agent_id(agent_id)
+            .with_payload(payload);
+
+        match self.state.route(params).await {
+            Some(result) => result.unwrap_or_else(|e| panic!("the call to {action} failed: {e}")),
+            None => panic!("no toolkit serves {action}"),
+        }
+    }
+
+    /// Send a message to every connected toolkit, as the server would.
+    pub fn send_raw(&self, message: ToolkitMessage) {
+        let text = serde_json::to_string(&message).expect("a message always serializes");
+        let count = self.state.lock_toolkits().len();
+        for toolkit in 0..count {
+            self.state.send(toolkit, text.clone());
+        }
+    }
+
+    /// The frames exchanged with the toolkits so far, in order.
+    pub fn frames(&self) -> Vec<RecordedFrame> {
+        lock(&self.state.frames).clone()
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl State {
+    fn lock_toolkits(&self) -> std::sync::MutexGuard<'_, Vec<Toolkit>> {
+        lock(&self.toolkits)
+    }
+
+    fn registered_actions(&self) -> HashMap<String, ActionDefinition> {
+        self.lock_toolkits()
+            .iter()
+            .flat_map(|toolkit| toolkit.actions.clone())
+            .collect()
+    }
+
+    /// The last connected toolkit serving `action`.
+    fn toolkit_serving(&self, action: &str) -> Option<usize> {
+        self.lock_toolkits()
+            .iter()
+            .rposition(|toolkit| toolkit.actions.contains_key(action))
+    }
+
+    /// Send a frame to a toolkit, if it's still connected.
+    fn send(&self, toolkit: usize, text: String) {
+        let sender = self.lock_toolkits()[toolkit].sender.clone();
+        if let Some(sender) = sender {
+            self.record(toolkit, Direction::Incoming, &text);
+            let _ = sender.send(text);
+        }
+    }
+
+    fn record(&self, toolkit: usize, direction: Direction, text: &str) {
+        lock(&self.frames).push(RecordedFrame {
+            toolkit,
+            direction,
+            text: text.to_string(),
+        });
+    }
+
+    /// Send a call to the toolkit serving its action, and wait for the result. Returns `None` if
+    /// no toolkit serves it.
+    async fn route(&self, mut params: ActionCallParams) -> Option<Result<ActionCallResult>> {
+        let toolkit = self.toolkit_serving(&params.action)?;
+        params.action_id = self.next_action_id.fetch_add(1, Ordering::Relaxed) + 1;
+
+        let (sender, receiver) = oneshot::channel();
+        lock(&self.pending).insert(params.action_id, (toolkit, sender));
+        let action = params.action.clone();
+        let message = ToolkitMessage::Action { data: params };
+        self.send(
+            toolkit,
+            serde_json::to_string(&message).expect("a message always serializes"),
+        );
+
+        // The sender is dropped if the toolkit disconnects.
+        Some(
+            receiver
+                .await
+                .map_err(|_| ToolkitError::ActionCancelled { action }),
+        )
+    }
+
+    fn receive(&self, toolkit: usize, text: &str) {
+        self.record(toolkit, Direction::Outgoing, text);
+
+        match ToolkitMessage::from_frame(text.as_bytes()) {
+            Ok(ToolkitMessage::RegisterActions { data }) => {
+                self.lock_toolkits()[toolkit].actions = data.actions;
+                let ack = ToolkitMessage::RegisterActionsAck {
+                    data: ActionsRegisterAck::default(),
+                };
+                self.send(toolkit, serde_json::to_string(&ack).unwrap());
+                self.registered.notify_waiters();
+            }
+            Ok(ToolkitMessage::ActionResult { data }) => {
+                if let Some((_, sender)) = lock(&self.pending).remove(&data.action_id) {
+                    let _ = sender.send(data);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn disconnect(&self, toolkit: usize) {
+        let mut toolkits = self.lock_toolkits();
+        toolkits[toolkit].actions.clear();
+        toolkits[toolkit].sender = None;
+        drop(toolkits);
+
+        lock(&self.pending).retain(|_, (answering, _)| *answering != toolkit);
+    }
+}
+
+impl Served for State {
+    fn definitions(&self) -> BoxFuture<'_, Result<HashMap<String, ActionDefinition>>> {
+        let definitions = self.registered_actions();
+        Box::pin(async move { Ok(definitions) })
+    }
+
+    fn call(&self, params: ActionCallParams) -> BoxFuture<'_, Option<Result<ActionResult<Value>>>> {
+        Box::pin(async move {
+            let result = self.route(params).await?;
+            Some(result.and_then(|result| {
+                Ok(ActionResult {
+                    payload: serde_json::from_str(result.payload.get())?,
+                    payment: result.payment,
+                })
+            }))
+        })
+    }
+}
+
+/// Exchange frames with a toolkit until it disconnects.
+async fn serve_toolkit(state: Arc<State>, stream: TcpStream) {
+    let Ok(mut ws_stream) = accept_async(stream).await else {
+        return;
+    };
+    let (sender, mut outgoing) = unbounded_channel();
+    let toolkit = {
+        let mut toolkits = state.lock_toolkits();
+        toolkits.push(Toolkit {
+            actions: HashMap::new(),
+            sender: Some(sender),
+        });
+        toolkits.len() - 1
+    };
+
+    loop {
+        tokio::select! {
+            // Reading also answers the pings of the toolkit.
+            message = ws_stream.next() => match message {
+                Some(Ok(Message::Text(text))) => state.receive(toolkit, &text),
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+            Some(text) = outgoing.recv() => {
+                if ws_stream.send(Message::text(text)).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    state.disconnect(toolkit);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        toolkit::{Action, ActionContext, ActionParams, ToolkitService},
+        tools::{CallToolArgs, SearchToolsArgs, ToolsError},
+    };
+    use serde_json::json;
+    use std::{convert::Infallible, time::Duration};
+    use tokio::time::timeout;
+
+    struct Echo;
+
+    impl Action for Echo {
+        const NAME: &'static str = "echo";
+
+        type Error = Infallible;
+        type Args = Value;
+        type Output = Value;
+
+        async fn definition(&self) -> ActionDefinition {
+            ActionDefinition {
+                description: "Echo the payload".to_string(),
+                payload: json!({ "content": "anything" }),
+                payment: None,
+                ..Default::default()
+            }
+        }
+
+        async fn call(
+            &self,
+            ctx: ActionContext,
+            params: ActionParams<Self::Args>,
+        ) -> std::result::Result<ActionResult<Self::Output>, Self::Error> {
+            Ok(ActionResult {
+                payload: json!({ "agent": ctx.agent_id, "echo": params.payload }),
+                payment: None,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_register_search_and_call() {
+        let backend = MockBackend::start().await;
+        let mut service = ToolkitService::from_config(backend.config()).unwrap();
+        service.add_action(Echo);
+        let handle = service.start().await.unwrap();
+        timeout(Duration::from_secs(5), backend.wait_for_action("echo"))
+            .await
+            .unwrap();
+        assert_eq!(
+            backend.registered_actions()["echo"].description,
+            "Echo the payload"
+        );
+
+        let (search_tools, call_tool) = backend.tools();
+        let args = SearchToolsArgs {
+            query: "echo".to_string(),
+            ..Default::default()
+        };
+        let found = search_tools.search(&args).await.unwrap();
+        assert_eq!(found[0].action, "echo");
+
+        let args = CallToolArgs {
+            action: "echo".to_string(),
+            payload: json!({ "content": "hello" }),
+            ..Default::default()
+        };
+        let response = call_tool.call_typed(&args).await.unwrap();
+        assert_eq!(
+            response.payload,
+            json!({ "agent": 0, "echo": { "content": "hello" } })
+        );
+
+        let result = backend.send_action_call("echo", json!("again"), 7).await;
+        assert_eq!(result.payload.get(), r#"{"agent":7,"echo":"again"}"#);
+
+        let kinds: Vec<_> = backend
+            .frames()
+            .iter()
+            .map(|frame| {
+                let message: Value = serde_json::from_str(&frame.text).unwrap();
+                (
+                    frame.direction,
+                    message["type"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (Direction::Outgoing, "registerActions"),
+                (Direction::Incoming, "registerActionsAck"),
+                (Direction::Incoming, "action"),
+                (Direction::Outgoing, "actionResult"),
+                (Direction::Incoming, "action"),
+                (Direction::Outgoing, "actionResult"),
+            ]
+            .map(|(direction, kind)| (direction, kind.to_string()))
+        );
+
+        handle.shutdown().await.unwrap().unwrap();
+        timeout(Duration::from_secs(5), async {
+            while !backend.registered_actions().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert!(matches!(
+            call_tool.call_typed(&args).await,
+            Err(ToolsError::Status { status: 404, .. })
+        ));
+    }
+}
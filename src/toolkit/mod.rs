@@ -11,13 +11,106 @@
 mod action;
 pub use action::*;
 
+mod bytes;
+pub use bytes::ActionBytes;
+
+mod cancel;
+
+#[cfg(any(test, feature = "test-utils"))]
+mod chaos;
+
 mod context;
 pub use context::*;
+/// Cancelled with a call, see [ActionContext::cancellation_token].
+pub use tokio_util::sync::CancellationToken;
+
+pub mod declarative;
 
 mod errors;
 pub use errors::*;
 
-mod messages;
+mod events;
+pub use events::*;
+
+mod examples;
+pub use examples::*;
+
+mod function;
+pub use function::{FunctionAction, FunctionError};
+
+#[cfg(feature = "http-server")]
+pub mod http;
+
+#[cfg(all(feature = "test-utils", feature = "tools"))]
+pub(crate) mod loopback;
+
+#[cfg(all(feature = "test-utils", feature = "tools"))]
+pub(crate) mod mock_backend;
+
+mod manager;
+pub use manager::*;
+
+pub mod manifest;
+pub use manifest::{run_from_config, ActionRegistry};
+
+#[cfg(feature = "mcp")]
+pub mod mcp;
+
+pub mod messages;
+
+#[cfg(feature = "grpc")]
+mod grpc;
+
+mod idempotency;
+pub use idempotency::{CallKey, IdempotencyStore, MemoryIdempotencyStore};
+
+mod limit;
+
+mod middleware;
+pub use middleware::{ActionMiddleware, AllowedAgents, MiddlewareFuture, Next};
+
+mod names;
+pub use names::{InvalidActionName, MAX_ACTION_NAME_LEN};
+
+mod observer;
+pub use observer::{ObservedCall, ToolkitObserver};
+
+pub mod openapi;
+
+mod polling;
+
+#[cfg(feature = "rig")]
+pub mod rig_adapter;
+
+mod recording;
+pub use recording::{record_to_file, RecordedCall};
+
+#[cfg(feature = "auto-register")]
+mod registration;
+#[cfg(feature = "auto-register")]
+pub use registration::DuplicateActionError;
+
+/// Used by [register_action](crate::register_action).
+#[cfg(feature = "auto-register")]
+#[doc(hidden)]
+pub mod __private {
+    pub use super::registration::{BoxedAction, RegisteredAction};
+    pub use inventory;
+}
+
+mod schema;
+pub use schema::{Field, PayloadSchema};
 
 mod service;
 pub use service::*;
+
+mod table;
+
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testing;
+
+pub mod validation;
+pub use validation::PayloadViolation;
+
+pub(crate) mod transport;
+pub use transport::{Incoming, ToolkitTransport, TransportPreference, WebSocketTransport};
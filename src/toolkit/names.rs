@@ -0,0 +1,147 @@
+//! The grammar of action names.
+//!
+//! A name is 1 to [MAX_ACTION_NAME_LEN] ASCII letters, digits, `_`, `-` or `.`, such as `echo` or
+//! `get_balance`. Surrounding ASCII whitespace is trimmed. Slashes are reserved for the identifiers the
+//! server composes, as in `Toolkit/123/echo`, which are routed to the action named by their last
+//! segment.
+
+/// The maximum length of an action name, in bytes.
+pub const MAX_ACTION_NAME_LEN: usize = 64;
+
+/// An action name outside of the grammar, see [ToolkitService::try_add_action].
+///
+/// A name is 1 to [MAX_ACTION_NAME_LEN] ASCII letters, digits, `_`, `-` or `.`, such as `echo` or
+/// `get_balance`. Surrounding ASCII whitespace is trimmed.
+///
+/// [ToolkitService::try_add_action]: super::ToolkitService::try_add_action
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum InvalidActionName {
+    #[error("Invalid action name: the name is empty")]
+    Empty,
+
+    #[error("Invalid action name {name:?}: longer than {MAX_ACTION_NAME_LEN} bytes")]
+    TooLong { name: String },
+
+    #[error(
+        "Invalid action name {name:?}: {character:?} is not allowed, only ASCII letters, digits, \
+         `_`, `-` and `.` are"
+    )]
+    InvalidCharacter { name: String, character: char },
+}
+
+/// Trim `name` of ASCII whitespace and check it against the grammar.
+pub(crate) fn normalize_action_name(name: &str) -> Result<&str, InvalidActionName> {
+    let name = name.trim_ascii();
+
+    if name.is_empty() {
+        return Err(InvalidActionName::Empty);
+    }
+    if name.len() > MAX_ACTION_NAME_LEN {
+        return Err(InvalidActionName::TooLong {
+            name: name.to_string(),
+        });
+    }
+    if let Some(character) = name
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.')))
+    {
+        return Err(InvalidActionName::InvalidCharacter {
+            name: name.to_string(),
+            character,
+        });
+    }
+
+    Ok(name)
+}
+
+/// Whether `name`, trimmed of ASCII whitespace, is within the grammar, for the names checked at
+/// compile time.
+pub(crate) const fn is_valid_action_name(name: &str) -> bool {
+    let name = name.trim_ascii().as_bytes();
+    if name.is_empty() || name.len() > MAX_ACTION_NAME_LEN {
+        return false;
+    }
+
+    let mut i = 0;
+    while i < name.len() {
+        if !(name[i].is_ascii_alphanumeric() || matches!(name[i], b'_' | b'-' | b'.')) {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// The name of the action an identifier received from the server routes to: its last segment,
+/// trimmed of ASCII whitespace.
+pub(crate) fn routed_name(name: &str) -> &str {
+    name.rsplit('/').next().unwrap_or(name).trim_ascii()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_action_name() {
+        assert_eq!(normalize_action_name("echo"), Ok("echo"));
+        assert_eq!(
+            normalize_action_name(" get_balance-v2.1\n"),
+            Ok("get_balance-v2.1")
+        );
+        assert_eq!(normalize_action_name("EchoSlam"), Ok("EchoSlam"));
+
+        assert_eq!(normalize_action_name("  "), Err(InvalidActionName::Empty));
+        assert!(matches!(
+            normalize_action_name(&"a".repeat(MAX_ACTION_NAME_LEN + 1)),
+            Err(InvalidActionName::TooLong { .. })
+        ));
+        assert!(normalize_action_name(&"a".repeat(MAX_ACTION_NAME_LEN)).is_ok());
+
+        for (name, character) in [("Toolkit/1/echo", '/'), ("echo slam", ' '), ("échо", 'é')] {
+            assert_eq!(
+                normalize_action_name(name),
+                Err(InvalidActionName::InvalidCharacter {
+                    name: name.to_string(),
+                    character
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_is_valid_action_name() {
+        for name in [
+            "echo",
+            " get_balance-v2.1\n",
+            "EchoSlam",
+            &"a".repeat(MAX_ACTION_NAME_LEN),
+        ] {
+            assert!(is_valid_action_name(name), "{name:?}");
+            assert!(normalize_action_name(name).is_ok());
+        }
+        for name in ["  ", "Toolkit/1/echo", "echo slam", "échо", &"a".repeat(65)] {
+            assert!(!is_valid_action_name(name), "{name:?}");
+            assert!(normalize_action_name(name).is_err());
+        }
+    }
+
+    #[test]
+    fn test_routed_name() {
+        assert_eq!(routed_name("echo"), "echo");
+        assert_eq!(routed_name("test_echo_1700000000/123/echo"), "echo");
+        assert_eq!(routed_name("Toolkit/123/echo "), "echo");
+        assert_eq!(routed_name("Toolkit/123/echo\u{a0}"), "echo\u{a0}");
+    }
+
+    #[test]
+    fn test_only_ascii_whitespace_is_trimmed() {
+        for name in ["\u{a0}echo", "echo\u{2003}"] {
+            assert!(!is_valid_action_name(name), "{name:?}");
+            assert!(matches!(
+                normalize_action_name(name),
+                Err(InvalidActionName::InvalidCharacter { .. })
+            ));
+        }
+    }
+}
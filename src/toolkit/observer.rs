@@ -0,0 +1,60 @@
+use super::{ToolkitError, ToolkitRuntimeEvent};
+use std::time::Duration;
+
+/// Hooks called as a Toolkit service runs, such as to export metrics of its calls, see
+/// [ToolkitService::with_observer](super::ToolkitService::with_observer).
+///
+/// The hooks are called on the tasks of the service, they must return quickly.
+///
+/// # Example
+/// ```
+/// use std::{sync::atomic::{AtomicU64, Ordering}, time::Duration};
+/// use unifai_sdk::toolkit::{ObservedCall, ToolkitError, ToolkitObserver};
+///
+/// #[derive(Default)]
+/// struct Metrics {
+///     calls: AtomicU64,
+///     failures: AtomicU64,
+/// }
+///
+/// impl ToolkitObserver for Metrics {
+///     fn on_action_end(&self, _call: &ObservedCall, outcome: Result<(), &ToolkitError>, _duration: Duration) {
+///         self.calls.fetch_add(1, Ordering::Relaxed);
+///         if outcome.is_err() {
+///             self.failures.fetch_add(1, Ordering::Relaxed);
+///         }
+///     }
+/// }
+/// ```
+pub trait ToolkitObserver: Send + Sync {
+    /// An action is called, once it was found.
+    fn on_action_start(&self, call: &ObservedCall) {
+        let _ = call;
+    }
+
+    /// An action call completed, with the error it failed with, if any.
+    fn on_action_end(
+        &self,
+        call: &ObservedCall,
+        outcome: Result<(), &ToolkitError>,
+        duration: Duration,
+    ) {
+        let _ = (call, outcome, duration);
+    }
+
+    /// The service emitted a runtime event, such as a failed send or its stop, including the
+    /// events emitted before any [ToolkitHandle::errors](super::ToolkitHandle::errors) receiver
+    /// subscribed.
+    fn on_connection_event(&self, event: &ToolkitRuntimeEvent) {
+        let _ = event;
+    }
+}
+
+/// An action call, as seen by a [ToolkitObserver].
+#[derive(Clone, Copy, Debug)]
+#[non_exhaustive]
+pub struct ObservedCall<'a> {
+    pub action: &'a str,
+    pub action_id: u64,
+    pub agent_id: u64,
+}
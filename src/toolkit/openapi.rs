@@ -0,0 +1,536 @@
+//! Export the actions of a Toolkit as an [OpenAPI 3.0](https://spec.openapis.org/oas/v3.0.3) document.
+//!
+//! The document describes the routes served by `toolkit::http::router`: each action is a
+//! `POST /actions/{name}` operation, whose request body is `{"payload": ..., "payment": ...}` with
+//! the payload schema of [ActionDefinition::input_schema], and whose failures return the
+//! `{"error": ..., "code": ...}` payload. The payment info of an action, if any, is rendered in the
+//! [PAYMENT_EXTENSION] field of its operation, and the `example` of its payload fields make up the
+//! request example.
+//!
+//! Payload schemas are converted to the OpenAPI flavor of JSON Schema: `const` becomes a
+//! single-value `enum`, `examples` the first `example`, a `null` type `nullable`, numeric exclusive
+//! bounds flags of `minimum` and `maximum`, and keywords OpenAPI doesn't know are kept as `x-`
+//! extensions.
+//!
+//! Keys are sorted, so the document can be compared against a snapshot.
+//!
+//! # Example
+//!
+//! ```ignore
+//! let info = OpenApiInfo::new("Echo Slam", "1.0.0");
+//! let document = unifai_sdk::toolkit::openapi::spec(&service, &info).await;
+//! std::fs::write("openapi.json", serde_json::to_string_pretty(&document).unwrap()).unwrap();
+//! ```
+
+use super::{action::ActionDefinition, service::ToolkitService};
+use futures_util::future::join_all;
+use serde_json::{json, Map, Value};
+
+/// The version of OpenAPI the documents follow.
+pub const OPENAPI_VERSION: &str = "3.0.3";
+
+/// The operation field holding the payment info of an action.
+pub const PAYMENT_EXTENSION: &str = "x-unifai-payment";
+
+/// Keywords of the OpenAPI 3.0 Schema Object.
+const SCHEMA_KEYWORDS: &[&str] = &[
+    "additionalProperties",
+    "allOf",
+    "anyOf",
+    "default",
+    "deprecated",
+    "description",
+    "discriminator",
+    "enum",
+    "example",
+    "exclusiveMaximum",
+    "exclusiveMinimum",
+    "externalDocs",
+    "format",
+    "items",
+    "maxItems",
+    "maxLength",
+    "maxProperties",
+    "maximum",
+    "minItems",
+    "minLength",
+    "minProperties",
+    "minimum",
+    "multipleOf",
+    "not",
+    "nullable",
+    "oneOf",
+    "pattern",
+    "properties",
+    "readOnly",
+    "required",
+    "title",
+    "type",
+    "uniqueItems",
+    "writeOnly",
+    "xml",
+];
+
+const TYPES: &[&str] = &["array", "boolean", "integer", "number", "object", "string"];
+
+/// The `info` of an OpenAPI document.
+#[derive(Clone, Debug)]
+pub struct OpenApiInfo {
+    pub title: String,
+    pub version: String,
+    pub description: Option<String>,
+}
+
+impl OpenApiInfo {
+    pub fn new(title: impl Into<String>, version: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            version: version.into(),
+            description: None,
+        }
+    }
+
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+}
+
+/// Render the actions of `service` as an OpenAPI 3.0 document.
+pub async fn spec(service: &ToolkitService, info: &OpenApiInfo) -> Value {
+    let actions = service.actions().entries();
+    let definitions = join_all(
+        actions
+            .into_iter()
+            .map(|(name, action)| async move { (name, action.definition().await) }),
+    )
+    .await;
+
+    let mut paths = Map::new();
+    for (name, definition) in definitions {
+        paths.insert(
+            format!("/actions/{name}"),
+            json!({ "post": operation(&name, &definition) }),
+        );
+    }
+
+    let mut info_object = json!({
+        "title": info.title,
+        "version": info.version,
+    });
+    if let Some(description) = &info.description {
+        info_object["description"] = json!(description);
+    }
+
+    sorted(json!({
+        "openapi": OPENAPI_VERSION,
+        "info": info_object,
+        "paths": paths,
+        "components": {
+            "schemas": {
+                "Error": {
+                    "type": "object",
+                    "required": ["error", "code"],
+                    "properties": {
+                        "error": {
+                            "type": "string",
+                            "description": "What went wrong.",
+                        },
+                        "code": {
+                            "type": "string",
+                            "description": "The kind of failure, such as `invalid_payload` or `action_error`.",
+                        },
+                    },
+                },
+            },
+            "responses": {
+                "InvalidRequest": error_response("The body is not a JSON object with a `payload`."),
+                "InvalidPayload": error_response("The payload does not match the action arguments."),
+                "ActionError": error_response("The action failed."),
+                "Timeout": error_response("The action timed out."),
+            },
+        },
+    }))
+}
+
+fn operation(name: &str, definition: &ActionDefinition) -> Value {
+    let payload = openapi_schema(definition.input_schema());
+
+    let mut media_type = json!({
+        "schema": {
+            "type": "object",
+            "required": ["payload"],
+            "properties": {
+                "payload": payload,
+                "payment": {
+                    "type": "integer",
+                    "minimum": 0,
+                    "nullable": true,
+                    "description": "The payment authorized for the call.",
+                },
+            },
+        },
+    });
+    if let Some(example) = payload_example(&media_type["schema"]["properties"]["payload"]) {
+        media_type["example"] = json!({ "payload": example });
+    }
+
+    let mut operation = json!({
+        "operationId": name,
+        "description": definition.description,
+        "requestBody": {
+            "required": true,
+            "content": { "application/json": media_type },
+        },
+        "responses": {
+            "200": {
+                "description": "The result payload of the action.",
+                "content": { "application/json": { "schema": {} } },
+            },
+            "400": { "$ref": "#/components/responses/InvalidRequest" },
+            "422": { "$ref": "#/components/responses/InvalidPayload" },
+            "500": { "$ref": "#/components/responses/ActionError" },
+            "504": { "$ref": "#/components/responses/Timeout" },
+        },
+    });
+    if let Some(payment) = &definition.payment {
+        operation[PAYMENT_EXTENSION] = payment.clone();
+    }
+
+    operation
+}
+
+fn error_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": {
+            "application/json": {
+                "schema": { "$ref": "#/components/schemas/Error" },
+            },
+        },
+    })
+}
+
+/// An example payload made of the `example` of each field, if any has one.
+fn payload_example(schema: &Value) -> Option<Value> {
+    let example: Map<String, Value> = schema["properties"]
+        .as_object()?
+        .iter()
+        .filter_map(|(name, field)| Some((name.clone(), field.get("example")?.clone())))
+        .collect();
+
+    (!example.is_empty()).then_some(Value::Object(example))
+}
+
+/// Convert a JSON Schema to an OpenAPI 3.0 Schema Object.
+fn openapi_schema(schema: Value) -> Value {
+    let Value::Object(schema) = schema else {
+        return json!({});
+    };
+
+    let mut converted = Map::new();
+    for (keyword, value) in schema {
+        match keyword.as_str() {
+            "$schema" | "$id" | "$comment" => {}
+            "const" => {
+                converted.insert("enum".to_string(), json!([value]));
+            }
+            // Draft 6 made them numbers, they are flags of `minimum` and `maximum` in OpenAPI.
+            "exclusiveMinimum" | "exclusiveMaximum" if value.is_number() => {
+                let bound = if keyword == "exclusiveMinimum" {
+                    "minimum"
+                } else {
+                    "maximum"
+                };
+                converted.insert(bound.to_string(), value);
+                converted.insert(keyword, json!(true));
+            }
+            "examples" => {
+                if let Some(example) = value.as_array().and_then(|examples| examples.first()) {
+                    converted.insert("example".to_string(), example.clone());
+                }
+            }
+            "type" => match value {
+                Value::String(t) if TYPES.contains(&t.as_str()) => {
+                    converted.insert(keyword, Value::String(t));
+                }
+                Value::Array(types) => {
+                    let mut types: Vec<_> = types.iter().filter_map(Value::as_str).collect();
+                    if types.contains(&"null") {
+                        types.retain(|t| *t != "null");
+                        converted.insert("nullable".to_string(), json!(true));
+                    }
+                    match types[..] {
+                        [t] if TYPES.contains(&t) => {
+                            converted.insert(keyword, json!(t));
+                        }
+                        [] => {}
+                        _ => {
+                            converted.insert("x-type".to_string(), json!(types));
+                        }
+                    }
+                }
+                value => {
+                    converted.insert("x-type".to_string(), value);
+                }
+            },
+            "properties" => {
+                let properties = value
+                    .as_object()
+                    .into_iter()
+                    .flatten()
+                    .map(|(name, field)| (name.clone(), openapi_schema(field.clone())))
+                    .collect();
+                converted.insert(keyword, Value::Object(properties));
+            }
+            "items" | "not" => {
+                converted.insert(keyword, openapi_schema(value));
+            }
+            "additionalProperties" if !value.is_boolean() => {
+                converted.insert(keyword, openapi_schema(value));
+            }
+            "allOf" | "anyOf" | "oneOf" => {
+                let schemas = value
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(|schema| openapi_schema(schema.clone()))
+                    .collect();
+                converted.insert(keyword, Value::Array(schemas));
+            }
+            // An empty list is not allowed, and means the same as none.
+            "required" if value.as_array().is_some_and(Vec::is_empty) => {}
+            _ if SCHEMA_KEYWORDS.contains(&keyword.as_str()) || keyword.starts_with("x-") => {
+                converted.insert(keyword, value);
+            }
+            _ => {
+                converted.insert(format!("x-{keyword}"), value);
+            }
+        }
+    }
+
+    Value::Object(converted)
+}
+
+/// Sort the keys of every object, whether or not serde_json preserves the insertion order.
+fn sorted(value: Value) -> Value {
+    match value {
+        Value::Object(object) => {
+            let mut entries: Vec<_> = object.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, sorted(value)))
+                    .collect(),
+            )
+        }
+        Value::Array(values) => Value::Array(values.into_iter().map(sorted).collect()),
+        value => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toolkit::{Action, ActionContext, ActionParams, ActionResult, PaymentDefinition};
+    use std::convert::Infallible;
+
+    struct Echo;
+
+    impl Action for Echo {
+        const NAME: &'static str = "echo";
+
+        type Error = Infallible;
+        type Args = Value;
+        type Output = Value;
+
+        async fn definition(&self) -> ActionDefinition {
+            ActionDefinition {
+                description: "Echo the message".to_string(),
+                payload: json!({
+                    "content": {
+                        "type": "string",
+                        "description": "The content to echo.",
+                        "example": "hello",
+                        "required": true
+                    },
+                    "loud": "Whether to shout."
+                }),
+                payment: None,
+                ..Default::default()
+            }
+        }
+
+        async fn call(
+            &self,
+            _ctx: ActionContext,
+            params: ActionParams<Self::Args>,
+        ) -> Result<ActionResult<Self::Output>, Self::Error> {
+            Ok(ActionResult {
+                payload: params.payload,
+                payment: None,
+            })
+        }
+    }
+
+    /// An action whose payload is a JSON Schema, as the tools of MCP servers have.
+    struct Transfer;
+
+    impl Action for Transfer {
+        const NAME: &'static str = "transfer";
+
+        type Error = Infallible;
+        type Args = Value;
+        type Output = Value;
+
+        async fn definition(&self) -> ActionDefinition {
+            ActionDefinition {
+                description: "Transfer tokens".to_string(),
+                payload: json!({
+                    "$schema": "http://json-schema.org/draft-07/schema#",
+                    "type": "object",
+                    "properties": {
+                        "amount": { "type": "number", "exclusiveMinimum": 0, "examples": [1.5] },
+                        "memo": { "type": ["string", "null"] },
+                        "chain": { "const": "solana" },
+                        "to": { "type": "string", "contentEncoding": "base58" }
+                    },
+                    "required": ["amount", "to"],
+                    "additionalProperties": false
+                }),
+                payment: Some(PaymentDefinition::fixed(1).with_token("USDC").into()),
+                ..Default::default()
+            }
+        }
+
+        async fn call(
+            &self,
+            _ctx: ActionContext,
+            params: ActionParams<Self::Args>,
+        ) -> Result<ActionResult<Self::Output>, Self::Error> {
+            Ok(ActionResult {
+                payload: params.payload,
+                payment: None,
+            })
+        }
+    }
+
+    fn service(transfer_first: bool) -> ToolkitService {
+        let mut service = ToolkitService::new("test");
+        if transfer_first {
+            service.add_action(Transfer);
+            service.add_action(Echo);
+        } else {
+            service.add_action(Echo);
+            service.add_action(Transfer);
+        }
+        service
+    }
+
+    fn info() -> OpenApiInfo {
+        OpenApiInfo::new("Echo Slam", "1.0.0").with_description("What's in, what's out.")
+    }
+
+    #[tokio::test]
+    async fn test_spec_validates_against_openapi_schema() {
+        let meta_schema: Value =
+            serde_json::from_str(include_str!("../../tests/fixtures/openapi-3.0.schema.json"))
+                .unwrap();
+        let validator = jsonschema::draft4::new(&meta_schema).unwrap();
+
+        let document = spec(&service(false), &info()).await;
+
+        let errors: Vec<_> = validator
+            .iter_errors(&document)
+            .map(|e| format!("{} at {}", e, e.instance_path))
+            .collect();
+        assert!(errors.is_empty(), "{errors:#?}");
+
+        // The meta-schema does reject the JSON Schema flavor of exclusive bounds.
+        let mut invalid = document;
+        invalid["paths"]["/actions/transfer"]["post"]["requestBody"]["content"]
+            ["application/json"]["schema"]["properties"]["payload"]["properties"]["amount"]
+            ["exclusiveMinimum"] = json!(0);
+        assert!(!validator.is_valid(&invalid));
+    }
+
+    #[tokio::test]
+    async fn test_spec_renders_actions() {
+        let document = spec(&service(false), &info()).await;
+
+        assert_eq!(document["openapi"], OPENAPI_VERSION);
+        assert_eq!(document["info"]["title"], "Echo Slam");
+
+        let echo = &document["paths"]["/actions/echo"]["post"];
+        assert_eq!(echo["operationId"], "echo");
+        assert!(echo.get(PAYMENT_EXTENSION).is_none());
+        assert_eq!(
+            echo["requestBody"]["content"]["application/json"],
+            json!({
+                "example": { "payload": { "content": "hello" } },
+                "schema": {
+                    "properties": {
+                        "payload": {
+                            "properties": {
+                                "content": {
+                                    "description": "The content to echo.",
+                                    "example": "hello",
+                                    "type": "string"
+                                },
+                                "loud": { "description": "Whether to shout." }
+                            },
+                            "required": ["content"],
+                            "type": "object"
+                        },
+                        "payment": {
+                            "description": "The payment authorized for the call.",
+                            "minimum": 0,
+                            "nullable": true,
+                            "type": "integer"
+                        }
+                    },
+                    "required": ["payload"],
+                    "type": "object"
+                }
+            })
+        );
+        assert_eq!(
+            echo["responses"]["422"]["$ref"],
+            "#/components/responses/InvalidPayload"
+        );
+
+        let transfer = &document["paths"]["/actions/transfer"]["post"];
+        assert_eq!(
+            transfer[PAYMENT_EXTENSION],
+            json!({ "amount": 1, "token": "USDC", "required": true })
+        );
+        let payload = &transfer["requestBody"]["content"]["application/json"]["schema"]
+            ["properties"]["payload"];
+        assert!(payload.get("$schema").is_none());
+        assert_eq!(
+            payload["properties"],
+            json!({
+                "amount": {
+                    "example": 1.5,
+                    "exclusiveMinimum": true,
+                    "minimum": 0,
+                    "type": "number"
+                },
+                "chain": { "enum": ["solana"] },
+                "memo": { "nullable": true, "type": "string" },
+                "to": { "type": "string", "x-contentEncoding": "base58" }
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_spec_is_stable() {
+        let first = spec(&service(false), &info()).await;
+        let second = spec(&service(true), &info()).await;
+
+        let first = serde_json::to_string_pretty(&first).unwrap();
+        assert_eq!(first, serde_json::to_string_pretty(&second).unwrap());
+        assert!(first.find("/actions/echo").unwrap() < first.find("/actions/transfer").unwrap());
+    }
+}
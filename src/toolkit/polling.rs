@@ -0,0 +1,639 @@
+//! The HTTP long-polling transport, for networks where WebSocket connections can't be opened.
+//!
+//! It carries the same [messages](super::messages) as the WebSocket, in the bodies of HTTPS
+//! requests to [UnifaiConfig::backend_poll_endpoint], authorized with the toolkit API key:
+//!
+//! - `POST /sessions?type=toolkit` opens a session, answered with `{"sessionId":"..."}`.
+//! - `POST /sessions/{id}/messages` sends a message, with an `X-Unifai-Sequence` header numbering
+//!   the messages of the session from 1, so the server can drop the copies of a retried request.
+//! - `GET /sessions/{id}/messages?after={seq}&waitMs={ms}` waits up to `waitMs` for the messages
+//!   numbered after `after`, answered with `{"messages":[{"seq":1,"message":{...}}],"closed":null}`.
+//!   `closed` holds the `code` and `reason` of a session the server ended. A `404` or `410` means
+//!   the session is gone.
+//! - `DELETE /sessions/{id}` closes the session.
+//!
+//! The server keeps a message until a poll asks for the ones after it, so a poll that fails or is
+//! cut short is repeated without losing any. Messages numbered up to the last one received are
+//! dropped, so none is delivered twice.
+
+use super::{
+    errors::{Result, ToolkitError},
+    transport::{Incoming, ToolkitTransport},
+};
+use crate::{config::UnifaiConfig, http::ApiClient};
+use reqwest::{header::CONTENT_TYPE, Response, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::value::RawValue;
+use std::time::Duration;
+use tokio::{
+    spawn,
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    task::JoinHandle,
+    time::sleep,
+};
+use tokio_tungstenite::tungstenite::Bytes;
+
+/// How long the server holds a poll when it has no message.
+const POLL_WAIT: Duration = Duration::from_secs(25);
+
+const SEQUENCE_HEADER: &str = "x-unifai-sequence";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Session {
+    session_id: String,
+}
+
+#[derive(Deserialize)]
+struct Poll {
+    #[serde(default)]
+    messages: Vec<Polled>,
+    closed: Option<Closed>,
+}
+
+#[derive(Deserialize)]
+struct Polled {
+    seq: u64,
+    message: Box<RawValue>,
+}
+
+#[derive(Deserialize)]
+struct Closed {
+    code: Option<u16>,
+    reason: Option<String>,
+}
+
+/// A session of the long-polling transport, polled on a task of its own so no message is lost
+/// when the service stops waiting for one.
+pub(crate) struct PollingTransport {
+    requests: Requests,
+    session_url: String,
+    /// The sequence number of the last message sent.
+    sent: u64,
+    incoming: UnboundedReceiver<Incoming>,
+    poller: JoinHandle<()>,
+}
+
+/// What the requests of a session share.
+#[derive(Clone)]
+struct Requests {
+    api_client: ApiClient,
+    timeout: Duration,
+    max_retries: u32,
+    retry_base_delay: Duration,
+}
+
+impl PollingTransport {
+    /// Open a session at `endpoint`.
+    pub(crate) async fn connect(
+        api_client: ApiClient,
+        endpoint: &str,
+        config: &UnifaiConfig,
+    ) -> Result<Self> {
+        let requests = Requests {
+            api_client,
+            timeout: config.request_timeout,
+            max_retries: config.max_retries,
+            retry_base_delay: config.retry_base_delay,
+        };
+
+        let request = requests
+            .api_client
+            .post(format!("{endpoint}/sessions?type=toolkit"))
+            .timeout(config.connect_timeout);
+        let response = requests
+            .api_client
+            .send(request)
+            .await
+            .and_then(Response::error_for_status)
+            .map_err(|e| {
+                ToolkitError::from_request(e, "polling connect", config.connect_timeout)
+            })?;
+        let session: Session = requests.json(response).await?;
+
+        let session_url = format!("{endpoint}/sessions/{}", session.session_id);
+        let (sender, incoming) = unbounded_channel();
+        let poller = spawn(
+            requests
+                .clone()
+                .poll(format!("{session_url}/messages"), sender),
+        );
+
+        Ok(Self {
+            requests,
+            session_url,
+            sent: 0,
+            incoming,
+            poller,
+        })
+    }
+}
+
+impl ToolkitTransport for PollingTransport {
+    async fn send(&mut self, text: String) -> Result<()> {
+        self.sent += 1;
+        let url = format!("{}/messages", self.session_url);
+
+        let mut retries = 0;
+        loop {
+            let request = self
+                .requests
+                .api_client
+                .post(&url)
+                .header(SEQUENCE_HEADER, self.sent)
+                .header(CONTENT_TYPE, "application/json")
+                .body(text.clone())
+                .timeout(self.requests.timeout);
+            let result = self
+                .requests
+                .api_client
+                .send(request)
+                .await
+                .and_then(Response::error_for_status);
+
+            match result {
+                Ok(_) => return Ok(()),
+                Err(e) if retries < self.requests.max_retries && is_transient(&e) => {
+                    sleep(self.requests.retry_base_delay * 2u32.pow(retries)).await;
+                    retries += 1;
+                }
+                Err(e) => {
+                    return Err(ToolkitError::from_request(
+                        e,
+                        "polling send",
+                        self.requests.timeout,
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Polls keep the session alive.
+    async fn ping(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn recv(&mut self) -> Option<Result<Incoming>> {
+        self.incoming.recv().await.map(Ok)
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.poller.abort();
+
+        let request = self
+            .requests
+            .api_client
+            .delete(&self.session_url)
+            .timeout(self.requests.timeout);
+        let response =
+            self.requests.api_client.send(request).await.map_err(|e| {
+                ToolkitError::from_request(e, "polling close", self.requests.timeout)
+            })?;
+        if !is_gone(response.status()) {
+            response.error_for_status()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for PollingTransport {
+    fn drop(&mut self) {
+        self.poller.abort();
+    }
+}
+
+impl Requests {
+    /// Poll the messages at `url` until the session ends, or more than `max_retries` polls in a
+    /// row fail. The service stops with [ShutdownReason::ConnectionLost] once `sender` is dropped.
+    ///
+    /// [ShutdownReason::ConnectionLost]: super::ShutdownReason::ConnectionLost
+    async fn poll(self, url: String, sender: UnboundedSender<Incoming>) {
+        let mut after = 0;
+        let mut failures = 0;
+
+        loop {
+            let mut poll = match self.poll_once(&url, after).await {
+                Ok(Some(poll)) => poll,
+                Ok(None) => return,
+                Err(e) => {
+                    failures += 1;
+                    if sender.send(Incoming::Failed(e)).is_err() || failures > self.max_retries {
+                        return;
+                    }
+                    sleep(self.retry_base_delay * 2u32.pow(failures - 1)).await;
+                    continue;
+                }
+            };
+            failures = 0;
+
+            poll.messages.sort_by_key(|polled| polled.seq);
+            for polled in poll.messages {
+                // Already received, in the answer to an earlier poll.
+                if polled.seq <= after {
+                    continue;
+                }
+                after = polled.seq;
+
+                let text = Bytes::from(String::from(Box::<str>::from(polled.message)));
+                if sender.send(Incoming::Text(text)).is_err() {
+                    return;
+                }
+            }
+
+            if let Some(closed) = poll.closed {
+                let _ = sender.send(Incoming::Closed {
+                    code: closed.code,
+                    reason: closed
+                        .reason
+                        .filter(|reason| !reason.is_empty())
+                        .map(Into::into),
+                });
+                return;
+            }
+        }
+    }
+
+    /// The messages after `after`, `None` if the session is gone.
+    async fn poll_once(&self, url: &str, after: u64) -> Result<Option<Poll>> {
+        let timeout = POLL_WAIT + self.timeout;
+        let request = self
+            .api_client
+            .get(url)
+            .query(&[("after", after), ("waitMs", POLL_WAIT.as_millis() as u64)])
+            .timeout(timeout);
+        let response = self
+            .api_client
+            .send(request)
+            .await
+            .map_err(|e| ToolkitError::from_request(e, "poll", timeout))?;
+        if is_gone(response.status()) {
+            return Ok(None);
+        }
+
+        self.json(response.error_for_status()?).await.map(Some)
+    }
+
+    async fn json<T: DeserializeOwned>(&self, response: Response) -> Result<T> {
+        let url = response.url().clone();
+        let body = response.bytes().await?;
+        self.api_client.log_response_body(&url, &body);
+
+        Ok(serde_json::from_slice(&body)?)
+    }
+}
+
+fn is_gone(status: StatusCode) -> bool {
+    matches!(status, StatusCode::NOT_FOUND | StatusCode::GONE)
+}
+
+/// Whether a request may succeed if sent again.
+fn is_transient(e: &reqwest::Error) -> bool {
+    e.is_connect() || e.is_timeout() || e.status().is_some_and(|s| s.is_server_error())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures,
+        toolkit::{
+            messages::ToolkitMessage, Action, ActionContext, ActionDefinition, ActionParams,
+            ActionResult, ShutdownReason, ToolkitService, TransportPreference,
+        },
+    };
+    use bytes::Bytes;
+    use http_body_util::{BodyExt, Full};
+    use hyper::{body::Incoming as Body, service::service_fn, Method, Request};
+    use hyper_util::{
+        rt::{TokioExecutor, TokioIo},
+        server::conn::auto,
+    };
+    use serde_json::{json, Value};
+    use std::{
+        collections::BTreeMap,
+        convert::Infallible,
+        sync::{Arc, Mutex},
+    };
+    use tokio::{net::TcpListener, sync::Notify, time::timeout};
+
+    struct Echo;
+
+    impl Action for Echo {
+        const NAME: &'static str = "echo";
+
+        type Error = Infallible;
+        type Args = Value;
+        type Output = Value;
+
+        async fn definition(&self) -> ActionDefinition {
+            ActionDefinition {
+                description: "Echo the payload".to_string(),
+                payload: json!({}),
+                payment: None,
+                ..Default::default()
+            }
+        }
+
+        async fn call(
+            &self,
+            _ctx: ActionContext,
+            params: ActionParams<Self::Args>,
+        ) -> std::result::Result<ActionResult<Self::Output>, Self::Error> {
+            Ok(ActionResult {
+                payload: params.payload,
+                payment: None,
+            })
+        }
+    }
+
+    /// A scripted polling backend with a single session.
+    #[derive(Default)]
+    struct Backend {
+        state: Mutex<State>,
+        queued: Notify,
+    }
+
+    #[derive(Default)]
+    struct State {
+        /// The messages for the toolkit, numbered from 1.
+        queued: Vec<String>,
+        /// The first copy of each message posted by the toolkit, by sequence number.
+        posted: BTreeMap<u64, String>,
+        /// Posts of a sequence number already received.
+        duplicates: usize,
+        /// The `after` of each poll.
+        polls: Vec<u64>,
+        /// Most messages per poll answer.
+        batch: usize,
+        /// Answer polls with the last message already received too, like a server that didn't
+        /// see the previous poll complete.
+        replay: bool,
+        /// Polls answered with a 503.
+        failing_polls: usize,
+        /// Posts answered with a 503 after being received, as if the answer was lost.
+        failing_posts: usize,
+        closed: Option<(u16, &'static str)>,
+        /// Answer polls with a 404, as for an expired session.
+        gone: bool,
+        deleted: bool,
+    }
+
+    impl Backend {
+        async fn serve(state: State) -> (String, Arc<Self>) {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let backend = Arc::new(Self {
+                state: Mutex::new(state),
+                queued: Notify::new(),
+            });
+
+            let server = backend.clone();
+            spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let server = server.clone();
+                    spawn(async move {
+                        let service = service_fn(|request| {
+                            let server = server.clone();
+                            async move { Ok::<_, Infallible>(server.handle(request).await) }
+                        });
+                        let _ = auto::Builder::new(TokioExecutor::new())
+                            .serve_connection(TokioIo::new(stream), service)
+                            .await;
+                    });
+                }
+            });
+
+            (format!("http://{addr}/poll"), backend)
+        }
+
+        fn queue(&self, message: Vec<u8>) {
+            let mut state = self.state.lock().unwrap();
+            state.queued.push(String::from_utf8(message).unwrap());
+            self.queued.notify_waiters();
+        }
+
+        fn posted(&self) -> Vec<ToolkitMessage> {
+            let state = self.state.lock().unwrap();
+            state
+                .posted
+                .values()
+                .map(|text| serde_json::from_str(text).unwrap())
+                .collect()
+        }
+
+        async fn handle(&self, request: Request<Body>) -> hyper::Response<Full<Bytes>> {
+            let method = request.method().clone();
+            let path = request.uri().path().to_string();
+            let query = request.uri().query().unwrap_or_default().to_string();
+            let sequence = request
+                .headers()
+                .get(SEQUENCE_HEADER)
+                .map(|value| value.to_str().unwrap().parse::<u64>().unwrap());
+            let body = request.into_body().collect().await.unwrap().to_bytes();
+
+            match (method, path.as_str()) {
+                (Method::POST, "/poll/sessions") => respond(200, json!({ "sessionId": "s1" })),
+                (Method::POST, "/poll/sessions/s1/messages") => {
+                    let mut state = self.state.lock().unwrap();
+                    let text = String::from_utf8(body.to_vec()).unwrap();
+                    if state.posted.insert(sequence.unwrap(), text).is_some() {
+                        state.duplicates += 1;
+                    }
+                    if state.failing_posts > 0 {
+                        state.failing_posts -= 1;
+                        return respond(503, json!({}));
+                    }
+                    respond(200, json!({}))
+                }
+                (Method::GET, "/poll/sessions/s1/messages") => {
+                    let after = query
+                        .split('&')
+                        .find_map(|pair| pair.strip_prefix("after="))
+                        .unwrap()
+                        .parse::<u64>()
+                        .unwrap();
+                    self.poll(after).await
+                }
+                (Method::DELETE, "/poll/sessions/s1") => {
+                    self.state.lock().unwrap().deleted = true;
+                    respond(200, json!({}))
+                }
+                _ => respond(404, json!({})),
+            }
+        }
+
+        async fn poll(&self, after: u64) -> hyper::Response<Full<Bytes>> {
+            {
+                let mut state = self.state.lock().unwrap();
+                state.polls.push(after);
+                if state.gone {
+                    return respond(404, json!({}));
+                }
+                if state.failing_polls > 0 {
+                    state.failing_polls -= 1;
+                    return respond(503, json!({}));
+                }
+            }
+
+            // Hold the poll a little while there's nothing to answer.
+            let _ = timeout(Duration::from_millis(100), async {
+                loop {
+                    let queued = self.queued.notified();
+                    if self.state.lock().unwrap().queued.len() as u64 > after {
+                        return;
+                    }
+                    queued.await;
+                }
+            })
+            .await;
+
+            let state = self.state.lock().unwrap();
+            let from = if state.replay {
+                after.saturating_sub(1)
+            } else {
+                after
+            };
+            let messages: Vec<Value> = state
+                .queued
+                .iter()
+                .zip(1u64..)
+                .skip(from as usize)
+                .take(state.batch.max(1))
+                .map(|(message, seq)| {
+                    json!({ "seq": seq, "message": serde_json::from_str::<Value>(message).unwrap() })
+                })
+                .collect();
+            let closed = state
+                .closed
+                .filter(|_| state.queued.len() as u64 <= after + messages.len() as u64)
+                .map(|(code, reason)| json!({ "code": code, "reason": reason }));
+
+            respond(200, json!({ "messages": messages, "closed": closed }))
+        }
+    }
+
+    fn respond(status: u16, body: Value) -> hyper::Response<Full<Bytes>> {
+        hyper::Response::builder()
+            .status(status)
+            .header("content-type", "application/json")
+            .body(Full::new(Bytes::from(body.to_string())))
+            .unwrap()
+    }
+
+    fn service_at(endpoint: String, transport: TransportPreference) -> ToolkitService {
+        let mut service = ToolkitService::from_config(UnifaiConfig {
+            toolkit_api_key: Some("test".to_string()),
+            // Nothing listens there.
+            backend_ws_endpoint: "ws://127.0.0.1:1".to_string(),
+            backend_poll_endpoint: endpoint,
+            retry_base_delay: Duration::from_millis(10),
+            ..UnifaiConfig::default()
+        })
+        .unwrap()
+        .with_transport(transport);
+        service.add_action(Echo);
+
+        service
+    }
+
+    /// The action ids of the results posted so far.
+    fn result_ids(backend: &Backend) -> Vec<u64> {
+        backend
+            .posted()
+            .into_iter()
+            .filter_map(|message| match message {
+                ToolkitMessage::ActionResult { data } => Some(data.action_id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_to_polling_and_delivers_every_call_once() {
+        let (endpoint, backend) = Backend::serve(State {
+            batch: 2,
+            replay: true,
+            failing_polls: 1,
+            failing_posts: 1,
+            ..State::default()
+        })
+        .await;
+
+        let handle = service_at(endpoint.clone(), TransportPreference::Auto)
+            .start()
+            .await
+            .unwrap();
+        assert_eq!(handle.endpoint(), Some(endpoint.as_str()));
+
+        for action_id in 1..=5 {
+            let payload = json!({ "n": action_id });
+            backend.queue(fixtures::action_frame("echo", action_id, &payload));
+        }
+
+        timeout(Duration::from_secs(5), async {
+            while result_ids(&backend).len() < 5 {
+                sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("every call gets a result");
+
+        let reason = handle.shutdown().await.unwrap().unwrap();
+        assert_eq!(reason, ShutdownReason::GracefulRequested { drained: true });
+
+        let mut ids = result_ids(&backend);
+        ids.sort();
+        assert_eq!(ids, [1, 2, 3, 4, 5]);
+        assert!(matches!(
+            backend.posted()[0],
+            ToolkitMessage::RegisterActions { .. }
+        ));
+
+        let state = backend.state.lock().unwrap();
+        // The registration was received twice, its first answer failed.
+        assert_eq!(state.duplicates, 1);
+        // The failed poll was repeated, and polls never went back.
+        assert_eq!(state.polls[..2], [0, 0]);
+        assert!(state.polls.is_sorted());
+        assert!(state.deleted);
+    }
+
+    #[tokio::test]
+    async fn test_server_closes_the_session() {
+        let (endpoint, _backend) = Backend::serve(State {
+            closed: Some((4000, "maintenance")),
+            ..State::default()
+        })
+        .await;
+
+        let handle = service_at(endpoint, TransportPreference::Polling)
+            .start()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            handle.await.unwrap().unwrap(),
+            ShutdownReason::ServerClosed {
+                code: Some(4000),
+                reason: Some("maintenance".into()),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stops_when_the_session_is_gone() {
+        let (endpoint, backend) = Backend::serve(State::default()).await;
+
+        let handle = service_at(endpoint, TransportPreference::Polling)
+            .start()
+            .await
+            .unwrap();
+        backend.state.lock().unwrap().gone = true;
+
+        assert_eq!(
+            handle.await.unwrap().unwrap(),
+            ShutdownReason::ConnectionLost
+        );
+    }
+}
@@ -0,0 +1,117 @@
+//! Record the action calls received from the server, to replay them as regression tests.
+
+use crate::logging::{events as log_events, targets};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+/// Receives each recorded call, see [ToolkitService::with_recorder](super::ToolkitService::with_recorder).
+pub(crate) type Recorder = Arc<dyn Fn(&RecordedCall) + Send + Sync>;
+
+/// An action call received from the server and its result, after redaction.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordedCall {
+    pub action: String,
+    pub action_id: u64,
+    pub agent_id: u64,
+    /// The payload as received, a string if the server sent it encoded.
+    pub payload: Value,
+    pub payment: Option<u64>,
+    /// The payload of the result.
+    pub result: Value,
+    pub result_payment: Option<u64>,
+}
+
+/// A recorder appending the calls to the file at `path` as JSON lines, creating it if needed.
+///
+/// Failing to write a call is logged, and doesn't affect the call.
+pub fn record_to_file(
+    path: impl AsRef<Path>,
+) -> std::io::Result<impl Fn(&RecordedCall) + Send + Sync + 'static> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let file = Mutex::new(file);
+
+    Ok(move |call: &RecordedCall| {
+        let mut line = serde_json::to_vec(call).expect("a recorded call always serializes");
+        line.push(b'\n');
+
+        if let Err(e) = file.lock().unwrap().write_all(&line) {
+            tracing::warn!(
+                target: targets::DISPATCH,
+                event = log_events::RECORD_FAILED,
+                action_id = call.action_id,
+                error = %e,
+                "Failed to record action call"
+            );
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toolkit::{
+        testing::ToolkitTestHarness, Action, ActionContext, ActionDefinition, ActionParams,
+        ActionResult, ToolkitService,
+    };
+    use serde_json::json;
+
+    struct Login;
+
+    impl Action for Login {
+        const NAME: &'static str = "login";
+
+        type Error = std::convert::Infallible;
+        type Args = Value;
+        type Output = Value;
+
+        async fn definition(&self) -> ActionDefinition {
+            ActionDefinition {
+                description: "Log in".to_string(),
+                payload: json!({}),
+                payment: None,
+                ..Default::default()
+            }
+        }
+
+        async fn call(
+            &self,
+            _ctx: ActionContext,
+            params: ActionParams<Self::Args>,
+        ) -> Result<ActionResult<Self::Output>, Self::Error> {
+            Ok(ActionResult {
+                payload: json!({ "user": params.payload["user"], "token": "session-secret" }),
+                payment: Some(3),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_records_redacted_calls() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let recorded = calls.clone();
+        let mut service = ToolkitService::new("test")
+            .with_recorder(move |call: &RecordedCall| recorded.lock().unwrap().push(call.clone()));
+        service.add_action(Login);
+        let mut harness = ToolkitTestHarness::start(service).await.unwrap();
+
+        let payload = json!({ "user": "ada", "password": "hunter2" });
+        harness.send_action_call("login", payload, 9).await;
+        harness.shutdown().await.unwrap();
+
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].action, "login");
+        assert_eq!(calls[0].agent_id, 9);
+        assert_eq!(calls[0].payload["user"], "ada");
+        assert_ne!(calls[0].payload["password"], "hunter2");
+        assert_ne!(calls[0].result["token"], "session-secret");
+        assert_eq!(calls[0].result_payment, Some(3));
+    }
+}
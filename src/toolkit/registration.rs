@@ -0,0 +1,208 @@
+//! Register actions where they're defined with [register_action](crate::register_action), instead
+//! of listing every one of them where the service is built.
+
+use super::{action::ActionDyn, Action, ExecutionMode, ToolkitService};
+use std::collections::HashMap;
+
+/// An action registered with [register_action](crate::register_action).
+#[doc(hidden)]
+pub struct RegisteredAction {
+    pub constructor: fn() -> BoxedAction,
+    pub file: &'static str,
+    pub line: u32,
+}
+
+#[doc(hidden)]
+pub struct BoxedAction(Box<dyn ActionDyn>);
+
+impl BoxedAction {
+    pub fn new(action: impl Action + 'static) -> Self {
+        Self(Box::new(action))
+    }
+}
+
+inventory::collect!(RegisteredAction);
+
+/// Register an action, to be added by [ToolkitService::add_registered_actions] from whichever
+/// module or crate of the binary it's registered in.
+///
+/// The argument is an expression building the action, such as a unit struct or a constructor call.
+/// It's evaluated each time the registered actions are added.
+///
+/// # Example
+/// ```ignore
+/// struct EchoSlam;
+///
+/// impl Action for EchoSlam {
+///     // ...
+/// }
+///
+/// unifai_sdk::register_action!(EchoSlam);
+/// ```
+#[macro_export]
+macro_rules! register_action {
+    ($action:expr) => {
+        $crate::toolkit::__private::inventory::submit! {
+            $crate::toolkit::__private::RegisteredAction {
+                constructor: || $crate::toolkit::__private::BoxedAction::new($action),
+                file: ::core::file!(),
+                line: ::core::line!(),
+            }
+        }
+    };
+}
+
+/// An action name registered more than once, see [ToolkitService::add_registered_actions].
+#[derive(Debug, thiserror::Error)]
+#[error("Action {name:?} is registered twice, at {first} and {second}")]
+pub struct DuplicateActionError {
+    pub name: String,
+    /// Where the action was registered first, as `file:line`, or `ToolkitService::add_action`.
+    pub first: String,
+    /// Where the action was registered again, as `file:line`.
+    pub second: String,
+}
+
+impl ToolkitService {
+    /// Add every action registered with [register_action](crate::register_action) in the binary.
+    ///
+    /// Fails without adding any of them if two registrations, or a registration and an action
+    /// added with [ToolkitService::add_action], have the same name.
+    ///
+    /// # Panics
+    ///
+    /// If the name of a registered action is invalid, see [ToolkitService::try_add_action].
+    pub fn add_registered_actions(&mut self) -> Result<(), DuplicateActionError> {
+        add_actions(self, inventory::iter::<RegisteredAction>)
+    }
+}
+
+fn add_actions<'a>(
+    service: &mut ToolkitService,
+    registrations: impl IntoIterator<Item = &'a RegisteredAction>,
+) -> Result<(), DuplicateActionError> {
+    // The registrations come in link order, sort them so errors are reproducible.
+    let mut registrations: Vec<_> = registrations.into_iter().collect();
+    registrations.sort_by_key(|registration| (registration.file, registration.line));
+
+    let mut actions = HashMap::new();
+    for registration in registrations {
+        let action = (registration.constructor)().0;
+        let name = match service.checked_name(&action.name()) {
+            Ok(name) => name.to_string(),
+            Err(e) => panic!(
+                "{e}, registered at {}:{}",
+                registration.file, registration.line
+            ),
+        };
+        let site = format!("{}:{}", registration.file, registration.line);
+
+        let first = match actions.get(&name) {
+            Some((first, _)) => Some(String::clone(first)),
+            None if service.actions().contains(&name) => {
+                Some("ToolkitService::add_action".to_string())
+            }
+            None => None,
+        };
+        if let Some(first) = first {
+            return Err(DuplicateActionError {
+                name,
+                first,
+                second: site,
+            });
+        }
+
+        actions.insert(name, (site, action));
+    }
+
+    let mut table = service.actions_mut();
+    for (name, (_, action)) in actions {
+        table.insert(name.into(), action.into(), ExecutionMode::Spawned);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toolkit::{
+        testing::ToolkitTestHarness, ActionContext, ActionDefinition, ActionParams, ActionResult,
+    };
+    use serde_json::{json, Value};
+
+    struct Echo;
+
+    impl Action for Echo {
+        const NAME: &'static str = "echo";
+
+        type Error = std::convert::Infallible;
+        type Args = Value;
+        type Output = Value;
+
+        async fn definition(&self) -> ActionDefinition {
+            ActionDefinition {
+                description: "Echo the payload".to_string(),
+                payload: json!({}),
+                payment: None,
+                ..Default::default()
+            }
+        }
+
+        async fn call(
+            &self,
+            _ctx: ActionContext,
+            params: ActionParams<Self::Args>,
+        ) -> Result<ActionResult<Self::Output>, Self::Error> {
+            Ok(ActionResult {
+                payload: params.payload,
+                payment: None,
+            })
+        }
+    }
+
+    fn echo_at(file: &'static str, line: u32) -> RegisteredAction {
+        RegisteredAction {
+            constructor: || BoxedAction::new(Echo),
+            file,
+            line,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_adds_registered_actions() {
+        let mut service = ToolkitService::new("test");
+
+        add_actions(&mut service, &[echo_at("src/echo.rs", 3)]).unwrap();
+
+        let mut harness = ToolkitTestHarness::start(service).await.unwrap();
+        assert_eq!(
+            harness.registered_actions()["echo"].description,
+            "Echo the payload"
+        );
+        let result = harness.send_action_call("echo", json!({ "n": 1 }), 1).await;
+        assert_eq!(result.payload.get(), r#"{"n":1}"#);
+
+        harness.shutdown().await.unwrap();
+    }
+
+    #[test]
+    fn test_rejects_duplicate_names() {
+        let mut service = ToolkitService::new("test");
+
+        let error = add_actions(
+            &mut service,
+            &[echo_at("src/b.rs", 7), echo_at("src/a.rs", 12)],
+        )
+        .unwrap_err();
+        assert_eq!(error.name, "echo");
+        assert_eq!(error.first, "src/a.rs:12");
+        assert_eq!(error.second, "src/b.rs:7");
+        assert_eq!(service.actions().len(), 0);
+
+        service.add_action(Echo);
+        let error = add_actions(&mut service, &[echo_at("src/a.rs", 12)]).unwrap_err();
+        assert_eq!(error.first, "ToolkitService::add_action");
+        assert_eq!(error.second, "src/a.rs:12");
+    }
+}
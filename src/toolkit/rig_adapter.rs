@@ -0,0 +1,220 @@
+//! Publish [rig] tools as actions, the reverse of [CallTool](crate::tools::CallTool).
+//!
+//! Rig tools know nothing of the Unifai side of a call, so the adapter fills the gaps:
+//!
+//! - The definition is requested with an empty prompt, as actions are defined once for every agent.
+//! - The JSON Schema parameters of the tool become the payload fields, each object property turned
+//!   into a field with a `required` flag.
+//! - The [ActionContext] is dropped, tools can't tell which agent called them.
+//! - The payment of the call is ignored, and the result never charges one.
+//! - The output is serialized as the result payload. Tools returning a `String` give a JSON string
+//!   payload, which is what agents get from a rig agent too.
+//! - Errors of the tool become [ToolkitError::ActionCallError](super::ToolkitError::ActionCallError),
+//!   like the errors of any action, and arguments that don't deserialize become
+//!   [ToolkitError::PayloadDecode](super::ToolkitError::PayloadDecode).
+//!
+//! # Example
+//!
+//! ```ignore
+//! let mut service = ToolkitService::new("UNIFAI_TOOLKIT_API_KEY");
+//! service.add_action(action_from_tool(Adder));
+//! ```
+
+use super::{Action, ActionContext, ActionDefinition, ActionParams, ActionResult};
+use rig::tool::Tool;
+use serde_json::{Map, Value};
+
+/// An action calling a rig tool, see [action_from_tool].
+pub struct ToolAction<T: Tool> {
+    tool: T,
+}
+
+/// Wrap a rig tool as an action, to be added with [ToolkitService::add_action](super::ToolkitService::add_action).
+pub fn action_from_tool<T: Tool>(tool: T) -> ToolAction<T> {
+    ToolAction { tool }
+}
+
+impl<T: Tool> ToolAction<T> {
+    /// The wrapped tool.
+    pub fn tool(&self) -> &T {
+        &self.tool
+    }
+}
+
+impl<T: Tool> Action for ToolAction<T> {
+    const NAME: &'static str = T::NAME;
+
+    type Error = T::Error;
+    type Args = T::Args;
+    type Output = T::Output;
+
+    fn name(&self) -> String {
+        self.tool.name()
+    }
+
+    async fn definition(&self) -> ActionDefinition {
+        let definition = self.tool.definition(String::new()).await;
+
+        ActionDefinition {
+            description: definition.description,
+            payload: payload_fields(definition.parameters),
+            payment: None,
+            ..Default::default()
+        }
+    }
+
+    async fn call(
+        &self,
+        _ctx: ActionContext,
+        params: ActionParams<Self::Args>,
+    ) -> Result<ActionResult<Self::Output>, Self::Error> {
+        Ok(ActionResult {
+            payload: self.tool.call(params.payload).await?,
+            payment: None,
+        })
+    }
+}
+
+/// Turn an object schema into payload fields, the inverse of [ActionDefinition::input_schema].
+///
+/// Schemas that aren't a plain object with `properties` are kept as they are, which
+/// [ActionDefinition::input_schema] passes through.
+fn payload_fields(schema: Value) -> Value {
+    let Value::Object(mut schema) = schema else {
+        return schema;
+    };
+    if schema.get("type").and_then(Value::as_str) != Some("object") {
+        return Value::Object(schema);
+    }
+    let Some(Value::Object(properties)) = schema.remove("properties") else {
+        return Value::Object(Map::new());
+    };
+
+    let required = schema.get("required").and_then(Value::as_array);
+    let is_required = |name: &str| required.is_some_and(|r| r.iter().any(|n| n == name));
+
+    let fields = properties
+        .into_iter()
+        .map(|(name, mut field)| {
+            if let Some(field) = field.as_object_mut() {
+                if is_required(&name) {
+                    field.insert("required".to_string(), Value::Bool(true));
+                }
+            }
+            (name, field)
+        })
+        .collect();
+
+    Value::Object(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toolkit::{messages::ActionCallParams, service::dispatch_action, ToolkitService};
+    use rig::completion::ToolDefinition;
+    use serde::Deserialize;
+    use serde_json::{json, value::to_raw_value};
+
+    struct Adder;
+
+    #[derive(Deserialize)]
+    struct AdderArgs {
+        x: i64,
+        y: i64,
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("overflow")]
+    struct Overflow;
+
+    impl Tool for Adder {
+        const NAME: &'static str = "add";
+
+        type Error = Overflow;
+        type Args = AdderArgs;
+        type Output = i64;
+
+        async fn definition(&self, _prompt: String) -> ToolDefinition {
+            ToolDefinition {
+                name: Self::NAME.to_string(),
+                description: "Add x and y".to_string(),
+                parameters: json!({
+                    "type": "object",
+                    "properties": {
+                        "x": { "type": "number", "description": "The first number" },
+                        "y": { "type": "number", "description": "The second number" },
+                    },
+                    "required": ["x"],
+                }),
+            }
+        }
+
+        async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+            args.x.checked_add(args.y).ok_or(Overflow)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_definition() {
+        let definition = Action::definition(&action_from_tool(Adder)).await;
+
+        assert_eq!(definition.description, "Add x and y");
+        assert_eq!(
+            definition.payload,
+            json!({
+                "x": { "type": "number", "description": "The first number", "required": true },
+                "y": { "type": "number", "description": "The second number" },
+            })
+        );
+        assert_eq!(
+            definition.input_schema(),
+            Adder.definition(String::new()).await.parameters
+        );
+    }
+
+    #[tokio::test]
+    async fn test_call() {
+        let mut service = ToolkitService::new("test");
+        service.add_action(action_from_tool(Adder));
+
+        let call = |payload| ActionCallParams {
+            action: "add".to_string(),
+            action_id: 1,
+            agent_id: 2,
+            payload: to_raw_value(&payload).unwrap(),
+            payment: Some(3),
+            trace_context: None,
+            extra: Default::default(),
+        };
+
+        let result = dispatch_action(&service, call(json!({ "x": 1, "y": 2 })))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.payload, 3);
+        assert_eq!(result.payment, None);
+
+        let error = dispatch_action(&service, call(json!({ "x": i64::MAX, "y": 1 })))
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(error.to_string(), "ActionCallError: overflow");
+
+        let error = dispatch_action(&service, call(json!({ "y": 1 })))
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            crate::toolkit::ToolkitError::PayloadDecode { .. }
+        ));
+    }
+
+    #[test]
+    fn test_payload_fields_keeps_other_schemas() {
+        let schema = json!({ "type": "string" });
+        assert_eq!(payload_fields(schema.clone()), schema);
+        assert_eq!(payload_fields(json!({ "type": "object" })), json!({}));
+    }
+}
@@ -0,0 +1,204 @@
+//! A builder of action payloads, so the schema of a payload is checked by the compiler rather
+//! than written by hand with `json!`.
+//!
+//! The builder writes the field format of [ActionDefinition::payload](super::ActionDefinition),
+//! `{"content": {"type": "string", "description": "...", "required": true}}`:
+//!
+//! ```
+//! # use unifai_sdk::toolkit::{ActionDefinition, Field, PayloadSchema};
+//! let definition = ActionDefinition {
+//!     description: "Echo the message".to_string(),
+//!     payload: PayloadSchema::object()
+//!         .with_field("content", Field::string().with_description("The content to echo.").required())
+//!         .with_field("times", Field::integer())
+//!         .into(),
+//!     payment: None,
+//!     ..Default::default()
+//! };
+//! ```
+
+use serde_json::{json, Map, Value};
+
+/// The schema of an action payload, turned into the [ActionDefinition](super::ActionDefinition)
+/// payload with `into()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PayloadSchema {
+    schema: Map<String, Value>,
+}
+
+impl PayloadSchema {
+    /// A payload with no field yet.
+    pub fn object() -> Self {
+        Self { schema: Map::new() }
+    }
+
+    /// The JSON Schema of the type `T`, from its `JsonSchema` derive.
+    #[cfg(feature = "schemars")]
+    pub fn from_type<T: schemars::JsonSchema>() -> Self {
+        let schema = serde_json::to_value(schemars::schema_for!(T))
+            .expect("a JSON schema always serializes");
+        let Value::Object(schema) = schema else {
+            unreachable!("a JSON schema is an object");
+        };
+
+        Self { schema }
+    }
+
+    /// Add a field, replacing any field of the same name.
+    pub fn with_field(mut self, name: impl Into<String>, field: Field) -> Self {
+        let name = name.into();
+        if !self.is_json_schema() {
+            self.schema.insert(name, Value::Object(field.schema));
+            return self;
+        }
+
+        // A field of a JSON Schema tells that it's required in the list of the object instead.
+        let mut field = field.schema;
+        if field.remove("required") == Some(Value::Bool(true)) {
+            let required = self.schema.entry("required").or_insert_with(|| json!([]));
+            if let Some(required) = required.as_array_mut() {
+                required.push(Value::String(name.clone()));
+            }
+        }
+        let properties = self.schema.entry("properties").or_insert_with(|| json!({}));
+        if let Some(properties) = properties.as_object_mut() {
+            properties.insert(name, Value::Object(field));
+        }
+
+        self
+    }
+
+    /// Whether the payload is a JSON Schema, with a top-level `type`, rather than fields.
+    fn is_json_schema(&self) -> bool {
+        self.schema.get("type").is_some_and(Value::is_string)
+    }
+}
+
+impl From<PayloadSchema> for Value {
+    fn from(schema: PayloadSchema) -> Self {
+        Value::Object(schema.schema)
+    }
+}
+
+/// A field of a [PayloadSchema].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Field {
+    schema: Map<String, Value>,
+}
+
+impl Field {
+    /// A field of the JSON type `kind`, such as `"string"`.
+    pub fn of_type(kind: &str) -> Self {
+        let mut schema = Map::new();
+        schema.insert("type".to_string(), Value::String(kind.to_string()));
+
+        Self { schema }
+    }
+
+    pub fn string() -> Self {
+        Self::of_type("string")
+    }
+
+    pub fn number() -> Self {
+        Self::of_type("number")
+    }
+
+    pub fn integer() -> Self {
+        Self::of_type("integer")
+    }
+
+    pub fn boolean() -> Self {
+        Self::of_type("boolean")
+    }
+
+    /// A list of `items`.
+    pub fn array(items: Field) -> Self {
+        Self::of_type("array").with("items", Value::Object(items.schema))
+    }
+
+    pub fn with_description(self, description: impl Into<String>) -> Self {
+        self.with("description", Value::String(description.into()))
+    }
+
+    /// Only let the field take one of `values`.
+    pub fn with_enum(self, values: impl IntoIterator<Item = impl Into<Value>>) -> Self {
+        let values = values.into_iter().map(Into::into).collect();
+        self.with("enum", Value::Array(values))
+    }
+
+    /// Make the field required, it's optional otherwise.
+    pub fn required(self) -> Self {
+        self.with("required", Value::Bool(true))
+    }
+
+    /// Set any other keyword of the field, such as `default` or `minimum`.
+    pub fn with(mut self, keyword: &str, value: Value) -> Self {
+        self.schema.insert(keyword.to_string(), value);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toolkit::ActionDefinition;
+
+    #[test]
+    fn test_builds_fields() {
+        let payload: Value = PayloadSchema::object()
+            .with_field(
+                "content",
+                Field::string()
+                    .with_description("The content to echo.")
+                    .required(),
+            )
+            .with_field("tags", Field::array(Field::string().with_enum(["a", "b"])))
+            .with_field("times", Field::integer().with("minimum", json!(1)))
+            .into();
+
+        assert_eq!(
+            payload,
+            json!({
+                "content": {
+                    "type": "string",
+                    "description": "The content to echo.",
+                    "required": true
+                },
+                "tags": { "type": "array", "items": { "type": "string", "enum": ["a", "b"] } },
+                "times": { "type": "integer", "minimum": 1 }
+            })
+        );
+
+        let definition = ActionDefinition {
+            description: "Echo".to_string(),
+            payload,
+            payment: None,
+            ..Default::default()
+        };
+        assert_eq!(definition.input_schema()["required"], json!(["content"]));
+    }
+
+    #[cfg(feature = "schemars")]
+    #[test]
+    fn test_from_type() {
+        #[derive(schemars::JsonSchema)]
+        #[allow(dead_code)]
+        struct EchoArgs {
+            /// The content to echo.
+            content: String,
+            times: Option<u32>,
+        }
+
+        let payload: Value = PayloadSchema::from_type::<EchoArgs>()
+            .with_field("loud", Field::boolean().required())
+            .into();
+
+        assert_eq!(payload["type"], "object");
+        assert_eq!(
+            payload["properties"]["content"]["description"],
+            "The content to echo."
+        );
+        assert_eq!(payload["properties"]["loud"], json!({ "type": "boolean" }));
+        assert_eq!(payload["required"], json!(["content", "loud"]));
+    }
+}
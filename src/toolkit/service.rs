@@ -1,26 +1,97 @@
+#[cfg(feature = "grpc")]
+use super::grpc::GrpcTransport;
+#[cfg(feature = "mcp")]
+use super::mcp::{McpAction, McpClient, McpError};
 use super::{
-    action::{ActionDyn, ActionResult},
-    errors::Result,
-    messages::{ActionCallParams, ActionCallResult, ActionsRegisterParams, ToolkitMessage},
-    Action, ActionContext, ActionParams,
+    action::{
+        into_raw_call, ActionDefinition, ActionDyn, ActionResult, Availability, RawCallFuture,
+    },
+    cancel::{AbortedCall, AmbiguousCancel, Answered, InFlightCalls},
+    context::{CallLink, CallMetadata, ProgressSender},
+    errors::{ActionError, IntoActionError, Result, ToolkitError},
+    events::{
+        EventSender, ShutdownReason, ToolkitEvent, ToolkitRuntimeEvent, RUNTIME_EVENT_CAPACITY,
+    },
+    idempotency::{
+        report_duplicate, CallKey, Idempotency, IdempotencyStore, MemoryIdempotencyStore,
+    },
+    limit::{Admission, CallLimiter},
+    messages::{
+        ActionCallParams, ActionCallResult, ActionsRegisterAck, ActionsRegisterParams, ClientInfo,
+        ToolkitMessage,
+    },
+    middleware::{ActionMiddleware, Next},
+    names::{is_valid_action_name, normalize_action_name, InvalidActionName},
+    observer::{ObservedCall, ToolkitObserver},
+    polling::PollingTransport,
+    recording::{RecordedCall, Recorder},
+    table::ActionTable,
+    transport::{connect_any, Incoming, ToolkitTransport, TransportPreference, WebSocketTransport},
+    validation::validate,
+    Action, ActionContext, ActionParams, CancellationToken,
 };
 use crate::{
-    constants::{DEFAULT_BACKEND_WS_ENDPOINT, DEFAULT_FRONTEND_API_ENDPOINT},
-    utils::build_api_client,
+    config::{ClientOptions, ConfigError, UnifaiConfig},
+    constants::SDK_VERSION,
+    http::{ApiClient, CredentialsError, Principal},
+    logging::{
+        default_redactor, events as log_events, frame_excerpt, outcomes, targets, Redactor,
+        WireLogging,
+    },
+    secrets::{ApiKeyProvider, SecretSource},
 };
-use futures_util::{future::join_all, SinkExt, StreamExt};
-use reqwest::Client;
+use futures_util::{future::Either, stream, FutureExt, StreamExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
-use std::{collections::HashMap, env, sync::Arc, time::Duration};
-use tokio::{net::TcpStream, spawn, sync::mpsc::unbounded_channel, task::JoinHandle, time::sleep};
-use tokio_tungstenite::{
-    connect_async,
-    tungstenite::{Bytes, Message},
-    MaybeTlsStream, WebSocketStream,
+use serde_json::{
+    json,
+    value::{to_raw_value, RawValue},
+    Map, Value,
 };
+use std::{
+    any::Any,
+    collections::HashMap,
+    future::{ready, Future},
+    panic::AssertUnwindSafe,
+    pin::Pin,
+    sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::{
+    spawn,
+    sync::{
+        broadcast,
+        mpsc::{unbounded_channel, UnboundedSender},
+        Notify,
+    },
+    task::{AbortHandle, JoinError, JoinHandle},
+    time::{interval, sleep, timeout, MissedTickBehavior},
+};
+
+/// How long a graceful shutdown waits for the calls in flight.
+const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long an inline action call may run on the dispatch loop before it moves to a task.
+const INLINE_BUDGET: Duration = Duration::from_millis(50);
 
-const PING_INTERVAL: Duration = Duration::from_millis(30_000);
+/// The most actions listed in an [ActionNotFound](ToolkitError::ActionNotFound) error, past which
+/// the list would bloat the result.
+const MAX_LISTED_ACTIONS: usize = 20;
+
+/// How much of a message of unknown type is logged.
+const UNKNOWN_FRAME_EXCERPT: usize = 1024;
+
+/// Where the calls of an action run, see [ToolkitService::add_action_with_mode].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExecutionMode {
+    /// On a task of their own, so slow calls don't hold up the others.
+    #[default]
+    Spawned,
+    /// On the dispatch loop, skipping the task and channel of a spawned call. Meant for actions
+    /// answering in microseconds, such as pure computations and cache lookups. A call still
+    /// running after 50 ms moves to a task of its own, so it can't stall the service.
+    Inline,
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct ToolkitInfo {
@@ -28,6 +99,26 @@ pub struct ToolkitInfo {
     pub description: String,
 }
 
+/// The info of a Toolkit as applied by the server, see [ToolkitService::update_info], as in
+/// `{"toolkitId":42,"name":"Echo Slam","description":"What's in, what's out."}`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ToolkitInfoResponse {
+    pub toolkit_id: Option<u64>,
+    pub name: String,
+    pub description: String,
+}
+
+/// A toolkit registered with an API key, see [ToolkitService::list_toolkits], as in
+/// `{"id":42,"name":"Echo Slam","description":"What's in, what's out."}`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ToolkitSummary {
+    pub id: u64,
+    pub name: String,
+    pub description: String,
+}
+
 /// A service that manages and runs a Toolkit.
 ///
 /// # Example
@@ -47,184 +138,4430 @@ pub struct ToolkitInfo {
 /// let _ = runner.await.unwrap();
 /// ```
 pub struct ToolkitService {
-    api_key: String,
-    api_client: Client,
-    actions: HashMap<String, Box<dyn ActionDyn>>,
+    api_key: ApiKeyProvider,
+    /// The only HTTP client of the service, whose contexts get a handle to it so every request
+    /// shares its connection pool.
+    api_client: ApiClient,
+    /// Shared with the [ToolkitHandle], to change the actions while the service runs.
+    actions: Arc<RwLock<ActionTable>>,
+    config: Arc<UnifaiConfig>,
+    pub(super) redactor: Redactor,
+    recorder: Option<Recorder>,
+    observer: Option<Arc<dyn ToolkitObserver>>,
+    /// Wrapping every action call, outermost first.
+    middlewares: Arc<[Arc<dyn ActionMiddleware>]>,
+    /// The lifecycle events, see [ToolkitService::events].
+    lifecycle: broadcast::Sender<ToolkitEvent>,
+    idempotency: Option<Arc<Idempotency>>,
+    wire: WireLogging,
+    legacy_registration: bool,
+    check_credentials: bool,
+    unchecked_names: bool,
+    validate_payloads: bool,
+    transport: TransportPreference,
 }
 
 impl ToolkitService {
     /// Create a Toolkit service with Unifai API Key.
+    ///
+    /// Endpoints are read from environment variables once, see [UnifaiConfig::from_env].
+    ///
+    /// # Panics
+    ///
+    /// If the API key isn't a valid header value, or the HTTP client can't be built, see
+    /// [ToolkitService::try_new].
     pub fn new(api_key: &str) -> Self {
+        Self::try_new(api_key).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Create a Toolkit service like [ToolkitService::new], failing if the API key isn't a valid
+    /// header value, such as one with a trailing newline, or if the HTTP client can't be built.
+    pub fn try_new(api_key: &str) -> std::result::Result<Self, ConfigError> {
+        let api_key = ApiKeyProvider::fixed(api_key, "toolkit_api_key")?;
+
+        Self::with_api_key(api_key, UnifaiConfig::from_env())
+    }
+
+    /// Create a Toolkit service with the Unifai API Key read from `source`, failing if it can't be
+    /// read. The key can then be rotated with [ToolkitService::api_key].
+    ///
+    /// Endpoints are read from environment variables once, see [UnifaiConfig::from_env].
+    pub async fn from_secret(source: SecretSource) -> std::result::Result<Self, ConfigError> {
+        let api_key = ApiKeyProvider::new(source).await?;
+
+        Self::with_api_key(api_key, UnifaiConfig::from_env())
+    }
+
+    /// Create a Toolkit service from a config, which must contain the toolkit API key.
+    pub fn from_config(config: UnifaiConfig) -> std::result::Result<Self, ConfigError> {
+        let api_key = ApiKeyProvider::fixed(config.require_toolkit_api_key()?, "toolkit_api_key")?;
+
+        Self::with_api_key(api_key, config)
+    }
+
+    fn with_api_key(
+        api_key: ApiKeyProvider,
+        config: UnifaiConfig,
+    ) -> std::result::Result<Self, ConfigError> {
+        Ok(Self {
+            api_client: ApiClient::new(api_key.clone(), &config.client)?,
+            api_key,
+            actions: Arc::default(),
+            config: Arc::new(config),
+            redactor: default_redactor(),
+            recorder: None,
+            observer: None,
+            middlewares: Arc::new([]),
+            lifecycle: broadcast::channel(RUNTIME_EVENT_CAPACITY).0,
+            idempotency: None,
+            wire: WireLogging::default(),
+            legacy_registration: false,
+            check_credentials: false,
+            unchecked_names: false,
+            validate_payloads: false,
+            transport: TransportPreference::default(),
+        })
+    }
+
+    /// Set the timeout of the WebSocket handshake, or of opening a long-polling session, in
+    /// [ToolkitService::start]. Defaults to 10 seconds.
+    pub fn with_connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        Arc::make_mut(&mut self.config).connect_timeout = connect_timeout;
+        self
+    }
+
+    /// Set the timeout of one-off HTTP requests, such as [ToolkitService::update_info] and
+    /// [ActionContext::create_transaction]. Defaults to 15 seconds.
+    pub fn with_request_timeout(mut self, request_timeout: Duration) -> Self {
+        Arc::make_mut(&mut self.config).request_timeout = request_timeout;
+        self
+    }
+
+    /// Set the maximum number of action definitions evaluated at once in [ToolkitService::start].
+    /// Defaults to 8.
+    pub fn with_definition_concurrency(mut self, concurrency: usize) -> Self {
+        Arc::make_mut(&mut self.config).definition_concurrency = concurrency;
+        self
+    }
+
+    /// Set the timeout of evaluating one action definition in [ToolkitService::start]. Defaults to
+    /// 30 seconds.
+    pub fn with_definition_timeout(mut self, definition_timeout: Duration) -> Self {
+        Arc::make_mut(&mut self.config).definition_timeout = definition_timeout;
+        self
+    }
+
+    /// Set the timeout of one action call, past which the call is dropped and answered with an
+    /// [ActionTimeout](ToolkitError::ActionTimeout) error. Actions may set their own with
+    /// [Action::timeout]. Unlimited by default.
+    pub fn with_action_timeout(mut self, action_timeout: Duration) -> Self {
+        Arc::make_mut(&mut self.config).action_timeout = Some(action_timeout);
+        self
+    }
+
+    /// Set how long a call cancelled by the server may run to stop cleanly before its task is
+    /// aborted, see [ActionContext::cancelled]. 5 seconds by default.
+    pub fn with_cancel_grace(mut self, cancel_grace: Duration) -> Self {
+        Arc::make_mut(&mut self.config).cancel_grace = cancel_grace;
+        self
+    }
+
+    /// Limit the number of action calls executed at once, the calls over the limit waiting in a
+    /// queue until others finish. Calls of actions added with [ExecutionMode::Inline] aren't
+    /// limited. Unlimited by default.
+    pub fn with_max_concurrent_actions(mut self, max_concurrent_actions: usize) -> Self {
+        Arc::make_mut(&mut self.config).max_concurrent_actions = Some(max_concurrent_actions);
+        self
+    }
+
+    /// Limit the number of action calls waiting for [ToolkitService::with_max_concurrent_actions],
+    /// the calls arriving while the queue is full being answered with a
+    /// [ToolkitBusy](ToolkitError::ToolkitBusy) error. Unlimited by default.
+    pub fn with_max_queued_actions(mut self, max_queued_actions: usize) -> Self {
+        Arc::make_mut(&mut self.config).max_queued_actions = Some(max_queued_actions);
+        self
+    }
+
+    /// Limit the size of a serialized action result, a larger result being replaced with a
+    /// [PayloadTooLarge](ToolkitError::PayloadTooLarge) error, such as to stay under the frame size
+    /// the server accepts. Unlimited by default.
+    pub fn with_max_result_bytes(mut self, max_result_bytes: usize) -> Self {
+        Arc::make_mut(&mut self.config).max_result_bytes = Some(max_result_bytes);
+        self
+    }
+
+    /// Set the interval of the pings keeping the connection alive. Defaults to 30 seconds.
+    pub fn with_ping_interval(mut self, ping_interval: Duration) -> Self {
+        Arc::make_mut(&mut self.config).ping_interval = ping_interval;
+        self
+    }
+
+    /// Set how many pings in a row the server may leave unanswered before the service deems the
+    /// connection dead and stops with [ShutdownReason::ConnectionLost]. Defaults to 2, 0 never
+    /// deems it dead.
+    pub fn with_missed_pongs(mut self, missed_pongs: u32) -> Self {
+        Arc::make_mut(&mut self.config).missed_pongs = missed_pongs;
+        self
+    }
+
+    /// Set how many times [ToolkitService::start] tries the WebSocket endpoints again when none is
+    /// reachable, waiting [UnifaiConfig::retry_base_delay] doubled on every retry. Defaults to 0.
+    pub fn with_connect_retries(mut self, connect_retries: u32) -> Self {
+        Arc::make_mut(&mut self.config).connect_retries = connect_retries;
+        self
+    }
+
+    /// Set the WebSocket endpoint of the backend, in place of
+    /// [UnifaiConfig::backend_ws_endpoint].
+    pub fn with_ws_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        Arc::make_mut(&mut self.config).backend_ws_endpoint = endpoint.into();
+        self
+    }
+
+    /// Set the frontend API endpoint, used by [ToolkitService::update_info], in place of
+    /// [UnifaiConfig::frontend_api_endpoint].
+    pub fn with_frontend_api_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        Arc::make_mut(&mut self.config).frontend_api_endpoint = endpoint.into();
+        self
+    }
+
+    /// Replace the whole config of the service, keeping its API key, e.g. with one built from
+    /// [UnifaiConfig::default] so that tests don't depend on environment variables.
+    ///
+    /// # Panics
+    ///
+    /// If the HTTP client can't be built with the options of the config. Build it and pass it to
+    /// [ToolkitService::with_client] to handle the error instead.
+    pub fn with_config(mut self, config: UnifaiConfig) -> Self {
+        self.api_client =
+            ApiClient::new(self.api_key.clone(), &config.client).unwrap_or_else(|e| panic!("{e}"));
+        self.api_client.set_wire_logging(self.wire.clone());
+        self.config = Arc::new(config);
+        self
+    }
+
+    /// Set the options of the HTTP client used by the service and the contexts of its actions.
+    ///
+    /// # Panics
+    ///
+    /// If the HTTP client can't be built with `options`, see [ToolkitService::with_config].
+    pub fn with_client_options(mut self, options: ClientOptions) -> Self {
+        self.api_client =
+            ApiClient::new(self.api_key.clone(), &options).unwrap_or_else(|e| panic!("{e}"));
+        self.api_client.set_wire_logging(self.wire.clone());
+        Arc::make_mut(&mut self.config).client = options;
+        self
+    }
+
+    /// Send the HTTP requests of the service and of the contexts of its actions with `client`, such
+    /// as the client of the application, in place of the shared one. The API key is still sent with
+    /// every request, but [ClientOptions] no longer apply, and the WebSocket connection doesn't
+    /// use it.
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.api_client.set_client(client);
+        self
+    }
+
+    /// Set the hook applied to payloads before they're logged. Defaults to
+    /// [redact_secrets](crate::logging::redact_secrets).
+    pub fn with_redactor(
+        mut self,
+        redactor: impl Fn(&Value) -> Value + Send + Sync + 'static,
+    ) -> Self {
+        self.redactor = Arc::new(redactor);
+        self
+    }
+
+    /// Record each action call received from the server and its result, after redaction. Use
+    /// [record_to_file](super::record_to_file) to append them to a file, and replay them with
+    /// `testing::replay_fixture`, under the `test-utils` feature.
+    pub fn with_recorder(
+        mut self,
+        recorder: impl Fn(&RecordedCall) + Send + Sync + 'static,
+    ) -> Self {
+        self.recorder = Some(Arc::new(recorder));
+        self
+    }
+
+    /// Call the hooks of `observer` on every action call and runtime event, such as to export
+    /// metrics of the service.
+    pub fn with_observer(mut self, observer: impl ToolkitObserver + 'static) -> Self {
+        self.observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Run every action call through `middleware`, such as to check who calls. Middlewares run in
+    /// the order they were added, see [ActionMiddleware]. Their result payloads are built as a
+    /// [Value], which sorts object keys.
+    pub fn with_middleware(mut self, middleware: impl ActionMiddleware + 'static) -> Self {
+        let mut middlewares = self.middlewares.to_vec();
+        middlewares.push(Arc::new(middleware));
+        self.middlewares = middlewares.into();
+        self
+    }
+
+    /// Answer the calls the server delivers again, such as after a reconnect, with the result of
+    /// their first delivery instead of calling the action again, keeping the results of the last
+    /// `capacity` calls for `ttl`. See [ToolkitService::with_idempotency_store] to keep them
+    /// elsewhere than in memory.
+    pub fn with_idempotency(self, capacity: usize, ttl: Duration) -> Self {
+        self.with_idempotency_store(MemoryIdempotencyStore::new(capacity, ttl))
+    }
+
+    /// Answer the calls delivered again like [ToolkitService::with_idempotency], keeping the
+    /// results in `store`.
+    pub fn with_idempotency_store(mut self, store: impl IdempotencyStore + 'static) -> Self {
+        self.idempotency = Some(Arc::new(Idempotency::new(Arc::new(store))));
+        self
+    }
+
+    /// Log the exchanges with the server, HTTP requests and WebSocket frames, see [WireLogging].
+    pub fn with_wire_logging(mut self, wire: WireLogging) -> Self {
+        self.api_client.set_wire_logging(wire.clone());
+        self.wire = wire;
+        self
+    }
+
+    /// Register actions exactly like SDK versions that didn't report their client info, for servers
+    /// that reject unknown fields. The service then gets no deprecation notices.
+    pub fn with_legacy_registration(mut self, legacy: bool) -> Self {
+        self.legacy_registration = legacy;
+        self
+    }
+
+    /// Verify the toolkit API key in [ToolkitService::start] before connecting, so a mistyped key
+    /// fails the start instead of leaving the service waiting for calls that never come.
+    pub fn with_credentials_check(mut self, check: bool) -> Self {
+        self.check_credentials = check;
+        self
+    }
+
+    /// Choose how the service connects to the server in [ToolkitService::start]. Defaults to
+    /// [TransportPreference::WebSocket].
+    pub fn with_transport(mut self, transport: TransportPreference) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Check the payload of every call against the definition of its action before calling it,
+    /// answering the calls that don't match with a [ToolkitError::InvalidPayload] listing every
+    /// violated field, see [validation](super::validation). The definition is evaluated for every
+    /// call. Off by default, the payload then only has to deserialize into the arguments.
+    pub fn with_payload_validation(mut self, validate: bool) -> Self {
+        self.validate_payloads = validate;
+        self
+    }
+
+    /// Add actions under their names as is, without checking them against the
+    /// [grammar](InvalidActionName) nor trimming them. [ToolkitService::add_action] still requires
+    /// the non-empty [Action::NAME] constants to be within it, add the other actions with
+    /// [ToolkitService::try_add_action].
+    pub fn allow_unchecked_names(mut self) -> Self {
+        self.unchecked_names = true;
+        self
+    }
+
+    /// The API key of the service, shared with the contexts of its actions. Rotating it changes the
+    /// key of the requests sent from then on, and of the next connection.
+    pub fn api_key(&self) -> &ApiKeyProvider {
+        &self.api_key
+    }
+
+    /// The config used by the service and the contexts of its actions.
+    pub fn config(&self) -> &UnifaiConfig {
+        &self.config
+    }
+
+    /// Update Toolkit's name and description, returning them as the server applied them.
+    ///
+    /// Fails with [ToolkitError::Unauthorized] if the server rejects the API key, and with
+    /// [ToolkitError::Rejected] and the server's message if it refuses the info.
+    pub async fn update_info(&self, info: ToolkitInfo) -> Result<ToolkitInfoResponse> {
+        let url = format!("{}/toolkits/fields/", self.config.frontend_api_endpoint);
+        let request = self.api_client.post(url).json(&info);
+        let body = self.frontend_request(request, "update_info").await?;
+
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// List the toolkits registered with the API key of the service.
+    pub async fn list_toolkits(&self) -> Result<Vec<ToolkitSummary>> {
+        let url = format!("{}/toolkits/", self.config.frontend_api_endpoint);
+        let body = self
+            .frontend_request(self.api_client.get(url), "list_toolkits")
+            .await?;
+
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// The toolkit `id`, failing with [ToolkitError::ToolkitNotFound] if there is none.
+    pub async fn get_toolkit(&self, id: u64) -> Result<ToolkitSummary> {
+        let url = format!("{}/toolkits/{id}/", self.config.frontend_api_endpoint);
+        let body = self
+            .frontend_request(self.api_client.get(url), "get_toolkit")
+            .await
+            .map_err(|e| e.not_found(id))?;
+
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Delete the toolkit `id`, such as a stale one left by tests, failing with
+    /// [ToolkitError::ToolkitNotFound] if there is none.
+    pub async fn delete_toolkit(&self, id: u64) -> Result<()> {
+        let url = format!("{}/toolkits/{id}/", self.config.frontend_api_endpoint);
+        self.frontend_request(self.api_client.delete(url), "delete_toolkit")
+            .await
+            .map_err(|e| e.not_found(id))?;
+
+        Ok(())
+    }
+
+    /// Send a request to the frontend API, returning the body of a successful response.
+    async fn frontend_request(
+        &self,
+        request: reqwest::RequestBuilder,
+        operation: &'static str,
+    ) -> Result<Vec<u8>> {
+        let request_timeout = self.config.request_timeout;
+        let into_error = |e| ToolkitError::from_request(e, operation, request_timeout);
+
+        let request = request.timeout(request_timeout);
+        let response = self.api_client.send(request).await.map_err(into_error)?;
+        let status = response.status();
+        let url = response.url().clone();
+        let body = response.bytes().await.map_err(into_error)?;
+        self.api_client.log_response_body(&url, &body);
+
+        if !status.is_success() {
+            return Err(ToolkitError::from_status(operation, status, &body));
+        }
+        Ok(body.to_vec())
+    }
+
+    /// Check that the server accepts the toolkit API key, with a request reading the toolkit's
+    /// fields, which [ToolkitService::update_info] updates.
+    pub async fn verify_credentials(&self) -> std::result::Result<Principal, CredentialsError> {
+        let url = format!("{}/toolkits/fields/", self.config.frontend_api_endpoint);
+        let request = self
+            .api_client
+            .get(url)
+            .timeout(self.config.request_timeout);
+
+        self.api_client.verify(request, "toolkit_api_key").await
+    }
+
+    /// Add an action that implements the [Action] trait to be registered when starting.
+    ///
+    /// An [Action::NAME] outside of the [grammar](InvalidActionName) fails to compile, unless it's
+    /// empty, as for the actions named by [Action::name].
+    ///
+    /// # Panics
+    ///
+    /// If the action overrides [Action::name] with an invalid name, see
+    /// [ToolkitService::try_add_action].
+    pub fn add_action<A: Action + 'static>(&mut self, action: A) {
+        self.add_action_with_mode(action, ExecutionMode::Spawned);
+    }
+
+    /// Add an action like [ToolkitService::add_action], choosing where its calls run.
+    ///
+    /// # Panics
+    ///
+    /// If the action overrides [Action::name] with an invalid name, see
+    /// [ToolkitService::try_add_action].
+    pub fn add_action_with_mode<A: Action + 'static>(&mut self, action: A, mode: ExecutionMode) {
+        const {
+            assert!(
+                A::NAME.is_empty() || is_valid_action_name(A::NAME),
+                "Invalid action name, see ToolkitService::try_add_action"
+            )
+        };
+        if let Err(e) = self.try_add_action_with_mode(action, mode) {
+            panic!("{e}");
+        }
+    }
+
+    /// Add an action like [ToolkitService::add_action], failing if its name is outside of the
+    /// [grammar](InvalidActionName), unless the service [allows unchecked
+    /// names](ToolkitService::allow_unchecked_names). Surrounding ASCII whitespace is trimmed.
+    pub fn try_add_action(
+        &mut self,
+        action: impl Action + 'static,
+    ) -> std::result::Result<(), InvalidActionName> {
+        self.try_add_action_with_mode(action, ExecutionMode::Spawned)
+    }
+
+    /// Add an action like [ToolkitService::try_add_action], choosing where its calls run.
+    pub fn try_add_action_with_mode(
+        &mut self,
+        action: impl Action + 'static,
+        mode: ExecutionMode,
+    ) -> std::result::Result<(), InvalidActionName> {
+        let name = self.checked_name(&action.name())?;
+        self.actions_mut().insert(name, Arc::new(action), mode);
+
+        Ok(())
+    }
+
+    /// A copy of the service to start again once it stopped. The copy shares the actions, so the
+    /// actions added or removed while the service ran are kept.
+    pub(super) fn to_restart(&self) -> Self {
         Self {
-            api_key: api_key.to_string(),
-            api_client: build_api_client(api_key),
-            actions: HashMap::new(),
+            api_key: self.api_key.clone(),
+            api_client: self.api_client.clone(),
+            actions: self.actions.clone(),
+            config: self.config.clone(),
+            redactor: self.redactor.clone(),
+            recorder: self.recorder.clone(),
+            observer: self.observer.clone(),
+            middlewares: self.middlewares.clone(),
+            lifecycle: self.lifecycle.clone(),
+            idempotency: self.idempotency.clone(),
+            wire: self.wire.clone(),
+            legacy_registration: self.legacy_registration,
+            check_credentials: self.check_credentials,
+            unchecked_names: self.unchecked_names,
+            validate_payloads: self.validate_payloads,
+            transport: self.transport,
         }
     }
 
-    /// Update Toolkit's name and description.
-    pub async fn update_info(&self, info: ToolkitInfo) -> Result<()> {
-        let client = build_api_client(&self.api_key);
-        let endpoint = env::var("UNIFAI_FRONTEND_API_ENDPOINT")
-            .unwrap_or(DEFAULT_FRONTEND_API_ENDPOINT.to_string());
-        let url = format!("{endpoint}/toolkits/fields/");
+    /// The name of an action as the service serves it, see
+    /// [ToolkitService::allow_unchecked_names].
+    pub(super) fn checked_name(
+        &self,
+        name: &str,
+    ) -> std::result::Result<Arc<str>, InvalidActionName> {
+        checked_name(name, self.unchecked_names)
+    }
+
+    /// The actions of the service.
+    pub(super) fn actions(&self) -> RwLockReadGuard<'_, ActionTable> {
+        self.actions.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(super) fn actions_mut(&self) -> RwLockWriteGuard<'_, ActionTable> {
+        self.actions.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Add each tool of an MCP server as an action, see [McpClient].
+    ///
+    /// The tools are listed once, tools added to the server later are not picked up. Fails
+    /// without adding any of them if the name of one is invalid, see
+    /// [ToolkitService::try_add_action].
+    #[cfg(feature = "mcp")]
+    pub async fn mount_mcp(&mut self, client: McpClient) -> std::result::Result<(), McpError> {
+        let mut actions = Vec::new();
+        for tool in client.list_tools().await? {
+            let name = self.checked_name(&tool.name)?;
+            actions.push((name, Arc::new(McpAction::new(client.clone(), tool))));
+        }
 
-        client.post(url).json(&info).send().await?;
+        let mut table = self.actions_mut();
+        for (name, action) in actions {
+            table.insert(name, action, ExecutionMode::Spawned);
+        }
 
         Ok(())
     }
 
-    /// Add an action that implements the [Action] trait to be registered when starting.
-    pub fn add_action(&mut self, action: impl Action + 'static) {
-        self.actions.insert(action.name(), Box::new(action));
+    /// Subscribe to the lifecycle events of the service: connecting, registering its actions,
+    /// calls starting and finishing, unreadable messages, and stopping.
+    ///
+    /// Subscribe before [ToolkitService::start] to receive the events of the start too. See
+    /// [ToolkitHandle::events] once started.
+    pub fn events(&self) -> broadcast::Receiver<ToolkitEvent> {
+        self.lifecycle.subscribe()
+    }
+
+    /// Emit a lifecycle event, whether or not anyone receives it.
+    fn emit(&self, event: ToolkitEvent) {
+        let _ = self.lifecycle.send(event);
     }
 
     /// Start the Toolkit service asynchronously.
     ///
-    /// Once the service is ready, it returns a [JoinHandle] that keeps the service alive.
-    pub async fn start(self) -> Result<JoinHandle<Result<()>>> {
-        let endpoint = env::var("UNIFAI_BACKEND_WS_ENDPOINT")
-            .unwrap_or(DEFAULT_BACKEND_WS_ENDPOINT.to_string());
-        let url = format!("{endpoint}?type=toolkit&api-key={}", self.api_key);
-
-        let (mut ws_stream, _) = connect_async(url).await?;
-
-        // Register actions
-        {
-            let actions = HashMap::from_iter(
-                join_all(
-                    self.actions
-                        .values()
-                        .map(|action| async { (action.name(), action.definition().await) }),
-                )
-                .await,
-            );
-            let message = ToolkitMessage::RegisterActions {
-                data: ActionsRegisterParams { actions },
-            };
+    /// Once the service is ready, it returns a [ToolkitHandle] that keeps the service alive.
+    ///
+    /// It must be called within a Tokio runtime, which can be either a current-thread or a
+    /// multi-thread one.
+    pub async fn start(self) -> Result<ToolkitHandle> {
+        if self.check_credentials {
+            self.verify_credentials().await?;
+        }
+
+        match self.transport {
+            TransportPreference::WebSocket => {
+                let (endpoint, transport) = self.connect().await?;
+                self.start_at(endpoint, transport).await
+            }
+            TransportPreference::Polling => {
+                let (endpoint, transport) = self.connect_polling().await?;
+                self.start_at(endpoint, transport).await
+            }
+            TransportPreference::Auto => match self.connect().await {
+                Ok((endpoint, transport)) => self.start_at(endpoint, transport).await,
+                Err(e) => {
+                    tracing::warn!(
+                        target: targets::TRANSPORT,
+                        event = log_events::TRANSPORT_FALLBACK,
+                        url = self.config.backend_poll_endpoint,
+                        error = %e,
+                        "WebSocket connection failed, falling back to long-polling"
+                    );
+                    let (endpoint, transport) = self.connect_polling().await?;
+                    self.start_at(endpoint, transport).await
+                }
+            },
+            #[cfg(feature = "grpc")]
+            TransportPreference::Grpc => {
+                let endpoint = self.config.backend_grpc_endpoint.clone();
+                let transport =
+                    GrpcTransport::connect(&self.config, &self.wire, "toolkit", &self.api_key)
+                        .await?;
+                self.start_at(endpoint, transport).await
+            }
+        }
+    }
+
+    async fn start_at(
+        self,
+        endpoint: String,
+        transport: impl ToolkitTransport,
+    ) -> Result<ToolkitHandle> {
+        let mut handle = self.start_over(transport).await?;
+        handle.endpoint = Some(endpoint.into());
+
+        Ok(handle)
+    }
+
+    /// Connect to the first reachable endpoint of [UnifaiConfig::backend_ws_endpoints], in order,
+    /// trying them all again up to [UnifaiConfig::connect_retries] times.
+    async fn connect(&self) -> Result<(String, WebSocketTransport)> {
+        connect_any(&self.config, &self.wire, "toolkit", &self.api_key).await
+    }
+
+    /// Open a long-polling session at [UnifaiConfig::backend_poll_endpoint].
+    async fn connect_polling(&self) -> Result<(String, PollingTransport)> {
+        let endpoint = self.config.backend_poll_endpoint.clone();
+        let transport =
+            PollingTransport::connect(self.api_client.clone(), &endpoint, &self.config).await?;
+
+        Ok((endpoint, transport))
+    }
+
+    /// Start the service over `transport` instead of connecting to the server, such as over a
+    /// [ToolkitTransport] of your own. The actions are registered over it first.
+    ///
+    /// The credentials aren't checked, even with
+    /// [ToolkitService::with_credentials_check](Self::with_credentials_check).
+    pub async fn start_over(self, mut transport: impl ToolkitTransport) -> Result<ToolkitHandle> {
+        self.emit(ToolkitEvent::Connected);
+        self.register(&mut transport).await?;
+
+        tracing::info!(target: targets::TRANSPORT, "Toolkit service is running");
+
+        Ok(ToolkitHandle::spawn(self, transport))
+    }
+
+    /// Send the definitions of the actions to the server.
+    async fn register(&self, transport: &mut impl ToolkitTransport) -> Result<()> {
+        let started = Instant::now();
+        let data = self.registration(self.definitions().await?);
+        let count = data.actions.len();
+
+        // Large toolkits have hundreds of KB of definitions, time their serialization separately.
+        let sending = Instant::now();
+        let text = serde_json::to_string(&ToolkitMessage::RegisterActions { data })?;
+        let bytes = text.len();
+        transport.send(text).await?;
+
+        tracing::info!(
+            target: targets::TRANSPORT,
+            event = log_events::ACTIONS_REGISTERED,
+            count,
+            bytes,
+            duration_ms = started.elapsed().as_millis() as u64,
+            send_ms = sending.elapsed().as_millis() as u64,
+            "Actions registered"
+        );
+        self.emit(ToolkitEvent::ActionsRegistered { count });
+
+        Ok(())
+    }
+
+    /// Evaluate the definitions of the actions, at most
+    /// [UnifaiConfig::definition_concurrency] at once.
+    pub(super) async fn definitions(&self) -> Result<HashMap<String, ActionDefinition>> {
+        let limit = self.config.definition_timeout;
+
+        // Create the futures up front, a stream holding the closure would make this future not
+        // `Send`, which the HTTP servers of the actions need.
+        let definitions: Vec<_> = self
+            .actions()
+            .entries()
+            .into_iter()
+            .map(|(name, action)| async move {
+                timeout(limit, action.definition())
+                    .await
+                    .map(|definition| (name.to_string(), definition))
+                    .map_err(|_| ToolkitError::DefinitionTimeout {
+                        action: name.to_string(),
+                        timeout: limit,
+                    })
+            })
+            .collect();
 
-            ws_stream
-                .send(Message::text(serde_json::to_string(&message)?))
-                .await?;
+        stream::iter(definitions)
+            .buffer_unordered(self.config.definition_concurrency.max(1))
+            .try_collect()
+            .await
+    }
+
+    pub(super) fn registration(
+        &self,
+        actions: HashMap<String, ActionDefinition>,
+    ) -> ActionsRegisterParams {
+        ActionsRegisterParams {
+            actions,
+            client_info: (!self.legacy_registration).then(ClientInfo::current),
         }
+    }
 
-        tracing::info!("Toolkit service is running");
+    async fn run_until_stopped(
+        self,
+        transport: impl ToolkitTransport,
+        events: EventSender,
+        signals: Signals,
+        limiter: Arc<CallLimiter>,
+    ) -> Result<ShutdownReason> {
+        let lifecycle = self.lifecycle.clone();
+        let result = self
+            .run_continuously(transport, events.clone(), signals, limiter)
+            .await;
+
+        match &result {
+            Ok(reason) => {
+                tracing::info!(
+                    target: targets::TRANSPORT,
+                    event = log_events::SERVICE_STOPPED,
+                    reason = ?reason,
+                    "Toolkit service stopped"
+                );
+
+                events.send(ToolkitRuntimeEvent::Stopped {
+                    reason: reason.clone(),
+                });
+            }
+            Err(e) => {
+                tracing::error!(
+                    target: targets::TRANSPORT,
+                    event = log_events::SERVICE_STOPPED,
+                    error = %e,
+                    "Toolkit service stopped"
+                );
 
-        let runner = spawn(self.run_continuously(ws_stream));
+                events.send(ToolkitRuntimeEvent::Fatal {
+                    message: e.to_string().into(),
+                });
+            }
+        }
+        let _ = lifecycle.send(ToolkitEvent::Disconnected {
+            reason: result.as_ref().ok().cloned(),
+        });
 
-        Ok(runner)
+        result
     }
 
     async fn run_continuously(
         self,
-        mut ws_stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
-    ) -> Result<()> {
+        mut transport: impl ToolkitTransport,
+        events: EventSender,
+        signals: Signals,
+        limiter: Arc<CallLimiter>,
+    ) -> Result<ShutdownReason> {
         let (response_sender, mut response_receiver) = unbounded_channel();
+        // The cancelled calls whose grace period is over.
+        let (expired_sender, mut expired_receiver) = unbounded_channel();
+        let mut in_flight = InFlightCalls::default();
+        // The pings sent since the last pong, and when it was received.
+        let mut unanswered_pings = 0;
+        let mut last_pong = Instant::now();
 
         let self_arc = Arc::new(self);
+        let max_result_bytes = self_arc.config.max_result_bytes;
+        // Other branches firing don't push the next ping back. A zero interval would panic.
+        let mut pings = interval(self_arc.config.ping_interval.max(Duration::from_millis(1)));
+        pings.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        pings.reset();
 
-        loop {
+        // `None` on a graceful shutdown, which drains the calls in flight first.
+        let reason = loop {
             tokio::select! {
-                _ = sleep(PING_INTERVAL) => {
-                    ws_stream.send(Message::Ping(Bytes::new())).await.unwrap_or_else(|e| {
-                        tracing::error!("Failed to send pong: {:?}", e);
-                    });
+                _ = signals.shutdown.notified() => break None,
+
+                _ = signals.actions_changed.notified() => {
+                    // Calls wait while the definitions are evaluated, so none arrives for an
+                    // action the server doesn't know yet.
+                    if let Err(e) = self_arc.register(&mut transport).await {
+                        tracing::error!(
+                            target: targets::TRANSPORT,
+                            event = log_events::SEND_FAILED,
+                            error = %e,
+                            "Failed to register the actions again"
+                        );
+
+                        events.send(ToolkitRuntimeEvent::SendFailed {
+                            message: e.to_string().into(),
+                        });
+                    }
+                }
+
+                _ = pings.tick() => {
+                    let missed_pongs = self_arc.config.missed_pongs;
+                    if transport.answers_pings()
+                        && missed_pongs > 0
+                        && unanswered_pings >= missed_pongs
+                    {
+                        let elapsed = last_pong.elapsed();
+                        tracing::warn!(
+                            target: targets::TRANSPORT,
+                            event = log_events::CONNECTION_DEAD,
+                            count = unanswered_pings,
+                            duration_ms = elapsed.as_millis() as u64,
+                            "No pong received for {elapsed:?}, the connection is dead"
+                        );
+                        break Some(ShutdownReason::ConnectionLost);
+                    }
+
+                    unanswered_pings += 1;
+                    if let Err(e) = transport.ping().await {
+                        tracing::error!(
+                            target: targets::TRANSPORT,
+                            event = log_events::SEND_FAILED,
+                            error = %e,
+                            "Failed to send ping"
+                        );
+
+                        events.send(ToolkitRuntimeEvent::SendFailed {
+                            message: e.to_string().into(),
+                        });
+                    }
                 }
 
                 Some(msg) = response_receiver.recv() => {
-                    ws_stream.send(Message::text(serde_json::to_string(&msg)?)).await.unwrap_or_else(|e| {
-                        tracing::error!("Failed to send response: {:?}", e);
-                    });
+                    send_message(&mut transport, msg, &events, max_result_bytes).await;
+                }
+
+                Some(key) = expired_receiver.recv() => {
+                    if let Some(call) = in_flight.abort(key) {
+                        let msg = aborted_result(call);
+                        send_message(&mut transport, msg, &events, max_result_bytes).await;
+                    }
                 }
 
-                Some(msg) = ws_stream.next() => {
-                    match msg {
-                        Ok(Message::Text(text)) => match serde_json::from_str::<ToolkitMessage>(&text) {
-                            Ok(ToolkitMessage::Action { data }) => {
-                                let self_arc = self_arc.clone();
-                                let response_sender = response_sender.clone();
-
-                                spawn(async move {
-                                    let action_name = data.action.clone();
-                                    tracing::info!("Action call: {:?}", data);
-
-                                    if let Some(result) = handle_action_call(self_arc, data).await {
-                                        tracing::info!("Action result: {:?}", result);
-
-                                        response_sender
-                                            .send(ToolkitMessage::ActionResult { data: result })
-                                            .unwrap();
-                                    } else {
-                                        tracing::warn!("Action not found: {}", action_name);
+                incoming = transport.recv() => {
+                    let Some(incoming) = incoming else {
+                        break Some(ShutdownReason::ConnectionLost);
+                    };
+                    match incoming? {
+                        Incoming::Text(text) => {
+                            match ToolkitMessage::from_frame(&text) {
+                                // Its first delivery answers it, and stays the one cancelled.
+                                Ok(ToolkitMessage::Action { data })
+                                    if self_arc.idempotency.is_some()
+                                        && in_flight.contains(CallKey {
+                                            agent_id: data.agent_id,
+                                            action_id: data.action_id,
+                                        }) =>
+                                {
+                                    let key = CallKey {
+                                        agent_id: data.agent_id,
+                                        action_id: data.action_id,
+                                    };
+                                    report_duplicate(&data.action, key, &events);
+                                }
+
+                                Ok(ToolkitMessage::Action { data })
+                                    if self_arc
+                                        .actions()
+                                        .find(&data.action)
+                                        .is_some_and(|found| found.inline) =>
+                                {
+                                    let (action, action_id, agent_id) =
+                                        (data.action.clone(), data.action_id, data.agent_id);
+                                    let cancellation = CancellationToken::new();
+                                    let answer = answer_action_call(
+                                        self_arc.clone(),
+                                        data,
+                                        call_link(&response_sender, &cancellation),
+                                        events.clone(),
+                                    );
+                                    let mut answer = Box::pin(answer);
+
+                                    match timeout(INLINE_BUDGET, &mut answer).await {
+                                        Ok(msg) => {
+                                            // The progress the call sent goes first.
+                                            while let Ok(progress) = response_receiver.try_recv() {
+                                                send_message(&mut transport, progress, &events, max_result_bytes)
+                                                    .await;
+                                            }
+                                            send_message(&mut transport, msg, &events, max_result_bytes).await;
+                                        }
+                                        Err(_) => {
+                                            tracing::debug!(
+                                                target: targets::DISPATCH,
+                                                event = log_events::INLINE_CALL_DEMOTED,
+                                                action,
+                                                action_id,
+                                                "Inline action call moved to a task"
+                                            );
+
+                                            spawn_call(
+                                                &mut in_flight,
+                                                CallKey { agent_id, action_id },
+                                                action,
+                                                cancellation,
+                                                answer,
+                                                &response_sender,
+                                            );
+                                        }
                                     }
-                                });
-                            }
+                                }
+
+                                Ok(ToolkitMessage::Action { data }) => {
+                                    let slot = match limiter.admit() {
+                                        Admission::Run(slot) => Either::Left(ready(slot)),
+                                        Admission::Queue(slot) => Either::Right(slot),
+                                        Admission::Busy => {
+                                            let msg = busy_result(data, limiter.queued());
+                                            send_message(&mut transport, msg, &events, max_result_bytes).await;
+                                            continue;
+                                        }
+                                    };
+                                    let (action, action_id, agent_id) =
+                                        (data.action.clone(), data.action_id, data.agent_id);
+                                    let cancellation = CancellationToken::new();
+                                    let answer = answer_action_call(
+                                        self_arc.clone(),
+                                        data,
+                                        call_link(&response_sender, &cancellation),
+                                        events.clone(),
+                                    );
+                                    let answer = async move {
+                                        let _slot = slot.await;
+                                        answer.await
+                                    };
+                                    spawn_call(
+                                        &mut in_flight,
+                                        CallKey { agent_id, action_id },
+                                        action,
+                                        cancellation,
+                                        answer,
+                                        &response_sender,
+                                    );
+                                }
+
+                                Ok(ToolkitMessage::ActionCancel { data }) => {
+                                    let (key, action) = match in_flight.cancel(data.action_id, data.agent_id) {
+                                        Ok(Some(call)) => call,
+                                        Ok(None) => continue,
+                                        Err(AmbiguousCancel { calls }) => {
+                                            tracing::warn!(
+                                                target: targets::DISPATCH,
+                                                event = log_events::AMBIGUOUS_CANCEL,
+                                                action_id = data.action_id,
+                                                calls,
+                                                reason = data.reason.as_deref(),
+                                                "Cancellation without an agent ID matches several calls, dropped"
+                                            );
+                                            continue;
+                                        }
+                                    };
+                                    tracing::info!(
+                                        target: targets::DISPATCH,
+                                        event = log_events::ACTION_CANCELLED,
+                                        action,
+                                        action_id = data.action_id,
+                                        reason = data.reason.as_deref(),
+                                        "Action call cancelled"
+                                    );
+
+                                    let (expired, grace) =
+                                        (expired_sender.clone(), self_arc.config.cancel_grace);
+                                    spawn(async move {
+                                        sleep(grace).await;
+                                        let _ = expired.send(key);
+                                    });
+                                }
 
-                            Ok(_) => {}
+                                Ok(ToolkitMessage::RegisterActionsAck { data }) => {
+                                    report_deprecation(data, &events);
+                                }
 
-                            Err(e) => {
-                                tracing::warn!("Received unknown message: {:?}", e);
+                                Ok(ToolkitMessage::Unknown { r#type, data }) => {
+                                    tracing::info!(
+                                        target: targets::TRANSPORT,
+                                        event = log_events::UNKNOWN_MESSAGE,
+                                        message_type = %r#type,
+                                        frame = %frame_excerpt(&text, UNKNOWN_FRAME_EXCERPT),
+                                        "Received message of unknown type"
+                                    );
+                                    self_arc.emit(ToolkitEvent::UnknownMessage {
+                                        message_type: r#type.into(),
+                                        data: Arc::new(data),
+                                    });
+                                }
+
+                                Ok(_) => {}
+
+                                Err(e) => {
+                                    tracing::warn!(
+                                        target: targets::TRANSPORT,
+                                        event = log_events::UNKNOWN_MESSAGE,
+                                        error = %e,
+                                        frame = %frame_excerpt(&text, UNKNOWN_FRAME_EXCERPT),
+                                        "Received unknown message"
+                                    );
+                                    self_arc.emit(ToolkitEvent::ProtocolError {
+                                        detail: e.to_string().into(),
+                                    });
+                                }
                             }
-                        },
+                        }
 
-                        Ok(Message::Ping(data)) => {
-                            ws_stream.send(Message::Pong(data)).await?;
+                        Incoming::Closed { code, reason } => {
+                            tracing::info!(
+                                target: targets::TRANSPORT,
+                                event = log_events::CONNECTION_CLOSED,
+                                code,
+                                reason = reason.as_deref(),
+                                "Connection closed by the server"
+                            );
+                            break Some(ShutdownReason::ServerClosed { code, reason });
                         }
 
-                        Ok(Message::Close(_)) => break,
+                        Incoming::Pong => {
+                            unanswered_pings = 0;
+                            last_pong = Instant::now();
+                        }
 
-                        Ok(_) => {}
+                        Incoming::Ignored => {}
 
-                        Err(e) => {
-                            tracing::error!("Failed to parse message: {:?}", e);
+                        Incoming::Failed(e) => {
+                            tracing::error!(
+                                target: targets::TRANSPORT,
+                                event = log_events::RECEIVE_FAILED,
+                                error = %e,
+                                "Failed to receive message"
+                            );
+                            self_arc.emit(ToolkitEvent::ProtocolError {
+                                detail: e.to_string().into(),
+                            });
                         }
                     }
                 }
             }
+        };
+
+        if let Some(reason) = reason {
+            return Ok(reason);
         }
 
-        Ok(())
+        // The answers still running hold the other senders, the channel ends with the last one.
+        drop(response_sender);
+        let drain = async {
+            while let Some(msg) = response_receiver.recv().await {
+                send_message(&mut transport, msg, &events, max_result_bytes).await;
+            }
+        };
+        let drained = timeout(SHUTDOWN_DRAIN_TIMEOUT, drain).await.is_ok();
+        transport.close().await?;
+
+        Ok(ShutdownReason::GracefulRequested { drained })
+    }
+}
+
+/// The name of an action as served, normalized unless `unchecked`.
+fn checked_name(name: &str, unchecked: bool) -> std::result::Result<Arc<str>, InvalidActionName> {
+    if unchecked {
+        Ok(name.into())
+    } else {
+        Ok(normalize_action_name(name)?.into())
     }
 }
 
-async fn handle_action_call(
+/// Call an action and build the result message, answering with an
+/// [ActionNotFound](ToolkitError::ActionNotFound) error if there is no such action.
+///
+/// With [ToolkitService::with_idempotency], a call delivered again is answered once.
+async fn answer_action_call(
     toolkit: Arc<ToolkitService>,
     params: ActionCallParams,
-) -> Option<ActionCallResult> {
-    if let Some(action) = toolkit.actions.get(&params.action) {
-        let result = action
-            .call(
-                ActionContext {
-                    api_client: toolkit.api_client.clone(),
-                    action: params.action.clone(),
-                    action_id: params.action_id.clone(),
-                    agent_id: params.agent_id.clone(),
-                },
-                ActionParams {
-                    payload: params.payload,
-                    payment: params.payment,
-                },
-            )
-            .await
-            .unwrap_or_else(|e| {
-                tracing::debug!("Error occured during action call: {:?}", e);
+    link: CallLink,
+    events: EventSender,
+) -> ToolkitMessage {
+    let data = match toolkit.idempotency.clone() {
+        Some(idempotency) => {
+            let key = CallKey {
+                agent_id: params.agent_id,
+                action_id: params.action_id,
+            };
+            let cancellation = link.cancellation.clone();
+            let answer = call_result(toolkit, params, link);
+            idempotency
+                .answer_once(key, &cancellation, answer, &events)
+                .await
+        }
+        None => call_result(toolkit, params, link).await,
+    };
 
-                ActionResult {
-                    payload: json!({
-                        "error": e.to_string()
-                    }),
-                    payment: None,
-                }
-            });
-
-        Some(ActionCallResult {
-            action: params.action,
-            action_id: params.action_id,
-            agent_id: params.agent_id,
-            payload: result.payload,
-            payment: result.payment,
+    ToolkitMessage::ActionResult { data }
+}
+
+async fn call_result(
+    toolkit: Arc<ToolkitService>,
+    params: ActionCallParams,
+    link: CallLink,
+) -> ActionCallResult {
+    let (action_id, agent_id, extra) = (params.action_id, params.agent_id, params.extra.clone());
+
+    handle_action_call(toolkit.clone(), params, link)
+        .await
+        .unwrap_or_else(|action| {
+            let actions = toolkit.actions();
+            let e = ToolkitError::ActionNotFound {
+                action: action.clone(),
+                available_actions: (actions.len() <= MAX_LISTED_ACTIONS).then(|| actions.names()),
+            };
+            ActionCallResult {
+                extra,
+                ..error_result(action, action_id, agent_id, &e)
+            }
         })
-    } else {
-        None
+}
+
+/// The result answering a call with the error `e`.
+fn error_result(
+    action: String,
+    action_id: u64,
+    agent_id: u64,
+    e: &ToolkitError,
+) -> ActionCallResult {
+    ActionCallResult {
+        action,
+        action_id,
+        agent_id,
+        payload: to_raw_value(&error_payload(e)).expect("a Value always serializes"),
+        payment: None,
+        extra: Map::new(),
+    }
+}
+
+/// The result answering a call arriving while `queued` calls wait for the concurrency limit.
+fn busy_result(params: ActionCallParams, queued: usize) -> ToolkitMessage {
+    tracing::warn!(
+        target: targets::DISPATCH,
+        event = log_events::TOOLKIT_BUSY,
+        action = params.action,
+        action_id = params.action_id,
+        agent_id = params.agent_id,
+        "Toolkit busy, action call rejected"
+    );
+
+    let e = ToolkitError::ToolkitBusy { queued };
+    ToolkitMessage::ActionResult {
+        data: ActionCallResult {
+            extra: params.extra,
+            ..error_result(params.action, params.action_id, params.agent_id, &e)
+        },
+    }
+}
+
+/// The result answering a call aborted at the end of the grace period of its cancellation.
+fn aborted_result(call: AbortedCall) -> ToolkitMessage {
+    tracing::warn!(
+        target: targets::DISPATCH,
+        event = log_events::ACTION_ABORTED,
+        action = call.action,
+        action_id = call.key.action_id,
+        "Cancelled action call still running, aborted"
+    );
+
+    let e = ToolkitError::ActionCancelled {
+        action: call.action.clone(),
+    };
+    ToolkitMessage::ActionResult {
+        data: error_result(call.action, call.key.action_id, call.key.agent_id, &e),
+    }
+}
+
+/// The link of a call received on the connection `response_sender` sends on.
+fn call_link(
+    response_sender: &UnboundedSender<ToolkitMessage>,
+    cancellation: &CancellationToken,
+) -> CallLink {
+    CallLink {
+        progress: Some(ProgressSender::new(response_sender.downgrade())),
+        cancellation: cancellation.clone(),
+    }
+}
+
+/// Answer an action call on a task, kept with the calls in flight so the server can cancel it.
+fn spawn_call(
+    in_flight: &mut InFlightCalls,
+    key: CallKey,
+    action: String,
+    cancellation: CancellationToken,
+    answer: impl Future<Output = ToolkitMessage> + Send + 'static,
+    response_sender: &UnboundedSender<ToolkitMessage>,
+) {
+    let answered = Answered::default();
+    let abort = spawn_answer(answer, response_sender.clone(), answered.clone());
+    in_flight.insert(key, action, cancellation, abort, answered);
+}
+
+/// Finish answering an action call on a task, handing the result to the dispatch loop unless the
+/// call was answered when aborted meanwhile.
+fn spawn_answer(
+    answer: impl Future<Output = ToolkitMessage> + Send + 'static,
+    response_sender: UnboundedSender<ToolkitMessage>,
+    answered: Answered,
+) -> AbortHandle {
+    spawn(async move {
+        let msg = answer.await;
+        if !answered.claim() {
+            return;
+        }
+        // The service may have stopped in the meantime, with no connection to send it on.
+        let _ = response_sender.send(msg);
+    })
+    .abort_handle()
+}
+
+/// Send a message, reporting failures without stopping the service.
+async fn send_message(
+    transport: &mut impl ToolkitTransport,
+    msg: ToolkitMessage,
+    events: &EventSender,
+    max_result_bytes: Option<usize>,
+) {
+    let Some(text) = encode_message(msg, events, max_result_bytes) else {
+        return;
+    };
+
+    if let Err(e) = transport.send(text).await {
+        tracing::error!(
+            target: targets::TRANSPORT,
+            event = log_events::SEND_FAILED,
+            error = %e,
+            "Failed to send response"
+        );
+
+        events.send(ToolkitRuntimeEvent::SendFailed {
+            message: e.to_string().into(),
+        });
+    }
+}
+
+/// Warn about the deprecation hints of the server's acknowledgement, if any.
+fn report_deprecation(ack: ActionsRegisterAck, events: &EventSender) {
+    if ack.deprecation.is_none() && ack.minimum_version.is_none() {
+        return;
+    }
+
+    tracing::warn!(
+        target: targets::TRANSPORT,
+        event = log_events::DEPRECATION_NOTICE,
+        deprecation = ack.deprecation.as_deref(),
+        minimum_version = ack.minimum_version.as_deref(),
+        version = SDK_VERSION,
+        "The server sent a deprecation notice, consider upgrading the SDK"
+    );
+    events.send(ToolkitRuntimeEvent::DeprecationNotice {
+        message: ack.deprecation.map(Into::into),
+        minimum_version: ack.minimum_version.map(Into::into),
+    });
+}
+
+/// Serialize an outgoing message.
+///
+/// A result that fails to serialize is replaced with an `internal_error` result, and one larger
+/// than `max_result_bytes` with a `payload_too_large` result, so the calling agent still gets a
+/// response and the service keeps running.
+fn encode_message(
+    msg: ToolkitMessage,
+    events: &EventSender,
+    max_result_bytes: Option<usize>,
+) -> Option<String> {
+    let e = match serde_json::to_string(&msg) {
+        Ok(text) => return Some(limit_result_size(text, msg, max_result_bytes)),
+        Err(e) => e,
+    };
+
+    let action_id = match &msg {
+        ToolkitMessage::ActionResult { data } => Some(data.action_id),
+        _ => None,
+    };
+
+    tracing::error!(
+        target: targets::TRANSPORT,
+        event = log_events::SERIALIZE_FAILED,
+        action_id,
+        error = %e,
+        "Failed to serialize message"
+    );
+
+    events.send(ToolkitRuntimeEvent::SerializationFailed {
+        action_id,
+        message: e.to_string().into(),
+    });
+
+    match msg {
+        ToolkitMessage::ActionResult { data } => {
+            let fallback = ToolkitMessage::ActionResult {
+                data: ActionCallResult {
+                    payload: to_raw_value(&json!({
+                        "error": format!("Failed to serialize action result: {e}"),
+                        "code": "internal_error",
+                    }))
+                    .expect("a Value always serializes"),
+                    payment: None,
+                    ..data
+                },
+            };
+
+            serde_json::to_string(&fallback).ok()
+        }
+        _ => None,
+    }
+}
+
+/// Replace the serialized result `text` of `msg` with a `PayloadTooLarge` error when it's larger
+/// than `max_result_bytes`.
+fn limit_result_size(text: String, msg: ToolkitMessage, max_result_bytes: Option<usize>) -> String {
+    let (Some(limit), ToolkitMessage::ActionResult { data }) = (max_result_bytes, msg) else {
+        return text;
+    };
+    if text.len() <= limit {
+        return text;
+    }
+
+    let e = ToolkitError::PayloadTooLarge {
+        size: text.len(),
+        limit,
+    };
+    tracing::error!(
+        target: targets::TRANSPORT,
+        event = log_events::RESULT_TOO_LARGE,
+        action_id = data.action_id,
+        size = text.len(),
+        limit,
+        "Action result is too large, sending an error instead"
+    );
+    let fallback = ToolkitMessage::ActionResult {
+        data: ActionCallResult {
+            payload: to_raw_value(&error_payload(&e)).expect("a Value always serializes"),
+            payment: None,
+            ..data
+        },
+    };
+
+    serde_json::to_string(&fallback).expect("an error result always serializes")
+}
+
+/// A handle to a running Toolkit service returned by [ToolkitService::start].
+///
+/// Awaiting the handle waits for the service to stop, like awaiting the underlying [JoinHandle],
+/// returning why it stopped.
+pub struct ToolkitHandle {
+    runner: JoinHandle<Result<ShutdownReason>>,
+    events: EventSender,
+    lifecycle: broadcast::Sender<ToolkitEvent>,
+    endpoint: Option<Arc<str>>,
+    signals: Signals,
+    limiter: Arc<CallLimiter>,
+    actions: Arc<RwLock<ActionTable>>,
+    unchecked_names: bool,
+}
+
+/// What the handle asks of the running service.
+#[derive(Clone, Default)]
+struct Signals {
+    shutdown: Arc<Notify>,
+    /// Register the actions again, they changed.
+    actions_changed: Arc<Notify>,
+}
+
+impl ToolkitHandle {
+    /// Run `service` over `transport` on a task.
+    fn spawn(service: ToolkitService, transport: impl ToolkitTransport) -> Self {
+        let events = EventSender::new(service.observer.clone());
+        let signals = Signals::default();
+        let limiter = CallLimiter::new(
+            service.config.max_concurrent_actions,
+            service.config.max_queued_actions,
+        );
+        let (actions, unchecked_names) = (service.actions.clone(), service.unchecked_names);
+        let lifecycle = service.lifecycle.clone();
+        let runner = spawn(service.run_until_stopped(
+            transport,
+            events.clone(),
+            signals.clone(),
+            limiter.clone(),
+        ));
+
+        Self {
+            runner,
+            events,
+            lifecycle,
+            endpoint: None,
+            signals,
+            limiter,
+            actions,
+            unchecked_names,
+        }
+    }
+
+    /// Add an action to the running service, replacing any action of the same name, and register
+    /// the actions with the server again. The calls in flight of a replaced action complete with
+    /// it.
+    ///
+    /// Fails if the name of the action is invalid, see [ToolkitService::try_add_action].
+    pub fn add_action(
+        &self,
+        action: impl Action + 'static,
+    ) -> std::result::Result<(), InvalidActionName> {
+        self.add_action_with_mode(action, ExecutionMode::Spawned)
+    }
+
+    /// Add an action like [ToolkitHandle::add_action], choosing where its calls run.
+    pub fn add_action_with_mode(
+        &self,
+        action: impl Action + 'static,
+        mode: ExecutionMode,
+    ) -> std::result::Result<(), InvalidActionName> {
+        self.add_action_dyn(Arc::new(action), mode)
+    }
+
+    /// Add an action like [ToolkitHandle::add_action_with_mode], whatever its kind.
+    pub(super) fn add_action_dyn(
+        &self,
+        action: Arc<dyn ActionDyn>,
+        mode: ExecutionMode,
+    ) -> std::result::Result<(), InvalidActionName> {
+        let name = self.checked_name(&action.name())?;
+        self.actions
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(name, action, mode);
+        self.signals.actions_changed.notify_one();
+
+        Ok(())
+    }
+
+    /// The name of an action as the running service serves it, see
+    /// [ToolkitService::allow_unchecked_names].
+    pub(super) fn checked_name(
+        &self,
+        name: &str,
+    ) -> std::result::Result<Arc<str>, InvalidActionName> {
+        checked_name(name, self.unchecked_names)
+    }
+
+    /// Remove an action from the running service, and register the remaining actions with the
+    /// server again, returning whether there was such an action.
+    ///
+    /// The calls in flight complete, the calls arriving later are answered with an
+    /// [ActionNotFound](ToolkitError::ActionNotFound) error.
+    pub fn remove_action(&self, name: &str) -> bool {
+        let removed = self
+            .actions
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(name);
+        if removed {
+            self.signals.actions_changed.notify_one();
+        }
+
+        removed
+    }
+
+    /// Make an action of the running service unavailable, whatever its
+    /// [Action::available](super::Action::available) tells, such as during the maintenance of
+    /// the API it depends on. [Availability::Available] leaves it to tell again. Returns whether
+    /// there is such an action.
+    pub fn set_availability(&self, name: &str, availability: Availability) -> bool {
+        self.actions
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .set_availability(name, availability)
+    }
+
+    /// The number of action calls executing, to monitor the saturation of
+    /// [ToolkitService::with_max_concurrent_actions]. Calls of actions added with
+    /// [ExecutionMode::Inline] aren't counted.
+    pub fn in_flight_actions(&self) -> usize {
+        self.limiter.in_flight()
+    }
+
+    /// The number of action calls waiting for the limit of concurrent calls.
+    pub fn queued_actions(&self) -> usize {
+        self.limiter.queued()
+    }
+
+    /// Subscribe to the warnings and errors of the running service without consuming the handle.
+    ///
+    /// Only events emitted after subscribing are received. See
+    /// [RUNTIME_EVENT_CAPACITY](super::RUNTIME_EVENT_CAPACITY) for the
+    /// behavior of receivers that fall behind.
+    pub fn errors(&self) -> broadcast::Receiver<ToolkitRuntimeEvent> {
+        self.events.subscribe()
+    }
+
+    /// Subscribe to the lifecycle events of the running service, see [ToolkitService::events].
+    pub fn events(&self) -> broadcast::Receiver<ToolkitEvent> {
+        self.lifecycle.subscribe()
+    }
+
+    /// The endpoint the service is connected to, out of [UnifaiConfig::backend_ws_endpoints], or
+    /// [UnifaiConfig::backend_poll_endpoint] when long-polling. `None` over other transports.
+    pub fn endpoint(&self) -> Option<&str> {
+        self.endpoint.as_deref()
+    }
+
+    /// Ask the service to stop: it stops receiving calls, sends the results of the calls in flight
+    /// for up to 10 seconds, then closes the connection, with
+    /// [ShutdownReason::GracefulRequested].
+    pub fn request_shutdown(&self) {
+        self.signals.shutdown.notify_one();
+    }
+
+    /// Ask the service to stop like [ToolkitHandle::request_shutdown], and wait for it.
+    pub async fn shutdown(self) -> std::result::Result<Result<ShutdownReason>, JoinError> {
+        self.request_shutdown();
+        self.await
+    }
+
+    /// Abort the running service.
+    pub fn abort(&self) {
+        self.runner.abort();
+    }
+
+    /// Convert the handle into the underlying [JoinHandle].
+    pub fn into_join_handle(self) -> JoinHandle<Result<ShutdownReason>> {
+        self.runner
+    }
+}
+
+impl Future for ToolkitHandle {
+    type Output = std::result::Result<Result<ShutdownReason>, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.runner).poll(cx)
+    }
+}
+
+impl ToolkitService {
+    /// Run the service over in-memory channels instead of a WebSocket, without registering its
+    /// actions, returning the backend end of the channels.
+    ///
+    /// Only meant for the benchmarks, it's not part of the public API.
+    #[cfg(any(test, feature = "test-utils"))]
+    #[doc(hidden)]
+    pub fn __start_over_channels(self) -> (ToolkitHandle, super::transport::ChannelPeer) {
+        let (transport, peer) = super::transport::ChannelTransport::new();
+
+        (ToolkitHandle::spawn(self, transport), peer)
+    }
+
+    /// Handle an action call frame as if it came from the server, returning the result message as
+    /// sent.
+    ///
+    /// Only meant for the dispatch benchmark, it's not part of the public API.
+    #[cfg(any(test, feature = "test-utils"))]
+    #[doc(hidden)]
+    pub async fn __handle_frame(self: Arc<Self>, frame: &[u8]) -> Option<String> {
+        let ToolkitMessage::Action { data } = ToolkitMessage::from_frame(frame).ok()? else {
+            return None;
+        };
+
+        let result = handle_action_call(self, data, CallLink::default())
+            .await
+            .ok()?;
+        serde_json::to_string(&ToolkitMessage::ActionResult { data: result }).ok()
+    }
+}
+
+/// Call an action and build the result message, answering failures with an error payload.
+///
+/// Fails with the name of the action, given back, if there is no such action.
+pub(super) async fn handle_action_call(
+    toolkit: Arc<ToolkitService>,
+    params: ActionCallParams,
+    link: CallLink,
+) -> std::result::Result<ActionCallResult, String> {
+    let ActionCallParams {
+        action,
+        action_id,
+        agent_id,
+        payload,
+        payment,
+        trace_context,
+        extra,
+    } = params;
+    let extra = Arc::new(extra);
+    let metadata = CallMetadata {
+        trace_context,
+        extra: extra.clone(),
+    };
+    // Only parse the payload when it's recorded.
+    let recorded = toolkit
+        .recorder
+        .as_ref()
+        .map(|_| (toolkit.redactor)(&raw_to_value(&payload)));
+    let params = ActionParams { payload, payment };
+
+    let result = dispatch::<Box<RawValue>>(
+        &toolkit, &action, action_id, agent_id, params, metadata, link,
+    )
+    .await;
+    let Some(result) = result else {
+        return Err(action);
+    };
+    let result = result.unwrap_or_else(|e| ActionResult {
+        payload: to_raw_value(&error_payload(&e)).expect("a Value always serializes"),
+        payment: None,
+    });
+
+    if let (Some(recorder), Some(payload)) = (&toolkit.recorder, recorded) {
+        recorder(&RecordedCall {
+            action: action.clone(),
+            action_id,
+            agent_id,
+            payload,
+            payment,
+            result: (toolkit.redactor)(&raw_to_value(&result.payload)),
+            result_payment: result.payment,
+        });
+    }
+
+    Ok(ActionCallResult {
+        action,
+        action_id,
+        agent_id,
+        payload: result.payload,
+        payment: result.payment,
+        extra: Arc::unwrap_or_clone(extra),
+    })
+}
+
+/// Call an action, logging the call and its result. This is shared by every transport serving
+/// the actions.
+///
+/// Returns `None` if there is no such action.
+pub(super) async fn dispatch_action(
+    toolkit: &ToolkitService,
+    params: ActionCallParams,
+) -> Option<Result<ActionResult<Value>>> {
+    let ActionCallParams {
+        action,
+        action_id,
+        agent_id,
+        payload,
+        payment,
+        trace_context,
+        extra,
+    } = params;
+    let metadata = CallMetadata {
+        trace_context,
+        extra: Arc::new(extra),
+    };
+
+    dispatch(
+        toolkit,
+        &action,
+        action_id,
+        agent_id,
+        ActionParams { payload, payment },
+        metadata,
+        CallLink::default(),
+    )
+    .await
+}
+
+/// The form of the result payloads returned by [dispatch].
+trait ResultPayload: Sized {
+    fn call(
+        action: &dyn ActionDyn,
+        ctx: ActionContext,
+        params: ActionParams<Box<RawValue>>,
+    ) -> Pin<Box<dyn Future<Output = Result<ActionResult<Self>>> + Send + Sync + '_>>;
+
+    /// Like [ResultPayload::call], for a call going through middlewares.
+    fn from_value<'a>(
+        call: Pin<Box<dyn Future<Output = Result<ActionResult<Value>>> + Send + Sync + 'a>>,
+    ) -> Pin<Box<dyn Future<Output = Result<ActionResult<Self>>> + Send + Sync + 'a>>;
+
+    fn log(redactor: &Redactor, action_id: u64, payload: &Self);
+}
+
+impl ResultPayload for Value {
+    fn call(
+        action: &dyn ActionDyn,
+        ctx: ActionContext,
+        params: ActionParams<Box<RawValue>>,
+    ) -> Pin<Box<dyn Future<Output = Result<ActionResult<Self>>> + Send + Sync + '_>> {
+        action.call(ctx, params)
+    }
+
+    fn from_value<'a>(
+        call: Pin<Box<dyn Future<Output = Result<ActionResult<Self>>> + Send + Sync + 'a>>,
+    ) -> Pin<Box<dyn Future<Output = Result<ActionResult<Self>>> + Send + Sync + 'a>> {
+        call
+    }
+
+    fn log(redactor: &Redactor, action_id: u64, payload: &Self) {
+        log_payload(redactor, action_id, payload);
+    }
+}
+
+/// The JSON text sent over the wire, serialized straight from the action's output.
+impl ResultPayload for Box<RawValue> {
+    fn call(
+        action: &dyn ActionDyn,
+        ctx: ActionContext,
+        params: ActionParams<Box<RawValue>>,
+    ) -> RawCallFuture<'_> {
+        action.call_raw(ctx, params)
+    }
+
+    fn from_value<'a>(
+        call: Pin<Box<dyn Future<Output = Result<ActionResult<Value>>> + Send + Sync + 'a>>,
+    ) -> RawCallFuture<'a> {
+        into_raw_call(call)
+    }
+
+    fn log(redactor: &Redactor, action_id: u64, payload: &Self) {
+        log_raw_payload(redactor, action_id, payload);
+    }
+}
+
+async fn dispatch<P: ResultPayload>(
+    toolkit: &ToolkitService,
+    name: &str,
+    action_id: u64,
+    agent_id: u64,
+    params: ActionParams<Box<RawValue>>,
+    metadata: CallMetadata,
+    link: CallLink,
+) -> Option<Result<ActionResult<P>>> {
+    // Correlates the logs of concurrent calls, its duration and outcome are recorded once done.
+    let span = tracing::info_span!(
+        target: targets::DISPATCH,
+        "action_call",
+        action = name,
+        action_id,
+        agent_id,
+        duration_ms = tracing::field::Empty,
+        outcome = tracing::field::Empty,
+    );
+    #[cfg(feature = "otel")]
+    crate::trace::join_remote_trace(&span, metadata.trace_context.as_ref());
+
+    let call = dispatch_call(toolkit, name, action_id, agent_id, params, metadata, link);
+
+    tracing::Instrument::instrument(call, span).await
+}
+
+/// The message of a panic, as given to `panic!`.
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+async fn dispatch_call<P: ResultPayload>(
+    toolkit: &ToolkitService,
+    name: &str,
+    action_id: u64,
+    agent_id: u64,
+    params: ActionParams<Box<RawValue>>,
+    metadata: CallMetadata,
+    link: CallLink,
+) -> Option<Result<ActionResult<P>>> {
+    let Some(found) = toolkit.actions().find(name) else {
+        tracing::warn!(
+            target: targets::DISPATCH,
+            event = log_events::ACTION_NOT_FOUND,
+            action = name,
+            action_id,
+            agent_id,
+            "Action not found"
+        );
+
+        return None;
+    };
+    let (name, action) = (&found.name, found.action.as_ref());
+    let observed = ObservedCall {
+        action: name,
+        action_id,
+        agent_id,
+    };
+    if let Some(observer) = &toolkit.observer {
+        observer.on_action_start(&observed);
+    }
+    let subscribed = toolkit.lifecycle.receiver_count() > 0;
+    if subscribed {
+        toolkit.emit(ToolkitEvent::ActionStarted {
+            action: name.clone(),
+            action_id,
+        });
+    }
+
+    tracing::info!(
+        target: targets::DISPATCH,
+        event = log_events::ACTION_CALL,
+        action = &**name,
+        action_id,
+        agent_id,
+        "Action call"
+    );
+    log_raw_payload(&toolkit.redactor, action_id, &params.payload);
+
+    // Reading the clock is a noticeable part of a trivial dispatch, only do it when it's reported.
+    let started = (toolkit.observer.is_some()
+        || subscribed
+        || tracing::enabled!(target: targets::DISPATCH, tracing::Level::INFO))
+    .then(Instant::now);
+
+    let context = ActionContext {
+        api_client: toolkit.api_client.clone(),
+        config: toolkit.config.clone(),
+        action: name.clone(),
+        action_id,
+        agent_id,
+        metadata,
+        link: link.clone(),
+    };
+    let unavailable = found.unavailable;
+    let call = async move {
+        let availability = match unavailable {
+            Some(availability) => availability,
+            None => action.available().await,
+        };
+        let violations = if toolkit.validate_payloads && availability == Availability::Available {
+            validate(&action.definition().await.input_schema(), &params.payload)
+        } else {
+            Vec::new()
+        };
+        if let Availability::Unavailable { retry_after } = availability {
+            Err(ToolkitError::ActionUnavailable {
+                action: name.to_string(),
+                retry_after,
+            })
+        } else if !violations.is_empty() {
+            Err(ToolkitError::InvalidPayload { violations })
+        } else if toolkit.middlewares.is_empty() {
+            P::call(action, context, params).await
+        } else {
+            P::from_value(Next::new(&toolkit.middlewares, action).run(context, params)).await
+        }
+    };
+    // A panicking action fails its call, rather than leaving the agent waiting for a result. The
+    // availability check is part of the call, under the same guard and time limit.
+    let call = AssertUnwindSafe(call).catch_unwind().map(|result| {
+        result.unwrap_or_else(|panic| {
+            Err(ToolkitError::ActionPanicked {
+                action: name.to_string(),
+                message: panic_message(panic.as_ref()),
+            })
+        })
+    });
+    let result = match action.timeout().or(toolkit.config.action_timeout) {
+        Some(limit) => timeout(limit, call).await.unwrap_or_else(|_| {
+            Err(ToolkitError::ActionTimeout {
+                action: name.to_string(),
+                timeout: limit,
+            })
+        }),
+        None => call.await,
+    };
+    if let Some(progress) = &link.progress {
+        progress.finish();
+    }
+    // Whatever the action made of it, the call was cancelled.
+    let result = if link.cancellation.is_cancelled() {
+        Err(ToolkitError::ActionCancelled {
+            action: name.to_string(),
+        })
+    } else {
+        result
+    };
+
+    match &result {
+        // A timeout is a fault of the action rather than of the call, which deserves attention.
+        Err(e @ ToolkitError::ActionTimeout { .. }) => tracing::warn!(
+            target: targets::DISPATCH,
+            action_id,
+            error = %e,
+            "Action call timed out"
+        ),
+        Err(e @ ToolkitError::ActionPanicked { .. }) => tracing::error!(
+            target: targets::DISPATCH,
+            action_id,
+            error = %e,
+            "Action call panicked"
+        ),
+        Err(e @ ToolkitError::ActionUnavailable { .. }) => tracing::info!(
+            target: targets::DISPATCH,
+            action_id,
+            error = %e,
+            "Action unavailable"
+        ),
+        // The server cancelled it, the action didn't fail.
+        Err(e @ ToolkitError::ActionCancelled { .. }) => tracing::debug!(
+            target: targets::DISPATCH,
+            action_id,
+            error = %e,
+            "Action call cancelled"
+        ),
+        Err(e) => tracing::warn!(
+            target: targets::DISPATCH,
+            action_id,
+            error = %e,
+            "Error occured during action call"
+        ),
+        Ok(_) => {}
+    }
+
+    let duration = started.map(|started| started.elapsed());
+    let duration_ms = duration.map(|duration| duration.as_millis() as u64);
+    let outcome = if result.is_ok() {
+        outcomes::OK
+    } else {
+        outcomes::ERROR
+    };
+    let span = tracing::Span::current();
+    span.record("duration_ms", duration_ms);
+    span.record("outcome", outcome);
+    tracing::info!(
+        target: targets::DISPATCH,
+        event = log_events::ACTION_RESULT,
+        action = &**name,
+        action_id,
+        agent_id,
+        duration_ms,
+        outcome,
+        "Action result"
+    );
+    if let Some(observer) = &toolkit.observer {
+        let outcome = result.as_ref().map(|_| ());
+        observer.on_action_end(&observed, outcome, duration.unwrap_or_default());
+    }
+    if subscribed {
+        toolkit.emit(ToolkitEvent::ActionFinished {
+            action: name.clone(),
+            action_id,
+            ok: result.is_ok(),
+            duration: duration.unwrap_or_default(),
+        });
+    }
+    match &result {
+        Ok(result) => P::log(&toolkit.redactor, action_id, &result.payload),
+        Err(e) => log_payload(&toolkit.redactor, action_id, &error_payload(e)),
+    }
+
+    Some(result)
+}
+
+/// The result payload reporting a failed action call.
+pub(super) fn error_payload(e: &ToolkitError) -> Value {
+    // The same codes as the HTTP statuses, so that agents can branch on them.
+    let (_, code) = e.http_status();
+    match e {
+        ToolkitError::InvalidPayload { violations } => json!({
+            "error": e.to_string(),
+            "code": code,
+            "violations": violations,
+        }),
+        ToolkitError::PayloadDecode { stages, .. } => json!({
+            "error": e.to_string(),
+            "code": code,
+            "stages": stages,
+        }),
+        ToolkitError::PayloadTooLarge { size, limit } => json!({
+            "error": e.to_string(),
+            "code": code,
+            "size": size,
+            "limit": limit,
+        }),
+        ToolkitError::ActionCallError(error) => {
+            let ActionError {
+                code,
+                message,
+                retryable,
+                details,
+            } = error.to_action_error();
+            json!({
+                "error": e.to_string(),
+                "code": code,
+                "message": message,
+                "retryable": retryable,
+                "details": details,
+            })
+        }
+        ToolkitError::ActionUnavailable { retry_after, .. } => json!({
+            "error": e.to_string(),
+            "code": code,
+            "retry_after_seconds": retry_after.map(|retry_after| retry_after.as_secs()),
+        }),
+        ToolkitError::ActionNotFound {
+            available_actions: Some(available_actions),
+            ..
+        } => json!({
+            "error": e.to_string(),
+            "code": code,
+            "available_actions": available_actions,
+        }),
+        _ => json!({
+            "error": e.to_string(),
+            "code": code,
+        }),
+    }
+}
+
+/// Like [log_payload], for a payload still in its JSON text.
+fn raw_to_value(payload: &RawValue) -> Value {
+    serde_json::from_str(payload.get()).expect("a raw value is valid JSON")
+}
+
+fn log_raw_payload(redactor: &Redactor, action_id: u64, payload: &RawValue) {
+    // The redactor works on values, only parse the payload when it's logged.
+    if tracing::enabled!(target: targets::DISPATCH, tracing::Level::DEBUG) {
+        if let Ok(payload) = serde_json::from_str(payload.get()) {
+            log_payload(redactor, action_id, &payload);
+        }
+    }
+}
+
+fn log_payload(redactor: &Redactor, action_id: u64, payload: &Value) {
+    if tracing::enabled!(target: targets::DISPATCH, tracing::Level::DEBUG) {
+        tracing::debug!(
+            target: targets::DISPATCH,
+            event = log_events::ACTION_PAYLOAD,
+            action_id,
+            payload = %redactor(payload),
+            "Action payload"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        constants::USER_AGENT,
+        fixtures,
+        logging::{capture::Captured, directions},
+        toolkit::{
+            testing::ToolkitTestHarness,
+            transport::{connect, ChannelTransport},
+        },
+    };
+    use futures_util::{SinkExt, StreamExt};
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Mutex,
+        },
+        time::Duration,
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+    use tokio_tungstenite::{
+        accept_async, connect_async,
+        tungstenite::{
+            http::header,
+            protocol::{frame::coding::CloseCode, CloseFrame},
+            Message,
+        },
+        MaybeTlsStream, WebSocketStream,
+    };
+
+    async fn ws_pair() -> (
+        WebSocketStream<MaybeTlsStream<TcpStream>>,
+        WebSocketStream<TcpStream>,
+    ) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            accept_async(stream).await.unwrap()
+        });
+
+        let (client, _) = connect_async(format!("ws://{addr}")).await.unwrap();
+
+        (client, server.await.unwrap())
+    }
+
+    fn start_with(
+        service: ToolkitService,
+        ws_stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    ) -> ToolkitHandle {
+        let transport = WebSocketTransport::new(ws_stream, service.wire.clone());
+
+        ToolkitHandle::spawn(service, transport)
+    }
+
+    fn action_call(action: &str, action_id: u64) -> Message {
+        let frame = fixtures::action_frame(action, action_id, &json!({}));
+
+        Message::text(String::from_utf8(frame).unwrap())
+    }
+
+    /// Accept TCP connections and never answer them.
+    async fn black_hole() -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                connections.push(stream);
+            }
+        });
+
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_connect_timeout() {
+        let addr = black_hole().await;
+        let started = std::time::Instant::now();
+
+        let result = connect(format!("ws://{addr}"), Duration::from_millis(200)).await;
+
+        assert!(matches!(
+            result,
+            Err(ToolkitError::Timeout {
+                operation: "WebSocket connect",
+                ..
+            })
+        ));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_connect_retries() {
+        let captured = Captured::default();
+        let _guard = captured.install();
+
+        // Drop every connection, so the handshakes fail right away.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let accepted = attempts.clone();
+        spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                drop(stream);
+            }
+        });
+
+        let service = ToolkitService::new("test")
+            .with_config(UnifaiConfig {
+                backend_ws_endpoint: format!("ws://{addr}"),
+                retry_base_delay: Duration::from_millis(10),
+                ..UnifaiConfig::default()
+            })
+            .with_connect_retries(2);
+
+        assert!(service.start().await.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        let retries = captured.events(log_events::CONNECT_RETRY);
+        assert_eq!(retries.len(), 2);
+        assert_eq!(retries[1].fields["delay_ms"], "20");
+    }
+
+    #[tokio::test]
+    async fn test_ping_interval() {
+        let (client, mut server) = ws_pair().await;
+        let service = ToolkitService::new("test").with_ping_interval(Duration::from_millis(50));
+        let _handle = start_with(service, client);
+
+        let message = timeout(Duration::from_secs(2), server.next())
+            .await
+            .unwrap();
+        assert!(matches!(message, Some(Ok(Message::Ping(_)))));
+    }
+
+    #[tokio::test]
+    async fn test_stops_when_pongs_are_missed() {
+        let captured = Captured::default();
+        let _guard = captured.install();
+
+        // The server never reads, so it never answers the pings.
+        let (client, _server) = ws_pair().await;
+        let service = ToolkitService::new("test").with_ping_interval(Duration::from_millis(50));
+        let handle = start_with(service, client);
+
+        let reason = timeout(Duration::from_secs(2), handle).await.unwrap();
+        assert_eq!(reason.unwrap().unwrap(), ShutdownReason::ConnectionLost);
+        let dead = captured.events(log_events::CONNECTION_DEAD);
+        assert_eq!(dead[0].fields["count"], "2");
+    }
+
+    #[tokio::test]
+    async fn test_answered_pings_keep_the_connection() {
+        let (client, mut server) = ws_pair().await;
+        let service = ToolkitService::new("test")
+            .with_ping_interval(Duration::from_millis(20))
+            .with_missed_pongs(1);
+        let mut handle = start_with(service, client);
+
+        // Reading the pings answers them.
+        let reading = spawn(async move { while server.next().await.is_some() {} });
+        assert!(timeout(Duration::from_millis(300), &mut handle)
+            .await
+            .is_err());
+        reading.abort();
+    }
+
+    #[tokio::test]
+    async fn test_other_frames_do_not_answer_pings() {
+        let (client, mut server) = ws_pair().await;
+        let service = ToolkitService::new("test")
+            .with_ping_interval(Duration::from_millis(50))
+            .with_missed_pongs(1);
+        let handle = start_with(service, client);
+
+        // The server keeps sending frames more often than the pings, but never reads them.
+        let sending = spawn(async move {
+            loop {
+                let frame = Message::Text(r#"{"type":"heartbeat","data":{}}"#.into());
+                if server.send(frame).await.is_err() {
+                    break;
+                }
+                sleep(Duration::from_millis(10)).await;
+            }
+        });
+        let reason = timeout(Duration::from_secs(2), handle).await.unwrap();
+        assert_eq!(reason.unwrap().unwrap(), ShutdownReason::ConnectionLost);
+        sending.abort();
+    }
+
+    #[tokio::test]
+    #[allow(clippy::result_large_err)]
+    async fn test_connect_sends_user_agent() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut user_agent = None;
+            let _ = tokio_tungstenite::accept_hdr_async(
+                stream,
+                |request: &tokio_tungstenite::tungstenite::handshake::server::Request, response| {
+                    user_agent = request.headers().get(header::USER_AGENT).cloned();
+                    Ok(response)
+                },
+            )
+            .await
+            .unwrap();
+            user_agent
+        });
+
+        connect(format!("ws://{addr}"), Duration::from_secs(5))
+            .await
+            .unwrap();
+
+        assert_eq!(server.await.unwrap().unwrap(), USER_AGENT);
+    }
+
+    #[tokio::test]
+    #[allow(clippy::result_large_err)]
+    async fn test_connect_authenticates() {
+        let captured = Captured::default();
+        let _guard = captured.install();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = spawn(async move {
+            let mut requests = Vec::new();
+            for _ in 0..2 {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut seen = None;
+                let _ = tokio_tungstenite::accept_hdr_async(
+                    stream,
+                    |request: &tokio_tungstenite::tungstenite::handshake::server::Request,
+                     response| {
+                        let authorization = request.headers().get(header::AUTHORIZATION);
+                        seen = Some((request.uri().to_string(), authorization.cloned()));
+                        Ok(response)
+                    },
+                )
+                .await
+                .unwrap();
+                requests.push(seen.unwrap());
+            }
+            requests
+        });
+
+        let key = "0123456789abcdef";
+        let mut config = UnifaiConfig {
+            backend_ws_endpoint: format!("ws://{addr}/ws"),
+            ..UnifaiConfig::default()
+        };
+        let wire = WireLogging::default();
+        let api_key = ApiKeyProvider::fixed(key, "toolkit_api_key").unwrap();
+        connect_any(&config, &wire, "toolkit", &api_key)
+            .await
+            .unwrap();
+        config.ws_api_key_in_query = true;
+        connect_any(&config, &wire, "toolkit", &api_key)
+            .await
+            .unwrap();
+
+        let requests = server.await.unwrap();
+        assert_eq!(requests[0].0, "/ws?type=toolkit");
+        assert_eq!(requests[0].1.as_ref().unwrap(), key);
+        assert_eq!(requests[1].0, format!("/ws?type=toolkit&api-key={key}"));
+        assert_eq!(requests[1].1, None);
+
+        let connects = captured.events(log_events::WS_CONNECT);
+        assert!(connects[1].fields["url"].ends_with("api-key=<redacted>cdef"));
+        for event in captured.all() {
+            for value in event.fields.values() {
+                assert!(!value.contains(key), "{value}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout() {
+        let addr = black_hole().await;
+        let started = std::time::Instant::now();
+        let request_timeout = Duration::from_millis(200);
+
+        let api_key = ApiKeyProvider::fixed("test", "toolkit_api_key").unwrap();
+        let error = ApiClient::new(api_key, &ClientOptions::default())
+            .unwrap()
+            .post(format!("http://{addr}/toolkits/fields/"))
+            .timeout(request_timeout)
+            .send()
+            .await
+            .unwrap_err();
+
+        assert!(matches!(
+            ToolkitError::from_request(error, "update_info", request_timeout),
+            ToolkitError::Timeout {
+                operation: "update_info",
+                ..
+            }
+        ));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    /// Answer one HTTP request with `response`, returning the request head.
+    async fn serve_once(response: String) -> (String, JoinHandle<String>) {
+        let (endpoint, server) = serve(vec![response]).await;
+
+        (endpoint, spawn(async { server.await.unwrap().remove(0) }))
+    }
+
+    /// Answer a request with each of `responses` in turn, returning the requests.
+    async fn serve(responses: Vec<String>) -> (String, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = spawn(async move {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                stream.write_all(response.as_bytes()).await.unwrap();
+                requests.push(String::from_utf8(request).unwrap());
+            }
+            requests
+        });
+
+        (format!("http://{addr}"), server)
+    }
+
+    fn service_at(frontend_api_endpoint: String) -> ToolkitService {
+        ToolkitService::from_config(UnifaiConfig {
+            toolkit_api_key: Some("test".to_string()),
+            frontend_api_endpoint,
+            // Nothing listens there, the start fails if it gets to connecting.
+            backend_ws_endpoint: "ws://127.0.0.1:1".to_string(),
+            ..UnifaiConfig::default()
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_verify_credentials() {
+        let body = r#"{"toolkitId":42}"#;
+        let response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let (endpoint, server) = serve_once(response).await;
+
+        let principal = service_at(endpoint).verify_credentials().await.unwrap();
+
+        assert_eq!(principal.toolkit_id, Some(42));
+        let request = server.await.unwrap().to_lowercase();
+        assert!(request.starts_with("get /toolkits/fields/ "), "{request}");
+        assert!(request.contains("authorization: test"));
+    }
+
+    fn info() -> ToolkitInfo {
+        ToolkitInfo {
+            name: "Echo Slam".to_string(),
+            description: "What's in, what's out.".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_update_info() {
+        let body = r#"{"toolkitId":42,"name":"Echo Slam","description":"What's in, what's out."}"#;
+        let response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let (endpoint, server) = serve_once(response).await;
+
+        let applied = service_at(endpoint).update_info(info()).await.unwrap();
+
+        assert_eq!(
+            applied,
+            ToolkitInfoResponse {
+                toolkit_id: Some(42),
+                name: "Echo Slam".to_string(),
+                description: "What's in, what's out.".to_string(),
+            }
+        );
+        let request = server.await.unwrap().to_lowercase();
+        assert!(request.starts_with("post /toolkits/fields/ "), "{request}");
+    }
+
+    #[tokio::test]
+    async fn test_update_info_failures() {
+        let (endpoint, _server) =
+            serve_once("HTTP/1.1 401 Unauthorized\r\ncontent-length: 0\r\n\r\n".to_string()).await;
+
+        let error = service_at(endpoint).update_info(info()).await.unwrap_err();
+
+        assert!(matches!(error, ToolkitError::Unauthorized { status: 401 }));
+
+        let body = r#"{"message":"name is too long"}"#;
+        let response = format!(
+            "HTTP/1.1 422 Unprocessable Entity\r\ncontent-length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let (endpoint, _server) = serve_once(response).await;
+
+        let error = service_at(endpoint).update_info(info()).await.unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "RequestRejected: update_info failed with status 422: name is too long"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_manage_toolkits() {
+        let body = r#"[{"id":7,"name":"test_echo_1700000000","description":"Echo"}]"#;
+        let listed = format!(
+            "HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let deleted = "HTTP/1.1 204 No Content\r\nconnection: close\r\n\r\n".to_string();
+        let (endpoint, server) = serve(vec![listed, deleted]).await;
+        let service = service_at(endpoint);
+
+        let toolkits = service.list_toolkits().await.unwrap();
+        assert_eq!(
+            toolkits,
+            vec![ToolkitSummary {
+                id: 7,
+                name: "test_echo_1700000000".to_string(),
+                description: "Echo".to_string(),
+            }]
+        );
+        service.delete_toolkit(7).await.unwrap();
+
+        let requests = server.await.unwrap();
+        assert!(requests[0].to_lowercase().starts_with("get /toolkits/ "));
+        assert!(requests[0].to_lowercase().contains("authorization: test"));
+        assert!(requests[1]
+            .to_lowercase()
+            .starts_with("delete /toolkits/7/ "));
+    }
+
+    #[tokio::test]
+    async fn test_toolkit_not_found() {
+        let body = r#"{"detail":"Not found."}"#;
+        let response = format!(
+            "HTTP/1.1 404 Not Found\r\ncontent-length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let (endpoint, server) = serve_once(response).await;
+
+        let error = service_at(endpoint).get_toolkit(9).await.unwrap_err();
+
+        assert_eq!(error.to_string(), "ToolkitNotFound: no toolkit with id 9");
+        let request = server.await.unwrap().to_lowercase();
+        assert!(request.starts_with("get /toolkits/9/ "), "{request}");
+    }
+
+    #[tokio::test]
+    async fn test_start_fails_fast_on_invalid_key() {
+        let (endpoint, _server) =
+            serve_once("HTTP/1.1 403 Forbidden\r\ncontent-length: 0\r\n\r\n".to_string()).await;
+
+        let result = service_at(endpoint)
+            .with_credentials_check(true)
+            .start()
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(ToolkitError::Credentials(CredentialsError::InvalidKey {
+                key: "toolkit_api_key",
+                status: 403,
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_try_new_rejects_invalid_keys() {
+        assert!(matches!(
+            ToolkitService::try_new("unifai-key\n"),
+            Err(ConfigError::Invalid { key, .. }) if key == "toolkit_api_key"
+        ));
+        assert!(ToolkitService::try_new("unifai-key").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_rotates_file_secret() {
+        let path = std::env::temp_dir().join(format!("unifai-toolkit-key-{}", std::process::id()));
+        std::fs::write(&path, "first\n").unwrap();
+        let response = "HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-length: 2\r\n\r\n{}";
+        let (endpoint, server) = serve(vec![response.to_string(); 2]).await;
+
+        let api_key = ApiKeyProvider::new(SecretSource::file(&path))
+            .await
+            .unwrap();
+        let service = ToolkitService::with_api_key(
+            api_key,
+            UnifaiConfig {
+                frontend_api_endpoint: endpoint,
+                ..UnifaiConfig::default()
+            },
+        )
+        .unwrap();
+        service.verify_credentials().await.unwrap();
+
+        // Rewritten by the platform mid-run, then signaled.
+        std::fs::write(&path, "second\n").unwrap();
+        service.api_key().clone().rotate().await.unwrap();
+        service.verify_credentials().await.unwrap();
+
+        // A key that can't be read is reported, and the current one kept.
+        std::fs::remove_file(&path).unwrap();
+        let error = service.api_key().rotate().await.unwrap_err();
+        assert!(error.source_name.starts_with("file "), "{error}");
+        assert_eq!(service.api_key().header(), "second");
+
+        let requests = server.await.unwrap();
+        assert!(requests[0]
+            .to_lowercase()
+            .contains("authorization: first\r\n"));
+        assert!(requests[1]
+            .to_lowercase()
+            .contains("authorization: second\r\n"));
+    }
+
+    struct Echo;
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("never")]
+    struct Never;
+
+    impl Action for Echo {
+        const NAME: &'static str = "echo";
+
+        type Error = Never;
+        type Args = serde_json::Value;
+        type Output = serde_json::Value;
+
+        async fn definition(&self) -> crate::toolkit::ActionDefinition {
+            crate::toolkit::ActionDefinition {
+                description: "Echo the payload".to_string(),
+                payload: json!({}),
+                payment: None,
+                ..Default::default()
+            }
+        }
+
+        async fn call(
+            &self,
+            _ctx: ActionContext,
+            params: ActionParams<Self::Args>,
+        ) -> std::result::Result<ActionResult<Self::Output>, Self::Error> {
+            Ok(ActionResult {
+                payload: params.payload,
+                payment: None,
+            })
+        }
+    }
+
+    /// Returns the metadata of its call.
+    struct Metadata;
+
+    impl Action for Metadata {
+        const NAME: &'static str = "metadata";
+
+        type Error = Never;
+        type Args = serde_json::Value;
+        type Output = Map<String, Value>;
+
+        async fn definition(&self) -> crate::toolkit::ActionDefinition {
+            crate::toolkit::ActionDefinition {
+                description: "Return the metadata of the call".to_string(),
+                payload: json!({}),
+                payment: None,
+                ..Default::default()
+            }
+        }
+
+        async fn call(
+            &self,
+            ctx: ActionContext,
+            _params: ActionParams<Self::Args>,
+        ) -> std::result::Result<ActionResult<Self::Output>, Self::Error> {
+            Ok(ActionResult {
+                payload: ctx.metadata().clone(),
+                payment: None,
+            })
+        }
+    }
+
+    /// Serializes like a float field holding NaN would with a strict serializer.
+    struct NotANumber;
+
+    impl Serialize for NotANumber {
+        fn serialize<S: serde::Serializer>(
+            &self,
+            _serializer: S,
+        ) -> std::result::Result<S::Ok, S::Error> {
+            Err(serde::ser::Error::custom(format!(
+                "{} is not a valid JSON number",
+                f64::NAN
+            )))
+        }
+    }
+
+    struct Nan;
+
+    impl Action for Nan {
+        const NAME: &'static str = "nan";
+
+        type Error = Never;
+        type Args = serde_json::Value;
+        type Output = NotANumber;
+
+        async fn definition(&self) -> crate::toolkit::ActionDefinition {
+            crate::toolkit::ActionDefinition {
+                description: "Return an unserializable payload".to_string(),
+                payload: json!({}),
+                payment: None,
+                ..Default::default()
+            }
+        }
+
+        async fn call(
+            &self,
+            _ctx: ActionContext,
+            _params: ActionParams<Self::Args>,
+        ) -> std::result::Result<ActionResult<Self::Output>, Self::Error> {
+            Ok(ActionResult {
+                payload: NotANumber,
+                payment: None,
+            })
+        }
+    }
+
+    struct Records;
+
+    impl Records {
+        fn output() -> Vec<fixtures::Record> {
+            fixtures::records(64 * 1024)
+        }
+    }
+
+    impl Action for Records {
+        const NAME: &'static str = "records";
+
+        type Error = Never;
+        type Args = serde_json::Value;
+        type Output = Vec<fixtures::Record>;
+
+        async fn definition(&self) -> crate::toolkit::ActionDefinition {
+            crate::toolkit::ActionDefinition {
+                description: "Return records".to_string(),
+                payload: json!({}),
+                payment: None,
+                ..Default::default()
+            }
+        }
+
+        async fn call(
+            &self,
+            _ctx: ActionContext,
+            _params: ActionParams<Self::Args>,
+        ) -> std::result::Result<ActionResult<Self::Output>, Self::Error> {
+            Ok(ActionResult {
+                payload: Records::output(),
+                payment: Some(3),
+            })
+        }
+    }
+
+    async fn next_result(server: &mut WebSocketStream<TcpStream>) -> ActionCallResult {
+        loop {
+            if let Message::Text(text) = server.next().await.unwrap().unwrap() {
+                if let ToolkitMessage::ActionResult { data } = serde_json::from_str(&text).unwrap()
+                {
+                    return data;
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unserializable_result_keeps_service_running() {
+        let (client, mut server) = ws_pair().await;
+        let mut service = ToolkitService::new("test");
+        service.add_action(Echo);
+        service.add_action(Nan);
+        let handle = start_with(service, client);
+
+        server.send(action_call("nan", 1)).await.unwrap();
+        let result = next_result(&mut server).await;
+        assert_eq!(result.action_id, 1);
+        let payload: Value = serde_json::from_str(result.payload.get()).unwrap();
+        assert!(payload["error"].as_str().unwrap().contains("NaN"));
+
+        server.send(action_call("echo", 2)).await.unwrap();
+        let result = next_result(&mut server).await;
+        assert_eq!(result.action_id, 2);
+        assert_eq!(result.payload.get(), "{}");
+
+        assert!(!handle.runner.is_finished());
+    }
+
+    #[tokio::test]
+    async fn test_raw_results_match_value_results() {
+        let mut service = ToolkitService::new("test");
+        service.add_action(Echo);
+        service.add_action(Records);
+        let service = Arc::new(service);
+
+        let echoed = json!({ "z": [1, 2.5, -3, "x\n"], "a": { "d": null, "c": true } });
+        let cases = [
+            ("echo", echoed.clone(), echoed, None),
+            (
+                "records",
+                json!({}),
+                serde_json::to_value(Records::output()).unwrap(),
+                Some(3),
+            ),
+        ];
+        for (action, payload, output, payment) in cases {
+            let frame = serde_json::to_vec(&ToolkitMessage::Action {
+                data: ActionCallParams::new(action)
+                    .with_action_id(7)
+                    .with_payload(payload),
+            })
+            .unwrap();
+            let text = service.clone().__handle_frame(&frame).await.unwrap();
+
+            // The message as sent when the payload went through a Value.
+            let expected = format!(
+                r#"{{"type":"actionResult","data":{{"action":"{action}","actionID":7,"agentID":1,"payload":{},"payment":{}}}}}"#,
+                serde_json::to_string(&output).unwrap(),
+                serde_json::to_string(&payment).unwrap(),
+            );
+            assert_eq!(text, expected);
+        }
+    }
+
+    #[test]
+    fn test_encode_message_keeps_valid_results() {
+        let events = EventSender::new(None);
+        let mut receiver = events.subscribe();
+        let message = ToolkitMessage::ActionResult {
+            data: ActionCallResult::new("echo")
+                .with_action_id(3)
+                .with_payload("ok"),
+        };
+
+        let text = encode_message(message, &events, None).unwrap();
+        assert!(text.contains("\"actionID\":3"));
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_large_result_is_replaced() {
+        let mut service = ToolkitService::new("test").with_max_result_bytes(1024);
+        service.add_action(Echo);
+        let mut harness = ToolkitTestHarness::start(service).await.unwrap();
+
+        let small = json!({ "content": "a".repeat(100) });
+        harness.send_raw(action_message("echo", 1, small.clone()));
+        let (_, payload) = next_result_payload(&mut harness).await;
+        assert_eq!(payload, small);
+
+        let large = json!({ "content": "a".repeat(2000) });
+        harness.send_raw(action_message("echo", 2, large));
+        let (action_id, payload) = next_result_payload(&mut harness).await;
+        assert_eq!(action_id, 2);
+        assert_eq!(payload["code"], "payload_too_large");
+        assert_eq!(payload["limit"], 1024);
+        assert!(payload["size"].as_u64().unwrap() > 2000);
+        assert!(harness.shutdown().await.is_ok());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_runs_on_current_thread_runtime() {
+        let (client, mut server) = ws_pair().await;
+        let mut service = ToolkitService::new("test");
+        service.add_action(Echo);
+        let handle = start_with(service, client);
+
+        for action_id in 0..3 {
+            server.send(action_call("echo", action_id)).await.unwrap();
+            assert_eq!(next_result(&mut server).await.action_id, action_id);
+        }
+
+        server.close(None).await.unwrap();
+        assert!(handle.await.unwrap().is_ok());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_logs_structured_action_events() {
+        let captured = Captured::default();
+        let _guard = captured.install();
+
+        let (client, mut server) = ws_pair().await;
+        let mut service = ToolkitService::new("test");
+        service.add_action(Echo);
+        let handle = start_with(service, client);
+
+        let message = ToolkitMessage::Action {
+            data: ActionCallParams::new("echo")
+                .with_action_id(5)
+                .with_payload(json!({ "content": "hello", "apiKey": "secret-key" })),
+        };
+        server
+            .send(Message::text(serde_json::to_string(&message).unwrap()))
+            .await
+            .unwrap();
+        next_result(&mut server).await;
+
+        server.close(None).await.unwrap();
+        handle.await.unwrap().unwrap();
+
+        let calls = captured.events(log_events::ACTION_CALL);
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].target, targets::DISPATCH);
+        assert_eq!(calls[0].fields["action"], "echo");
+        assert_eq!(calls[0].fields["action_id"], "5");
+
+        let results = captured.events(log_events::ACTION_RESULT);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].fields["outcome"], outcomes::OK);
+        assert!(results[0].fields.contains_key("duration_ms"));
+
+        let payloads = captured.events(log_events::ACTION_PAYLOAD);
+        assert_eq!(payloads.len(), 2);
+        for payload in payloads {
+            assert_eq!(payload.level, tracing::Level::DEBUG);
+            assert!(payload.fields["payload"].contains("hello"));
+            assert!(!payload.fields["payload"].contains("secret-key"));
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_wire_logging_redacts_frames() {
+        let captured = Captured::default();
+        let _guard = captured.install();
+
+        let (client, mut server) = ws_pair().await;
+        let mut service =
+            ToolkitService::new("test").with_wire_logging(WireLogging::default().log_bodies(true));
+        service.add_action(Echo);
+        let handle = start_with(service, client);
+
+        let message = ToolkitMessage::Action {
+            data: ActionCallParams::new("echo")
+                .with_action_id(9)
+                .with_payload(json!({ "privateKey": "secret-key", "content": "hello" })),
+        };
+        server
+            .send(Message::text(serde_json::to_string(&message).unwrap()))
+            .await
+            .unwrap();
+        next_result(&mut server).await;
+        let unknown = r#"{"type":"novel","data":{"apiKey":"secret-api-key"}}"#;
+        server.send(Message::text(unknown)).await.unwrap();
+
+        server.close(None).await.unwrap();
+        handle.await.unwrap().unwrap();
+
+        let frames = captured.events(log_events::WS_FRAME);
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].fields["direction"], directions::RECEIVED);
+        assert_eq!(frames[1].fields["direction"], directions::SENT);
+        assert_eq!(frames[2].fields["bytes"], unknown.len().to_string());
+        let warning = &captured.events(log_events::UNKNOWN_MESSAGE)[0];
+        assert!(warning.fields["frame"].contains(r#""apiKey":"<redacted>""#));
+        for event in captured.all() {
+            for value in event.fields.values() {
+                assert!(!value.contains("secret-"), "{value}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_registration_reports_client_info() {
+        let registration = |service: ToolkitService| {
+            serde_json::to_value(ToolkitMessage::RegisterActions {
+                data: service.registration(HashMap::new()),
+            })
+            .unwrap()
+        };
+
+        let message = registration(ToolkitService::new("test"));
+        assert_eq!(message["data"]["clientInfo"]["sdk"], "rust");
+        assert_eq!(message["data"]["clientInfo"]["version"], SDK_VERSION);
+
+        let message = registration(ToolkitService::new("test").with_legacy_registration(true));
+        assert_eq!(
+            message,
+            json!({ "type": "registerActions", "data": { "actions": {} } })
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_logs_registration_size_and_send_time() {
+        let captured = Captured::default();
+        let _guard = captured.install();
+
+        let mut service = ToolkitService::new("test");
+        service.add_action(Echo);
+        let (mut transport, mut peer) = ChannelTransport::new();
+
+        service.register(&mut transport).await.unwrap();
+
+        let text = peer.recv().await.unwrap();
+        let registered = captured.events(log_events::ACTIONS_REGISTERED);
+        assert_eq!(registered.len(), 1);
+        assert_eq!(registered[0].fields["count"], "1");
+        assert_eq!(registered[0].fields["bytes"], text.len().to_string());
+        assert!(registered[0].fields.contains_key("send_ms"));
+    }
+
+    /// Takes `delay` to define, counting the definitions evaluated at once.
+    struct SlowDefinition {
+        name: String,
+        delay: Duration,
+        in_flight: Arc<AtomicUsize>,
+        peak: Arc<AtomicUsize>,
+    }
+
+    impl ActionDyn for SlowDefinition {
+        fn name(&self) -> String {
+            self.name.clone()
+        }
+
+        fn definition(&self) -> Pin<Box<dyn Future<Output = ActionDefinition> + Send + Sync + '_>> {
+            Box::pin(async move {
+                let current = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                self.peak.fetch_max(current, Ordering::SeqCst);
+                sleep(self.delay).await;
+                self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+                ActionDefinition {
+                    description: self.name.clone(),
+                    payload: json!({}),
+                    payment: None,
+                    ..Default::default()
+                }
+            })
+        }
+
+        fn call(
+            &self,
+            _ctx: ActionContext,
+            params: ActionParams<Box<RawValue>>,
+        ) -> Pin<Box<dyn Future<Output = Result<ActionResult<Value>>> + Send + Sync + '_>> {
+            Box::pin(async move {
+                Ok(ActionResult {
+                    payload: serde_json::from_str(params.payload.get())?,
+                    payment: None,
+                })
+            })
+        }
+    }
+
+    fn slow_service(delays: &[(&str, Duration)]) -> (ToolkitService, Arc<AtomicUsize>) {
+        let service = ToolkitService::new("test");
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        for (name, delay) in delays {
+            service.actions_mut().insert(
+                (*name).into(),
+                Arc::new(SlowDefinition {
+                    name: name.to_string(),
+                    delay: *delay,
+                    in_flight: in_flight.clone(),
+                    peak: peak.clone(),
+                }),
+                ExecutionMode::Spawned,
+            );
+        }
+
+        (service, peak)
+    }
+
+    #[tokio::test]
+    async fn test_definitions_bounded_concurrency() {
+        let names: Vec<String> = (0..20).map(|i| format!("action_{i}")).collect();
+        let delays: Vec<_> = names
+            .iter()
+            .map(|name| (name.as_str(), Duration::from_millis(20)))
+            .collect();
+        let (service, peak) = slow_service(&delays);
+
+        let definitions = service
+            .with_definition_concurrency(3)
+            .definitions()
+            .await
+            .unwrap();
+
+        assert_eq!(definitions.len(), 20);
+        assert_eq!(definitions["action_7"].description, "action_7");
+        assert_eq!(peak.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_definition_timeout_names_action() {
+        let (service, _) =
+            slow_service(&[("fast", Duration::ZERO), ("slow", Duration::from_secs(10))]);
+        let started = Instant::now();
+
+        let result =
+            ToolkitTestHarness::start(service.with_definition_timeout(Duration::from_millis(100)))
+                .await;
+
+        let Err(error) = result else {
+            panic!("the registration didn't time out");
+        };
+        assert!(
+            matches!(&error, ToolkitError::DefinitionTimeout { action, .. } if action == "slow"),
+            "{error}"
+        );
+        assert!(error.to_string().contains("slow"));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_errors_reports_deprecation_notice() {
+        let harness = ToolkitTestHarness::start(ToolkitService::new("test"))
+            .await
+            .unwrap();
+        let mut errors = harness.errors();
+
+        harness.send_raw(ToolkitMessage::RegisterActionsAck {
+            data: ActionsRegisterAck {
+                deprecation: Some("payment is deprecated".to_string()),
+                minimum_version: Some("0.2.0".to_string()),
+            },
+        });
+
+        match errors.recv().await.unwrap() {
+            ToolkitRuntimeEvent::DeprecationNotice {
+                message,
+                minimum_version,
+            } => {
+                assert_eq!(message.as_deref(), Some("payment is deprecated"));
+                assert_eq!(minimum_version.as_deref(), Some("0.2.0"));
+            }
+            event => panic!("unexpected event: {event:?}"),
+        }
+
+        assert!(harness.shutdown().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_runs_over_any_transport() {
+        let mut service = ToolkitService::new("test");
+        service.add_action(Echo);
+        let mut harness = ToolkitTestHarness::start(service).await.unwrap();
+
+        let result = harness.send_action_call("echo", json!({}), 1).await;
+        assert_eq!(result.action, "echo");
+
+        assert_eq!(
+            harness.shutdown().await.unwrap(),
+            ShutdownReason::ServerClosed {
+                code: None,
+                reason: None
+            }
+        );
+    }
+
+    /// Sleeps for as many milliseconds as its payload.
+    struct Sleep;
+
+    impl Action for Sleep {
+        const NAME: &'static str = "sleep";
+
+        type Error = Never;
+        type Args = u64;
+        type Output = u64;
+
+        async fn definition(&self) -> crate::toolkit::ActionDefinition {
+            crate::toolkit::ActionDefinition {
+                description: "Sleep for the milliseconds of the payload".to_string(),
+                payload: json!({}),
+                payment: None,
+                ..Default::default()
+            }
+        }
+
+        async fn call(
+            &self,
+            _ctx: ActionContext,
+            params: ActionParams<Self::Args>,
+        ) -> std::result::Result<ActionResult<Self::Output>, Self::Error> {
+            sleep(Duration::from_millis(params.payload)).await;
+
+            Ok(ActionResult {
+                payload: params.payload,
+                payment: None,
+            })
+        }
+    }
+
+    fn action_message(action: &str, action_id: u64, payload: Value) -> ToolkitMessage {
+        ToolkitMessage::from_frame(&fixtures::action_frame(action, action_id, &payload)).unwrap()
+    }
+
+    async fn next_result_id(harness: &mut ToolkitTestHarness) -> u64 {
+        let text = harness.next_frame().await.unwrap();
+        match serde_json::from_str(&text).unwrap() {
+            ToolkitMessage::ActionResult { data } => data.action_id,
+            _ => panic!("unexpected message: {text}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_slow_inline_call_moves_to_task() {
+        let captured = Captured::default();
+        let _guard = captured.install();
+
+        let mut service = ToolkitService::new("test");
+        service.add_action_with_mode(Sleep, ExecutionMode::Inline);
+        let mut harness = ToolkitTestHarness::start(service).await.unwrap();
+        let started = Instant::now();
+
+        harness.send_raw(action_message("sleep", 1, json!(600_000)));
+        harness.send_raw(action_message("sleep", 2, json!(1)));
+
+        // The slow call left the dispatch loop after its budget instead of stalling it, and pings
+        // with it.
+        assert_eq!(next_result_id(&mut harness).await, 2);
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let demoted = captured.events(log_events::INLINE_CALL_DEMOTED);
+        assert_eq!(demoted.len(), 1);
+        assert_eq!(demoted[0].fields["action_id"], "1");
+
+        assert!(harness.shutdown().await.is_ok());
+    }
+
+    /// Like [Sleep], with its own timeout.
+    struct Impatient(Duration);
+
+    impl Action for Impatient {
+        const NAME: &'static str = "impatient";
+
+        type Error = Never;
+        type Args = u64;
+        type Output = u64;
+
+        async fn definition(&self) -> crate::toolkit::ActionDefinition {
+            Action::definition(&Sleep).await
+        }
+
+        async fn call(
+            &self,
+            ctx: ActionContext,
+            params: ActionParams<Self::Args>,
+        ) -> std::result::Result<ActionResult<Self::Output>, Self::Error> {
+            Action::call(&Sleep, ctx, params).await
+        }
+
+        fn timeout(&self) -> Option<Duration> {
+            Some(self.0)
+        }
+    }
+
+    async fn next_result_payload(harness: &mut ToolkitTestHarness) -> (u64, Value) {
+        let text = harness.next_frame().await.unwrap();
+        match serde_json::from_str(&text).unwrap() {
+            ToolkitMessage::ActionResult { data } => (
+                data.action_id,
+                serde_json::from_str(data.payload.get()).unwrap(),
+            ),
+            _ => panic!("unexpected message: {text}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_action_timeout() {
+        let captured = Captured::default();
+        let _guard = captured.install();
+
+        let mut service = ToolkitService::new("test").with_action_timeout(Duration::from_secs(10));
+        service.add_action(Sleep);
+        service.add_action(Impatient(Duration::from_millis(50)));
+        let mut harness = ToolkitTestHarness::start(service).await.unwrap();
+
+        harness.send_raw(action_message("impatient", 1, json!(600_000)));
+        assert_eq!(
+            next_result_payload(&mut harness).await,
+            (
+                1,
+                json!({ "error": "ActionTimeout: action impatient timed out after 50ms", "code": "timeout" })
+            )
+        );
+        harness.send_raw(action_message("sleep", 2, json!(1)));
+        assert_eq!(next_result_payload(&mut harness).await, (2, json!(1)));
+
+        let results = captured.events(log_events::ACTION_RESULT);
+        assert_eq!(results[0].fields["outcome"], outcomes::ERROR);
+        assert_eq!(results[1].fields["outcome"], outcomes::OK);
+
+        assert!(harness.shutdown().await.is_ok());
+    }
+
+    /// Fails with an [ActionError] as its source if its payload is `true`.
+    struct Failing;
+
+    #[derive(Debug, thiserror::Error)]
+    enum FailingError {
+        #[error("quota exceeded")]
+        Quota(#[source] ActionError),
+        #[error("disk full")]
+        Disk,
+    }
+
+    impl Action for Failing {
+        const NAME: &'static str = "failing";
+
+        type Error = FailingError;
+        type Args = bool;
+        type Output = ();
+
+        async fn definition(&self) -> crate::toolkit::ActionDefinition {
+            crate::toolkit::ActionDefinition {
+                description: "Fail".to_string(),
+                payload: json!({}),
+                payment: None,
+                ..Default::default()
+            }
+        }
+
+        async fn call(
+            &self,
+            _ctx: ActionContext,
+            params: ActionParams<Self::Args>,
+        ) -> std::result::Result<ActionResult<Self::Output>, Self::Error> {
+            Err(match params.payload {
+                true => FailingError::Quota(
+                    ActionError::new("quota_exceeded", "daily quota used")
+                        .with_retryable(true)
+                        .with_details(json!({ "reset_in": 60 })),
+                ),
+                false => FailingError::Disk,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_action_error_payload() {
+        let captured = Captured::default();
+        let _guard = captured.install();
+
+        let mut service = ToolkitService::new("test");
+        service.add_action(Failing);
+        let mut harness = ToolkitTestHarness::start(service).await.unwrap();
+
+        harness.send_raw(action_message("failing", 1, json!(true)));
+        assert_eq!(
+            next_result_payload(&mut harness).await,
+            (
+                1,
+                json!({
+                    "error": "ActionCallError: quota exceeded",
+                    "code": "quota_exceeded",
+                    "message": "daily quota used",
+                    "retryable": true,
+                    "details": { "reset_in": 60 },
+                })
+            )
+        );
+        harness.send_raw(action_message("failing", 2, json!(false)));
+        assert_eq!(
+            next_result_payload(&mut harness).await,
+            (
+                2,
+                json!({
+                    "error": "ActionCallError: disk full",
+                    "code": "action_error",
+                    "message": "disk full",
+                    "retryable": false,
+                    "details": null,
+                })
+            )
+        );
+
+        let failures: Vec<_> = captured
+            .all()
+            .into_iter()
+            .filter(|event| {
+                event.fields.get("message").map(String::as_str)
+                    == Some("Error occured during action call")
+            })
+            .collect();
+        assert_eq!(failures.len(), 2);
+        assert!(failures
+            .iter()
+            .all(|event| event.level == tracing::Level::WARN));
+
+        assert!(harness.shutdown().await.is_ok());
+    }
+
+    /// Unavailable for a minute.
+    struct Maintenance;
+
+    impl Action for Maintenance {
+        const NAME: &'static str = "maintenance";
+
+        type Error = Never;
+        type Args = Value;
+        type Output = Value;
+
+        async fn definition(&self) -> crate::toolkit::ActionDefinition {
+            crate::toolkit::ActionDefinition {
+                description: "Under maintenance".to_string(),
+                payload: json!({}),
+                ..Default::default()
+            }
+        }
+
+        async fn available(&self) -> Availability {
+            Availability::Unavailable {
+                retry_after: Some(Duration::from_secs(60)),
+            }
+        }
+
+        async fn call(
+            &self,
+            _ctx: ActionContext,
+            _params: ActionParams<Self::Args>,
+        ) -> std::result::Result<ActionResult<Self::Output>, Self::Error> {
+            panic!("called while unavailable")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unavailable_actions() {
+        let mut service = ToolkitService::new("test");
+        service.add_action(Maintenance);
+        service.add_action(Echo);
+        let mut harness = ToolkitTestHarness::start(service).await.unwrap();
+
+        harness.send_raw(action_message("maintenance", 1, json!({})));
+        assert_eq!(
+            next_result_payload(&mut harness).await,
+            (
+                1,
+                json!({
+                    "error": "ActionUnavailable: action maintenance is unavailable",
+                    "code": "unavailable",
+                    "retry_after_seconds": 60,
+                })
+            )
+        );
+
+        // Toggled from the handle.
+        assert!(harness
+            .handle()
+            .set_availability("echo", Availability::from(false)));
+        assert!(!harness
+            .handle()
+            .set_availability("missing", Availability::from(false)));
+        harness.send_raw(action_message("echo", 2, json!("hi")));
+        let (_, payload) = next_result_payload(&mut harness).await;
+        assert_eq!(payload["code"], "unavailable");
+        assert_eq!(payload["retry_after_seconds"], Value::Null);
+
+        harness
+            .handle()
+            .set_availability("echo", Availability::Available);
+        harness.send_raw(action_message("echo", 3, json!("hi")));
+        assert_eq!(next_result_payload(&mut harness).await, (3, json!("hi")));
+
+        assert!(harness.shutdown().await.is_ok());
+    }
+
+    fn greet(validate: bool) -> ToolkitService {
+        let definition = crate::toolkit::ActionDefinition {
+            description: "Greet someone".to_string(),
+            payload: json!({
+                "name": { "type": "string", "description": "Who to greet.", "required": true },
+            }),
+            payment: None,
+            ..Default::default()
+        };
+        let mut service = ToolkitService::new("test").with_payload_validation(validate);
+        service.add_action(crate::toolkit::FunctionAction::new(
+            "greet",
+            definition,
+            |_ctx, payload: Value| async move { Ok(format!("Hello {}", payload["name"])) },
+        ));
+        service
+    }
+
+    #[tokio::test]
+    async fn test_payload_validation() {
+        let mut harness = ToolkitTestHarness::start(greet(true)).await.unwrap();
+
+        harness.send_raw(action_message("greet", 1, json!({ "name": 7 })));
+        assert_eq!(
+            next_result_payload(&mut harness).await,
+            (
+                1,
+                json!({
+                    "error": "InvalidPayload: name: expected string",
+                    "code": "invalid_payload",
+                    "violations": [{
+                        "field": "name",
+                        "problem": "expected string",
+                        "description": "Who to greet.",
+                    }],
+                })
+            )
+        );
+        harness.send_raw(action_message("greet", 2, json!({ "name": "Ada" })));
+        assert_eq!(
+            next_result_payload(&mut harness).await,
+            (2, json!("Hello \"Ada\""))
+        );
+        assert!(harness.shutdown().await.is_ok());
+
+        // Off by default, the action gets any payload its arguments deserialize from.
+        let mut harness = ToolkitTestHarness::start(greet(false)).await.unwrap();
+        harness.send_raw(action_message("greet", 1, json!({ "name": 7 })));
+        assert_eq!(
+            next_result_payload(&mut harness).await,
+            (1, json!("Hello 7"))
+        );
+        assert!(harness.shutdown().await.is_ok());
+    }
+
+    /// An action sending its progress, and keeping its context to send more once it returned.
+    fn render(mode: ExecutionMode) -> (ToolkitService, Arc<std::sync::Mutex<Vec<ActionContext>>>) {
+        let kept = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let definition = crate::toolkit::ActionDefinition {
+            description: "Render a scene".to_string(),
+            payload: json!({}),
+            payment: None,
+            ..Default::default()
+        };
+        let mut service = ToolkitService::new("test");
+        let contexts = kept.clone();
+        service.add_action_with_mode(
+            crate::toolkit::FunctionAction::new("render", definition, move |ctx, _: Value| {
+                let contexts = contexts.clone();
+                async move {
+                    ctx.send_progress(json!({ "done": 0.5 }))?;
+                    contexts.lock().unwrap().push(ctx);
+                    Ok("rendered")
+                }
+            }),
+            mode,
+        );
+        (service, kept)
+    }
+
+    #[tokio::test]
+    async fn test_action_progress() {
+        let captured = Captured::default();
+        let _guard = captured.install();
+
+        for mode in [ExecutionMode::Inline, ExecutionMode::Spawned] {
+            let (service, kept) = render(mode);
+            let mut harness = ToolkitTestHarness::start(service).await.unwrap();
+
+            harness.send_raw(action_message("render", 1, json!({})));
+            let text = harness.next_frame().await.unwrap();
+            match serde_json::from_str(&text).unwrap() {
+                ToolkitMessage::ActionProgress { data } => {
+                    assert_eq!((data.action_id, data.agent_id), (1, 1));
+                    assert_eq!(data.payload.get(), r#"{"done":0.5}"#);
+                }
+                _ => panic!("unexpected message: {text}"),
+            }
+            assert_eq!(
+                next_result_payload(&mut harness).await,
+                (1, json!("rendered"))
+            );
+
+            // The call returned, its progress goes nowhere.
+            let ctx = kept.lock().unwrap().pop().unwrap();
+            ctx.send_progress(json!({ "done": 1.0 })).unwrap();
+            assert!(harness.shutdown().await.is_ok());
+        }
+
+        let dropped = captured.events(log_events::PROGRESS_DROPPED);
+        assert_eq!(dropped.len(), 2);
+        assert_eq!(dropped[0].fields["action_id"], "1");
+    }
+
+    fn cancel_message(action_id: u64) -> ToolkitMessage {
+        ToolkitMessage::ActionCancel {
+            data: crate::toolkit::messages::ActionCallCancel {
+                action_id,
+                agent_id: None,
+                reason: Some("agent disconnected".to_string()),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancel_action_call() {
+        let captured = Captured::default();
+        let _guard = captured.install();
+
+        let definition = crate::toolkit::ActionDefinition {
+            description: "Wait to be cancelled".to_string(),
+            payload: json!({}),
+            payment: None,
+            ..Default::default()
+        };
+        let mut service = ToolkitService::new("test").with_cancel_grace(Duration::from_millis(50));
+        service.add_action(crate::toolkit::FunctionAction::new(
+            "wait",
+            definition,
+            |ctx, _: Value| async move {
+                ctx.cancelled().await;
+                Ok("stopped cleanly")
+            },
+        ));
+        service.add_action(Sleep);
+        let mut harness = ToolkitTestHarness::start(service).await.unwrap();
+        let cancelled = |action: &str| json!({ "error": format!("ActionCancelled: call of action {action} was cancelled"), "code": "cancelled" });
+
+        // The action stops on its own, its result is replaced.
+        harness.send_raw(action_message("wait", 1, json!({})));
+        harness.send_raw(cancel_message(1));
+        assert_eq!(
+            next_result_payload(&mut harness).await,
+            (1, cancelled("wait"))
+        );
+
+        // The action ignores the cancellation, it's aborted after the grace period.
+        harness.send_raw(action_message("sleep", 2, json!(600_000)));
+        harness.send_raw(cancel_message(2));
+        assert_eq!(
+            next_result_payload(&mut harness).await,
+            (2, cancelled("sleep"))
+        );
+
+        // Cancelling a call that isn't in flight is a no-op.
+        harness.send_raw(cancel_message(3));
+        harness.send_raw(action_message("sleep", 4, json!(1)));
+        assert_eq!(next_result_payload(&mut harness).await, (4, json!(1)));
+
+        let events = captured.events(log_events::ACTION_CANCELLED);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].fields["reason"], "agent disconnected");
+        let aborted = captured.events(log_events::ACTION_ABORTED);
+        assert_eq!(aborted.len(), 1);
+        assert_eq!(aborted[0].fields["action_id"], "2");
+
+        assert!(harness.shutdown().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_action_call_of_an_agent() {
+        let captured = Captured::default();
+        let _guard = captured.install();
+
+        let definition = crate::toolkit::ActionDefinition {
+            description: "Wait to be cancelled".to_string(),
+            payload: json!({}),
+            payment: None,
+            ..Default::default()
+        };
+        let mut service = ToolkitService::new("test");
+        service.add_action(crate::toolkit::FunctionAction::new(
+            "wait",
+            definition,
+            |ctx, _: Value| async move {
+                ctx.cancelled().await;
+                Ok("stopped cleanly")
+            },
+        ));
+        let mut harness = ToolkitTestHarness::start(service).await.unwrap();
+
+        // Two agents call with the same action ID.
+        for agent_id in [1, 2] {
+            let data = ActionCallParams::new("wait")
+                .with_action_id(1)
+                .with_agent_id(agent_id);
+            harness.send_raw(ToolkitMessage::Action { data });
+        }
+        // Without an agent, the cancellation can't tell which call it's meant for.
+        harness.send_raw(cancel_message(1));
+
+        let cancel = crate::toolkit::messages::ActionCallCancel {
+            action_id: 1,
+            agent_id: Some(2),
+            reason: None,
+        };
+        harness.send_raw(ToolkitMessage::ActionCancel { data: cancel });
+        let text = harness.next_frame().await.unwrap();
+        match serde_json::from_str(&text).unwrap() {
+            ToolkitMessage::ActionResult { data } => {
+                assert_eq!((data.agent_id, data.action_id), (2, 1));
+            }
+            _ => panic!("unexpected message: {text}"),
+        }
+
+        let ambiguous = captured.events(log_events::AMBIGUOUS_CANCEL);
+        assert_eq!(ambiguous.len(), 1);
+        assert_eq!(ambiguous[0].fields["calls"], "2");
+        assert_eq!(captured.events(log_events::ACTION_CANCELLED).len(), 1);
+
+        assert!(harness.shutdown().await.is_ok());
+    }
+
+    /// The names of the actions of the next registration, sorted.
+    async fn next_registration(harness: &mut ToolkitTestHarness) -> Vec<String> {
+        let text = harness.next_frame().await.unwrap();
+        match serde_json::from_str(&text).unwrap() {
+            ToolkitMessage::RegisterActions { data } => {
+                let mut names: Vec<_> = data.actions.into_keys().collect();
+                names.sort();
+                names
+            }
+            _ => panic!("unexpected message: {text}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_and_remove_actions_while_running() {
+        let mut service = ToolkitService::new("test");
+        service.add_action(Sleep);
+        let mut harness = ToolkitTestHarness::start(service).await.unwrap();
+
+        harness.handle().add_action(Echo).unwrap();
+        assert_eq!(next_registration(&mut harness).await, ["echo", "sleep"]);
+
+        // The call in flight completes, the later ones find no action.
+        harness.send_raw(action_message("sleep", 1, json!(100)));
+        while harness.handle().in_flight_actions() == 0 {
+            sleep(Duration::from_millis(1)).await;
+        }
+        assert!(harness.handle().remove_action("sleep"));
+        assert!(!harness.handle().remove_action("sleep"));
+        assert_eq!(next_registration(&mut harness).await, ["echo"]);
+
+        harness.send_raw(action_message("sleep", 2, json!(1)));
+        assert_eq!(
+            next_result_payload(&mut harness).await,
+            (
+                2,
+                json!({
+                    "error": "ActionNotFound: no action named sleep",
+                    "code": "not_found",
+                    "available_actions": ["echo"],
+                })
+            )
+        );
+        assert_eq!(next_result_payload(&mut harness).await, (1, json!(100)));
+
+        assert!(harness.handle().add_action(Renamed("no spaces")).is_err());
+        assert!(harness.shutdown().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_service_action_timeout() {
+        let mut service =
+            ToolkitService::new("test").with_action_timeout(Duration::from_millis(50));
+        service.add_action(Sleep);
+        let mut harness = ToolkitTestHarness::start(service).await.unwrap();
+
+        harness.send_raw(action_message("sleep", 1, json!(600_000)));
+        assert_eq!(
+            next_result_payload(&mut harness).await,
+            (
+                1,
+                json!({ "error": "ActionTimeout: action sleep timed out after 50ms", "code": "timeout" })
+            )
+        );
+
+        assert!(harness.shutdown().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrent_actions() {
+        let captured = Captured::default();
+        let _guard = captured.install();
+
+        let mut service = ToolkitService::new("test")
+            .with_max_concurrent_actions(1)
+            .with_max_queued_actions(1);
+        service.add_action(Sleep);
+        let (handle, mut peer) = service.__start_over_channels();
+
+        for (action_id, millis) in [(1, 200), (2, 1), (3, 1)] {
+            peer.send(fixtures::action_frame("sleep", action_id, &json!(millis)));
+        }
+
+        let result = |text: String| match serde_json::from_str(&text).unwrap() {
+            ToolkitMessage::ActionResult { data } => {
+                let payload: Value = serde_json::from_str(data.payload.get()).unwrap();
+                (data.action_id, payload)
+            }
+            message => panic!("unexpected message: {message:?}"),
+        };
+
+        // The third call found the queue full, and is answered first.
+        assert_eq!(
+            result(peer.recv().await.unwrap()),
+            (
+                3,
+                json!({ "error": "ToolkitBusy: 1 action calls already waiting", "code": "toolkit_busy" })
+            )
+        );
+        assert_eq!(
+            (handle.in_flight_actions(), handle.queued_actions()),
+            (1, 1)
+        );
+        assert_eq!(captured.events(log_events::TOOLKIT_BUSY).len(), 1);
+
+        assert_eq!(result(peer.recv().await.unwrap()), (1, json!(200)));
+        assert_eq!(result(peer.recv().await.unwrap()), (2, json!(1)));
+        assert_eq!(
+            (handle.in_flight_actions(), handle.queued_actions()),
+            (0, 0)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stops_when_the_server_closes() {
+        let (client, mut server) = ws_pair().await;
+        let handle = start_with(ToolkitService::new("test"), client);
+        let mut errors = handle.errors();
+
+        server
+            .close(Some(CloseFrame {
+                code: CloseCode::Library(4000),
+                reason: "maintenance".into(),
+            }))
+            .await
+            .unwrap();
+
+        let expected = ShutdownReason::ServerClosed {
+            code: Some(4000),
+            reason: Some("maintenance".into()),
+        };
+        assert_eq!(handle.await.unwrap().unwrap(), expected);
+        match errors.recv().await.unwrap() {
+            ToolkitRuntimeEvent::Stopped { reason } => assert_eq!(reason, expected),
+            event => panic!("unexpected event: {event:?}"),
+        }
+        assert!(expected.should_restart());
+    }
+
+    #[tokio::test]
+    async fn test_stops_when_the_connection_is_lost() {
+        let (handle, peer) = ToolkitService::new("test").__start_over_channels();
+        drop(peer);
+
+        assert_eq!(
+            handle.await.unwrap().unwrap(),
+            ShutdownReason::ConnectionLost
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drains_calls_in_flight() {
+        let (client, mut server) = ws_pair().await;
+        let mut service = ToolkitService::new("test");
+        service.add_action(Sleep);
+        let handle = start_with(service, client);
+
+        let frame = fixtures::action_frame("sleep", 1, &json!(200));
+        server
+            .send(Message::text(String::from_utf8(frame).unwrap()))
+            .await
+            .unwrap();
+        sleep(Duration::from_millis(50)).await;
+
+        let shutdown = spawn(handle.shutdown());
+
+        assert_eq!(next_result(&mut server).await.action_id, 1);
+        assert!(matches!(
+            server.next().await,
+            Some(Ok(Message::Close(None)))
+        ));
+        let reason = shutdown.await.unwrap().unwrap().unwrap();
+        assert_eq!(reason, ShutdownReason::GracefulRequested { drained: true });
+        assert!(!reason.should_restart());
+    }
+
+    #[tokio::test]
+    async fn test_add_action_with_mode() {
+        let mut service = ToolkitService::new("test");
+        service.add_action_with_mode(Echo, ExecutionMode::Inline);
+        assert!(service.actions().is_inline("echo"));
+
+        service.add_action(Echo);
+        assert!(!service.actions().is_inline("echo"));
+
+        service.add_action_with_mode(Echo, ExecutionMode::Inline);
+        let mut harness = ToolkitTestHarness::start(service).await.unwrap();
+        let result = harness.send_action_call("echo", json!({}), 1).await;
+        assert_eq!(result.action, "echo");
+
+        assert_eq!(
+            harness.shutdown().await.unwrap(),
+            ShutdownReason::ServerClosed {
+                code: None,
+                reason: None
+            }
+        );
+    }
+
+    /// Echo under another name.
+    struct Renamed(&'static str);
+
+    impl Action for Renamed {
+        const NAME: &'static str = "renamed";
+
+        type Error = Never;
+        type Args = Value;
+        type Output = Value;
+
+        fn name(&self) -> String {
+            self.0.to_string()
+        }
+
+        async fn definition(&self) -> ActionDefinition {
+            Action::definition(&Echo).await
+        }
+
+        async fn call(
+            &self,
+            ctx: ActionContext,
+            params: ActionParams<Self::Args>,
+        ) -> std::result::Result<ActionResult<Self::Output>, Self::Error> {
+            Action::call(&Echo, ctx, params).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_action_normalizes_names() {
+        let mut service = ToolkitService::new("test");
+        service.add_action(Renamed(" echo\n"));
+        assert_eq!(
+            service.try_add_action(Renamed("Toolkit/1/echo")),
+            Err(InvalidActionName::InvalidCharacter {
+                name: "Toolkit/1/echo".to_string(),
+                character: '/'
+            })
+        );
+        assert_eq!(
+            service.try_add_action(Renamed("")),
+            Err(InvalidActionName::Empty)
+        );
+
+        let definitions = service.definitions().await.unwrap();
+        assert_eq!(definitions.keys().collect::<Vec<_>>(), ["echo"]);
+
+        // Calls by the identifiers the server composes reach the action.
+        let mut harness = ToolkitTestHarness::start(service).await.unwrap();
+        let result = harness
+            .send_action_call("test_echo_1700000000/123/echo", json!({ "text": "hi" }), 1)
+            .await;
+        assert_eq!(result.payload.get(), r#"{"text":"hi"}"#);
+
+        assert!(harness.shutdown().await.is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid action name \"echo slam\"")]
+    fn test_add_action_panics_on_invalid_name() {
+        ToolkitService::new("test").add_action(Renamed("echo slam"));
+    }
+
+    #[test]
+    fn test_allow_unchecked_names() {
+        let mut service = ToolkitService::new("test").allow_unchecked_names();
+        service.add_action(Renamed(" echo slam"));
+
+        assert!(service.actions().contains(" echo slam"));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_action_is_answered() {
+        let mut service = ToolkitService::new("test");
+        service.add_action(Echo);
+        let mut harness = ToolkitTestHarness::start(service).await.unwrap();
+        let mut errors = harness.errors();
+
+        harness.send_raw(action_message("missing", 7, json!({})));
+
+        let (action_id, payload) = next_result_payload(&mut harness).await;
+        assert_eq!(action_id, 7);
+        assert_eq!(
+            payload,
+            json!({
+                "error": "ActionNotFound: no action named missing",
+                "code": "not_found",
+                "available_actions": ["echo"],
+            })
+        );
+
+        // The service keeps running.
+        harness.send_action_call("echo", json!({}), 1).await;
+
+        assert!(harness.shutdown().await.is_ok());
+        assert!(matches!(
+            errors.try_recv(),
+            Ok(ToolkitRuntimeEvent::Stopped { .. })
+        ));
+        assert!(errors.try_recv().is_err());
+    }
+
+    /// Panics on every call.
+    struct Panicking;
+
+    impl Action for Panicking {
+        const NAME: &'static str = "panicking";
+
+        type Error = Never;
+        type Args = serde_json::Value;
+        type Output = serde_json::Value;
+
+        async fn definition(&self) -> crate::toolkit::ActionDefinition {
+            Action::definition(&Echo).await
+        }
+
+        async fn call(
+            &self,
+            _ctx: ActionContext,
+            _params: ActionParams<Self::Args>,
+        ) -> std::result::Result<ActionResult<Self::Output>, Self::Error> {
+            panic!("boom")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_panicking_action_is_answered() {
+        for mode in [ExecutionMode::Spawned, ExecutionMode::Inline] {
+            let mut service = ToolkitService::new("test");
+            service.add_action_with_mode(Panicking, mode);
+            service.add_action(Echo);
+            let mut harness = ToolkitTestHarness::start(service).await.unwrap();
+
+            harness.send_raw(action_message("panicking", 7, json!({})));
+
+            let (action_id, payload) = next_result_payload(&mut harness).await;
+            assert_eq!(action_id, 7);
+            assert_eq!(
+                payload,
+                json!({ "error": "ActionPanicked: action panicking panicked: boom", "code": "action_error" })
+            );
+
+            // The service keeps running.
+            harness.send_action_call("echo", json!({}), 1).await;
+            assert!(harness.shutdown().await.is_ok());
+        }
+    }
+
+    struct PanickingAvailability;
+
+    impl Action for PanickingAvailability {
+        const NAME: &'static str = "panicking";
+
+        type Error = Never;
+        type Args = serde_json::Value;
+        type Output = serde_json::Value;
+
+        async fn definition(&self) -> crate::toolkit::ActionDefinition {
+            Action::definition(&Echo).await
+        }
+
+        async fn available(&self) -> Availability {
+            panic!("no status")
+        }
+
+        async fn call(
+            &self,
+            _ctx: ActionContext,
+            params: ActionParams<Self::Args>,
+        ) -> std::result::Result<ActionResult<Self::Output>, Self::Error> {
+            Ok(ActionResult {
+                payload: params.payload,
+                payment: None,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_panicking_availability_is_answered() {
+        for mode in [ExecutionMode::Spawned, ExecutionMode::Inline] {
+            let mut service = ToolkitService::new("test");
+            service.add_action_with_mode(PanickingAvailability, mode);
+            service.add_action(Echo);
+            let mut harness = ToolkitTestHarness::start(service).await.unwrap();
+
+            harness.send_raw(action_message("panicking", 7, json!({})));
+
+            assert_eq!(
+                next_result_payload(&mut harness).await,
+                (
+                    7,
+                    json!({ "error": "ActionPanicked: action panicking panicked: no status", "code": "action_error" })
+                )
+            );
+
+            // The service keeps running.
+            harness.send_action_call("echo", json!({}), 1).await;
+            assert!(harness.shutdown().await.is_ok());
+        }
+    }
+
+    #[derive(Default)]
+    struct Journal(Mutex<Vec<String>>);
+
+    impl ToolkitObserver for Arc<Journal> {
+        fn on_action_start(&self, call: &ObservedCall) {
+            self.push(format!("start {}", call.action));
+        }
+
+        fn on_action_end(
+            &self,
+            call: &ObservedCall,
+            outcome: std::result::Result<(), &ToolkitError>,
+            _duration: Duration,
+        ) {
+            let outcome = outcome.map_or_else(|e| e.to_string(), |_| "ok".to_string());
+            self.push(format!("end {} {outcome}", call.action));
+        }
+
+        fn on_connection_event(&self, event: &ToolkitRuntimeEvent) {
+            if let ToolkitRuntimeEvent::Stopped { .. } = event {
+                self.push("stopped".to_string());
+            }
+        }
+    }
+
+    impl Journal {
+        fn push(&self, entry: String) {
+            self.0.lock().unwrap().push(entry);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_observer_sees_calls_and_events() {
+        let journal = Arc::new(Journal::default());
+        let mut service = ToolkitService::new("test").with_observer(journal.clone());
+        service.add_action(Panicking);
+        service.add_action(Echo);
+        let mut harness = ToolkitTestHarness::start(service).await.unwrap();
+
+        harness.send_action_call("echo", json!({}), 1).await;
+        harness.send_raw(action_message("panicking", 2, json!({})));
+        next_result_payload(&mut harness).await;
+        // A missing action is not a call.
+        harness.send_raw(action_message("missing", 3, json!({})));
+        next_result_payload(&mut harness).await;
+        assert!(harness.shutdown().await.is_ok());
+
+        assert_eq!(
+            *journal.0.lock().unwrap(),
+            [
+                "start echo",
+                "end echo ok",
+                "start panicking",
+                "end panicking ActionPanicked: action panicking panicked: boom",
+                "stopped",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lifecycle_events() {
+        let mut service = ToolkitService::new("test");
+        service.add_action(Echo);
+        let mut events = service.events();
+        let (transport, mut peer) = ChannelTransport::new();
+        let handle = service.start_over(transport).await.unwrap();
+        peer.recv().await.unwrap();
+
+        peer.send(b"not json".to_vec());
+        peer.send(br#"{"type":"agentJoined","data":{"agentID":2}}"#.to_vec());
+        peer.send(fixtures::action_frame("echo", 1, &json!({})));
+        peer.recv().await.unwrap();
+        peer.close();
+        handle.await.unwrap().unwrap();
+
+        let mut received = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            received.push(event);
+        }
+        assert!(
+            matches!(
+                &received[..],
+                [
+                    ToolkitEvent::Connected,
+                    ToolkitEvent::ActionsRegistered { count: 1 },
+                    ToolkitEvent::ProtocolError { .. },
+                    ToolkitEvent::UnknownMessage { message_type, data },
+                    ToolkitEvent::ActionStarted { action, action_id: 1 },
+                    ToolkitEvent::ActionFinished { ok: true, .. },
+                    ToolkitEvent::Disconnected {
+                        reason: Some(ShutdownReason::ServerClosed { code: None, .. })
+                    },
+                ] if &**action == "echo"
+                    && &**message_type == "agentJoined"
+                    && **data == json!({ "agentID": 2 })
+            ),
+            "{received:?}"
+        );
+    }
+
+    /// Counts its calls, returning the count.
+    struct Counter(Arc<AtomicUsize>);
+
+    impl Action for Counter {
+        const NAME: &'static str = "counter";
+
+        type Error = Never;
+        type Args = serde_json::Value;
+        type Output = usize;
+
+        async fn definition(&self) -> crate::toolkit::ActionDefinition {
+            Action::definition(&Echo).await
+        }
+
+        async fn call(
+            &self,
+            _ctx: ActionContext,
+            _params: ActionParams<Self::Args>,
+        ) -> std::result::Result<ActionResult<Self::Output>, Self::Error> {
+            Ok(ActionResult {
+                payload: self.0.fetch_add(1, Ordering::SeqCst) + 1,
+                payment: None,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_calls_are_answered_once() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut service = ToolkitService::new("test").with_idempotency(10, Duration::from_secs(60));
+        service.add_action(Counter(calls.clone()));
+        let mut harness = ToolkitTestHarness::start(service).await.unwrap();
+        let mut events = harness.errors();
+
+        for (action_id, expected) in [(1, 1), (1, 1), (2, 2)] {
+            harness.send_raw(action_message("counter", action_id, json!({})));
+            let (answered, payload) = next_result_payload(&mut harness).await;
+            assert_eq!(answered, action_id);
+            assert_eq!(payload, expected);
+        }
+        assert!(harness.shutdown().await.is_ok());
+
+        let duplicate = events.recv().await.unwrap();
+        assert!(
+            matches!(
+                duplicate,
+                ToolkitRuntimeEvent::DuplicateCall {
+                    ref action,
+                    agent_id: 1,
+                    action_id: 1,
+                } if &**action == "counter"
+            ),
+            "{duplicate:?}"
+        );
+
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_of_a_call_in_flight_leaves_it_cancellable() {
+        let definition = crate::toolkit::ActionDefinition {
+            description: "Wait to be cancelled".to_string(),
+            payload: json!({}),
+            payment: None,
+            ..Default::default()
+        };
+        // The calls which saw their cancellation.
+        let stopped = Arc::new(AtomicUsize::new(0));
+        let mut service = ToolkitService::new("test").with_idempotency(10, Duration::from_secs(60));
+        service.add_action(crate::toolkit::FunctionAction::new("wait", definition, {
+            let stopped = stopped.clone();
+            move |ctx, _: Value| {
+                let stopped = stopped.clone();
+                async move {
+                    ctx.cancelled().await;
+                    stopped.fetch_add(1, Ordering::SeqCst);
+                    Ok("stopped cleanly")
+                }
+            }
+        }));
+        service.add_action(Sleep);
+        let mut harness = ToolkitTestHarness::start(service).await.unwrap();
+        let mut events = harness.errors();
+
+        harness.send_raw(action_message("wait", 1, json!({})));
+        harness.send_raw(action_message("wait", 1, json!({})));
+        harness.send_raw(cancel_message(1));
+        assert_eq!(
+            next_result_payload(&mut harness).await,
+            (
+                1,
+                json!({ "error": "ActionCancelled: call of action wait was cancelled", "code": "cancelled" })
+            )
+        );
+
+        // No other answer of the call comes before the next result.
+        harness.send_raw(action_message("sleep", 2, json!(1)));
+        assert_eq!(next_result_payload(&mut harness).await, (2, json!(1)));
+        assert!(harness.shutdown().await.is_ok());
+
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            ToolkitRuntimeEvent::DuplicateCall { action_id: 1, .. }
+        ));
+        assert_eq!(stopped.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_call_metadata_is_read_and_echoed() {
+        let mut service = ToolkitService::new("test");
+        service.add_action(Metadata);
+        let mut harness = ToolkitTestHarness::start(service).await.unwrap();
+
+        for action in ["metadata", "missing"] {
+            let call = ActionCallParams::new(action)
+                .with_extra("locale", "fr-FR")
+                .with_extra("conversationID", 42);
+            harness.send_raw(ToolkitMessage::Action { data: call });
+            let text = harness.next_frame().await.unwrap();
+            let ToolkitMessage::ActionResult { data } = serde_json::from_str(&text).unwrap() else {
+                panic!("unexpected message: {text}");
+            };
+
+            let extra = json!({ "locale": "fr-FR", "conversationID": 42 });
+            assert_eq!(Value::Object(data.extra), extra);
+            if action == "metadata" {
+                assert_eq!(raw_to_value(&data.payload), extra);
+            }
+        }
+        assert!(harness.shutdown().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_action_lists_few_actions() {
+        let names: Vec<String> = (0..21).map(|i| format!("action_{i:02}")).collect();
+        let delays: Vec<_> = names
+            .iter()
+            .map(|name| (name.as_str(), Duration::ZERO))
+            .collect();
+
+        let (service, _) = slow_service(&delays[..20]);
+        let mut harness = ToolkitTestHarness::start(service).await.unwrap();
+        harness.send_raw(action_message("missing", 1, json!({})));
+        let (_, payload) = next_result_payload(&mut harness).await;
+        assert_eq!(payload["available_actions"], json!(names[..20]));
+
+        let (service, _) = slow_service(&delays);
+        let mut harness = ToolkitTestHarness::start(service).await.unwrap();
+        harness.send_raw(action_message("missing", 1, json!({})));
+        let (_, payload) = next_result_payload(&mut harness).await;
+        assert_eq!(
+            payload,
+            json!({ "error": "ActionNotFound: no action named missing", "code": "not_found" })
+        );
+    }
+
+    /// Serve HTTPS on keep-alive connections with a new self-signed certificate, answering every
+    /// request after a short delay, and count the TLS handshakes.
+    async fn tls_server() -> (String, Arc<AtomicUsize>) {
+        use tokio_rustls::{rustls, TlsAcceptor};
+
+        let rcgen::CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = rustls::ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![cert.der().clone()],
+                key_pair.serialize_der().try_into().unwrap(),
+            )
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handshakes = Arc::new(AtomicUsize::new(0));
+
+        let count = handshakes.clone();
+        spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (acceptor, count) = (acceptor.clone(), count.clone());
+                spawn(async move {
+                    let Ok(stream) = acceptor.accept(stream).await else {
+                        return;
+                    };
+                    count.fetch_add(1, Ordering::SeqCst);
+
+                    let service = hyper::service::service_fn(|_| async {
+                        sleep(Duration::from_millis(50)).await;
+                        let body = http_body_util::Full::new(bytes::Bytes::from(
+                            r#"{"id":"tx-1","status":"pending","type":"test","payload":{}}"#,
+                        ));
+                        Ok::<_, std::convert::Infallible>(hyper::Response::new(body))
+                    });
+                    let _ = hyper::server::conn::http1::Builder::new()
+                        .serve_connection(hyper_util::rt::TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+
+        (format!("https://localhost:{port}"), handshakes)
+    }
+
+    struct Transact;
+
+    impl Action for Transact {
+        const NAME: &'static str = "transact";
+
+        type Error = ToolkitError;
+        type Args = Value;
+        type Output = crate::toolkit::Transaction;
+
+        async fn definition(&self) -> crate::toolkit::ActionDefinition {
+            crate::toolkit::ActionDefinition {
+                description: "Create a transaction".to_string(),
+                payload: json!({}),
+                payment: None,
+                ..Default::default()
+            }
+        }
+
+        async fn call(
+            &self,
+            ctx: ActionContext,
+            _params: ActionParams<Self::Args>,
+        ) -> std::result::Result<ActionResult<Self::Output>, Self::Error> {
+            Ok(ActionResult {
+                payload: ctx.create_transaction("test", json!({})).await?,
+                payment: None,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_actions_share_connections() {
+        let (endpoint, handshakes) = tls_server().await;
+
+        let mut service = ToolkitService::new("test");
+        Arc::make_mut(&mut service.config).frontend_api_endpoint = endpoint.clone();
+        Arc::make_mut(&mut service.config).transaction_api_endpoint = endpoint;
+        let client = crate::http::client_builder(&service.config.client)
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap();
+        let api_key = ApiKeyProvider::fixed("test", "toolkit_api_key").unwrap();
+        service.api_client = ApiClient::with_client(client, api_key);
+        service.add_action(Transact);
+
+        let info = ToolkitInfo {
+            name: "Test".to_string(),
+            description: "Transactions".to_string(),
+        };
+        service.update_info(info).await.unwrap();
+        assert_eq!(handshakes.load(Ordering::SeqCst), 1);
+
+        // Bursts of concurrent actions, each creating a transaction.
+        let (handle, mut peer) = service.__start_over_channels();
+        let mut after_burst = Vec::new();
+        for burst in 0..3 {
+            for action_id in 0..8 {
+                peer.send(fixtures::action_frame(
+                    "transact",
+                    burst * 8 + action_id,
+                    &json!({}),
+                ));
+            }
+            for _ in 0..8 {
+                let text = peer.recv().await.unwrap();
+                assert!(text.contains(r#""id":"tx-1""#), "{text}");
+            }
+            after_burst.push(handshakes.load(Ordering::SeqCst));
+        }
+
+        // The first burst needs a connection per concurrent call, the update_info one among them,
+        // then every call reuses them.
+        assert!(after_burst[0] <= 9, "{after_burst:?}");
+        assert!(
+            after_burst.iter().all(|&n| n == after_burst[0]),
+            "{after_burst:?}"
+        );
+
+        peer.close();
+        assert!(handle.await.unwrap().is_ok());
     }
 }
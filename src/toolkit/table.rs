@@ -0,0 +1,120 @@
+//! The actions a service serves, shared with its [ToolkitHandle](super::ToolkitHandle) so they can
+//! be added and removed while it runs.
+//!
+//! The actions are reference counted: a call holds its action until it returns, so removing an
+//! action doesn't cut short the calls in flight.
+
+use super::{
+    action::{ActionDyn, Availability},
+    names::routed_name,
+    ExecutionMode,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+#[derive(Default)]
+pub(super) struct ActionTable {
+    actions: HashMap<Arc<str>, Arc<dyn ActionDyn>>,
+    /// The actions added with [ExecutionMode::Inline].
+    inline: HashSet<Arc<str>>,
+    /// The actions made unavailable by the handle, whatever they tell.
+    unavailable: HashMap<Arc<str>, Availability>,
+}
+
+/// An action found by the name it was called by.
+pub(super) struct FoundAction {
+    pub(super) name: Arc<str>,
+    pub(super) action: Arc<dyn ActionDyn>,
+    pub(super) inline: bool,
+    /// Set if the action was made unavailable by the handle.
+    pub(super) unavailable: Option<Availability>,
+}
+
+impl ActionTable {
+    /// Add an action, replacing any action of the same name.
+    pub(super) fn insert(
+        &mut self,
+        name: Arc<str>,
+        action: Arc<dyn ActionDyn>,
+        mode: ExecutionMode,
+    ) {
+        match mode {
+            ExecutionMode::Spawned => self.inline.remove(&name),
+            ExecutionMode::Inline => self.inline.insert(name.clone()),
+        };
+        self.unavailable.remove(&name);
+        self.actions.insert(name, action);
+    }
+
+    /// Remove an action, returning whether there was one.
+    pub(super) fn remove(&mut self, name: &str) -> bool {
+        self.inline.remove(name);
+        self.unavailable.remove(name);
+        self.actions.remove(name).is_some()
+    }
+
+    /// Make an action unavailable whatever it tells, or leave it to tell again with
+    /// [Availability::Available], returning whether there is such an action.
+    pub(super) fn set_availability(&mut self, name: &str, availability: Availability) -> bool {
+        let Some((name, _)) = self.actions.get_key_value(name) else {
+            return false;
+        };
+        match availability {
+            Availability::Available => self.unavailable.remove(name),
+            unavailable => self.unavailable.insert(name.clone(), unavailable),
+        };
+
+        true
+    }
+
+    pub(super) fn contains(&self, name: &str) -> bool {
+        self.actions.contains_key(name)
+    }
+
+    /// The action named `name`.
+    pub(super) fn get(&self, name: &str) -> Option<Arc<dyn ActionDyn>> {
+        self.actions.get(name).cloned()
+    }
+
+    /// The action called by `name`, either its name or an identifier composed by the server, as
+    /// in `Toolkit/123/echo`.
+    pub(super) fn find(&self, name: &str) -> Option<FoundAction> {
+        let (name, action) = self
+            .actions
+            .get_key_value(name)
+            .or_else(|| self.actions.get_key_value(routed_name(name)))?;
+
+        Some(FoundAction {
+            name: name.clone(),
+            action: action.clone(),
+            inline: self.inline.contains(name),
+            unavailable: self.unavailable.get(name).copied(),
+        })
+    }
+
+    #[cfg(test)]
+    pub(super) fn is_inline(&self, name: &str) -> bool {
+        self.inline.contains(name)
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.actions.len()
+    }
+
+    /// The names of the actions, sorted.
+    pub(super) fn names(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.actions.keys().map(|name| name.to_string()).collect();
+        names.sort();
+        names
+    }
+
+    /// The actions with their names, to evaluate their definitions without holding the table.
+    pub(super) fn entries(&self) -> Vec<(Arc<str>, Arc<dyn ActionDyn>)> {
+        self.actions
+            .iter()
+            .map(|(name, action)| (name.clone(), action.clone()))
+            .collect()
+    }
+}
@@ -0,0 +1,901 @@
+//! Run a Toolkit service end to end in-process, without any network, or against a scripted
+//! WebSocket server, see [ScriptedServer]. To work at the level of the frames instead, start the
+//! service over a [ChannelTransport] with
+//! [ToolkitService::start_over](super::ToolkitService::start_over). [ToolkitTestHarness::start_with_faults] injects faults
+//! between the service and the harness, to test how it copes with an unreliable connection.
+//!
+//! ```
+//! use serde_json::json;
+//! use unifai_sdk::toolkit::{testing::ToolkitTestHarness, *};
+//!
+//! struct Echo;
+//!
+//! impl Action for Echo {
+//!     const NAME: &'static str = "echo";
+//!
+//!     type Error = std::convert::Infallible;
+//!     type Args = serde_json::Value;
+//!     type Output = serde_json::Value;
+//!
+//!     async fn definition(&self) -> ActionDefinition {
+//!         ActionDefinition {
+//!             description: "Echo the payload".to_string(),
+//!             payload: json!({}),
+//!             payment: None,
+//!             ..Default::default()
+//!         }
+//!     }
+//!
+//!     async fn call(
+//!         &self,
+//!         _ctx: ActionContext,
+//!         params: ActionParams<Self::Args>,
+//!     ) -> Result<ActionResult<Self::Output>, Self::Error> {
+//!         Ok(ActionResult {
+//!             payload: params.payload,
+//!             payment: None,
+//!         })
+//!     }
+//! }
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let mut service = ToolkitService::new("test");
+//! service.add_action(Echo);
+//!
+//! let mut harness = ToolkitTestHarness::start(service).await.unwrap();
+//! assert!(harness.registered_actions().contains_key("echo"));
+//!
+//! let result = harness.send_action_call("echo", json!({ "content": "hello" }), 1).await;
+//! assert_eq!(result.payload.get(), r#"{"content":"hello"}"#);
+//!
+//! harness.shutdown().await.unwrap();
+//! # }
+//! ```
+
+pub use super::chaos::{ChaosTransport, Direction, Fault, FaultCounters, FaultCounts, FaultPlan};
+pub use super::transport::{ChannelPeer, ChannelTransport};
+use super::{
+    errors::Result,
+    messages::{ActionCallParams, ActionCallResult, ActionsRegisterParams, ToolkitMessage},
+    transport::ToolkitTransport,
+    ActionDefinition, RecordedCall, ShutdownReason, ToolkitHandle, ToolkitRuntimeEvent,
+    ToolkitService,
+};
+use crate::logging::Redactor;
+use futures_util::{SinkExt, StreamExt};
+use serde::Serialize;
+use serde_json::{
+    value::{to_raw_value, RawValue},
+    Value,
+};
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    net::TcpListener,
+    spawn,
+    sync::broadcast::{self, error::RecvError},
+    task::JoinHandle,
+    time::sleep,
+};
+use tokio_tungstenite::{accept_async, tungstenite::Message};
+
+/// A running Toolkit service, connected to in-memory channels standing in for the server.
+///
+/// Dropping the harness aborts the service, use [ToolkitTestHarness::shutdown] to stop it cleanly.
+pub struct ToolkitTestHarness {
+    handle: Option<ToolkitHandle>,
+    peer: ChannelPeer,
+    events: broadcast::Receiver<ToolkitRuntimeEvent>,
+    redactor: Redactor,
+    registration: ActionsRegisterParams,
+    frames: Vec<String>,
+    next_action_id: u64,
+}
+
+impl ToolkitTestHarness {
+    /// Start the service, registering its actions like [ToolkitService::start] does.
+    pub async fn start(service: ToolkitService) -> Result<Self> {
+        let (transport, peer) = ChannelTransport::new();
+
+        Self::start_over(service, transport, peer).await
+    }
+
+    /// Start the service like [ToolkitTestHarness::start], injecting the faults of `plan` between
+    /// the service and the harness, and return the counters of the injected faults.
+    ///
+    /// Calls and results may be lost, so send calls with [ToolkitTestHarness::send_raw] and wait
+    /// for their results with a timeout rather than with [ToolkitTestHarness::send_action_call].
+    pub async fn start_with_faults(
+        service: ToolkitService,
+        plan: FaultPlan,
+    ) -> Result<(Self, FaultCounters)> {
+        let (transport, peer) = ChannelTransport::new();
+        let transport = ChaosTransport::new(transport, plan);
+        let counters = transport.counters();
+
+        Ok((Self::start_over(service, transport, peer).await?, counters))
+    }
+
+    async fn start_over(
+        service: ToolkitService,
+        transport: impl ToolkitTransport,
+        mut peer: ChannelPeer,
+    ) -> Result<Self> {
+        let redactor = service.redactor.clone();
+        let handle = service.start_over(transport).await?;
+        let events = handle.errors();
+
+        let frame = peer.recv().await.expect("the service registers first");
+        let registration = match ToolkitMessage::from_frame(frame.as_bytes())? {
+            ToolkitMessage::RegisterActions { data } => data,
+            _ => panic!("unexpected first message: {frame}"),
+        };
+
+        Ok(Self {
+            handle: Some(handle),
+            peer,
+            events,
+            redactor,
+            registration,
+            frames: vec![frame],
+            next_action_id: 1,
+        })
+    }
+
+    /// The handle of the running service, to add and remove actions as it runs.
+    pub fn handle(&self) -> &ToolkitHandle {
+        self.handle
+            .as_ref()
+            .expect("the service runs until shut down")
+    }
+
+    /// The actions registered by the service, by name.
+    pub fn registered_actions(&self) -> &HashMap<String, ActionDefinition> {
+        &self.registration.actions
+    }
+
+    /// Call an action as the server would, and wait for its result.
+    ///
+    /// # Panics
+    ///
+    /// If the service stopped.
+    pub async fn send_action_call(
+        &mut self,
+        action: &str,
+        payload: impl Serialize,
+        agent_id: u64,
+    ) -> ActionCallResult {
+        let payload = to_raw_value(&payload).expect("the payload serializes");
+
+        self.call(action, payload, agent_id, None).await
+    }
+
+    async fn call(
+        &mut self,
+        action: &str,
+        payload: Box<RawValue>,
+        agent_id: u64,
+        payment: Option<u64>,
+    ) -> ActionCallResult {
+        let action_id = self.next_action_id;
+        self.next_action_id += 1;
+
+        self.send_raw(ToolkitMessage::Action {
+            data: ActionCallParams {
+                action: action.to_string(),
+                action_id,
+                agent_id,
+                payload,
+                payment,
+                trace_context: None,
+                extra: Default::default(),
+            },
+        });
+
+        loop {
+            tokio::select! {
+                frame = self.peer.recv() => {
+                    let frame = frame.expect("the service stopped");
+                    let message = ToolkitMessage::from_frame(frame.as_bytes());
+                    self.frames.push(frame);
+
+                    if let Ok(ToolkitMessage::ActionResult { data }) = message {
+                        if data.action_id == action_id {
+                            return data;
+                        }
+                    }
+                }
+
+                event = self.events.recv() => match event {
+                    Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => panic!("the service stopped"),
+                },
+            }
+        }
+    }
+
+    /// Send a message as the server would.
+    pub fn send_raw(&self, message: ToolkitMessage) {
+        let frame = serde_json::to_vec(&message).expect("a message always serializes");
+        self.peer.send(frame);
+    }
+
+    /// Wait for the next frame sent by the service, `None` once it stopped.
+    pub async fn next_frame(&mut self) -> Option<String> {
+        let frame = self.peer.recv().await?;
+        self.frames.push(frame.clone());
+
+        Some(frame)
+    }
+
+    /// The frames sent by the service and received so far, starting with the registration.
+    pub fn received_frames(&self) -> &[String] {
+        &self.frames
+    }
+
+    /// Subscribe to the warnings and errors of the service, see [ToolkitHandle::errors].
+    pub fn errors(&self) -> broadcast::Receiver<ToolkitRuntimeEvent> {
+        self.events.resubscribe()
+    }
+
+    /// Close the connection as the server would, and wait for the service to stop, with
+    /// [ShutdownReason::ServerClosed].
+    pub async fn shutdown(mut self) -> Result<ShutdownReason> {
+        self.peer.close();
+        let handle = self
+            .handle
+            .take()
+            .expect("the service runs until shut down");
+
+        handle.await.expect("the service doesn't panic")
+    }
+}
+
+impl Drop for ToolkitTestHarness {
+    fn drop(&mut self) {
+        if let Some(handle) = &self.handle {
+            handle.abort();
+        }
+    }
+}
+
+/// A replayed call whose result differs from the recorded one, see [replay_fixture].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReplayMismatch {
+    /// The line of the call in the fixture, from 1.
+    pub line: usize,
+    pub action: String,
+    pub recorded: Value,
+    pub replayed: Value,
+}
+
+/// Replay the calls recorded at `path` by [record_to_file](super::record_to_file) through the
+/// harness, and return those whose result payload changed.
+///
+/// The replayed results go through the redactor of the service, like the recorded ones. The values
+/// at the JSON pointers of `ignore_paths`, such as `/timestamp`, aren't compared.
+///
+/// # Panics
+///
+/// If the service dropped a call, like [ToolkitTestHarness::send_action_call].
+pub async fn replay_fixture(
+    path: impl AsRef<Path>,
+    harness: &mut ToolkitTestHarness,
+    ignore_paths: &[&str],
+) -> Result<Vec<ReplayMismatch>> {
+    let fixture = std::fs::read_to_string(path)?;
+    let mut mismatches = Vec::new();
+
+    for (i, line) in fixture.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let call: RecordedCall = serde_json::from_str(line)?;
+        let payload = to_raw_value(&call.payload)?;
+        let result = harness
+            .call(&call.action, payload, call.agent_id, call.payment)
+            .await;
+        let replayed: Value = serde_json::from_str(result.payload.get())?;
+
+        let mut recorded = call.result;
+        let mut replayed = (harness.redactor)(&replayed);
+        for pointer in ignore_paths {
+            for value in [&mut recorded, &mut replayed] {
+                if let Some(ignored) = value.pointer_mut(pointer) {
+                    *ignored = Value::Null;
+                }
+            }
+        }
+
+        if recorded != replayed {
+            mismatches.push(ReplayMismatch {
+                line: i + 1,
+                action: call.action,
+                recorded,
+                replayed,
+            });
+        }
+    }
+
+    Ok(mismatches)
+}
+
+/// Check the examples of every action of the service, running those of the actions flagged as
+/// side effect free, see [verify_and_run_examples](super::verify_and_run_examples).
+///
+/// # Panics
+///
+/// If any example fails, listing every failure.
+pub async fn assert_examples(service: &ToolkitService) {
+    if let Err(errors) = super::verify_and_run_examples(service).await {
+        panic!("{errors}");
+    }
+}
+
+/// Set to a value other than `0` to write the snapshots of [assert_definitions_snapshot] instead
+/// of comparing them.
+pub const UPDATE_SNAPSHOTS: &str = "UPDATE_SNAPSHOTS";
+
+/// Compare the registration message of the service with the snapshot at `path`.
+///
+/// The snapshot is the message as [ToolkitService::start] would send it, pretty-printed with
+/// sorted keys. The SDK version of the client info is left out, so upgrading the SDK doesn't change
+/// it. The snapshot is written when the file doesn't exist, or when [UPDATE_SNAPSHOTS] is set.
+///
+/// # Panics
+///
+/// If the registration differs from the snapshot, showing their differences, or if the definitions
+/// or the snapshot can't be read.
+pub async fn assert_definitions_snapshot(service: &ToolkitService, path: impl AsRef<Path>) {
+    let path = path.as_ref();
+    let definitions = service
+        .definitions()
+        .await
+        .expect("the definitions evaluate");
+    let mut message = serde_json::to_value(ToolkitMessage::RegisterActions {
+        data: service.registration(definitions),
+    })
+    .expect("the registration serializes");
+    if let Some(version) = message.pointer_mut("/data/clientInfo/version") {
+        *version = Value::from("*");
+    }
+    let snapshot = serde_json::to_string_pretty(&sorted(message)).unwrap() + "\n";
+
+    let update = std::env::var(UPDATE_SNAPSHOTS).is_ok_and(|value| value != "0");
+    if update || !path.exists() {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).expect("the snapshot directory is created");
+        }
+        std::fs::write(path, snapshot).expect("the snapshot is written");
+        return;
+    }
+
+    let expected = std::fs::read_to_string(path).expect("the snapshot is read");
+    if expected != snapshot {
+        panic!(
+            "the definitions differ from the snapshot at {}, run with {UPDATE_SNAPSHOTS}=1 to \
+             update it:\n{}",
+            path.display(),
+            line_diff(&expected, &snapshot)
+        );
+    }
+}
+
+/// The value with the keys of its objects sorted, whether or not `serde_json` preserves their order.
+fn sorted(value: Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Value::Object(entries.into_iter().map(|(k, v)| (k, sorted(v))).collect())
+        }
+        Value::Array(values) => Value::Array(values.into_iter().map(sorted).collect()),
+        value => value,
+    }
+}
+
+/// The lines removed from `old` prefixed with `-`, and those added in `new` with `+`, around the
+/// lines they share.
+fn line_diff(old: &str, new: &str) -> String {
+    const CONTEXT: usize = 2;
+
+    let (old, new): (Vec<_>, Vec<_>) = (old.lines().collect(), new.lines().collect());
+    // The length of the longest common subsequence of the lines from `i` and `j` on.
+    let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push((' ', old[i]));
+            (i, j) = (i + 1, j + 1);
+        } else if i < old.len() && (j == new.len() || common[i + 1][j] >= common[i][j + 1]) {
+            lines.push(('-', old[i]));
+            i += 1;
+        } else {
+            lines.push(('+', new[j]));
+            j += 1;
+        }
+    }
+
+    // Only keep the shared lines close to a change.
+    let changed: Vec<_> = (0..lines.len()).filter(|&k| lines[k].0 != ' ').collect();
+    let mut diff = String::new();
+    let mut skipped = false;
+    for (k, (sign, line)) in lines.iter().enumerate() {
+        let near = changed.iter().any(|&c| c.abs_diff(k) <= CONTEXT);
+        if near {
+            diff.push_str(&format!("{sign} {line}\n"));
+            skipped = false;
+        } else if !skipped {
+            diff.push_str("  ...\n");
+            skipped = true;
+        }
+    }
+    diff
+}
+
+/// A step of a [ScriptedServer].
+pub enum Step {
+    /// Accept the next connection and its WebSocket upgrade, dropping the current one if any.
+    Accept,
+    /// Send a text frame.
+    Send(String),
+    /// Wait for a text frame the predicate holds for, skipping the others. Pings are answered
+    /// while waiting.
+    Expect(Box<dyn Fn(&str) -> bool + Send>),
+    /// Send a close frame, and wait for the client to close the connection.
+    Close,
+    /// Drop the connection without closing it, as a network failure would.
+    Drop,
+    /// Stop reading the connection for that long, leaving its pings unanswered.
+    Stall(Duration),
+}
+
+impl Step {
+    /// Wait for a message of type `kind`, such as `registerActions`.
+    pub fn expect_message(kind: &'static str) -> Self {
+        Self::Expect(Box::new(move |text| {
+            serde_json::from_str::<Value>(text).is_ok_and(|message| message["type"] == kind)
+        }))
+    }
+}
+
+/// Scripts of the usual server behaviors.
+pub mod scripts {
+    use super::Step;
+    use std::time::Duration;
+
+    /// Accept the service and its registration, then close the connection.
+    pub fn close_after_registration() -> Vec<Step> {
+        vec![
+            Step::Accept,
+            Step::expect_message("registerActions"),
+            Step::Close,
+        ]
+    }
+
+    /// Accept the service and its registration, then never answer its pings again.
+    pub fn stop_ponging() -> Vec<Step> {
+        vec![
+            Step::Accept,
+            Step::expect_message("registerActions"),
+            Step::Stall(Duration::MAX),
+        ]
+    }
+}
+
+/// A WebSocket server on a local port, playing a script of [Step]s with the services connecting
+/// to it, to test what happens below the messages.
+///
+/// ```
+/// # use unifai_sdk::{config::UnifaiConfig, toolkit::{testing::*, ToolkitService}};
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let server = ScriptedServer::start(scripts::close_after_registration()).await;
+/// let service = ToolkitService::from_config(UnifaiConfig {
+///     toolkit_api_key: Some("test".to_string()),
+///     backend_ws_endpoint: server.url(),
+///     ..UnifaiConfig::default()
+/// })
+/// .unwrap();
+///
+/// let handle = service.start().await.unwrap();
+/// handle.await.unwrap().unwrap();
+///
+/// assert_eq!(server.finish().await.len(), 1);
+/// # }
+/// ```
+pub struct ScriptedServer {
+    url: String,
+    received: Arc<Mutex<Vec<String>>>,
+    player: JoinHandle<()>,
+}
+
+impl ScriptedServer {
+    /// Listen on a local port and play `script` in the background.
+    pub async fn start(script: Vec<Step>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/ws", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(Vec::new()));
+
+        let player = spawn(play(listener, script, received.clone()));
+
+        Self {
+            url,
+            received,
+            player,
+        }
+    }
+
+    /// The URL to connect to, to use as [UnifaiConfig::backend_ws_endpoint].
+    ///
+    /// [UnifaiConfig::backend_ws_endpoint]: crate::config::UnifaiConfig::backend_ws_endpoint
+    pub fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    /// The text frames received so far, from every connection.
+    pub fn received_frames(&self) -> Vec<String> {
+        self.received.lock().unwrap().clone()
+    }
+
+    /// Wait for the end of the script, returning the text frames received.
+    ///
+    /// # Panics
+    ///
+    /// If a step failed, such as a connection closed while expecting a frame.
+    pub async fn finish(self) -> Vec<String> {
+        if let Err(e) = self.player.await {
+            std::panic::resume_unwind(e.into_panic());
+        }
+
+        self.received.lock().unwrap().clone()
+    }
+}
+
+async fn play(listener: TcpListener, script: Vec<Step>, received: Arc<Mutex<Vec<String>>>) {
+    let mut connection = None;
+
+    for step in script {
+        match step {
+            Step::Accept => {
+                let (stream, _) = listener.accept().await.unwrap();
+                connection = Some(accept_async(stream).await.unwrap());
+            }
+            Step::Send(text) => {
+                let stream = connection.as_mut().expect("no connection to send to");
+                stream.send(Message::text(text)).await.unwrap();
+            }
+            Step::Expect(predicate) => {
+                let stream = connection.as_mut().expect("no connection to receive from");
+                loop {
+                    match stream.next().await {
+                        Some(Ok(Message::Text(text))) => {
+                            received.lock().unwrap().push(text.to_string());
+                            if predicate(&text) {
+                                break;
+                            }
+                        }
+                        Some(Ok(_)) => {}
+                        Some(Err(e)) => panic!("expected a frame, the connection failed: {e}"),
+                        None => panic!("expected a frame, the connection was closed"),
+                    }
+                }
+            }
+            Step::Close => {
+                let mut stream = connection.take().expect("no connection to close");
+                stream.close(None).await.unwrap();
+                while let Some(Ok(_)) = stream.next().await {}
+            }
+            Step::Drop => {
+                connection = None;
+            }
+            Step::Stall(duration) => sleep(duration).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::UnifaiConfig,
+        logging::{capture::Captured, events},
+        toolkit::{record_to_file, Action, ActionContext, ActionParams, ActionResult},
+    };
+    use futures_util::FutureExt;
+    use serde_json::{json, Value};
+    use std::{convert::Infallible, panic::AssertUnwindSafe};
+
+    struct Echo;
+
+    impl Action for Echo {
+        const NAME: &'static str = "echo";
+
+        type Error = Infallible;
+        type Args = Value;
+        type Output = Value;
+
+        async fn definition(&self) -> ActionDefinition {
+            ActionDefinition {
+                description: "Echo the payload".to_string(),
+                payload: json!({ "content": "anything" }),
+                payment: None,
+                ..Default::default()
+            }
+        }
+
+        async fn call(
+            &self,
+            ctx: ActionContext,
+            params: ActionParams<Self::Args>,
+        ) -> std::result::Result<ActionResult<Self::Output>, Self::Error> {
+            Ok(ActionResult {
+                payload: json!({ "agent": ctx.agent_id, "echo": params.payload }),
+                payment: None,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_harness_round_trip() {
+        let mut service = ToolkitService::new("test");
+        service.add_action(Echo);
+        let mut harness = ToolkitTestHarness::start(service).await.unwrap();
+
+        assert_eq!(
+            harness.registered_actions()["echo"].payload,
+            json!({ "content": "anything" })
+        );
+
+        let first = harness
+            .send_action_call("echo", json!({ "content": "hello" }), 7)
+            .await;
+        let second = harness
+            .send_action_call("echo", json!({ "content": "again" }), 7)
+            .await;
+
+        assert_eq!((first.action_id, second.action_id), (1, 2));
+        assert_eq!(
+            serde_json::from_str::<Value>(first.payload.get()).unwrap(),
+            json!({ "agent": 7, "echo": { "content": "hello" } })
+        );
+
+        let frames = harness.received_frames();
+        assert_eq!(frames.len(), 3);
+        assert!(frames[0].contains("registerActions"));
+        assert!(frames[2].contains("again"));
+
+        harness.shutdown().await.unwrap();
+    }
+
+    fn service_for(server: &ScriptedServer) -> ToolkitService {
+        let mut service = ToolkitService::from_config(UnifaiConfig {
+            toolkit_api_key: Some("test".to_string()),
+            backend_ws_endpoint: server.url(),
+            ..UnifaiConfig::default()
+        })
+        .unwrap();
+        service.add_action(Echo);
+
+        service
+    }
+
+    #[tokio::test]
+    async fn test_scripted_close_after_registration() {
+        let server = ScriptedServer::start(scripts::close_after_registration()).await;
+
+        let handle = service_for(&server).start().await.unwrap();
+
+        assert!(handle.await.unwrap().is_ok());
+        let frames = server.finish().await;
+        assert_eq!(frames.len(), 1);
+        assert!(frames[0].contains(r#""echo""#));
+    }
+
+    #[tokio::test]
+    async fn test_connects_to_endpoints_in_order() {
+        let primary = ScriptedServer::start(scripts::close_after_registration()).await;
+        let secondary = ScriptedServer::start(scripts::close_after_registration()).await;
+        let config = UnifaiConfig {
+            toolkit_api_key: Some("test".to_string()),
+            backend_ws_endpoint: primary.url(),
+            backend_ws_fallback_endpoints: vec![secondary.url()],
+            ..UnifaiConfig::default()
+        };
+
+        let service = ToolkitService::from_config(config.clone()).unwrap();
+        let handle = service.start().await.unwrap();
+        assert_eq!(handle.endpoint(), Some(primary.url().as_str()));
+        assert!(handle.await.unwrap().is_ok());
+
+        // The primary server is down once its script ends, the next start connects to the
+        // secondary one.
+        let primary_url = primary.url();
+        primary.finish().await;
+        let captured = Captured::default();
+        let _guard = captured.install();
+
+        let service = ToolkitService::from_config(config).unwrap();
+        let handle = service.start().await.unwrap();
+        assert_eq!(handle.endpoint(), Some(secondary.url().as_str()));
+        assert!(handle.await.unwrap().is_ok());
+        assert_eq!(secondary.finish().await.len(), 1);
+
+        let unreachable = captured.events(events::ENDPOINT_UNREACHABLE);
+        assert_eq!(unreachable.len(), 1);
+        assert_eq!(unreachable[0].fields["url"], primary_url);
+    }
+
+    #[tokio::test]
+    async fn test_scripted_malformed_frames_are_skipped() {
+        let call = crate::fixtures::action_frame("echo", 3, &json!({ "content": "hello" }));
+        let server = ScriptedServer::start(vec![
+            Step::Accept,
+            Step::expect_message("registerActions"),
+            Step::Send("{ not json".to_string()),
+            Step::Send(String::from_utf8(call).unwrap()),
+            Step::expect_message("actionResult"),
+            Step::Close,
+        ])
+        .await;
+
+        let handle = service_for(&server).start().await.unwrap();
+
+        assert!(handle.await.unwrap().is_ok());
+        let frames = server.finish().await;
+        assert!(frames[1].contains("hello"), "{frames:?}");
+    }
+
+    #[tokio::test]
+    async fn test_scripted_stop_ponging_keeps_connection() {
+        let server = ScriptedServer::start(scripts::stop_ponging()).await;
+
+        let handle = service_for(&server).start().await.unwrap();
+        sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(server.received_frames().len(), 1);
+        let runner = handle.into_join_handle();
+        assert!(!runner.is_finished());
+        runner.abort();
+    }
+
+    /// Like [Echo], with the time of the call.
+    struct Stamped;
+
+    impl Action for Stamped {
+        const NAME: &'static str = "echo";
+
+        type Error = Infallible;
+        type Args = Value;
+        type Output = Value;
+
+        async fn definition(&self) -> ActionDefinition {
+            Echo.definition().await
+        }
+
+        async fn call(
+            &self,
+            ctx: ActionContext,
+            params: ActionParams<Self::Args>,
+        ) -> std::result::Result<ActionResult<Self::Output>, Self::Error> {
+            let time = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos() as u64;
+
+            Ok(ActionResult {
+                payload: json!({ "agent": ctx.agent_id, "echo": params.payload, "time": time }),
+                payment: None,
+            })
+        }
+    }
+
+    async fn harness_with(action: impl Action + 'static) -> ToolkitTestHarness {
+        let mut service = ToolkitService::new("test");
+        service.add_action(action);
+
+        ToolkitTestHarness::start(service).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_replay_fixture() {
+        let path =
+            std::env::temp_dir().join(format!("unifai-fixture-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut service = ToolkitService::new("test").with_recorder(record_to_file(&path).unwrap());
+        service.add_action(Stamped);
+        let mut harness = ToolkitTestHarness::start(service).await.unwrap();
+        harness.send_action_call("echo", json!({ "n": 1 }), 1).await;
+        harness.send_action_call("echo", "{\"n\":2}", 2).await;
+        harness.shutdown().await.unwrap();
+
+        let mut harness = harness_with(Stamped).await;
+        let mismatches = replay_fixture(&path, &mut harness, &[]).await.unwrap();
+        assert_eq!(mismatches.len(), 2);
+        assert_eq!(mismatches[1].line, 2);
+        assert_eq!(mismatches[1].replayed["echo"], json!({ "n": 2 }));
+
+        let mismatches = replay_fixture(&path, &mut harness, &["/time"])
+            .await
+            .unwrap();
+        assert!(mismatches.is_empty(), "{mismatches:?}");
+
+        let mut harness = harness_with(Echo).await;
+        let mismatches = replay_fixture(&path, &mut harness, &["/time"])
+            .await
+            .unwrap();
+        assert_eq!(mismatches.len(), 2);
+        assert_eq!(
+            mismatches[0].replayed,
+            json!({ "agent": 1, "echo": { "n": 1 } })
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_definitions_snapshot() {
+        let path = std::env::temp_dir()
+            .join(format!("unifai-snapshot-{}", std::process::id()))
+            .join("definitions.json");
+        let _ = std::fs::remove_file(&path);
+
+        let mut service = ToolkitService::new("test");
+        service.add_action(Echo);
+        assert_definitions_snapshot(&service, &path).await;
+        let snapshot = std::fs::read_to_string(&path).unwrap();
+        assert!(snapshot.contains(r#""version": "*""#), "{snapshot}");
+        assert!(snapshot.find(r#""clientInfo""#) < snapshot.find(r#""protocolFeatures""#));
+        assert_definitions_snapshot(&service, &path).await;
+
+        let mut service = ToolkitService::new("test").with_legacy_registration(true);
+        service.add_action(Echo);
+        let changed = AssertUnwindSafe(assert_definitions_snapshot(&service, &path));
+        let panic = changed.catch_unwind().await.unwrap_err();
+        let message = panic.downcast_ref::<String>().unwrap();
+        assert!(message.contains("UPDATE_SNAPSHOTS=1"), "{message}");
+        assert!(message.contains("-     \"clientInfo\": {"), "{message}");
+        assert!(!message.contains("+ "), "{message}");
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_line_diff() {
+        let old = "a\nb\nc\nd\ne\nf\ng\n";
+        let new = "a\nb\nc\nd\nE\nf\ng\n";
+        assert_eq!(line_diff(old, new), "  ...\n  c\n  d\n- e\n+ E\n  f\n  g\n");
+    }
+
+    #[tokio::test]
+    async fn test_harness_answers_unknown_action() {
+        let mut harness = ToolkitTestHarness::start(ToolkitService::new("test"))
+            .await
+            .unwrap();
+
+        let result = harness.send_action_call("missing", json!({}), 1).await;
+
+        assert_eq!(
+            result.payload.get(),
+            r#"{"available_actions":[],"code":"not_found","error":"ActionNotFound: no action named missing"}"#
+        );
+    }
+}
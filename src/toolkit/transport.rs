@@ -0,0 +1,355 @@
+use super::errors::{Result, ToolkitError};
+use crate::{
+    config::UnifaiConfig,
+    constants::USER_AGENT,
+    logging::{directions, events, redact_url, targets, WireLogging},
+    secrets::ApiKeyProvider,
+};
+use futures_util::{SinkExt, StreamExt};
+use std::{future::Future, sync::Arc, time::Duration};
+#[cfg(any(test, feature = "test-utils"))]
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::{
+    net::TcpStream,
+    time::{sleep, timeout},
+};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{
+        client::IntoClientRequest,
+        handshake::client::Request,
+        http::{header, HeaderValue},
+        Bytes, Message,
+    },
+    MaybeTlsStream, WebSocketStream,
+};
+
+/// What a transport received from the backend.
+#[non_exhaustive]
+pub enum Incoming {
+    /// A toolkit message, encoded as JSON, see [ToolkitMessage::from_frame]. Built from a
+    /// `String` or `Vec<u8>` with `.into()`.
+    ///
+    /// [ToolkitMessage::from_frame]: super::messages::ToolkitMessage::from_frame
+    Text(Bytes),
+    /// The backend closed the connection, with the code and the reason of its close frame, if any.
+    Closed {
+        code: Option<u16>,
+        reason: Option<Arc<str>>,
+    },
+    /// A message could not be received, later ones may still be.
+    Failed(ToolkitError),
+    /// The answer to a [ToolkitTransport::ping], telling that the connection is alive.
+    Pong,
+    /// Anything handled by the transport itself, such as the pings of the backend.
+    Ignored,
+}
+
+/// How a Toolkit service connects to the server, see [ToolkitService::with_transport].
+///
+/// [ToolkitService::with_transport]: super::ToolkitService::with_transport
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TransportPreference {
+    /// A WebSocket connection to one of [UnifaiConfig::backend_ws_endpoints].
+    ///
+    /// [UnifaiConfig::backend_ws_endpoints]: crate::config::UnifaiConfig::backend_ws_endpoints
+    #[default]
+    WebSocket,
+    /// HTTP long-polling of [UnifaiConfig::backend_poll_endpoint], for networks blocking WebSocket
+    /// connections. Calls take longer to arrive.
+    ///
+    /// [UnifaiConfig::backend_poll_endpoint]: crate::config::UnifaiConfig::backend_poll_endpoint
+    Polling,
+    /// A WebSocket connection, falling back to long-polling when no WebSocket endpoint can be
+    /// connected to.
+    Auto,
+    /// A gRPC stream to [UnifaiConfig::backend_grpc_endpoint], for infrastructures handling gRPC
+    /// better than WebSocket connections.
+    ///
+    /// [UnifaiConfig::backend_grpc_endpoint]: crate::config::UnifaiConfig::backend_grpc_endpoint
+    #[cfg(feature = "grpc")]
+    Grpc,
+}
+
+/// A connection to the backend carrying the toolkit messages, so the service runs the same
+/// whatever the messages are carried over.
+///
+/// The service uses a WebSocket, long-polling or gRPC connection depending on its
+/// [TransportPreference]. Implement this trait to carry the messages over something else, and
+/// start the service over it with [ToolkitService::start_over].
+///
+/// [ToolkitService::start_over]: super::ToolkitService::start_over
+pub trait ToolkitTransport: Send + 'static {
+    /// Send a toolkit message, encoded as JSON.
+    fn send(&mut self, text: String) -> impl Future<Output = Result<()>> + Send;
+
+    /// Keep the connection alive, called periodically while the service runs.
+    fn ping(&mut self) -> impl Future<Output = Result<()>> + Send;
+
+    /// Whether the server answers the pings with an [Incoming::Pong], so that pings left
+    /// unanswered tell the connection is dead, see [UnifaiConfig::missed_pongs].
+    fn answers_pings(&self) -> bool {
+        false
+    }
+
+    /// Wait for the next incoming frame. `None` once the connection is gone, an error when the
+    /// connection can't be used anymore.
+    ///
+    /// It must be cancel safe: the service drops the future when something else happens first,
+    /// and no frame may be lost then.
+    fn recv(&mut self) -> impl Future<Output = Option<Result<Incoming>>> + Send;
+
+    /// Close the connection from the service side, on a graceful shutdown.
+    fn close(&mut self) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// The default transport, a WebSocket connection.
+pub struct WebSocketTransport {
+    stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    wire: WireLogging,
+}
+
+impl WebSocketTransport {
+    /// Carry the messages over `stream`, logging its frames as set by `wire`.
+    pub fn new(stream: WebSocketStream<MaybeTlsStream<TcpStream>>, wire: WireLogging) -> Self {
+        Self { stream, wire }
+    }
+}
+
+impl ToolkitTransport for WebSocketTransport {
+    async fn send(&mut self, text: String) -> Result<()> {
+        self.wire.ws_frame(directions::SENT, &text);
+        self.stream.send(Message::text(text)).await?;
+
+        Ok(())
+    }
+
+    async fn ping(&mut self) -> Result<()> {
+        self.stream.send(Message::Ping(Bytes::new())).await?;
+
+        Ok(())
+    }
+
+    fn answers_pings(&self) -> bool {
+        true
+    }
+
+    async fn recv(&mut self) -> Option<Result<Incoming>> {
+        let incoming = match self.stream.next().await? {
+            Ok(Message::Text(text)) => {
+                self.wire.ws_frame(directions::RECEIVED, &text);
+                Incoming::Text(text.into())
+            }
+            Ok(Message::Ping(data)) => {
+                if let Err(e) = self.stream.send(Message::Pong(data)).await {
+                    return Some(Err(e.into()));
+                }
+                Incoming::Ignored
+            }
+            Ok(Message::Close(frame)) => Incoming::Closed {
+                code: frame.as_ref().map(|frame| frame.code.into()),
+                reason: frame
+                    .filter(|frame| !frame.reason.is_empty())
+                    .map(|frame| frame.reason.as_str().into()),
+            },
+            Ok(Message::Pong(_)) => Incoming::Pong,
+            Ok(_) => Incoming::Ignored,
+            Err(e) => Incoming::Failed(e.into()),
+        };
+
+        Some(Ok(incoming))
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.stream.close(None).await?;
+
+        Ok(())
+    }
+}
+
+/// A transport over in-memory channels, to run a service offline and drive it at the level of its
+/// frames from the [ChannelPeer] end, see [ToolkitService::start_over].
+///
+/// [ToolkitService::start_over]: super::ToolkitService::start_over
+#[cfg(any(test, feature = "test-utils"))]
+pub struct ChannelTransport {
+    sent: UnboundedSender<String>,
+    incoming: UnboundedReceiver<Incoming>,
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+impl ChannelTransport {
+    /// Create a transport and the backend end of its channels.
+    pub fn new() -> (Self, ChannelPeer) {
+        let (sent, outgoing) = unbounded_channel();
+        let (incoming, receiver) = unbounded_channel();
+        let transport = Self {
+            sent,
+            incoming: receiver,
+        };
+
+        (transport, ChannelPeer { incoming, outgoing })
+    }
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+impl ToolkitTransport for ChannelTransport {
+    async fn send(&mut self, text: String) -> Result<()> {
+        let _ = self.sent.send(text);
+        Ok(())
+    }
+
+    async fn ping(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn recv(&mut self) -> Option<Result<Incoming>> {
+        self.incoming.recv().await.map(Ok)
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The backend end of a [ChannelTransport], sending frames as the server would and receiving those
+/// of the service.
+#[cfg(any(test, feature = "test-utils"))]
+pub struct ChannelPeer {
+    incoming: UnboundedSender<Incoming>,
+    outgoing: UnboundedReceiver<String>,
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+impl ChannelPeer {
+    /// Send a frame to the service.
+    pub fn send(&self, frame: impl Into<Bytes>) {
+        let _ = self.incoming.send(Incoming::Text(frame.into()));
+    }
+
+    /// Receive the next message sent by the service, `None` once it stopped.
+    pub async fn recv(&mut self) -> Option<String> {
+        self.outgoing.recv().await
+    }
+
+    /// Close the connection, which stops the service.
+    pub fn close(&self) {
+        let _ = self.incoming.send(Incoming::Closed {
+            code: None,
+            reason: None,
+        });
+    }
+}
+
+pub(crate) async fn connect(
+    request: impl IntoClientRequest,
+    connect_timeout: Duration,
+) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>> {
+    let mut request = request.into_client_request()?;
+    request
+        .headers_mut()
+        .insert(header::USER_AGENT, HeaderValue::from_static(USER_AGENT));
+
+    let (ws_stream, _) = timeout(connect_timeout, connect_async(request))
+        .await
+        .map_err(|_| ToolkitError::Timeout {
+            operation: "WebSocket connect",
+            timeout: connect_timeout,
+        })??;
+
+    Ok(ws_stream)
+}
+
+/// Connect to the first reachable endpoint of [UnifaiConfig::backend_ws_endpoints], in order,
+/// trying them all again up to [UnifaiConfig::connect_retries] times, as a client of
+/// `client_type` authenticated with `api_key`, see [ws_request].
+pub(crate) async fn connect_any(
+    config: &UnifaiConfig,
+    wire: &WireLogging,
+    client_type: &str,
+    api_key: &ApiKeyProvider,
+) -> Result<(String, WebSocketTransport)> {
+    let mut retries = 0;
+    loop {
+        match connect_once(config, wire, client_type, api_key).await {
+            Err(e) if retries < config.connect_retries => {
+                retries += 1;
+                let delay = config.retry_base_delay * 2u32.pow(retries - 1);
+                tracing::warn!(
+                    target: targets::TRANSPORT,
+                    event = events::CONNECT_RETRY,
+                    attempt = retries,
+                    delay_ms = delay.as_millis() as u64,
+                    error = %e,
+                    "No WebSocket endpoint reachable, retrying"
+                );
+                sleep(delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// The request opening a connection of `client_type` at `endpoint`, with the API key in an
+/// `Authorization` header, or in the query of the URL if [UnifaiConfig::ws_api_key_in_query] is
+/// set. The URL is logged with the key redacted.
+fn ws_request(
+    config: &UnifaiConfig,
+    endpoint: &str,
+    client_type: &str,
+    api_key: &ApiKeyProvider,
+) -> Result<Request> {
+    let mut url = format!("{endpoint}?type={client_type}");
+    if config.ws_api_key_in_query {
+        url = format!("{url}&api-key={}", api_key.expose());
+    }
+    tracing::debug!(
+        target: targets::WIRE,
+        event = events::WS_CONNECT,
+        url = %redact_url(&url),
+        "WebSocket connect"
+    );
+
+    let mut request = url.into_client_request()?;
+    if !config.ws_api_key_in_query {
+        request
+            .headers_mut()
+            .insert(header::AUTHORIZATION, api_key.header());
+    }
+
+    Ok(request)
+}
+
+async fn connect_once(
+    config: &UnifaiConfig,
+    wire: &WireLogging,
+    client_type: &str,
+    api_key: &ApiKeyProvider,
+) -> Result<(String, WebSocketTransport)> {
+    let endpoints = config.backend_ws_endpoints();
+    let (last, others) = endpoints.split_last().expect("there is a primary endpoint");
+    let request = |endpoint| ws_request(config, endpoint, client_type, api_key);
+
+    for endpoint in others {
+        match connect(request(endpoint)?, config.connect_timeout).await {
+            Ok(ws_stream) => {
+                let transport = WebSocketTransport::new(ws_stream, wire.clone());
+                return Ok((endpoint.to_string(), transport));
+            }
+            Err(e) => tracing::warn!(
+                target: targets::TRANSPORT,
+                event = events::ENDPOINT_UNREACHABLE,
+                url = endpoint,
+                error = %e,
+                "WebSocket endpoint unreachable, trying the next one"
+            ),
+        }
+    }
+
+    let ws_stream = connect(request(last)?, config.connect_timeout).await?;
+
+    Ok((
+        last.to_string(),
+        WebSocketTransport::new(ws_stream, wire.clone()),
+    ))
+}
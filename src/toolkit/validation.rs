@@ -0,0 +1,170 @@
+//! Validation of the payloads of action calls against the definitions of their actions, see
+//! [ToolkitService::with_payload_validation](super::ToolkitService::with_payload_validation).
+//!
+//! The payload is checked against [ActionDefinition::input_schema](super::ActionDefinition::input_schema),
+//! which covers both the `{"type", "description", "required"}` fields of the usual payload
+//! definitions and payloads that already are a schema. Only the top-level fields are checked: the
+//! required ones must be present, and each field must be of its `type` and one of its `enum`
+//! values, if any. Fields missing from the definition are let through.
+
+use serde::Serialize;
+use serde_json::{value::RawValue, Map, Value};
+use std::fmt;
+
+/// A field of a payload that doesn't match the definition of its action.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PayloadViolation {
+    /// The name of the field, `.` for the whole payload.
+    pub field: String,
+    /// What is wrong with it, as in `missing` or `expected string`.
+    pub problem: String,
+    /// The description of the field in the definition, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+impl fmt::Display for PayloadViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.problem)
+    }
+}
+
+/// Check `payload`, which may also be encoded as a JSON string, against the input `schema` of its
+/// action, returning every violation.
+pub(crate) fn validate(schema: &Value, payload: &RawValue) -> Vec<PayloadViolation> {
+    let payload = if payload.get().starts_with('"') {
+        serde_json::from_str::<String>(payload.get())
+            .ok()
+            .and_then(|payload| serde_json::from_str(&payload).ok())
+    } else {
+        serde_json::from_str(payload.get()).ok()
+    };
+    let Some(Value::Object(payload)) = payload else {
+        return vec![violation(".", "expected object".to_string(), None)];
+    };
+
+    let empty = Map::new();
+    let properties = schema["properties"].as_object().unwrap_or(&empty);
+    let required = schema["required"].as_array().map_or(&[][..], Vec::as_slice);
+    let mut violations = Vec::new();
+
+    for (name, field) in properties {
+        let description = field["description"].as_str();
+        let Some(value) = payload.get(name) else {
+            if required.iter().any(|required| required == name) {
+                violations.push(violation(name, "missing".to_string(), description));
+            }
+            continue;
+        };
+
+        if let Some(problem) = check_field(field, value) {
+            violations.push(violation(name, problem, description));
+        }
+    }
+
+    violations
+}
+
+fn violation(field: &str, problem: String, description: Option<&str>) -> PayloadViolation {
+    PayloadViolation {
+        field: field.to_string(),
+        problem,
+        description: description.map(str::to_string),
+    }
+}
+
+/// What is wrong with the `value` of a field, if anything.
+fn check_field(field: &Value, value: &Value) -> Option<String> {
+    let types: Vec<&str> = match &field["type"] {
+        Value::String(name) => vec![name],
+        Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    if !types.is_empty() && !types.iter().any(|name| is_of_type(value, name)) {
+        return Some(format!("expected {}", types.join(" or ")));
+    }
+
+    match &field["enum"] {
+        Value::Array(values) if !values.contains(value) => {
+            let values: Vec<_> = values.iter().map(Value::to_string).collect();
+            Some(format!("expected one of {}", values.join(", ")))
+        }
+        _ => None,
+    }
+}
+
+/// Whether `value` is of the JSON Schema type `name`, unknown types matching anything.
+fn is_of_type(value: &Value, name: &str) -> bool {
+    match name {
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "boolean" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toolkit::ActionDefinition;
+    use serde_json::{json, value::to_raw_value};
+
+    fn violations(payload: Value) -> Vec<String> {
+        let definition = ActionDefinition {
+            description: "Send a message".to_string(),
+            payload: json!({
+                "content": { "type": "string", "description": "The message.", "required": true },
+                "count": { "type": "integer" },
+                "tone": { "type": "string", "enum": ["calm", "loud"] },
+                "notes": "Anything else.",
+            }),
+            payment: None,
+            ..Default::default()
+        };
+        let payload = to_raw_value(&payload).unwrap();
+
+        validate(&definition.input_schema(), &payload)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(violations(json!({ "content": "hi", "count": 2, "extra": true })).is_empty());
+        assert!(violations(json!(r#"{"content":"hi"}"#)).is_empty());
+
+        assert_eq!(
+            violations(json!({ "count": 1.5, "tone": "sad", "notes": [] })),
+            [
+                "content: missing",
+                "count: expected integer",
+                r#"tone: expected one of "calm", "loud""#,
+            ]
+        );
+        assert_eq!(violations(json!([1])), [".: expected object"]);
+    }
+
+    #[test]
+    fn test_violations_carry_the_description() {
+        let payload = to_raw_value(&json!({})).unwrap();
+        let schema = json!({
+            "type": "object",
+            "properties": { "content": { "type": "string", "description": "The message." } },
+            "required": ["content"],
+        });
+
+        assert_eq!(
+            validate(&schema, &payload),
+            [PayloadViolation {
+                field: "content".to_string(),
+                problem: "missing".to_string(),
+                description: Some("The message.".to_string()),
+            }]
+        );
+    }
+}
@@ -0,0 +1,219 @@
+//! Blocking versions of the tools, for synchronous applications, such as a plugin host loading the
+//! SDK in a cdylib.
+//!
+//! The tools run the async ones on a current-thread runtime of their own, with their own
+//! connection pool, so the retries, fallback endpoints and errors are the same.
+//!
+//! ```no_run
+//! use serde_json::json;
+//! use unifai_sdk::tools::{blocking, CallToolArgs, SearchToolsArgs};
+//!
+//! let (search_tools, call_tool) = blocking::get_tools("UNIFAI_AGENT_API_KEY");
+//!
+//! let args = SearchToolsArgs { query: "solana balance".to_string(), ..Default::default() };
+//! let found = search_tools.search(&args).unwrap();
+//!
+//! let args = CallToolArgs {
+//!     action: found[0].action.clone(),
+//!     payload: json!({ "wallet": "..." }),
+//!     ..Default::default()
+//! };
+//! println!("{:?}", call_tool.call(&args).unwrap().payload);
+//! ```
+//!
+//! # Panics
+//!
+//! Calling the tools from async code panics, as it would block the runtime; use the async tools
+//! there.
+
+use super::{
+    CallToolArgs, CallToolResponse, SearchToolsArgs, ToolRecord, ToolsError, UnifaiClient,
+};
+use crate::{
+    config::{ConfigError, UnifaiConfig},
+    http::client_builder,
+};
+use std::{future::Future, sync::Arc};
+use tokio::runtime::{Builder, Runtime};
+
+/// Returns the two essential tools, sharing one runtime and connection pool.
+///
+/// Endpoints are read from environment variables, see [UnifaiConfig::from_env].
+///
+/// # Panics
+///
+/// If the API key isn't a valid header value, or the HTTP client can't be built, see
+/// [get_tools_from_config].
+pub fn get_tools(api_key: &str) -> (SearchTools, CallTool) {
+    let config = UnifaiConfig {
+        agent_api_key: Some(api_key.to_string()),
+        ..UnifaiConfig::from_env()
+    };
+
+    get_tools_from_config(&config).unwrap_or_else(|e| panic!("{e}"))
+}
+
+/// Returns the two essential tools built from a config, which must contain the agent API key.
+pub fn get_tools_from_config(
+    config: &UnifaiConfig,
+) -> Result<(SearchTools, CallTool), ConfigError> {
+    let tools = BlockingClient::from_config(config)?;
+
+    Ok((
+        SearchTools {
+            tools: tools.clone(),
+        },
+        CallTool { tools },
+    ))
+}
+
+/// The blocking version of [SearchTools](super::SearchTools).
+#[derive(Clone, Debug)]
+pub struct SearchTools {
+    tools: BlockingClient,
+}
+
+impl SearchTools {
+    /// Create the tool with Unifai Agent API Key, see [get_tools].
+    pub fn new(api_key: &str) -> Self {
+        get_tools(api_key).0
+    }
+
+    /// Create the tool from a config, which must contain the agent API key.
+    pub fn from_config(config: &UnifaiConfig) -> Result<Self, ConfigError> {
+        Ok(get_tools_from_config(config)?.0)
+    }
+
+    /// Search tools, parsing the response, see [UnifaiClient::search_tools_typed].
+    pub fn search(&self, args: &SearchToolsArgs) -> Result<Vec<ToolRecord>, ToolsError> {
+        let client = &self.tools.client;
+        self.tools.block_on(client.search_tools_typed(args))
+    }
+}
+
+/// The blocking version of [CallTool](super::CallTool).
+#[derive(Clone, Debug)]
+pub struct CallTool {
+    tools: BlockingClient,
+}
+
+impl CallTool {
+    /// Create the tool with Unifai Agent API Key, see [get_tools].
+    pub fn new(api_key: &str) -> Self {
+        get_tools(api_key).1
+    }
+
+    /// Create the tool from a config, which must contain the agent API key.
+    pub fn from_config(config: &UnifaiConfig) -> Result<Self, ConfigError> {
+        Ok(get_tools_from_config(config)?.1)
+    }
+
+    /// Call the tool, parsing its response, see [UnifaiClient::call_tool_typed].
+    pub fn call(&self, args: &CallToolArgs) -> Result<CallToolResponse, ToolsError> {
+        let client = &self.tools.client;
+        self.tools.block_on(client.call_tool_typed(args))
+    }
+}
+
+/// A client of the tools API with the runtime it runs on.
+#[derive(Clone, Debug)]
+struct BlockingClient {
+    client: UnifaiClient,
+    runtime: Arc<Runtime>,
+}
+
+impl BlockingClient {
+    fn from_config(config: &UnifaiConfig) -> Result<Self, ConfigError> {
+        // The connections of a pool are driven by the runtime that opened them, which only runs
+        // during a call here, so the pool can't be shared with the async tools.
+        let http = client_builder(&config.client)
+            .build()
+            .map_err(ConfigError::Client)?;
+        let runtime = Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("a current-thread runtime builds");
+
+        Ok(Self {
+            client: UnifaiClient::from_config(config)?.with_client(http),
+            runtime: Arc::new(runtime),
+        })
+    }
+
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    /// Serve one request with `body` on a thread, returning the endpoint.
+    fn serve_once(body: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            let response = format!(
+                "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{body}",
+                body.len()
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+
+        endpoint
+    }
+
+    fn config(endpoint: String) -> UnifaiConfig {
+        UnifaiConfig {
+            agent_api_key: Some("test".to_string()),
+            backend_api_endpoint: endpoint,
+            ..UnifaiConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_blocking_tools() {
+        let endpoint = serve_once(r#"[{"action":"echo","description":"Echo"}]"#);
+        let search_tools = SearchTools::from_config(&config(endpoint)).unwrap();
+        let found = search_tools
+            .search(&SearchToolsArgs {
+                query: "echo".to_string(),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(found[0].action, "echo");
+
+        let endpoint = serve_once(r#"{"payload":{"n":1},"payment":null}"#);
+        let call_tool = CallTool::from_config(&config(endpoint)).unwrap();
+        let response = call_tool
+            .call(&CallToolArgs {
+                action: "echo".to_string(),
+                payload: json!({ "n": 1 }),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(response.payload, json!({ "n": 1 }));
+    }
+
+    #[test]
+    fn test_missing_api_key() {
+        let result = CallTool::from_config(&UnifaiConfig::default());
+
+        assert!(matches!(result, Err(ConfigError::MissingApiKey(_))));
+    }
+}
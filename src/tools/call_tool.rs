@@ -1,33 +1,222 @@
-use crate::{constants::DEFAULT_BACKEND_API_ENDPOINT, utils::build_api_client};
-use reqwest::Client;
+use super::{OutputPolicy, RateLimit, RateLimiter, RetryPolicy, ToolsError, UnifaiClient};
+use crate::{
+    config::{ConfigError, UnifaiConfig},
+    constants::DEFAULT_BATCH_CONCURRENCY,
+};
+use futures_util::{stream, StreamExt};
+#[cfg(feature = "rig")]
 use rig::{completion::ToolDefinition, tool::Tool};
 use serde::{Deserialize, Serialize};
-use serde_json::{json, Value};
-use std::{env, time::Duration};
+#[cfg(feature = "rig")]
+use serde_json::json;
+use serde_json::Value;
+use std::time::Duration;
 
 /// A tool used to call specific tool on Unifai server.
 pub struct CallTool {
-    api_client: Client,
+    client: UnifaiClient,
+    output: Option<OutputPolicy>,
 }
 
 impl CallTool {
+    /// Create the tool with Unifai Agent API Key.
+    ///
+    /// Endpoints are read from environment variables once, see [UnifaiConfig::from_env].
+    ///
+    /// # Panics
+    ///
+    /// If the API key isn't a valid header value, or the HTTP client can't be built, see
+    /// [CallTool::try_new].
     pub fn new(api_key: &str) -> Self {
-        let api_client = build_api_client(api_key);
-        Self { api_client }
+        UnifaiClient::new(api_key).into()
+    }
+
+    /// Create the tool like [CallTool::new], failing if the API key isn't a valid header value, or
+    /// if the HTTP client can't be built.
+    pub fn try_new(api_key: &str) -> Result<Self, ConfigError> {
+        Ok(UnifaiClient::try_new(api_key)?.into())
+    }
+
+    /// Create the tool with Unifai Agent API Key, retrying the requests that failed for a
+    /// transient reason, see [RetryPolicy].
+    ///
+    /// # Panics
+    ///
+    /// Like [CallTool::new].
+    pub fn new_with_retry(api_key: &str, policy: RetryPolicy) -> Self {
+        UnifaiClient::new(api_key).with_retry(policy).into()
+    }
+
+    /// Create the tool with Unifai Agent API Key, holding its calls to `limit`, see
+    /// [UnifaiClient::with_rate_limiter].
+    ///
+    /// # Panics
+    ///
+    /// Like [CallTool::new].
+    pub fn new_with_rate_limit(api_key: &str, limit: RateLimit) -> Self {
+        UnifaiClient::new(api_key).with_rate_limit(limit).into()
+    }
+
+    /// Create the tool from a config, which must contain the agent API key.
+    pub fn from_config(config: &UnifaiConfig) -> Result<Self, ConfigError> {
+        Ok(UnifaiClient::from_config(config)?.into())
+    }
+
+    /// The client used by the tool.
+    pub fn client(&self) -> &UnifaiClient {
+        &self.client
+    }
+
+    /// Send the requests with `client`, see [UnifaiClient::with_client].
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = self.client.with_client(client);
+        self
+    }
+
+    /// Hold the requests to the limit of `limiter`, such as the limiter of another tool, see
+    /// [UnifaiClient::with_rate_limiter].
+    pub fn with_rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.client = self.client.with_rate_limiter(limiter);
+        self
+    }
+
+    /// Cut the outputs of the rig tool down to `policy`, so they fit in the context of the model.
+    pub fn with_output_policy(mut self, policy: OutputPolicy) -> Self {
+        self.output = Some(policy);
+        self
+    }
+
+    /// Cut the outputs of the rig tool to `max_chars` characters, with a notice, see
+    /// [OutputPolicy::new].
+    pub fn with_max_output_chars(self, max_chars: usize) -> Self {
+        self.with_output_policy(OutputPolicy::new(max_chars))
+    }
+
+    /// `output` cut down to the output policy, if any.
+    #[cfg(feature = "rig")]
+    fn limit_output(&self, output: String) -> String {
+        match &self.output {
+            Some(policy) => policy.apply(output),
+            None => output,
+        }
+    }
+
+    /// Give up on calls after `timeout`, failing with [ToolsError::Timeout], see
+    /// [UnifaiClient::with_call_timeout].
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.client = self.client.with_call_timeout(timeout);
+        self
+    }
+
+    /// Call the tool, parsing its response, see [UnifaiClient::call_tool_typed].
+    pub async fn call_typed(&self, args: &CallToolArgs) -> Result<CallToolResponse, ToolsError> {
+        self.client.call_tool_typed(args).await
+    }
+
+    /// Make the `calls`, `concurrency` at a time, returning their results in the order of
+    /// `calls`. Each call has its own timeout, see [CallToolArgs::timeout_ms].
+    pub async fn call_batch(
+        &self,
+        calls: Vec<CallToolArgs>,
+        concurrency: usize,
+    ) -> Vec<Result<CallToolResponse, ToolsError>> {
+        stream::iter(calls)
+            .map(|args| async move { self.call_typed(&args).await })
+            .buffered(concurrency.max(1))
+            .collect()
+            .await
+    }
+}
+
+impl From<UnifaiClient> for CallTool {
+    fn from(client: UnifaiClient) -> Self {
+        Self {
+            client,
+            output: None,
+        }
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct CallToolArgs {
     pub action: String,
     pub payload: Value,
     pub payment: Option<u64>,
+    /// The timeout of this call in milliseconds, in place of the timeout of the client, see
+    /// [UnifaiClient::with_call_timeout]. It's not sent to the server.
+    #[serde(default, skip_serializing)]
+    pub timeout_ms: Option<u64>,
+}
+
+/// The response to a tool call, as in `{"payload":"Balance of SOL: 1","payment":null}`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CallToolResponse {
+    /// The result payload of the action.
+    #[serde(default)]
+    pub payload: Value,
+    pub payment: Option<u64>,
+    /// Why the call failed, if it did.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
 }
 
+/// A tool making several calls at once, as [CallTool::call_batch], so the agent calls the same
+/// action for many inputs in one turn.
+///
+/// Each call is answered with its response, or `{"error": ...}` if it failed, in the order of the
+/// calls.
+pub struct CallToolBatch {
+    tool: CallTool,
+    concurrency: usize,
+}
+
+impl CallToolBatch {
+    /// Make the calls with `tool`, 8 at a time.
+    pub fn new(tool: CallTool) -> Self {
+        Self {
+            tool,
+            concurrency: DEFAULT_BATCH_CONCURRENCY,
+        }
+    }
+
+    /// Make up to `concurrency` calls at a time.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency;
+        self
+    }
+
+    /// Make the `calls`, returning the response or error of each as JSON.
+    pub async fn call_all(&self, calls: Vec<CallToolArgs>) -> Vec<Value> {
+        self.tool
+            .call_batch(calls, self.concurrency)
+            .await
+            .into_iter()
+            .map(|result| match result {
+                Ok(response) => {
+                    serde_json::to_value(response).expect("a response always serializes")
+                }
+                Err(e) => serde_json::json!({ "error": e.to_string() }),
+            })
+            .collect()
+    }
+}
+
+impl From<UnifaiClient> for CallToolBatch {
+    fn from(client: UnifaiClient) -> Self {
+        Self::new(client.into())
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CallToolBatchArgs {
+    pub calls: Vec<CallToolArgs>,
+}
+
+#[cfg(feature = "rig")]
 impl Tool for CallTool {
     const NAME: &'static str = "invoke_service";
 
-    type Error = reqwest::Error;
+    type Error = ToolsError;
     type Args = CallToolArgs;
     type Output = String;
 
@@ -57,32 +246,178 @@ impl Tool for CallTool {
     }
 
     async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
-        let endpoint = env::var("UNIFAI_BACKEND_API_ENDPOINT")
-            .unwrap_or(DEFAULT_BACKEND_API_ENDPOINT.to_string());
-        let url = format!("{endpoint}/actions/call");
-
-        self.api_client
-            .post(url)
-            .json(&args)
-            .timeout(Duration::from_millis(50_000))
-            .send()
-            .await?
-            .text()
-            .await
+        let response = self.call_typed(&args).await?;
+
+        Ok(self
+            .limit_output(serde_json::to_string(&response).expect("a response always serializes")))
+    }
+}
+
+#[cfg(feature = "rig")]
+impl Tool for CallToolBatch {
+    const NAME: &'static str = "invoke_service_batch";
+
+    type Error = ToolsError;
+    type Args = CallToolBatchArgs;
+    type Output = String;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: Self::NAME.to_string(),
+            description: "Call tools returned by search_services several times at once, such as the same action for many inputs. Results are returned in the order of the calls.".to_string(),
+            parameters: json!({
+              "type": "object",
+              "properties": {
+                "calls": {
+                  "type": "array",
+                  "description": "The calls to make, each as you would call invoke_service.",
+                  "items": {
+                    "type": "object",
+                    "properties": {
+                      "action": {
+                        "type": "string",
+                        "description": "The exact action you want to call in the search_services result."
+                      },
+                      "payload": {
+                        "type": "string",
+                        "description": "Action payload, based on the payload schema in the search_services result. You can pass either the json object directly or json encoded string of the object.",
+                      },
+                      "payment": {
+                        "type": "number",
+                        "description": "Amount to authorize in USD, as in invoke_service. Only include this field if the action you are calling includes payment information.",
+                      }
+                    },
+                    "required": ["action", "payload"],
+                  }
+                }
+              },
+              "required": ["calls"],
+            }),
+        }
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        let results = self.call_all(args.calls).await;
+
+        Ok(self.tool.limit_output(Value::Array(results).to_string()))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::tools::{CallTool, CallToolArgs};
+    use super::*;
+    #[cfg(feature = "rig")]
     use rig::tool::Tool;
-    use serde_json::{json, Value};
-    use std::env;
+    use serde_json::json;
+    use std::time::Instant;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    /// Answer each call with its payload after sleeping for its payload in milliseconds, or with
+    /// a 500 status for a negative payload.
+    async fn serve_calls() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buf = [0; 1024];
+                    let body = loop {
+                        let n = stream.read(&mut buf).await.unwrap();
+                        request.extend_from_slice(&buf[..n]);
+                        let text = String::from_utf8_lossy(&request);
+                        if let Some((_, body)) = text.split_once("\r\n\r\n") {
+                            if let Ok(args) = serde_json::from_str::<CallToolArgs>(body) {
+                                break args.payload;
+                            }
+                        }
+                    };
+                    let delay = body.as_i64().unwrap();
+                    let response = if delay < 0 {
+                        "HTTP/1.1 500 Internal Server Error\r\nconnection: close\r\ncontent-length: 4\r\n\r\nfail".to_string()
+                    } else {
+                        tokio::time::sleep(Duration::from_millis(delay as u64)).await;
+                        let body = json!({ "payload": delay, "payment": null }).to_string();
+                        format!(
+                            "HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-length: {}\r\n\r\n{body}",
+                            body.len()
+                        )
+                    };
+                    stream.write_all(response.as_bytes()).await.unwrap();
+                });
+            }
+        });
+
+        endpoint
+    }
+
+    fn call_tool(endpoint: String) -> CallTool {
+        let config = UnifaiConfig {
+            agent_api_key: Some("test".to_string()),
+            backend_api_endpoint: endpoint,
+            ..UnifaiConfig::default()
+        };
+        CallTool::from_config(&config).unwrap()
+    }
+
+    fn calls(payloads: &[i64]) -> Vec<CallToolArgs> {
+        payloads
+            .iter()
+            .map(|payload| CallToolArgs {
+                action: "sleep".to_string(),
+                payload: json!(payload),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_call_batch() {
+        let tool = call_tool(serve_calls().await);
+
+        let started = Instant::now();
+        let results = tool.call_batch(calls(&[300, 100, -1, 200]), 4).await;
+        assert!(started.elapsed() < Duration::from_millis(550));
+
+        let payloads: Vec<_> = results
+            .iter()
+            .map(|result| {
+                result
+                    .as_ref()
+                    .ok()
+                    .map(|response| response.payload.clone())
+            })
+            .collect();
+        assert_eq!(
+            payloads,
+            [Some(json!(300)), Some(json!(100)), None, Some(json!(200))]
+        );
+        assert!(matches!(
+            results[2],
+            Err(ToolsError::Status { status: 500, .. })
+        ));
+
+        // Each call has its own timeout.
+        let mut batch = calls(&[0, 300]);
+        batch[1].timeout_ms = Some(50);
+        let results = tool.call_batch(batch, 1).await;
+        assert!(results[0].is_ok());
+        assert!(matches!(results[1], Err(ToolsError::Timeout(_))));
+
+        let results = CallToolBatch::new(tool).call_all(calls(&[0, -1])).await;
+        assert_eq!(results[0], json!({ "payload": 0, "payment": null }));
+        assert!(results[1]["error"].as_str().is_some());
+    }
 
+    #[cfg(feature = "rig")]
     #[tokio::test]
     async fn test_call_tool_api() {
         let unifai_agent_api_key =
-            env::var("UNIFAI_AGENT_API_KEY").expect("UNIFAI_AGENT_API_KEY not set");
+            std::env::var("UNIFAI_AGENT_API_KEY").expect("UNIFAI_AGENT_API_KEY not set");
         let call_tool = CallTool::new(&unifai_agent_api_key);
 
         let response = call_tool
@@ -92,6 +427,7 @@ mod tests {
                     "walletAddress": "11111111111111111111111111111111"
                 }),
                 payment: None,
+                ..Default::default()
             })
             .await
             .unwrap();
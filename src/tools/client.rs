@@ -0,0 +1,1310 @@
+use super::{
+    failover::Endpoints,
+    retry::{self, Failure},
+    CallToolArgs, CallToolResponse, RateLimit, RateLimiter, RetryPolicy, SearchToolsArgs,
+    ToolRecord, ToolsError,
+};
+use crate::{
+    config::{ConfigError, UnifaiConfig},
+    http::{ApiClient, CredentialsError, Principal},
+    logging::{events, outcomes, targets, WireLogging},
+    secrets::{ApiKeyProvider, SecretSource},
+};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+use std::{future::Future, sync::Arc, time::Duration};
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+/// A plain client of the Unifai tools API.
+///
+/// It's what [SearchTools](super::SearchTools) and [CallTool](super::CallTool) use under the hood,
+/// and the API to use where the rig integration is not available, such as on wasm32.
+/// Cloning the client is cheap, and all clients share one connection pool, see [crate::http].
+///
+/// With [UnifaiConfig::backend_api_fallback_endpoints], requests fail over to the next endpoint
+/// when the active one is unreachable or keeps erroring. Clones share the active endpoint.
+///
+/// Failed requests aren't retried, unless a [RetryPolicy] is set with
+/// [UnifaiClient::with_retry], and requests aren't limited, unless a [RateLimiter] is set with
+/// [UnifaiClient::with_rate_limit].
+#[derive(Clone, Debug)]
+pub struct UnifaiClient {
+    api_client: ApiClient,
+    endpoints: Arc<Endpoints>,
+    call_timeout: Duration,
+    max_response_bytes: Option<usize>,
+    retry: Arc<RetryPolicy>,
+    rate_limiter: Option<RateLimiter>,
+}
+
+impl UnifaiClient {
+    /// Create a client with Unifai Agent API Key.
+    ///
+    /// Endpoints are read from environment variables once, see [UnifaiConfig::from_env].
+    ///
+    /// # Panics
+    ///
+    /// If the API key isn't a valid header value, or the HTTP client can't be built, see
+    /// [UnifaiClient::try_new].
+    pub fn new(api_key: &str) -> Self {
+        Self::try_new(api_key).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Create a client like [UnifaiClient::new], failing if the API key isn't a valid header
+    /// value, such as one with a trailing newline, or if the HTTP client can't be built.
+    pub fn try_new(api_key: &str) -> Result<Self, ConfigError> {
+        let api_key = ApiKeyProvider::fixed(api_key, "agent_api_key")?;
+
+        Self::with_api_key(api_key, &UnifaiConfig::from_env())
+    }
+
+    /// Create a client with the Unifai Agent API Key read from `source`, failing if it can't be
+    /// read. The key can then be rotated with [UnifaiClient::api_key].
+    ///
+    /// Endpoints are read from environment variables once, see [UnifaiConfig::from_env].
+    pub async fn from_secret(source: SecretSource) -> Result<Self, ConfigError> {
+        let api_key = ApiKeyProvider::new(source).await?;
+
+        Self::with_api_key(api_key, &UnifaiConfig::from_env())
+    }
+
+    /// Create a client from a config, which must contain the agent API key.
+    pub fn from_config(config: &UnifaiConfig) -> Result<Self, ConfigError> {
+        let api_key = ApiKeyProvider::fixed(config.require_agent_api_key()?, "agent_api_key")?;
+
+        Self::with_api_key(api_key, config)
+    }
+
+    fn with_api_key(api_key: ApiKeyProvider, config: &UnifaiConfig) -> Result<Self, ConfigError> {
+        Ok(Self {
+            api_client: ApiClient::new(api_key, &config.client)?,
+            endpoints: Arc::new(Endpoints::new(config)),
+            call_timeout: config.call_timeout,
+            max_response_bytes: config.client.max_response_bytes,
+            retry: Arc::new(RetryPolicy::never()),
+            rate_limiter: None,
+        })
+    }
+
+    /// The API key of the client, shared with its clones. Rotating it changes the key of the
+    /// requests sent from then on.
+    pub fn api_key(&self) -> &ApiKeyProvider {
+        self.api_client.api_key()
+    }
+
+    /// The backend API endpoint requests are sent to, see [UnifaiConfig::backend_api_endpoints].
+    pub fn active_endpoint(&self) -> &str {
+        self.endpoints.active()
+    }
+
+    /// Log the exchanges with the server, see [WireLogging].
+    pub fn with_wire_logging(mut self, wire: WireLogging) -> Self {
+        self.api_client.set_wire_logging(wire);
+        self
+    }
+
+    /// Send the requests with `client`, such as the client of the application, in place of the
+    /// shared one. The API key is still sent with every request, but
+    /// [ClientOptions](crate::config::ClientOptions) no longer apply.
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.api_client.set_client(client);
+        self
+    }
+
+    /// Give up on tool calls after `timeout`, failing with [ToolsError::Timeout], in place of
+    /// [UnifaiConfig::call_timeout]. A call can set its own with [CallToolArgs::timeout_ms].
+    pub fn with_call_timeout(mut self, timeout: Duration) -> Self {
+        self.call_timeout = timeout;
+        self
+    }
+
+    /// Retry the requests that failed for a transient reason, see [RetryPolicy].
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Arc::new(policy);
+        self
+    }
+
+    /// Hold the requests to `limit`, see [UnifaiClient::with_rate_limiter].
+    pub fn with_rate_limit(self, limit: RateLimit) -> Self {
+        self.with_rate_limiter(RateLimiter::new(limit))
+    }
+
+    /// Hold the requests to the limit of `limiter`, shared with the other clients it's given to
+    /// and with the clones of this one.
+    ///
+    /// Requests wait for their turn, and fail with [ToolsError::RateLimited] if they'd wait longer
+    /// than [RateLimit::max_wait]. A 429 response with a `Retry-After` within `max_wait` is waited
+    /// for and sent again once, even without a [RetryPolicy], except for paid calls.
+    pub fn with_rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(limiter);
+        self
+    }
+
+    /// The rate limiter of the client, if it has one.
+    pub fn rate_limiter(&self) -> Option<&RateLimiter> {
+        self.rate_limiter.as_ref()
+    }
+
+    /// Search tools, returning the raw response body.
+    pub async fn search_tools(&self, args: &SearchToolsArgs) -> Result<String, ToolsError> {
+        Ok(self.search(args).await?.body)
+    }
+
+    /// Search tools, parsing the response. Failing statuses are errors, see
+    /// [UnifaiClient::call_tool_typed].
+    pub async fn search_tools_typed(
+        &self,
+        args: &SearchToolsArgs,
+    ) -> Result<Vec<ToolRecord>, ToolsError> {
+        parse_response(self.search(args).await?)
+    }
+
+    async fn search(&self, args: &SearchToolsArgs) -> Result<Reply, ToolsError> {
+        self.retrying("search_tools", false, || self.search_once(args))
+            .await
+    }
+
+    async fn search_once(&self, args: &SearchToolsArgs) -> Attempt {
+        let (index, endpoint) = self.endpoints.pick();
+        let url = format!("{endpoint}/actions/search");
+        let started = Instant::now();
+        let mut failure = None;
+
+        let result = async {
+            let request = self.api_client.get(url).query(args);
+            let response = self.api_client.send(request).await;
+            self.endpoints.record(index, &response);
+            failure = Failure::of(&response);
+            let response = response?;
+            let status = response.status();
+            let body = read_text(
+                &self.api_client,
+                response,
+                self.max_response_bytes,
+                ToolsError::from,
+            )
+            .await?;
+
+            Ok((status, body))
+        }
+        .await;
+
+        log_request("search_tools", started, &result);
+        (result, failure)
+    }
+
+    /// Check that the server accepts the agent API key, with a search for a single tool.
+    ///
+    /// Call it before handing the tools to an agent, so a mistyped key fails right away instead of
+    /// showing up as empty search results.
+    pub async fn verify_credentials(&self) -> Result<Principal, CredentialsError> {
+        let url = format!("{}/actions/search", self.endpoints.active());
+        let args = SearchToolsArgs {
+            query: String::new(),
+            limit: Some(1),
+            ..Default::default()
+        };
+        let request = self.api_client.get(url).query(&args);
+
+        self.api_client.verify(request, "agent_api_key").await
+    }
+
+    /// Call a tool, returning the raw response body, whatever the status of the response.
+    ///
+    /// With the `otel` feature, the call runs in a `call_tool` span whose context is sent along,
+    /// see [crate::trace].
+    pub async fn call_tool(&self, args: &CallToolArgs) -> Result<String, ToolsError> {
+        Ok(self.call(args).await?.body)
+    }
+
+    /// Call a tool, parsing its response. Failing statuses are errors:
+    /// [ToolsError::Unauthorized] for 401 and 403, [ToolsError::RateLimited] for 429, with the
+    /// `Retry-After` of the server, and [ToolsError::Status] for the others.
+    pub async fn call_tool_typed(
+        &self,
+        args: &CallToolArgs,
+    ) -> Result<CallToolResponse, ToolsError> {
+        parse_response(self.call(args).await?)
+    }
+
+    async fn call(&self, args: &CallToolArgs) -> Result<Reply, ToolsError> {
+        let paid = args.payment.is_some();
+        let call = self.retrying("call_tool", paid, || self.send_call(args));
+        #[cfg(feature = "otel")]
+        let call = tracing::Instrument::instrument(
+            call,
+            tracing::info_span!(target: targets::TOOLS, "call_tool", action = %args.action),
+        );
+
+        call.await
+    }
+
+    async fn send_call(&self, args: &CallToolArgs) -> Attempt {
+        let (index, endpoint) = self.endpoints.pick();
+        let url = format!("{endpoint}/actions/call");
+        let started = Instant::now();
+
+        let request = self.api_client.post(url).json(args);
+        #[cfg(feature = "otel")]
+        let request = crate::trace::inject(request);
+
+        let mut status = StatusCode::OK;
+        // Unless a response says otherwise, such as when it timed out on wasm32.
+        let mut failure = Some(Failure::Network);
+        let result = send_text(
+            &self.api_client,
+            request,
+            |response| {
+                self.endpoints.record(index, response);
+                failure = Failure::of(response);
+                if let Ok(response) = response {
+                    status = response.status();
+                }
+            },
+            args.timeout_ms
+                .map_or(self.call_timeout, Duration::from_millis),
+            self.max_response_bytes,
+        )
+        .await
+        .map(|body| (status, body));
+
+        log_request("call_tool", started, &result);
+        (result, failure)
+    }
+
+    /// Make attempts at a request until one succeeds or the retry policy gives up, returning the
+    /// result of the last one. `paid` requests are only retried if they never reached the server.
+    async fn retrying<F>(
+        &self,
+        operation: &'static str,
+        paid: bool,
+        mut attempt: impl FnMut() -> F,
+    ) -> Result<Reply, ToolsError>
+    where
+        F: Future<Output = Attempt>,
+    {
+        let mut retries = 0;
+        let mut waited_retry_after = false;
+        loop {
+            if let Some(limiter) = &self.rate_limiter {
+                limiter.acquire().await?;
+            }
+            let (result, failure) = attempt().await;
+            retries += 1;
+            let Some(delay) = failure.as_ref().and_then(|failure| {
+                self.retry
+                    .delay(retries, failure, paid)
+                    .or_else(|| self.retry_after_delay(failure, paid, &mut waited_retry_after))
+            }) else {
+                let retry_after = match failure {
+                    Some(Failure::Status { retry_after, .. }) => retry_after,
+                    _ => None,
+                };
+                return result.map(|(status, body)| Reply {
+                    status,
+                    body,
+                    retry_after,
+                });
+            };
+
+            let delay_ms = delay.as_millis() as u64;
+            match &result {
+                Ok((status, _)) => tracing::warn!(
+                    target: targets::TOOLS,
+                    event = events::TOOL_RETRY,
+                    operation,
+                    attempt = retries,
+                    delay_ms,
+                    status = status.as_u16(),
+                    "Tools request failed, retrying"
+                ),
+                Err(e) => tracing::warn!(
+                    target: targets::TOOLS,
+                    event = events::TOOL_RETRY,
+                    operation,
+                    attempt = retries,
+                    delay_ms,
+                    error = %e,
+                    "Tools request failed, retrying"
+                ),
+            }
+            retry::sleep(delay).await;
+        }
+    }
+
+    /// The `Retry-After` of a 429 response, waited for once with a rate limiter if within its
+    /// `max_wait`, holding the other requests of the limiter meanwhile.
+    fn retry_after_delay(
+        &self,
+        failure: &Failure,
+        paid: bool,
+        waited: &mut bool,
+    ) -> Option<Duration> {
+        let limiter = self.rate_limiter.as_ref()?;
+        let Failure::Status {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            retry_after: Some(after),
+        } = failure
+        else {
+            return None;
+        };
+        if paid || *waited || *after > limiter.limit().max_wait {
+            return None;
+        }
+
+        *waited = true;
+        limiter.pause(*after);
+        Some(*after)
+    }
+}
+
+/// The result of an attempt at a request, and why it failed, if it did.
+type Attempt = (Result<(StatusCode, String), ToolsError>, Option<Failure>);
+
+/// The response to a request, once the retries are over.
+struct Reply {
+    status: StatusCode,
+    body: String,
+    /// The `Retry-After` of a failing response.
+    retry_after: Option<Duration>,
+}
+
+/// Parse the body of a successful response.
+fn parse_response<T: DeserializeOwned>(reply: Reply) -> Result<T, ToolsError> {
+    if !reply.status.is_success() {
+        return Err(ToolsError::from_status(
+            reply.status.as_u16(),
+            reply.body,
+            reply.retry_after,
+        ));
+    }
+
+    serde_json::from_str(&reply.body).map_err(ToolsError::InvalidResponse)
+}
+
+fn log_request<T>(operation: &'static str, started: Instant, result: &Result<T, ToolsError>) {
+    let duration_ms = started.elapsed().as_millis() as u64;
+
+    match result {
+        Ok(_) => tracing::debug!(
+            target: targets::TOOLS,
+            event = events::TOOL_REQUEST,
+            operation,
+            duration_ms,
+            outcome = outcomes::OK,
+            "Tools request"
+        ),
+        Err(e) => tracing::debug!(
+            target: targets::TOOLS,
+            event = events::TOOL_REQUEST,
+            operation,
+            duration_ms,
+            outcome = outcomes::ERROR,
+            error = %e,
+            "Tools request"
+        ),
+    }
+}
+
+/// Send a request and read the response body, all within the timeout.
+#[cfg(not(target_arch = "wasm32"))]
+async fn send_text(
+    client: &ApiClient,
+    request: RequestBuilder,
+    on_response: impl FnOnce(&reqwest::Result<Response>),
+    timeout: Duration,
+    limit: Option<usize>,
+) -> Result<String, ToolsError> {
+    let into_error = |e| ToolsError::from_request(e, timeout);
+
+    let response = client.send(request.timeout(timeout)).await;
+    on_response(&response);
+    let response = response.map_err(into_error)?;
+
+    read_text(client, response, limit, into_error).await
+}
+
+/// Read a response body, failing as soon as it exceeds `limit` bytes.
+///
+/// The body is counted after decompression, so a small compressed body can't expand past the limit.
+#[cfg(not(target_arch = "wasm32"))]
+async fn read_text(
+    client: &ApiClient,
+    mut response: Response,
+    limit: Option<usize>,
+    into_error: impl Fn(reqwest::Error) -> ToolsError,
+) -> Result<String, ToolsError> {
+    let url = response.url().clone();
+    let mut body = Vec::new();
+
+    while let Some(chunk) = response.chunk().await.map_err(&into_error)? {
+        if let Some(limit) = limit.filter(|&limit| body.len() + chunk.len() > limit) {
+            return Err(ToolsError::ResponseTooLarge(limit));
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    client.log_response_body(&url, &body);
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+/// Read a response body, failing if it exceeds `limit` bytes.
+///
+/// The browser decompresses and buffers the whole body before it's counted.
+#[cfg(target_arch = "wasm32")]
+async fn read_text(
+    client: &ApiClient,
+    response: Response,
+    limit: Option<usize>,
+    into_error: impl Fn(reqwest::Error) -> ToolsError,
+) -> Result<String, ToolsError> {
+    let url = response.url().clone();
+    let body = response.bytes().await.map_err(into_error)?;
+
+    match limit {
+        Some(limit) if body.len() > limit => Err(ToolsError::ResponseTooLarge(limit)),
+        _ => {
+            client.log_response_body(&url, &body);
+            Ok(String::from_utf8_lossy(&body).into_owned())
+        }
+    }
+}
+
+/// Send a request and read the response body, all within the timeout.
+///
+/// The wasm request builder has no timeout, the request is raced against a browser timer instead.
+#[cfg(target_arch = "wasm32")]
+async fn send_text(
+    client: &ApiClient,
+    request: RequestBuilder,
+    on_response: impl FnOnce(&reqwest::Result<Response>),
+    timeout: Duration,
+    limit: Option<usize>,
+) -> Result<String, ToolsError> {
+    use futures_util::future::{select, Either};
+    use gloo_timers::future::TimeoutFuture;
+
+    let response = Box::pin(async move {
+        let response = client.send(request).await;
+        on_response(&response);
+        let response = response?;
+        read_text(client, response, limit, ToolsError::from).await
+    });
+    let timer = TimeoutFuture::new(timeout.as_millis().try_into().unwrap_or(u32::MAX));
+
+    match select(response, timer).await {
+        Either::Left((result, _)) => result,
+        Either::Right(_) => Err(ToolsError::Timeout(timeout)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::ClientOptions, constants::USER_AGENT, logging::capture::Captured};
+    use serde_json::json;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    /// Serve one request with an empty JSON array and return the raw request head.
+    async fn capture_request() -> (String, tokio::task::JoinHandle<String>) {
+        serve_once(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\n[]".to_vec()).await
+    }
+
+    /// Serve one request with a raw response and return the raw request head.
+    async fn serve_once(response: Vec<u8>) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                let n = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream.write_all(&response).await.unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        (format!("http://{addr}"), server)
+    }
+
+    #[tokio::test]
+    async fn test_sends_user_agent() {
+        let (endpoint, server) = capture_request().await;
+        let config = UnifaiConfig {
+            agent_api_key: Some("test".to_string()),
+            backend_api_endpoint: endpoint,
+            ..UnifaiConfig::default()
+        };
+
+        let response = UnifaiClient::from_config(&config)
+            .unwrap()
+            .search_tools(&SearchToolsArgs {
+                query: "echo".to_string(),
+                limit: None,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(response, "[]");
+        assert!(server
+            .await
+            .unwrap()
+            .to_lowercase()
+            .contains(&format!("user-agent: {}", USER_AGENT.to_lowercase())));
+    }
+
+    #[tokio::test]
+    async fn test_custom_client_keeps_api_key() {
+        let (endpoint, server) = capture_request().await;
+        let config = UnifaiConfig {
+            agent_api_key: Some("test".to_string()),
+            backend_api_endpoint: endpoint,
+            ..UnifaiConfig::default()
+        };
+        let client = reqwest::Client::builder()
+            .user_agent("my-app")
+            .build()
+            .unwrap();
+
+        UnifaiClient::from_config(&config)
+            .unwrap()
+            .with_client(client)
+            .search_tools(&SearchToolsArgs {
+                query: "echo".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let request = server.await.unwrap().to_lowercase();
+        assert!(request.contains("user-agent: my-app"), "{request}");
+        assert!(request.contains("authorization: test"), "{request}");
+    }
+
+    #[test]
+    fn test_tools_share_client() {
+        let config = UnifaiConfig {
+            agent_api_key: Some("test".to_string()),
+            ..UnifaiConfig::default()
+        };
+        let client = UnifaiClient::from_config(&config)
+            .unwrap()
+            .with_call_timeout(Duration::from_secs(3));
+
+        let (search_tools, call_tool) = crate::tools::get_tools_with_client(client);
+        assert!(Arc::ptr_eq(
+            &search_tools.client().endpoints,
+            &call_tool.client().endpoints
+        ));
+        assert_eq!(call_tool.client().call_timeout, Duration::from_secs(3));
+    }
+
+    #[tokio::test]
+    async fn test_search_filters() {
+        let (endpoint, server) = capture_request().await;
+
+        client_with(endpoint, ClientOptions::default())
+            .search_tools(&SearchToolsArgs {
+                query: "swap".to_string(),
+                toolkit_id: Some(12),
+                free_only: Some(true),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let request = server.await.unwrap();
+        assert!(
+            request.starts_with("GET /actions/search?query=swap&toolkit_id=12&free_only=true "),
+            "{request}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_credentials() {
+        let (endpoint, server) = capture_request().await;
+
+        let principal = client_with(endpoint, ClientOptions::default())
+            .verify_credentials()
+            .await
+            .unwrap();
+
+        assert_eq!(principal, Principal::default());
+        assert!(server
+            .await
+            .unwrap()
+            .starts_with("GET /actions/search?query=&limit=1 "));
+
+        let (endpoint, _server) =
+            serve_once(b"HTTP/1.1 401 Unauthorized\r\ncontent-length: 0\r\n\r\n".to_vec()).await;
+        let error = client_with(endpoint, ClientOptions::default())
+            .verify_credentials()
+            .await
+            .unwrap_err();
+
+        assert!(matches!(
+            error,
+            CredentialsError::InvalidKey {
+                key: "agent_api_key",
+                status: 401
+            }
+        ));
+        assert!(error.to_string().contains("rejected the agent_api_key"));
+
+        let (endpoint, _server) =
+            serve_once(b"HTTP/1.1 502 Bad Gateway\r\ncontent-length: 0\r\n\r\n".to_vec()).await;
+        let error = client_with(endpoint, ClientOptions::default())
+            .verify_credentials()
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, CredentialsError::UnexpectedStatus(502)));
+    }
+
+    #[tokio::test]
+    async fn test_call_tool_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                connections.push(stream);
+            }
+        });
+
+        let config = UnifaiConfig {
+            agent_api_key: Some("test".to_string()),
+            backend_api_endpoint: format!("http://{addr}"),
+            call_timeout: Duration::from_millis(200),
+            ..UnifaiConfig::default()
+        };
+        let client = UnifaiClient::from_config(&config).unwrap();
+        let mut args = CallToolArgs {
+            action: "echo".to_string(),
+            payload: json!({}),
+            payment: None,
+            ..Default::default()
+        };
+
+        let result = client.call_tool(&args).await;
+        assert!(matches!(result, Err(ToolsError::Timeout(t)) if t == Duration::from_millis(200)));
+
+        let client = client.with_call_timeout(Duration::from_millis(100));
+        let result = client.call_tool(&args).await;
+        assert!(matches!(result, Err(ToolsError::Timeout(t)) if t == Duration::from_millis(100)));
+
+        // The timeout of the call takes precedence.
+        args.timeout_ms = Some(50);
+        let result = client.call_tool(&args).await;
+        assert!(matches!(result, Err(ToolsError::Timeout(t)) if t == Duration::from_millis(50)));
+        assert!(!serde_json::to_string(&args).unwrap().contains("timeout"));
+    }
+
+    #[tokio::test]
+    async fn test_call_tool_typed() {
+        let args = CallToolArgs {
+            action: "echo".to_string(),
+            payload: json!({}),
+            payment: None,
+            ..Default::default()
+        };
+        let body = r#"{"payload":"hi","payment":3}"#;
+        let (endpoint, _server) = serve_once(
+            format!(
+                "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{body}",
+                body.len()
+            )
+            .into(),
+        )
+        .await;
+        let response = client_with(endpoint, ClientOptions::default())
+            .call_tool_typed(&args)
+            .await
+            .unwrap();
+        assert_eq!(
+            response,
+            CallToolResponse {
+                payload: json!("hi"),
+                payment: Some(3),
+                error: None,
+            }
+        );
+
+        let body = r#"{"error":"Action not found"}"#;
+        let (endpoint, _server) = serve_once(
+            format!(
+                "HTTP/1.1 404 Not Found\r\ncontent-length: {}\r\n\r\n{body}",
+                body.len()
+            )
+            .into(),
+        )
+        .await;
+        let error = client_with(endpoint, ClientOptions::default())
+            .call_tool_typed(&args)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, ToolsError::Status { status: 404, body: b } if b == body));
+
+        let (endpoint, _server) =
+            serve_once(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\n[]".to_vec()).await;
+        let error = client_with(endpoint, ClientOptions::default())
+            .call_tool_typed(&args)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, ToolsError::InvalidResponse(_)));
+    }
+
+    #[tokio::test]
+    async fn test_search_tools_typed() {
+        let body = r#"[{"action":"Echo/1/echo","description":"Echo","payload":{},"rank":1}]"#;
+        let (endpoint, _server) = serve_once(
+            format!(
+                "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{body}",
+                body.len()
+            )
+            .into(),
+        )
+        .await;
+        let tools = client_with(endpoint, ClientOptions::default())
+            .search_tools_typed(&search_args())
+            .await
+            .unwrap();
+
+        assert_eq!(tools.len(), 1);
+        assert_eq!(tools[0].action, "Echo/1/echo");
+        assert_eq!(tools[0].description, "Echo");
+        assert_eq!(tools[0].payment, None);
+        assert_eq!(tools[0].extra["rank"], 1);
+
+        let (endpoint, _server) =
+            serve_once(b"HTTP/1.1 401 Unauthorized\r\ncontent-length: 0\r\n\r\n".to_vec()).await;
+        let error = client_with(endpoint, ClientOptions::default())
+            .search_tools_typed(&search_args())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            ToolsError::Unauthorized { status: 401, .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_failing_statuses() {
+        let error = |response: &'static [u8]| async move {
+            let (endpoint, _server) = serve_once(response.to_vec()).await;
+            client_with(endpoint, ClientOptions::default())
+                .call_tool_typed(&call_args(None))
+                .await
+                .unwrap_err()
+        };
+
+        let forbidden = error(b"HTTP/1.1 403 Forbidden\r\ncontent-length: 6\r\n\r\ndenied").await;
+        assert!(
+            matches!(forbidden, ToolsError::Unauthorized { status: 403, body } if body == "denied")
+        );
+
+        let limited = error(
+            b"HTTP/1.1 429 Too Many Requests\r\nretry-after: 7\r\ncontent-length: 4\r\n\r\nslow",
+        )
+        .await;
+        assert!(matches!(
+            limited,
+            ToolsError::RateLimited { retry_after: Some(d), .. } if d == Duration::from_secs(7)
+        ));
+
+        let failed =
+            error(b"HTTP/1.1 500 Internal Server Error\r\ncontent-length: 4\r\n\r\noops").await;
+        assert!(matches!(failed, ToolsError::Status { status: 500, body } if body == "oops"));
+    }
+
+    #[tokio::test]
+    async fn test_logs_tool_request() {
+        let captured = Captured::default();
+        let _guard = captured.install();
+        let (endpoint, server) = capture_request().await;
+        let config = UnifaiConfig {
+            agent_api_key: Some("test".to_string()),
+            backend_api_endpoint: endpoint,
+            ..UnifaiConfig::default()
+        };
+
+        UnifaiClient::from_config(&config)
+            .unwrap()
+            .search_tools(&SearchToolsArgs {
+                query: "echo".to_string(),
+                limit: None,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        server.await.unwrap();
+
+        let requests = captured.events(events::TOOL_REQUEST);
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].target, targets::TOOLS);
+        assert_eq!(requests[0].level, tracing::Level::DEBUG);
+        assert_eq!(requests[0].fields["operation"], "search_tools");
+        assert_eq!(requests[0].fields["outcome"], outcomes::OK);
+        assert!(requests[0].fields.contains_key("duration_ms"));
+    }
+
+    /// A response with a gzip-compressed body.
+    fn gzip_response(body: &[u8]) -> Vec<u8> {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(body).unwrap();
+        let body = encoder.finish().unwrap();
+
+        let mut response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-encoding: gzip\r\ncontent-length: {}\r\n\r\n",
+            body.len()
+        )
+        .into_bytes();
+        response.extend_from_slice(&body);
+        response
+    }
+
+    fn search_args() -> SearchToolsArgs {
+        SearchToolsArgs {
+            query: "echo".to_string(),
+            limit: None,
+            ..Default::default()
+        }
+    }
+
+    fn client_with(endpoint: String, options: ClientOptions) -> UnifaiClient {
+        UnifaiClient::from_config(&UnifaiConfig {
+            agent_api_key: Some("test".to_string()),
+            backend_api_endpoint: endpoint,
+            client: options,
+            ..UnifaiConfig::default()
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_gzip_response_is_decompressed() {
+        let body = json!([{ "action": "echo", "description": "Echo" }]).to_string();
+        let (endpoint, server) = serve_once(gzip_response(body.as_bytes())).await;
+
+        let response = client_with(endpoint, ClientOptions::default())
+            .search_tools(&search_args())
+            .await
+            .unwrap();
+
+        assert_eq!(response, body);
+        let request = server.await.unwrap().to_lowercase();
+        assert!(request.contains("accept-encoding: gzip, br"));
+    }
+
+    #[tokio::test]
+    async fn test_decompression_can_be_disabled() {
+        let (endpoint, server) = capture_request().await;
+        let options = ClientOptions {
+            decompression: false,
+            ..ClientOptions::default()
+        };
+
+        client_with(endpoint, options)
+            .search_tools(&search_args())
+            .await
+            .unwrap();
+
+        assert!(!server
+            .await
+            .unwrap()
+            .to_lowercase()
+            .contains("accept-encoding"));
+    }
+
+    #[tokio::test]
+    async fn test_response_cap_counts_decompressed_bytes() {
+        // About 1 KiB compressed, 1 MiB decompressed.
+        let (endpoint, _server) = serve_once(gzip_response(&vec![b' '; 1024 * 1024])).await;
+        let options = ClientOptions {
+            max_response_bytes: Some(64 * 1024),
+            ..ClientOptions::default()
+        };
+
+        let result = client_with(endpoint, options)
+            .search_tools(&search_args())
+            .await;
+
+        assert!(matches!(result, Err(ToolsError::ResponseTooLarge(65536))));
+    }
+
+    #[tokio::test]
+    async fn test_wire_logging_never_logs_secrets() {
+        let captured = Captured::default();
+        let _guard = captured.install();
+        let body = json!({ "apiKey": "secret-response", "content": "hello" }).to_string();
+        let response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let (endpoint, server) = serve_once(response.into_bytes()).await;
+
+        client_with(endpoint, ClientOptions::default())
+            .with_wire_logging(WireLogging::default().log_bodies(true))
+            .call_tool(&CallToolArgs {
+                action: "echo".to_string(),
+                payload: json!({ "password": "secret-request", "content": "hi" }),
+                payment: None,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        server.await.unwrap();
+
+        let requests = captured.events(events::HTTP_REQUEST);
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].fields["method"], "POST");
+        assert_eq!(requests[0].fields["status"], "200");
+
+        let bodies = captured.events(events::HTTP_BODY);
+        assert_eq!(bodies.len(), 2);
+        assert!(bodies[0].fields["body"].contains("\"hi\""));
+        assert!(bodies[1].fields["body"].contains("hello"));
+
+        for event in captured.all() {
+            for value in event.fields.values() {
+                assert!(!value.contains("secret-"), "{value}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_wire_logging_skips_bodies_by_default() {
+        let captured = Captured::default();
+        let _guard = captured.install();
+        let (endpoint, server) = capture_request().await;
+
+        client_with(endpoint, ClientOptions::default())
+            .search_tools(&search_args())
+            .await
+            .unwrap();
+        server.await.unwrap();
+
+        assert_eq!(captured.events(events::HTTP_REQUEST).len(), 1);
+        assert!(captured.events(events::HTTP_BODY).is_empty());
+    }
+
+    /// A regional backend answering searches with `[]`, or with 503 while it's marked down.
+    struct Region {
+        endpoint: String,
+        up: Arc<AtomicBool>,
+        requests: Arc<AtomicUsize>,
+        server: tokio::task::JoinHandle<()>,
+    }
+
+    impl Region {
+        async fn start() -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let endpoint = format!("http://{}", listener.local_addr().unwrap());
+            let up = Arc::new(AtomicBool::new(true));
+            let requests = Arc::new(AtomicUsize::new(0));
+
+            let server = tokio::spawn({
+                let up = up.clone();
+                let requests = requests.clone();
+                async move {
+                    while let Ok((mut stream, _)) = listener.accept().await {
+                        let mut request = Vec::new();
+                        let mut buf = [0; 1024];
+                        while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                            let n = stream.read(&mut buf).await.unwrap();
+                            request.extend_from_slice(&buf[..n]);
+                        }
+                        requests.fetch_add(1, Ordering::SeqCst);
+                        let response: &[u8] = if up.load(Ordering::SeqCst) {
+                            b"HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-length: 2\r\n\r\n[]"
+                        } else {
+                            b"HTTP/1.1 503 Service Unavailable\r\nconnection: close\r\ncontent-length: 0\r\n\r\n"
+                        };
+                        stream.write_all(response).await.unwrap();
+                    }
+                }
+            });
+
+            Self {
+                endpoint,
+                up,
+                requests,
+                server,
+            }
+        }
+
+        fn set_up(&self, up: bool) {
+            self.up.store(up, Ordering::SeqCst);
+        }
+
+        fn requests(&self) -> usize {
+            self.requests.load(Ordering::SeqCst)
+        }
+    }
+
+    fn regional_client(primary: &Region, secondary: &Region, cooldown: Duration) -> UnifaiClient {
+        UnifaiClient::from_config(&UnifaiConfig {
+            agent_api_key: Some("test".to_string()),
+            backend_api_endpoint: primary.endpoint.clone(),
+            backend_api_fallback_endpoints: vec![secondary.endpoint.clone()],
+            failover_threshold: 2,
+            failover_cooldown: cooldown,
+            ..UnifaiConfig::default()
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_fails_over_when_primary_is_unreachable() {
+        let mut primary = Region::start().await;
+        let secondary = Region::start().await;
+        let client = regional_client(&primary, &secondary, Duration::from_secs(60));
+        let captured = Captured::default();
+        let _guard = captured.install();
+
+        assert_eq!(client.search_tools(&search_args()).await.unwrap(), "[]");
+        assert_eq!(primary.requests(), 1);
+
+        // Taken down: connections are refused from now on.
+        primary.server.abort();
+        let _ = (&mut primary.server).await;
+        for _ in 0..2 {
+            assert!(client.search_tools(&search_args()).await.is_err());
+        }
+
+        assert_eq!(client.active_endpoint(), secondary.endpoint);
+        assert_eq!(
+            client.clone().search_tools(&search_args()).await.unwrap(),
+            "[]"
+        );
+        assert_eq!(secondary.requests(), 1);
+
+        let switched = captured.events(events::ENDPOINT_SWITCHED);
+        assert_eq!(switched.len(), 1);
+        assert_eq!(switched[0].fields["from"], primary.endpoint);
+        assert_eq!(switched[0].fields["to"], secondary.endpoint);
+    }
+
+    #[tokio::test]
+    async fn test_sticks_to_fallback_until_cooldown() {
+        let primary = Region::start().await;
+        let secondary = Region::start().await;
+        let client = regional_client(&primary, &secondary, Duration::from_millis(300));
+
+        // A single 5xx isn't enough to fail over.
+        primary.set_up(false);
+        client.search_tools(&search_args()).await.unwrap();
+        primary.set_up(true);
+        client.search_tools(&search_args()).await.unwrap();
+        assert_eq!(client.active_endpoint(), primary.endpoint);
+
+        primary.set_up(false);
+        for _ in 0..2 {
+            client.search_tools(&search_args()).await.unwrap();
+        }
+        assert_eq!(client.active_endpoint(), secondary.endpoint);
+
+        // Sticky: the primary recovered, the requests stay on the secondary.
+        primary.set_up(true);
+        client.search_tools(&search_args()).await.unwrap();
+        assert_eq!((primary.requests(), secondary.requests()), (4, 1));
+
+        // After the cool-down, the primary is tried again and left at its first failure.
+        primary.set_up(false);
+        tokio::time::sleep(Duration::from_millis(350)).await;
+        client.search_tools(&search_args()).await.unwrap();
+        assert_eq!(client.active_endpoint(), secondary.endpoint);
+        assert_eq!((primary.requests(), secondary.requests()), (5, 1));
+
+        // Healthy after the next cool-down, it stays active.
+        primary.set_up(true);
+        tokio::time::sleep(Duration::from_millis(350)).await;
+        client.search_tools(&search_args()).await.unwrap();
+        client.search_tools(&search_args()).await.unwrap();
+        assert_eq!(client.active_endpoint(), primary.endpoint);
+        assert_eq!((primary.requests(), secondary.requests()), (7, 1));
+    }
+
+    /// Serve `responses` in turn, one per connection, counting the requests.
+    async fn serve_in_turn(responses: Vec<&'static [u8]>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        tokio::spawn({
+            let requests = requests.clone();
+            async move {
+                for response in responses {
+                    let (mut stream, _) = listener.accept().await.unwrap();
+                    let mut request = Vec::new();
+                    let mut buf = [0; 1024];
+                    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                        let n = stream.read(&mut buf).await.unwrap();
+                        request.extend_from_slice(&buf[..n]);
+                    }
+                    requests.fetch_add(1, Ordering::SeqCst);
+                    stream.write_all(response).await.unwrap();
+                }
+            }
+        });
+
+        (endpoint, requests)
+    }
+
+    const UNAVAILABLE: &[u8] =
+        b"HTTP/1.1 503 Service Unavailable\r\nconnection: close\r\nretry-after: 0\r\ncontent-length: 4\r\n\r\ndown";
+    const CALLED: &[u8] =
+        b"HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-length: 16\r\n\r\n{\"payload\":\"hi\"}";
+    const TOO_MANY: &[u8] =
+        b"HTTP/1.1 429 Too Many Requests\r\nconnection: close\r\nretry-after: 0\r\ncontent-length: 4\r\n\r\nslow";
+
+    fn retrying_client(endpoint: String) -> UnifaiClient {
+        client_with(endpoint, ClientOptions::default()).with_retry(RetryPolicy {
+            max_retries: 2,
+            base_delay: Duration::from_millis(10),
+            ..RetryPolicy::default()
+        })
+    }
+
+    fn call_args(payment: Option<u64>) -> CallToolArgs {
+        CallToolArgs {
+            action: "echo".to_string(),
+            payload: json!({}),
+            payment,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retries_transient_failures() {
+        let captured = Captured::default();
+        let _guard = captured.install();
+        let (endpoint, requests) = serve_in_turn(vec![
+            UNAVAILABLE,
+            b"HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-length: 2\r\n\r\n[]",
+        ])
+        .await;
+
+        let response = retrying_client(endpoint)
+            .search_tools_typed(&search_args())
+            .await
+            .unwrap();
+
+        assert!(response.is_empty());
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        let retries = captured.events(events::TOOL_RETRY);
+        assert_eq!(retries.len(), 1);
+        assert_eq!(retries[0].fields["status"], "503");
+        assert_eq!(retries[0].fields["delay_ms"], "0");
+
+        let (endpoint, requests) = serve_in_turn(vec![UNAVAILABLE, CALLED]).await;
+        let response = retrying_client(endpoint)
+            .call_tool_typed(&call_args(None))
+            .await
+            .unwrap();
+
+        assert_eq!(response.payload, "hi");
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_retries() {
+        let (endpoint, requests) = serve_in_turn(vec![UNAVAILABLE; 3]).await;
+
+        let error = retrying_client(endpoint)
+            .search_tools_typed(&search_args())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, ToolsError::Status { status: 503, .. }));
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_paid_calls_are_not_sent_twice() {
+        let (endpoint, requests) = serve_in_turn(vec![UNAVAILABLE, CALLED]).await;
+
+        let error = retrying_client(endpoint)
+            .call_tool_typed(&call_args(Some(10)))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, ToolsError::Status { status: 503, .. }));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // Refused connections never reached the server.
+        let endpoint = {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            format!("http://{}", listener.local_addr().unwrap())
+        };
+        let captured = Captured::default();
+        let _guard = captured.install();
+
+        let error = retrying_client(endpoint)
+            .call_tool(&call_args(Some(10)))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, ToolsError::Network(_)));
+        assert_eq!(captured.events(events::TOOL_RETRY).len(), 2);
+    }
+
+    fn limited_client(endpoint: String, limit: RateLimit) -> UnifaiClient {
+        client_with(endpoint, ClientOptions::default()).with_rate_limit(limit)
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_waits_for_retry_after_once() {
+        let (endpoint, requests) = serve_in_turn(vec![TOO_MANY, CALLED]).await;
+        let response = limited_client(endpoint, RateLimit::new(100.0, 10))
+            .call_tool_typed(&call_args(None))
+            .await
+            .unwrap();
+
+        assert_eq!(response.payload, "hi");
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        let (endpoint, requests) = serve_in_turn(vec![TOO_MANY, TOO_MANY, CALLED]).await;
+        let error = limited_client(endpoint, RateLimit::new(100.0, 10))
+            .call_tool_typed(&call_args(None))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, ToolsError::RateLimited { .. }));
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_over_max_wait_fails() {
+        let (endpoint, requests) = serve_in_turn(vec![CALLED]).await;
+        let limit = RateLimit {
+            max_wait: Duration::from_millis(10),
+            ..RateLimit::new(0.1, 1)
+        };
+        let (search_tools, call_tool) =
+            crate::tools::get_tools_with_client(limited_client(endpoint, limit));
+
+        call_tool.call_typed(&call_args(None)).await.unwrap();
+        let error = search_tools.search(&search_args()).await.unwrap_err();
+
+        assert!(matches!(
+            error,
+            ToolsError::RateLimited { retry_after: Some(wait), .. } if wait > Duration::from_secs(9)
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+}
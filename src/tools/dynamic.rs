@@ -0,0 +1,453 @@
+use crate::logging::{events, targets};
+use rig::{
+    embeddings::{EmbeddingError, EmbeddingModel, ToolSchema},
+    vector_store::{VectorStoreError, VectorStoreIndex},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Version of the cache file format, bumped on incompatible changes.
+const CACHE_VERSION: u32 = 1;
+
+#[derive(Debug, thiserror::Error)]
+pub enum DynamicToolSetError {
+    #[error("IoError: {0}")]
+    IoError(#[from] io::Error),
+
+    #[error("EmbeddingError: {0}")]
+    EmbeddingError(#[from] EmbeddingError),
+}
+
+/// An action of the catalog with the embeddings of its documents.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ActionDocument {
+    /// The name of the action.
+    pub name: String,
+    /// The texts embedded for the action, see [ToolEmbedding::embedding_docs](rig::tool::ToolEmbedding::embedding_docs).
+    pub docs: Vec<String>,
+    /// The hash of the docs, telling whether they changed since they were embedded.
+    pub hash: String,
+    /// The embedding of each doc.
+    pub vectors: Vec<Vec<f64>>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct CacheFile {
+    version: u32,
+    ndims: usize,
+    actions: Vec<ActionDocument>,
+}
+
+/// The embedded actions of a catalog, used to select the tools relevant to a prompt.
+///
+/// Embedding a whole catalog on every start is slow and costly, so the embeddings are persisted to
+/// a file, and only the actions whose docs changed are embedded again when it's loaded. It is a
+/// [VectorStoreIndex] whose ids are the action names, to be given to
+/// [AgentBuilder::dynamic_tools](rig::agent::AgentBuilder::dynamic_tools) with the tool set, or
+/// its [documents](Self::documents) can be inserted into another vector store.
+///
+/// # Example
+///
+/// ```ignore
+/// let index = DynamicToolSet::load_or_build("tools.json", toolset.schemas()?, model.clone()).await?;
+///
+/// let agent = openai.agent("gpt-4o").dynamic_tools(2, index, toolset).build();
+/// ```
+#[derive(Clone, Debug)]
+pub struct DynamicToolSet<M: EmbeddingModel> {
+    model: M,
+    documents: BTreeMap<String, ActionDocument>,
+}
+
+impl<M: EmbeddingModel> DynamicToolSet<M> {
+    /// Load the embeddings persisted at `path`, embed the actions of `catalog` that are new or
+    /// whose docs changed, drop the ones no longer in the catalog, and persist the result.
+    ///
+    /// A missing or unreadable file, or one embedded with a model of another size, is rebuilt from
+    /// scratch.
+    pub async fn load_or_build(
+        path: impl AsRef<Path>,
+        catalog: impl IntoIterator<Item = ToolSchema>,
+        model: M,
+    ) -> Result<Self, DynamicToolSetError> {
+        let path = path.as_ref();
+        let mut cached = read_cache(path, model.ndims());
+        let catalog: Vec<_> = catalog.into_iter().collect();
+
+        let mut documents = BTreeMap::new();
+        let mut stale = Vec::new();
+        for schema in catalog {
+            let hash = content_hash(&schema.embedding_docs);
+            match cached.remove(&schema.name) {
+                Some(document) if document.hash == hash => {
+                    documents.insert(schema.name, document);
+                }
+                _ => stale.push((schema, hash)),
+            }
+        }
+        let (reused, evicted) = (documents.len(), cached.len());
+
+        let texts: Vec<String> = stale
+            .iter()
+            .flat_map(|(schema, _)| schema.embedding_docs.iter().cloned())
+            .collect();
+        let mut vectors = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(M::MAX_DOCUMENTS.max(1)) {
+            let embeddings = model.embed_texts(batch.to_vec()).await?;
+            vectors.extend(embeddings.into_iter().map(|embedding| embedding.vec));
+        }
+
+        let mut vectors = vectors.into_iter();
+        let embedded = stale.len();
+        for (schema, hash) in stale {
+            let document = ActionDocument {
+                vectors: vectors.by_ref().take(schema.embedding_docs.len()).collect(),
+                name: schema.name,
+                docs: schema.embedding_docs,
+                hash,
+            };
+            documents.insert(document.name.clone(), document);
+        }
+
+        let toolset = Self { model, documents };
+        toolset.save(path)?;
+
+        tracing::info!(
+            target: targets::TOOLS,
+            event = events::TOOL_EMBEDDINGS_LOADED,
+            reused,
+            embedded,
+            evicted,
+            "Tool embeddings loaded"
+        );
+
+        Ok(toolset)
+    }
+
+    /// The embedded actions, sorted by name.
+    pub fn documents(&self) -> impl Iterator<Item = &ActionDocument> {
+        self.documents.values()
+    }
+
+    fn save(&self, path: &Path) -> io::Result<()> {
+        let cache = CacheFile {
+            version: CACHE_VERSION,
+            ndims: self.model.ndims(),
+            actions: self.documents.values().cloned().collect(),
+        };
+
+        // Write a sibling file first, so a crash never leaves a truncated cache behind.
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        let partial = PathBuf::from(partial);
+        fs::write(&partial, serde_json::to_vec(&cache)?)?;
+        fs::rename(partial, path)
+    }
+
+    /// The actions ranked by the best similarity of their docs to `query`.
+    async fn rank(
+        &self,
+        query: &str,
+        n: usize,
+    ) -> Result<Vec<(f64, &ActionDocument)>, VectorStoreError> {
+        let query = self.model.embed_text(query).await?.vec;
+
+        let mut ranked: Vec<_> = self
+            .documents
+            .values()
+            .filter_map(|document| {
+                let score = document
+                    .vectors
+                    .iter()
+                    .map(|vector| cosine_similarity(&query, vector))
+                    .reduce(f64::max)?;
+                Some((score, document))
+            })
+            .collect();
+        ranked.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        ranked.truncate(n);
+
+        Ok(ranked)
+    }
+}
+
+impl<M: EmbeddingModel> VectorStoreIndex for DynamicToolSet<M> {
+    async fn top_n<T: for<'a> Deserialize<'a> + Send>(
+        &self,
+        query: &str,
+        n: usize,
+    ) -> Result<Vec<(f64, String, T)>, VectorStoreError> {
+        self.rank(query, n)
+            .await?
+            .into_iter()
+            .map(|(score, document)| {
+                let doc = json!({ "name": document.name, "embedding_docs": document.docs });
+                Ok((score, document.name.clone(), serde_json::from_value(doc)?))
+            })
+            .collect()
+    }
+
+    async fn top_n_ids(
+        &self,
+        query: &str,
+        n: usize,
+    ) -> Result<Vec<(f64, String)>, VectorStoreError> {
+        Ok(self
+            .rank(query, n)
+            .await?
+            .into_iter()
+            .map(|(score, document)| (score, document.name.clone()))
+            .collect())
+    }
+}
+
+/// The cached documents by name, empty if the cache can't be used.
+fn read_cache(path: &Path, ndims: usize) -> HashMap<String, ActionDocument> {
+    let cache = match fs::read(path) {
+        Ok(content) => serde_json::from_slice::<CacheFile>(&content).map_err(io::Error::from),
+        Err(e) => Err(e),
+    };
+
+    match cache {
+        Ok(cache) if cache.version == CACHE_VERSION && cache.ndims == ndims => cache
+            .actions
+            .into_iter()
+            .map(|document| (document.name.clone(), document))
+            .collect(),
+        Ok(_) => HashMap::new(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+        Err(e) => {
+            tracing::warn!(
+                target: targets::TOOLS,
+                event = events::TOOL_EMBEDDINGS_CACHE_IGNORED,
+                path = %path.display(),
+                error = %e,
+                "Ignoring unreadable tool embeddings cache"
+            );
+            HashMap::new()
+        }
+    }
+}
+
+/// A hash of the docs that is stable across builds and Rust versions, unlike the std hashers.
+fn content_hash(docs: &[String]) -> String {
+    // FNV-1a, 64 bits.
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for doc in docs {
+        // Separate the docs, so ["ab"] and ["a", "b"] differ.
+        for byte in doc.bytes().chain([0xff]) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    format!("{hash:016x}")
+}
+
+fn cosine_similarity(a: &[f64], b: &[f64]) -> f64 {
+    let dot: f64 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norms =
+        a.iter().map(|x| x * x).sum::<f64>().sqrt() * b.iter().map(|x| x * x).sum::<f64>().sqrt();
+
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rig::embeddings::Embedding;
+    use std::sync::{Arc, Mutex};
+
+    /// Embeds a text as the counts of the letters `a`, `b` and `c`, recording the texts embedded.
+    #[derive(Clone, Default)]
+    struct FakeEmbedder {
+        embedded: Arc<Mutex<Vec<String>>>,
+        ndims: usize,
+    }
+
+    impl FakeEmbedder {
+        fn new() -> Self {
+            Self {
+                ndims: 3,
+                ..Self::default()
+            }
+        }
+
+        fn take_embedded(&self) -> Vec<String> {
+            std::mem::take(&mut *self.embedded.lock().unwrap())
+        }
+    }
+
+    impl EmbeddingModel for FakeEmbedder {
+        const MAX_DOCUMENTS: usize = 2;
+
+        fn ndims(&self) -> usize {
+            self.ndims
+        }
+
+        async fn embed_texts(
+            &self,
+            texts: impl IntoIterator<Item = String> + Send,
+        ) -> Result<Vec<Embedding>, EmbeddingError> {
+            let texts: Vec<String> = texts.into_iter().collect();
+            assert!(texts.len() <= Self::MAX_DOCUMENTS);
+            self.embedded.lock().unwrap().extend(texts.iter().cloned());
+
+            Ok(texts
+                .into_iter()
+                .map(|text| Embedding {
+                    vec: ['a', 'b', 'c']
+                        .iter()
+                        .map(|letter| text.matches(*letter).count() as f64)
+                        .collect(),
+                    document: text,
+                })
+                .collect())
+        }
+    }
+
+    fn schema(name: &str, docs: &[&str]) -> ToolSchema {
+        ToolSchema {
+            name: name.to_string(),
+            context: serde_json::Value::Null,
+            embedding_docs: docs.iter().map(|doc| doc.to_string()).collect(),
+        }
+    }
+
+    fn cache_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("unifai-sdk-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn names<M: EmbeddingModel>(toolset: &DynamicToolSet<M>) -> Vec<&str> {
+        toolset
+            .documents()
+            .map(|document| document.name.as_str())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_incremental_rebuild() {
+        let path = cache_path("incremental.json");
+        let model = FakeEmbedder::new();
+
+        let catalog = vec![
+            schema("swap", &["swap aaa", "exchange a"]),
+            schema("bridge", &["bridge bbb"]),
+            schema("stake", &["stake ccc"]),
+        ];
+        let toolset = DynamicToolSet::load_or_build(&path, catalog.clone(), model.clone())
+            .await
+            .unwrap();
+        assert_eq!(names(&toolset), ["bridge", "stake", "swap"]);
+        assert_eq!(model.take_embedded().len(), 4);
+
+        // Nothing changed, nothing is embedded again.
+        let reloaded = DynamicToolSet::load_or_build(&path, catalog, model.clone())
+            .await
+            .unwrap();
+        assert!(model.take_embedded().is_empty());
+        assert_eq!(
+            reloaded.documents().collect::<Vec<_>>(),
+            toolset.documents().collect::<Vec<_>>()
+        );
+
+        // Only the changed and the new actions are embedded, the removed one is evicted.
+        let catalog = vec![
+            schema("swap", &["swap aaa", "exchange a"]),
+            schema("bridge", &["bridge bbb across chains"]),
+            schema("lend", &["lend cc"]),
+        ];
+        let toolset = DynamicToolSet::load_or_build(&path, catalog, model.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            model.take_embedded(),
+            ["bridge bbb across chains", "lend cc"]
+        );
+        assert_eq!(names(&toolset), ["bridge", "lend", "swap"]);
+
+        let cache: CacheFile = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        let cached: Vec<_> = cache.actions.iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(cached, ["bridge", "lend", "swap"]);
+    }
+
+    #[tokio::test]
+    async fn test_rebuilds_unusable_cache() {
+        let path = cache_path("unusable.json");
+        let catalog = vec![schema("swap", &["swap aaa"])];
+
+        fs::write(&path, "not json").unwrap();
+        let model = FakeEmbedder::new();
+        DynamicToolSet::load_or_build(&path, catalog.clone(), model.clone())
+            .await
+            .unwrap();
+        assert_eq!(model.take_embedded().len(), 1);
+
+        // Vectors of another model can't be compared to the new ones.
+        let model = FakeEmbedder {
+            ndims: 4,
+            ..FakeEmbedder::default()
+        };
+        DynamicToolSet::load_or_build(&path, catalog, model.clone())
+            .await
+            .unwrap();
+        assert_eq!(model.take_embedded().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_top_n_ids() {
+        let path = cache_path("top_n.json");
+        let catalog = vec![
+            schema("swap", &["swap aaa", "exchange"]),
+            schema("bridge", &["bridge bbb"]),
+            schema("stake", &["stake ccc"]),
+        ];
+        let toolset = DynamicToolSet::load_or_build(&path, catalog, FakeEmbedder::new())
+            .await
+            .unwrap();
+
+        let ids: Vec<_> = toolset
+            .top_n_ids("cc", 2)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(_, id)| id)
+            .collect();
+        assert_eq!(ids[0], "stake");
+        assert_eq!(ids.len(), 2);
+
+        let top: Vec<(f64, String, serde_json::Value)> = toolset.top_n("aa", 1).await.unwrap();
+        assert_eq!(top[0].1, "swap");
+        assert_eq!(
+            top[0].2,
+            json!({ "name": "swap", "embedding_docs": ["swap aaa", "exchange"] })
+        );
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let docs = |docs: &[&str]| docs.iter().map(|d| d.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            content_hash(&docs(&["swap"])),
+            content_hash(&docs(&["swap"]))
+        );
+        assert_ne!(
+            content_hash(&docs(&["ab"])),
+            content_hash(&docs(&["a", "b"]))
+        );
+    }
+}
@@ -0,0 +1,73 @@
+use std::time::Duration;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ToolsError {
+    #[error("NetworkError: {0}")]
+    Network(reqwest::Error),
+
+    #[error("TimeoutError: request timed out after {0:?}")]
+    Timeout(Duration),
+
+    /// The response body exceeded [ClientOptions::max_response_bytes](crate::config::ClientOptions).
+    #[error("ResponseTooLarge: response body exceeded {0} bytes")]
+    ResponseTooLarge(usize),
+
+    /// The server presented none of the pinned keys, see
+    /// [ClientOptions::pin_certificates](crate::config::ClientOptions).
+    #[error("CertificatePinMismatch: {endpoint} presented none of the pinned keys")]
+    CertificatePinMismatch { endpoint: String },
+
+    /// The server refused the agent API key, with a 401 or 403 status.
+    #[error("Unauthorized: status {status}: {body}")]
+    Unauthorized { status: u16, body: String },
+
+    /// The server answered with a 429 status, asking to wait `retry_after` if it said how long,
+    /// or the [RateLimiter](super::RateLimiter) of the client would have had the request wait
+    /// `retry_after`, longer than its `max_wait`.
+    #[error("RateLimited: {body}")]
+    RateLimited {
+        retry_after: Option<Duration>,
+        body: String,
+    },
+
+    /// The server answered with another failing status, see [UnifaiClient::call_tool_typed](super::UnifaiClient::call_tool_typed).
+    #[error("HttpError: status {status}: {body}")]
+    Status { status: u16, body: String },
+
+    /// The response body isn't of the expected shape.
+    #[error("InvalidResponse: {0}")]
+    InvalidResponse(serde_json::Error),
+}
+
+impl ToolsError {
+    /// The error of a response with a failing `status`.
+    pub(crate) fn from_status(status: u16, body: String, retry_after: Option<Duration>) -> Self {
+        match status {
+            401 | 403 => Self::Unauthorized { status, body },
+            429 => Self::RateLimited { retry_after, body },
+            _ => Self::Status { status, body },
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn from_request(e: reqwest::Error, timeout: Duration) -> Self {
+        if e.is_timeout() {
+            Self::Timeout(timeout)
+        } else {
+            e.into()
+        }
+    }
+}
+
+impl From<reqwest::Error> for ToolsError {
+    fn from(e: reqwest::Error) -> Self {
+        #[cfg(feature = "rustls")]
+        if crate::pinning::is_pin_mismatch(&e) {
+            return Self::CertificatePinMismatch {
+                endpoint: crate::pinning::endpoint(&e),
+            };
+        }
+
+        Self::Network(e)
+    }
+}
@@ -0,0 +1,123 @@
+//! Failover of the tools between the backend API endpoint and its fallbacks.
+//!
+//! Requests go to the active endpoint. After [UnifaiConfig::failover_threshold] consecutive
+//! connection errors or 5xx responses, the next endpoint becomes active, and stays so even once
+//! the primary one recovers. After [UnifaiConfig::failover_cooldown] away from the primary
+//! endpoint, it's tried again, and left at its first failure.
+
+use crate::{
+    config::UnifaiConfig,
+    logging::{events, targets},
+};
+use reqwest::Response;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+use std::{sync::Mutex, time::Duration};
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+#[derive(Debug)]
+pub(crate) struct Endpoints {
+    urls: Vec<String>,
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    active: usize,
+    failures: u32,
+    /// When the primary endpoint was left.
+    left_primary: Option<Instant>,
+    /// Whether the primary endpoint is tried again after the cool-down.
+    probing: bool,
+}
+
+impl Endpoints {
+    pub(crate) fn new(config: &UnifaiConfig) -> Self {
+        Self {
+            urls: config
+                .backend_api_endpoints()
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            threshold: config.failover_threshold.max(1),
+            cooldown: config.failover_cooldown,
+            state: Mutex::default(),
+        }
+    }
+
+    /// The index and the URL of the endpoint to send the next request to.
+    pub(crate) fn pick(&self) -> (usize, &str) {
+        let mut state = self.state.lock().unwrap();
+        if state
+            .left_primary
+            .is_some_and(|left| left.elapsed() >= self.cooldown)
+        {
+            self.switch(&mut state, 0);
+            state.probing = true;
+        }
+
+        (state.active, &self.urls[state.active])
+    }
+
+    /// The URL of the endpoint requests are sent to.
+    pub(crate) fn active(&self) -> &str {
+        &self.urls[self.state.lock().unwrap().active]
+    }
+
+    /// Record the outcome of a request sent to the endpoint `index`, ignored if another endpoint
+    /// became active since.
+    pub(crate) fn record(&self, index: usize, response: &reqwest::Result<Response>) {
+        let mut state = self.state.lock().unwrap();
+        if index != state.active {
+            return;
+        }
+
+        if is_healthy(response) {
+            state.failures = 0;
+            state.probing = false;
+            return;
+        }
+
+        state.failures += 1;
+        if state.probing || state.failures >= self.threshold {
+            self.switch(&mut state, (index + 1) % self.urls.len());
+        }
+    }
+
+    fn switch(&self, state: &mut State, to: usize) {
+        let from = state.active;
+        state.failures = 0;
+        state.probing = false;
+        if from == to {
+            return;
+        }
+
+        tracing::warn!(
+            target: targets::TOOLS,
+            event = events::ENDPOINT_SWITCHED,
+            from = self.urls[from],
+            to = self.urls[to],
+            "Switched backend API endpoint"
+        );
+        state.active = to;
+        state.left_primary = match (from, to) {
+            (_, 0) => None,
+            (0, _) => Some(Instant::now()),
+            _ => state.left_primary,
+        };
+    }
+}
+
+fn is_healthy(response: &reqwest::Result<Response>) -> bool {
+    match response {
+        Ok(response) => !response.status().is_server_error(),
+        #[cfg(not(target_arch = "wasm32"))]
+        Err(e) => !e.is_connect(),
+        // Requests only fail in the browser when they can't reach the server.
+        #[cfg(target_arch = "wasm32")]
+        Err(_) => false,
+    }
+}
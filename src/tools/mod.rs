@@ -5,16 +5,71 @@
 //! See examples/openai_agent.rs
 //!
 //! ```no_run
-#![doc = include_str!("../../examples/openai_agent.rs")]
+#![cfg_attr(feature = "rig", doc = include_str!("../../examples/openai_agent.rs"))]
 //! ```
 
+use crate::config::{ConfigError, UnifaiConfig};
+
+#[cfg(feature = "blocking")]
+pub mod blocking;
+
 mod call_tool;
 pub use call_tool::*;
 
+mod client;
+pub use client::*;
+
+#[cfg(feature = "rig")]
+mod dynamic;
+#[cfg(feature = "rig")]
+pub use dynamic::*;
+
+mod errors;
+pub use errors::*;
+
+mod failover;
+
+mod output;
+pub use output::{OutputPolicy, OutputStrategy};
+
+mod rate_limit;
+pub use rate_limit::{RateLimit, RateLimiter};
+
+mod retry;
+pub use retry::RetryPolicy;
+
 mod search_tools;
 pub use search_tools::*;
 
+#[cfg(feature = "rig")]
+mod toolset;
+#[cfg(feature = "rig")]
+pub use toolset::{DynamicUnifaiTools, UnifaiTool};
+
+#[cfg(feature = "tower")]
+mod service;
+#[cfg(feature = "tower")]
+pub use service::*;
+
 /// Returns two essential tools to integrate Unifai with your agent.
+///
+/// The tools share one HTTP client, see [get_tools_with_client].
 pub fn get_tools(api_key: &str) -> (SearchTools, CallTool) {
-    (SearchTools::new(api_key), CallTool::new(api_key))
+    get_tools_with_client(UnifaiClient::new(api_key))
+}
+
+/// Returns the two essential tools built from a config, which must contain the agent API key.
+pub fn get_tools_from_config(
+    config: &UnifaiConfig,
+) -> Result<(SearchTools, CallTool), ConfigError> {
+    Ok(get_tools_with_client(UnifaiClient::from_config(config)?))
+}
+
+/// Returns the two essential tools using `client`, configured once for both, such as with
+/// [UnifaiClient::with_client] or [UnifaiClient::with_call_timeout].
+///
+/// The tools share the connection pool and the active endpoint of `client`, so creating them per
+/// request, as per-request agents do, opens no new connections.
+pub fn get_tools_with_client(client: UnifaiClient) -> (SearchTools, CallTool) {
+    (client.clone().into(), client.into())
 }
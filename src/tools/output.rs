@@ -0,0 +1,211 @@
+//! Keeping the outputs of the rig tools within the context window of the model, see
+//! [OutputPolicy].
+
+use serde_json::Value;
+
+/// Strings are never shortened below this many characters by [OutputStrategy::SummarizeJson].
+const MIN_STRING_CHARS: usize = 32;
+
+/// How an output over [OutputPolicy::max_chars] is cut down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputStrategy {
+    /// Cut the output at the limit, which may leave invalid JSON.
+    Truncate,
+    /// Cut the output at the limit, followed by a notice telling how much was cut.
+    TruncateWithNotice,
+    /// Keep the output valid JSON: arrays are cut to their first `max_items` items, fewer while
+    /// it's over the limit, then long strings are shortened, each noting how much was omitted.
+    /// A `truncated` field, or a last item for an array, tells what was elided.
+    ///
+    /// Outputs that aren't JSON are cut as with [OutputStrategy::TruncateWithNotice].
+    SummarizeJson { max_items: usize },
+}
+
+/// The longest output a tool gives the model, set with
+/// [CallTool::with_output_policy](super::CallTool::with_output_policy) or
+/// [SearchTools::with_output_policy](super::SearchTools::with_output_policy).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutputPolicy {
+    /// The longest output, in characters.
+    pub max_chars: usize,
+    pub strategy: OutputStrategy,
+}
+
+impl OutputPolicy {
+    /// Outputs of up to `max_chars` characters, cut with a notice beyond.
+    pub fn new(max_chars: usize) -> Self {
+        Self {
+            max_chars,
+            strategy: OutputStrategy::TruncateWithNotice,
+        }
+    }
+
+    pub fn with_strategy(mut self, strategy: OutputStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// `output` cut down to the policy, if it's over the limit.
+    pub fn apply(&self, output: String) -> String {
+        if output.chars().count() <= self.max_chars {
+            return output;
+        }
+
+        match self.strategy {
+            OutputStrategy::Truncate => truncate(&output, self.max_chars).to_string(),
+            OutputStrategy::TruncateWithNotice => with_notice(&output, self.max_chars),
+            OutputStrategy::SummarizeJson { max_items } => match serde_json::from_str(&output) {
+                Ok(value) => summarize(&value, self.max_chars, max_items),
+                Err(_) => with_notice(&output, self.max_chars),
+            },
+        }
+    }
+}
+
+/// The first `max_chars` characters of `text`.
+fn truncate(text: &str, max_chars: usize) -> &str {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    }
+}
+
+fn with_notice(text: &str, max_chars: usize) -> String {
+    format!(
+        "{}\n[truncated: {max_chars} of {} characters shown]",
+        truncate(text, max_chars),
+        text.chars().count()
+    )
+}
+
+/// What [prune] elided.
+#[derive(Default)]
+struct Elided {
+    items: usize,
+    strings: usize,
+}
+
+/// Prune `value` harder until it fits in `max_chars`, or can't be pruned further.
+fn summarize(value: &Value, max_chars: usize, max_items: usize) -> String {
+    let mut items = max_items.max(1);
+    let mut string_chars = max_chars.max(MIN_STRING_CHARS);
+    loop {
+        let mut elided = Elided::default();
+        let mut pruned = prune(value, items, string_chars, &mut elided);
+        if elided.items > 0 || elided.strings > 0 {
+            let notice = format!(
+                "output over {max_chars} characters: {} array items omitted, {} strings shortened",
+                elided.items, elided.strings
+            );
+            match &mut pruned {
+                Value::Object(fields) => {
+                    fields.insert("truncated".to_string(), notice.into());
+                }
+                Value::Array(items) => items.push(notice.into()),
+                _ => {}
+            }
+        }
+
+        let text = pruned.to_string();
+        if text.chars().count() <= max_chars || (items == 1 && string_chars == MIN_STRING_CHARS) {
+            return text;
+        }
+        if items > 1 {
+            items /= 2;
+        } else {
+            string_chars = (string_chars / 2).max(MIN_STRING_CHARS);
+        }
+    }
+}
+
+/// `value` with its arrays cut to `items` items and its strings to `string_chars` characters.
+fn prune(value: &Value, items: usize, string_chars: usize, elided: &mut Elided) -> Value {
+    match value {
+        Value::Array(values) => {
+            let mut pruned: Vec<_> = values
+                .iter()
+                .take(items)
+                .map(|value| prune(value, items, string_chars, elided))
+                .collect();
+            if values.len() > items {
+                let omitted = values.len() - items;
+                elided.items += omitted;
+                pruned.push(format!("... {omitted} more items omitted").into());
+            }
+            Value::Array(pruned)
+        }
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, value)| (key.clone(), prune(value, items, string_chars, elided)))
+                .collect(),
+        ),
+        Value::String(text) if text.chars().count() > string_chars => {
+            elided.strings += 1;
+            let omitted = text.chars().count() - string_chars;
+            format!(
+                "{}... ({omitted} more characters)",
+                truncate(text, string_chars)
+            )
+            .into()
+        }
+        value => value.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_truncate() {
+        let policy = OutputPolicy::new(5).with_strategy(OutputStrategy::Truncate);
+        assert_eq!(policy.apply("héllo world".to_string()), "héllo");
+        assert_eq!(policy.apply("short".to_string()), "short");
+
+        let policy = OutputPolicy::new(5);
+        assert_eq!(
+            policy.apply("héllo world".to_string()),
+            "héllo\n[truncated: 5 of 11 characters shown]"
+        );
+    }
+
+    #[test]
+    fn test_summarize_json() {
+        let policy =
+            OutputPolicy::new(200).with_strategy(OutputStrategy::SummarizeJson { max_items: 3 });
+        let output = json!({
+            "payload": { "transfers": (0..100).collect::<Vec<_>>() },
+            "payment": null,
+        });
+
+        let summary: Value = serde_json::from_str(&policy.apply(output.to_string())).unwrap();
+        assert_eq!(
+            summary,
+            json!({
+                "payload": { "transfers": [0, 1, 2, "... 97 more items omitted"] },
+                "payment": null,
+                "truncated": "output over 200 characters: 97 array items omitted, 0 strings shortened",
+            })
+        );
+
+        // Pruned harder while it doesn't fit, as far as it goes.
+        let policy =
+            OutputPolicy::new(80).with_strategy(OutputStrategy::SummarizeJson { max_items: 10 });
+        let output = json!([{ "name": "x".repeat(200) }, 2, 3, 4, 5]);
+        let summary: Value = serde_json::from_str(&policy.apply(output.to_string())).unwrap();
+        assert_eq!(summary.as_array().unwrap().len(), 3);
+        assert_eq!(summary[1], "... 4 more items omitted");
+        assert!(summary[0]["name"]
+            .as_str()
+            .unwrap()
+            .ends_with("... (168 more characters)"));
+
+        // Not JSON.
+        let output = "a".repeat(100);
+        assert!(policy
+            .apply(output)
+            .ends_with("[truncated: 80 of 100 characters shown]"));
+    }
+}
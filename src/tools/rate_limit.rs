@@ -0,0 +1,176 @@
+//! Client-side rate limiting of the tools requests, see [RateLimiter].
+
+use super::{retry, ToolsError};
+use crate::constants::DEFAULT_RETRY_MAX_DELAY;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+/// How many requests a [RateLimiter] lets through.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RateLimit {
+    /// Requests per second, on average.
+    pub per_second: f64,
+    /// Requests sent back to back before being held to `per_second`.
+    pub burst: u32,
+    /// The longest a request waits, for the limiter or for the `Retry-After` of a 429 response.
+    /// Requests that would wait longer fail with [ToolsError::RateLimited] right away.
+    pub max_wait: Duration,
+}
+
+impl RateLimit {
+    /// `per_second` requests per second, in bursts of up to `burst`, waiting up to 30 seconds.
+    pub fn new(per_second: f64, burst: u32) -> Self {
+        Self {
+            per_second,
+            burst,
+            max_wait: DEFAULT_RETRY_MAX_DELAY,
+        }
+    }
+}
+
+/// A token bucket holding the tools requests to a [RateLimit], set with
+/// [UnifaiClient::with_rate_limit](super::UnifaiClient::with_rate_limit).
+///
+/// Clones share the bucket, so one limiter can be given to several clients, such as those of
+/// [SearchTools](super::SearchTools) and [CallTool](super::CallTool), to hold them to a single
+/// limit. A 429 response with a `Retry-After` holds every request of the limiter until then.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    limit: Arc<RateLimit>,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+    paused_until: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            bucket: Arc::new(Mutex::new(Bucket {
+                tokens: limit.burst.max(1) as f64,
+                refilled: Instant::now(),
+                paused_until: None,
+            })),
+            limit: Arc::new(limit),
+        }
+    }
+
+    pub fn limit(&self) -> &RateLimit {
+        &self.limit
+    }
+
+    /// Wait for the turn of a request, failing with [ToolsError::RateLimited] if it would wait
+    /// longer than [RateLimit::max_wait].
+    pub(crate) async fn acquire(&self) -> Result<(), ToolsError> {
+        let wait = self.reserve().map_err(|wait| ToolsError::RateLimited {
+            retry_after: Some(wait),
+            body: format!("client rate limit, {wait:?} to wait"),
+        })?;
+        if !wait.is_zero() {
+            retry::sleep(wait).await;
+        }
+
+        Ok(())
+    }
+
+    /// Take a token, returning how long to wait for it, or the wait as an error if it's too long,
+    /// in which case the token isn't taken.
+    fn reserve(&self) -> Result<Duration, Duration> {
+        let now = Instant::now();
+        let mut bucket = self.bucket.lock().unwrap();
+
+        let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens =
+            (bucket.tokens + elapsed * self.limit.per_second).min(self.limit.burst.max(1) as f64);
+        bucket.refilled = now;
+
+        let refill = if bucket.tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            Duration::try_from_secs_f64((1.0 - bucket.tokens) / self.limit.per_second)
+                .unwrap_or(Duration::MAX)
+        };
+        let paused = bucket
+            .paused_until
+            .map_or(Duration::ZERO, |until| until.saturating_duration_since(now));
+        let wait = refill.max(paused);
+        if wait > self.limit.max_wait {
+            return Err(wait);
+        }
+
+        // Waiting requests take their token ahead, so the next ones queue behind them.
+        bucket.tokens -= 1.0;
+        Ok(wait)
+    }
+
+    /// Hold every request for `delay`, as asked by the server.
+    pub(crate) fn pause(&self, delay: Duration) {
+        let until = Instant::now() + delay;
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.paused_until = Some(
+            bucket
+                .paused_until
+                .map_or(until, |paused| paused.max(until)),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(per_second: f64, burst: u32) -> RateLimiter {
+        RateLimiter::new(RateLimit {
+            max_wait: Duration::from_secs(1),
+            ..RateLimit::new(per_second, burst)
+        })
+    }
+
+    #[test]
+    fn test_bursts_then_waits() {
+        let limiter = limiter(2.0, 2);
+
+        assert_eq!(limiter.reserve(), Ok(Duration::ZERO));
+        assert_eq!(limiter.reserve(), Ok(Duration::ZERO));
+        let wait = limiter.reserve().unwrap();
+        assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500));
+        let wait = limiter.reserve().unwrap();
+        assert!(wait > Duration::from_millis(900) && wait <= Duration::from_secs(1));
+
+        // Over max_wait, and no token is taken.
+        let wait = limiter.reserve().unwrap_err();
+        assert!(wait > Duration::from_secs(1));
+        assert!(limiter.reserve().is_err());
+    }
+
+    #[test]
+    fn test_clones_share_the_bucket() {
+        let limiter = limiter(1.0, 1);
+        let shared = limiter.clone();
+
+        assert_eq!(limiter.reserve(), Ok(Duration::ZERO));
+        assert!(shared.reserve().unwrap() > Duration::ZERO);
+    }
+
+    #[test]
+    fn test_pause() {
+        let limiter = limiter(100.0, 10);
+        limiter.pause(Duration::from_millis(500));
+
+        let wait = limiter.reserve().unwrap();
+        assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500));
+
+        limiter.pause(Duration::from_secs(5));
+        assert!(limiter.reserve().is_err());
+    }
+}
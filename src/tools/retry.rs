@@ -0,0 +1,197 @@
+//! Retries of the tools requests that failed for a transient reason, see [RetryPolicy].
+
+use crate::{
+    config::UnifaiConfig,
+    constants::{DEFAULT_MAX_RETRIES, DEFAULT_RETRY_BASE_DELAY, DEFAULT_RETRY_MAX_DELAY},
+};
+use reqwest::{header::RETRY_AFTER, Response, StatusCode};
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+/// When and how often the tools send a failed request again.
+///
+/// Searches are retried on network errors and on the statuses of `retry_on_status`. So are tool
+/// calls, except the paid ones, those with a `payment`: they are only retried when the connection
+/// to the server could not be established, so the call never reached it and can't be charged
+/// twice.
+///
+/// The delay before the `n`th retry is `base_delay * 2^(n-1)`, with up to half of it taken off at
+/// random so clients failing together don't retry together, and capped at `max_delay`. A server
+/// asking to wait with a `Retry-After` header in seconds is waited for instead, unless it asks for
+/// more than `max_delay`, and the request fails right away.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt, 0 to never retry.
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// The response statuses worth a retry.
+    pub retry_on_status: Vec<u16>,
+}
+
+impl Default for RetryPolicy {
+    /// 3 retries, from 500ms, on 429 and the 5xx statuses of a server or gateway in trouble.
+    fn default() -> Self {
+        Self {
+            max_retries: DEFAULT_MAX_RETRIES,
+            base_delay: DEFAULT_RETRY_BASE_DELAY,
+            max_delay: DEFAULT_RETRY_MAX_DELAY,
+            retry_on_status: vec![429, 500, 502, 503, 504],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Never retry, the policy of the tools unless set otherwise.
+    pub fn never() -> Self {
+        Self {
+            max_retries: 0,
+            ..Self::default()
+        }
+    }
+
+    /// The default policy, with the retries and base delay of [UnifaiConfig::max_retries] and
+    /// [UnifaiConfig::retry_base_delay].
+    pub fn from_config(config: &UnifaiConfig) -> Self {
+        Self {
+            max_retries: config.max_retries,
+            base_delay: config.retry_base_delay,
+            ..Self::default()
+        }
+    }
+
+    /// The delay before the `retry`th retry of a request that failed with `failure`, `None` if it
+    /// must not be retried.
+    pub(crate) fn delay(&self, retry: u32, failure: &Failure, paid: bool) -> Option<Duration> {
+        if retry > self.max_retries {
+            return None;
+        }
+
+        let retry_after = match failure {
+            Failure::Connect => None,
+            _ if paid => return None,
+            Failure::Network => None,
+            Failure::Status {
+                status,
+                retry_after,
+            } => {
+                if !self.retry_on_status.contains(&status.as_u16()) {
+                    return None;
+                }
+                *retry_after
+            }
+        };
+
+        match retry_after {
+            Some(after) if after > self.max_delay => None,
+            Some(after) => Some(after),
+            None => {
+                let backoff = self
+                    .base_delay
+                    .saturating_mul(2u32.saturating_pow(retry - 1))
+                    .min(self.max_delay);
+                Some(backoff.mul_f64(1.0 - random() / 2.0))
+            }
+        }
+    }
+}
+
+/// Why an attempt failed.
+#[derive(Debug)]
+pub(crate) enum Failure {
+    /// The connection could not be established, the request never reached the server.
+    Connect,
+    /// The request failed, maybe after reaching the server.
+    Network,
+    /// The server answered with a failing status.
+    Status {
+        status: StatusCode,
+        retry_after: Option<Duration>,
+    },
+}
+
+impl Failure {
+    /// The failure of a request that got `response`, `None` if it succeeded.
+    pub(crate) fn of(response: &reqwest::Result<Response>) -> Option<Self> {
+        match response {
+            Ok(response) if response.status().is_success() => None,
+            Ok(response) => Some(Self::Status {
+                status: response.status(),
+                retry_after: response
+                    .headers()
+                    .get(RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok()?.trim().parse().ok())
+                    .map(Duration::from_secs),
+            }),
+            #[cfg(not(target_arch = "wasm32"))]
+            Err(e) if e.is_connect() => Some(Self::Connect),
+            Err(_) => Some(Self::Network),
+        }
+    }
+}
+
+/// A number in `[0, 1]`, random enough to spread retries.
+fn random() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+    random as f64 / u64::MAX as f64
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) async fn sleep(delay: Duration) {
+    tokio::time::sleep(delay).await;
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) async fn sleep(delay: Duration) {
+    gloo_timers::future::sleep(delay).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(status: u16, retry_after: Option<u64>) -> Failure {
+        Failure::Status {
+            status: StatusCode::from_u16(status).unwrap(),
+            retry_after: retry_after.map(Duration::from_secs),
+        }
+    }
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(5),
+            ..RetryPolicy::default()
+        };
+
+        for (retry, max) in [(1, 100), (2, 200), (3, 400)] {
+            let delay = policy.delay(retry, &Failure::Network, false).unwrap();
+            let max = Duration::from_millis(max);
+            assert!(delay >= max / 2 && delay <= max, "{retry}: {delay:?}");
+        }
+        assert_eq!(policy.delay(4, &Failure::Network, false), None);
+
+        assert!(policy.delay(1, &status(503, None), false).is_some());
+        assert_eq!(policy.delay(1, &status(400, None), false), None);
+        assert_eq!(
+            policy.delay(1, &status(429, Some(2)), false),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(policy.delay(1, &status(429, Some(60)), false), None);
+    }
+
+    #[test]
+    fn test_delay_of_paid_calls() {
+        let policy = RetryPolicy::default();
+
+        assert!(policy.delay(1, &Failure::Connect, true).is_some());
+        assert_eq!(policy.delay(1, &Failure::Network, true), None);
+        assert_eq!(policy.delay(1, &status(503, None), true), None);
+        assert_eq!(RetryPolicy::never().delay(1, &Failure::Connect, true), None);
+    }
+}
@@ -1,32 +1,153 @@
-use crate::{constants::DEFAULT_BACKEND_API_ENDPOINT, utils::build_api_client};
-use reqwest::Client;
+use super::{OutputPolicy, RateLimit, RateLimiter, RetryPolicy, ToolsError, UnifaiClient};
+use crate::config::{ConfigError, UnifaiConfig};
+#[cfg(feature = "rig")]
 use rig::{completion::ToolDefinition, tool::Tool};
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "rig")]
 use serde_json::json;
-use std::env;
+use serde_json::{Map, Value};
 
 /// A tool used to search tools on Unifai server.
 pub struct SearchTools {
-    api_client: Client,
+    client: UnifaiClient,
+    output: Option<OutputPolicy>,
 }
 
 impl SearchTools {
+    /// Create the tool with Unifai Agent API Key.
+    ///
+    /// Endpoints are read from environment variables once, see [UnifaiConfig::from_env].
+    ///
+    /// # Panics
+    ///
+    /// If the API key isn't a valid header value, or the HTTP client can't be built, see
+    /// [SearchTools::try_new].
     pub fn new(api_key: &str) -> Self {
-        let api_client = build_api_client(api_key);
-        Self { api_client }
+        UnifaiClient::new(api_key).into()
+    }
+
+    /// Create the tool like [SearchTools::new], failing if the API key isn't a valid header value, or
+    /// if the HTTP client can't be built.
+    pub fn try_new(api_key: &str) -> Result<Self, ConfigError> {
+        Ok(UnifaiClient::try_new(api_key)?.into())
+    }
+
+    /// Create the tool with Unifai Agent API Key, retrying the requests that failed for a
+    /// transient reason, see [RetryPolicy].
+    ///
+    /// # Panics
+    ///
+    /// Like [SearchTools::new].
+    pub fn new_with_retry(api_key: &str, policy: RetryPolicy) -> Self {
+        UnifaiClient::new(api_key).with_retry(policy).into()
+    }
+
+    /// Create the tool with Unifai Agent API Key, holding its searches to `limit`, see
+    /// [UnifaiClient::with_rate_limiter].
+    ///
+    /// # Panics
+    ///
+    /// Like [SearchTools::new].
+    pub fn new_with_rate_limit(api_key: &str, limit: RateLimit) -> Self {
+        UnifaiClient::new(api_key).with_rate_limit(limit).into()
+    }
+
+    /// Create the tool from a config, which must contain the agent API key.
+    pub fn from_config(config: &UnifaiConfig) -> Result<Self, ConfigError> {
+        Ok(UnifaiClient::from_config(config)?.into())
+    }
+
+    /// The client used by the tool.
+    pub fn client(&self) -> &UnifaiClient {
+        &self.client
+    }
+
+    /// Send the requests with `client`, see [UnifaiClient::with_client].
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = self.client.with_client(client);
+        self
+    }
+
+    /// Hold the requests to the limit of `limiter`, such as the limiter of another tool, see
+    /// [UnifaiClient::with_rate_limiter].
+    pub fn with_rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.client = self.client.with_rate_limiter(limiter);
+        self
+    }
+
+    /// Cut the outputs of the rig tool down to `policy`, so they fit in the context of the model.
+    pub fn with_output_policy(mut self, policy: OutputPolicy) -> Self {
+        self.output = Some(policy);
+        self
+    }
+
+    /// Cut the outputs of the rig tool to `max_chars` characters, with a notice, see
+    /// [OutputPolicy::new].
+    pub fn with_max_output_chars(self, max_chars: usize) -> Self {
+        self.with_output_policy(OutputPolicy::new(max_chars))
+    }
+
+    /// `output` cut down to the output policy, if any.
+    #[cfg(feature = "rig")]
+    fn limit_output(&self, output: String) -> String {
+        match &self.output {
+            Some(policy) => policy.apply(output),
+            None => output,
+        }
+    }
+
+    /// Search tools, parsing the response, see [UnifaiClient::search_tools_typed].
+    pub async fn search(&self, args: &SearchToolsArgs) -> Result<Vec<ToolRecord>, ToolsError> {
+        self.client.search_tools_typed(args).await
+    }
+}
+
+impl From<UnifaiClient> for SearchTools {
+    fn from(client: UnifaiClient) -> Self {
+        Self {
+            client,
+            output: None,
+        }
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct SearchToolsArgs {
     pub query: String,
     pub limit: Option<usize>,
+    /// Only the tools of this category.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    /// Only the tools of the toolkit with this id.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub toolkit_id: Option<u64>,
+    /// Only the tools that take no payment, if `true`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub free_only: Option<bool>,
 }
 
+/// A tool found by a search, to call with [CallToolArgs](super::CallToolArgs) naming its
+/// `action`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ToolRecord {
+    pub action: String,
+    #[serde(default)]
+    pub description: String,
+    /// The payload the tool takes, described like the payload of a Toolkit action.
+    #[serde(default)]
+    pub payload: Value,
+    #[serde(default)]
+    pub payment: Option<Value>,
+    /// The other fields of the record, such as those added by newer servers.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[cfg(feature = "rig")]
 impl Tool for SearchTools {
     const NAME: &'static str = "search_services";
 
-    type Error = reqwest::Error;
+    type Error = ToolsError;
     type Args = SearchToolsArgs;
     type Output = String;
 
@@ -44,6 +165,18 @@ impl Tool for SearchTools {
                   "limit": {
                     "type": "number",
                     "description": "The maximum number of tools to return, must be between 1 and 100, default is 10, recommend at least 10"
+                  },
+                  "category": {
+                    "type": "string",
+                    "description": "Only return the tools of this category"
+                  },
+                  "toolkit_id": {
+                    "type": "integer",
+                    "description": "Only return the tools of the toolkit with this id"
+                  },
+                  "free_only": {
+                    "type": "boolean",
+                    "description": "Only return the tools that don't require a payment"
                   }
                 },
                 "required": ["query"],
@@ -52,21 +185,17 @@ impl Tool for SearchTools {
     }
 
     async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
-        let endpoint = env::var("UNIFAI_BACKEND_API_ENDPOINT")
-            .unwrap_or(DEFAULT_BACKEND_API_ENDPOINT.to_string());
-        let url = format!("{endpoint}/actions/search");
-
-        self.api_client
-            .get(url)
-            .query(&args)
-            .send()
-            .await?
-            .text()
-            .await
+        let tools = self.search(&args).await?;
+
+        Ok(
+            self.limit_output(
+                serde_json::to_string(&tools).expect("tool records always serialize"),
+            ),
+        )
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "rig"))]
 mod tests {
     use crate::tools::{SearchTools, SearchToolsArgs};
     use rig::tool::Tool;
@@ -83,6 +212,7 @@ mod tests {
             .call(SearchToolsArgs {
                 query: "solana".to_string(),
                 limit: Some(10),
+                ..Default::default()
             })
             .await
             .unwrap();
@@ -0,0 +1,188 @@
+//! [tower_service::Service] implementations of [UnifaiClient], so requests can go through tower
+//! layers such as timeouts, retries and concurrency limits.
+//!
+//! The client has no rate limiter of its own and is always ready, back-pressure comes from the
+//! layers wrapping it. Each request is sent with a clone of the client, sharing its connection
+//! pool.
+
+use super::{CallToolArgs, SearchToolsArgs, ToolsError, UnifaiClient};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// The future of a tools request, with the raw response body.
+#[cfg(not(target_arch = "wasm32"))]
+pub type ToolsFuture = Pin<Box<dyn Future<Output = Result<String, ToolsError>> + Send>>;
+
+/// The future of a tools request, with the raw response body.
+#[cfg(target_arch = "wasm32")]
+pub type ToolsFuture = Pin<Box<dyn Future<Output = Result<String, ToolsError>>>>;
+
+impl tower_service::Service<SearchToolsArgs> for UnifaiClient {
+    type Response = String;
+    type Error = ToolsError;
+    type Future = ToolsFuture;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, args: SearchToolsArgs) -> Self::Future {
+        let client = self.clone();
+        Box::pin(async move { client.search_tools(&args).await })
+    }
+}
+
+impl tower_service::Service<CallToolArgs> for UnifaiClient {
+    type Response = String;
+    type Error = ToolsError;
+    type Future = ToolsFuture;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, args: CallToolArgs) -> Self::Future {
+        let client = self.clone();
+        Box::pin(async move { client.call_tool(&args).await })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UnifaiConfig;
+    use serde_json::json;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+    use tower::{retry::Policy, BoxError, ServiceBuilder, ServiceExt};
+
+    /// Retry failed requests, up to `attempts` times.
+    #[derive(Clone)]
+    struct Attempts(usize);
+
+    impl<Req: Clone, Res, E> Policy<Req, Res, E> for Attempts {
+        type Future = std::future::Ready<()>;
+
+        fn retry(&mut self, _req: &mut Req, result: &mut Result<Res, E>) -> Option<Self::Future> {
+            if result.is_err() && self.0 > 1 {
+                self.0 -= 1;
+                Some(std::future::ready(()))
+            } else {
+                None
+            }
+        }
+
+        fn clone_request(&mut self, req: &Req) -> Option<Req> {
+            Some(req.clone())
+        }
+    }
+
+    /// A backend dropping the first `failures` connections, and answering the others with `body`.
+    async fn flaky_backend(failures: usize, body: &'static str) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+
+        let counter = connections.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                if counter.fetch_add(1, Ordering::SeqCst) < failures {
+                    continue;
+                }
+
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{body}",
+                    body.len()
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        (format!("http://{addr}"), connections)
+    }
+
+    fn client(endpoint: String) -> UnifaiClient {
+        UnifaiClient::from_config(&UnifaiConfig {
+            agent_api_key: Some("test".to_string()),
+            backend_api_endpoint: endpoint,
+            ..UnifaiConfig::default()
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_layer_stack() {
+        let (endpoint, connections) = flaky_backend(2, "[]").await;
+
+        let search = ServiceBuilder::new()
+            .retry(Attempts(3))
+            .timeout(Duration::from_secs(5))
+            .service(client(endpoint.clone()));
+
+        let args = SearchToolsArgs {
+            query: "echo".to_string(),
+            limit: Some(1),
+            ..Default::default()
+        };
+        let response = search.oneshot(args).await.unwrap();
+
+        assert_eq!(response, "[]");
+        assert_eq!(connections.load(Ordering::SeqCst), 3);
+
+        let call = ServiceBuilder::new()
+            .retry(Attempts(1))
+            .timeout(Duration::from_secs(5))
+            .service(client(endpoint));
+        let args = CallToolArgs {
+            action: "echo".to_string(),
+            payload: json!({}),
+            payment: None,
+            ..Default::default()
+        };
+
+        assert_eq!(call.oneshot(args).await.unwrap(), "[]");
+    }
+
+    #[tokio::test]
+    async fn test_timeout_layer() {
+        // Accept connections and never answer them.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                connections.push(stream);
+            }
+        });
+
+        let search = ServiceBuilder::new()
+            .timeout(Duration::from_millis(100))
+            .service(client(format!("http://{addr}")));
+        let args = SearchToolsArgs {
+            query: "echo".to_string(),
+            limit: None,
+            ..Default::default()
+        };
+        let error: BoxError = search.oneshot(args).await.unwrap_err();
+
+        assert!(error.is::<tower::timeout::error::Elapsed>());
+    }
+}
@@ -0,0 +1,304 @@
+//! The tools found by a search, each given to the agent as a tool of its own.
+
+use super::{CallToolArgs, SearchToolsArgs, ToolRecord, ToolsError, UnifaiClient};
+use rig::{
+    completion::ToolDefinition,
+    tool::{Tool, ToolEmbedding, ToolSet},
+};
+use serde_json::Value;
+use std::{collections::HashSet, convert::Infallible};
+
+/// Longest tool name the model providers accept.
+const MAX_TOOL_NAME_LEN: usize = 64;
+
+/// A tool found by a search, given to the agent as a tool of its own, with the description and
+/// payload schema of its action. The model calls it directly, rather than carrying the action
+/// name from `search_services` to `invoke_service`.
+///
+/// Its name is the action name, with the characters the model providers reject replaced by `_`.
+/// Calls go through [UnifaiClient::call_tool_typed], without payment.
+#[derive(Clone, Debug)]
+pub struct UnifaiTool {
+    client: UnifaiClient,
+    record: ToolRecord,
+    name: String,
+}
+
+impl UnifaiTool {
+    pub fn new(client: UnifaiClient, record: ToolRecord) -> Self {
+        let name = tool_name(&record.action);
+
+        Self {
+            client,
+            record,
+            name,
+        }
+    }
+
+    /// The tool as found by the search.
+    pub fn record(&self) -> &ToolRecord {
+        &self.record
+    }
+}
+
+impl Tool for UnifaiTool {
+    /// Unused, each tool is named after its action.
+    const NAME: &'static str = "unifai_tool";
+
+    type Error = ToolsError;
+    type Args = Value;
+    type Output = String;
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: self.name.clone(),
+            description: self.record.description.clone(),
+            parameters: crate::payload::input_schema(&self.record.payload),
+        }
+    }
+
+    async fn call(&self, payload: Self::Args) -> Result<Self::Output, Self::Error> {
+        let args = CallToolArgs {
+            action: self.record.action.clone(),
+            payload,
+            ..Default::default()
+        };
+        let response = self.client.call_tool_typed(&args).await?;
+
+        Ok(serde_json::to_string(&response).expect("a response always serializes"))
+    }
+}
+
+impl ToolEmbedding for UnifaiTool {
+    type InitError = Infallible;
+    type Context = ToolRecord;
+    type State = UnifaiClient;
+
+    fn embedding_docs(&self) -> Vec<String> {
+        vec![self.record.action.clone(), self.record.description.clone()]
+    }
+
+    fn context(&self) -> Self::Context {
+        self.record.clone()
+    }
+
+    fn init(client: Self::State, record: Self::Context) -> Result<Self, Self::InitError> {
+        Ok(Self::new(client, record))
+    }
+}
+
+/// How [DynamicUnifaiTools] selects its tools.
+#[derive(Clone, Debug)]
+enum Selection {
+    Search(SearchToolsArgs),
+    Actions(Vec<String>),
+}
+
+/// The tools selected up-front, by a search or by action name, each given to the agent as a
+/// [UnifaiTool].
+///
+/// # Example
+///
+/// ```ignore
+/// let args = SearchToolsArgs { query: "solana".to_string(), ..Default::default() };
+/// let tools = DynamicUnifaiTools::search(client, args).await?;
+///
+/// // Every tool, on every prompt
+/// let mut agent = openai.agent("gpt-4o");
+/// for tool in tools.tools() {
+///     agent = agent.tool(tool.clone());
+/// }
+///
+/// // Or only the tools relevant to the prompt
+/// let index = DynamicToolSet::load_or_build("tools.json", tools.toolset().schemas()?, model).await?;
+/// let agent = openai.agent("gpt-4o").dynamic_tools(2, index, tools.toolset()).build();
+/// ```
+#[derive(Clone, Debug)]
+pub struct DynamicUnifaiTools {
+    client: UnifaiClient,
+    selection: Selection,
+    tools: Vec<UnifaiTool>,
+}
+
+impl DynamicUnifaiTools {
+    /// The tools found searching with `args`.
+    pub async fn search(client: UnifaiClient, args: SearchToolsArgs) -> Result<Self, ToolsError> {
+        Self::select(client, Selection::Search(args)).await
+    }
+
+    /// The tools of the actions named `actions`, each found by searching its name. The actions
+    /// the search doesn't find are left out.
+    pub async fn actions(
+        client: UnifaiClient,
+        actions: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Result<Self, ToolsError> {
+        let actions = actions.into_iter().map(Into::into).collect();
+
+        Self::select(client, Selection::Actions(actions)).await
+    }
+
+    async fn select(client: UnifaiClient, selection: Selection) -> Result<Self, ToolsError> {
+        let mut tools = Self {
+            client,
+            selection,
+            tools: Vec::new(),
+        };
+        tools.refresh().await?;
+
+        Ok(tools)
+    }
+
+    /// Select the tools again, such as to pick up the actions added since. The tools are kept if
+    /// it fails.
+    ///
+    /// Agents keep the tools they were built with, build them again with the new ones.
+    pub async fn refresh(&mut self) -> Result<(), ToolsError> {
+        let records = match &self.selection {
+            Selection::Search(args) => self.client.search_tools_typed(args).await?,
+            Selection::Actions(actions) => {
+                let mut records = Vec::new();
+                for action in actions {
+                    let args = SearchToolsArgs {
+                        query: action.clone(),
+                        ..Default::default()
+                    };
+                    let found = self.client.search_tools_typed(&args).await?;
+                    records.extend(found.into_iter().find(|record| &record.action == action));
+                }
+                records
+            }
+        };
+
+        // Names must be unique in a tool set, the first tool of a name wins.
+        let mut names = HashSet::new();
+        self.tools = records
+            .into_iter()
+            .map(|record| UnifaiTool::new(self.client.clone(), record))
+            .filter(|tool| names.insert(tool.name.clone()))
+            .collect();
+
+        Ok(())
+    }
+
+    /// The selected tools.
+    pub fn tools(&self) -> &[UnifaiTool] {
+        &self.tools
+    }
+
+    /// The selected tools as a [ToolSet] of dynamic tools, to be given to
+    /// [AgentBuilder::dynamic_tools](rig::agent::AgentBuilder::dynamic_tools) with an index of
+    /// their [schemas](ToolSet::schemas), such as a [DynamicToolSet](super::DynamicToolSet).
+    pub fn toolset(&self) -> ToolSet {
+        self.tools
+            .iter()
+            .fold(ToolSet::builder(), |builder, tool| {
+                builder.dynamic_tool(tool.clone())
+            })
+            .build()
+    }
+}
+
+/// The action name, with the characters model providers reject in tool names replaced.
+fn tool_name(action: &str) -> String {
+    action
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(MAX_TOOL_NAME_LEN)
+        .collect()
+}
+
+#[cfg(all(test, feature = "test-utils", feature = "toolkit"))]
+mod tests {
+    use super::*;
+    use crate::{
+        config::UnifaiConfig,
+        testing::loopback,
+        toolkit::{
+            Action, ActionContext, ActionDefinition, ActionParams, ActionResult, ToolkitService,
+        },
+    };
+    use rig::tool::ToolDyn;
+    use serde_json::json;
+
+    struct Balance;
+
+    impl Action for Balance {
+        const NAME: &'static str = "Solana/getBalance";
+
+        type Error = Infallible;
+        type Args = Value;
+        type Output = Value;
+
+        async fn definition(&self) -> ActionDefinition {
+            ActionDefinition {
+                description: "Get the balance of a wallet".to_string(),
+                payload: json!({ "wallet": { "type": "string", "required": true } }),
+                payment: None,
+                ..Default::default()
+            }
+        }
+
+        async fn call(
+            &self,
+            _ctx: ActionContext,
+            params: ActionParams<Self::Args>,
+        ) -> Result<ActionResult<Self::Output>, Self::Error> {
+            Ok(ActionResult {
+                payload: json!({ "balance": 1, "wallet": params.payload["wallet"] }),
+                payment: None,
+            })
+        }
+    }
+
+    async fn client() -> UnifaiClient {
+        let mut service = ToolkitService::new("test").allow_unchecked_names();
+        service.try_add_action(Balance).unwrap();
+        let (search_tools, _) = loopback(service, &UnifaiConfig::default()).await;
+
+        search_tools.client().clone()
+    }
+
+    #[tokio::test]
+    async fn test_tools_of_actions() {
+        let tools = DynamicUnifaiTools::actions(client().await, ["Solana/getBalance", "missing"])
+            .await
+            .unwrap();
+        assert_eq!(tools.tools().len(), 1);
+
+        let tool = &tools.tools()[0];
+        let definition = ToolDyn::definition(tool, String::new()).await;
+        assert_eq!(definition.name, "Solana_getBalance");
+        assert_eq!(definition.description, "Get the balance of a wallet");
+        assert_eq!(definition.parameters["required"], json!(["wallet"]));
+
+        let output = ToolDyn::call(tool, r#"{"wallet":"abc"}"#.to_string())
+            .await
+            .unwrap();
+        let response: Value = serde_json::from_str(&output).unwrap();
+        let response: Value = serde_json::from_str(response.as_str().unwrap()).unwrap();
+        assert_eq!(
+            response["payload"],
+            json!({ "balance": 1, "wallet": "abc" })
+        );
+
+        let toolset = tools.toolset();
+        assert!(toolset.contains("Solana_getBalance"));
+        assert_eq!(toolset.schemas().unwrap()[0].name, "Solana_getBalance");
+    }
+
+    #[test]
+    fn test_tool_name() {
+        assert_eq!(tool_name("Solana/7/get-balance"), "Solana_7_get-balance");
+        assert_eq!(tool_name(&"a".repeat(100)).len(), MAX_TOOL_NAME_LEN);
+    }
+}
@@ -0,0 +1,160 @@
+//! W3C trace context, carried from the tool call of an agent to the action it calls, so both sides
+//! can be part of one distributed trace.
+//!
+//! With the `otel` feature, [UnifaiClient::call_tool] runs in a `call_tool` span and sends its
+//! context in the `traceparent` and `tracestate` headers, which the server forwards with the action
+//! call. The Toolkit then runs the action in an `action_call` span, child of the remote one. The
+//! spans are exported by the `tracing-opentelemetry` layer of the
+//! application, if any.
+//!
+//! [ActionContext::trace_id] gives the trace id of a call for log correlation, with or without the
+//! feature. Calls arriving without a context run as they always did.
+//!
+//! [UnifaiClient::call_tool]: crate::tools::UnifaiClient::call_tool
+//! [ActionContext::trace_id]: crate::toolkit::ActionContext::trace_id
+
+use serde::{Deserialize, Serialize};
+
+/// The trace an action call is part of, as in
+/// `{"traceparent":"00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"}`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TraceContext {
+    pub traceparent: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tracestate: Option<String>,
+}
+
+impl TraceContext {
+    /// The trace id of `traceparent`, in lower case hex, `None` if it's malformed.
+    pub fn trace_id(&self) -> Option<&str> {
+        let mut parts = self.traceparent.split('-');
+        let (_version, trace_id, span_id, _flags) =
+            (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+
+        let is_id = |id: &str, len: usize| {
+            id.len() == len
+                && id.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+                && id.bytes().any(|b| b != b'0')
+        };
+        (is_id(trace_id, 32) && is_id(span_id, 16)).then_some(trace_id)
+    }
+
+    /// The context sent in the `traceparent` and `tracestate` headers of a request, if any.
+    #[cfg(any(
+        feature = "http-server",
+        all(feature = "toolkit", feature = "test-utils", feature = "tools")
+    ))]
+    pub(crate) fn from_headers(headers: &reqwest::header::HeaderMap) -> Option<Self> {
+        let header = |name| headers.get(name)?.to_str().ok().map(str::to_string);
+
+        Some(Self {
+            traceparent: header("traceparent")?,
+            tracestate: header("tracestate"),
+        })
+    }
+
+    /// The context of the current tracing span, `None` if it isn't part of an OpenTelemetry trace.
+    #[cfg(feature = "otel")]
+    pub fn current() -> Option<Self> {
+        use opentelemetry::trace::TraceContextExt;
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+        let context = tracing::Span::current().context();
+        let span = context.span();
+        let span = span.span_context();
+        if !span.is_valid() {
+            return None;
+        }
+
+        let tracestate = span.trace_state().header();
+        Some(Self {
+            traceparent: format!(
+                "00-{}-{}-{:02x}",
+                span.trace_id(),
+                span.span_id(),
+                span.trace_flags().to_u8()
+            ),
+            tracestate: (!tracestate.is_empty()).then_some(tracestate),
+        })
+    }
+
+    /// The remote span this context names, `None` if `traceparent` is malformed.
+    #[cfg(all(feature = "otel", feature = "toolkit"))]
+    pub(crate) fn span_context(&self) -> Option<opentelemetry::trace::SpanContext> {
+        use opentelemetry::trace::{SpanContext, SpanId, TraceFlags, TraceId, TraceState};
+
+        let trace_id = self.trace_id()?;
+        let mut parts = self.traceparent.split('-').skip(2);
+        let span_id = SpanId::from_hex(parts.next()?).ok()?;
+        let flags = u8::from_str_radix(parts.next()?, 16).ok()?;
+        let state = self
+            .tracestate
+            .as_deref()
+            .and_then(|state| state.parse::<TraceState>().ok())
+            .unwrap_or_default();
+
+        Some(SpanContext::new(
+            TraceId::from_hex(trace_id).ok()?,
+            span_id,
+            TraceFlags::new(flags),
+            true,
+            state,
+        ))
+    }
+}
+
+/// Set the `traceparent` and `tracestate` headers of `request` from the current span.
+#[cfg(all(feature = "otel", feature = "tools"))]
+pub(crate) fn inject(request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+    let Some(context) = TraceContext::current() else {
+        return request;
+    };
+
+    let request = request.header("traceparent", context.traceparent);
+    match context.tracestate {
+        Some(tracestate) => request.header("tracestate", tracestate),
+        None => request,
+    }
+}
+
+/// Make the span of an action call a child of the remote span of `trace_context`, if any.
+#[cfg(all(feature = "otel", feature = "toolkit"))]
+pub(crate) fn join_remote_trace(span: &tracing::Span, trace_context: Option<&TraceContext>) {
+    use opentelemetry::trace::TraceContextExt;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    if let Some(remote) = trace_context.and_then(TraceContext::span_context) {
+        // Only fails without an OpenTelemetry layer, when there is no trace to join.
+        let _ = span.set_parent(opentelemetry::Context::new().with_remote_span_context(remote));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(traceparent: &str) -> TraceContext {
+        TraceContext {
+            traceparent: traceparent.to_string(),
+            tracestate: None,
+        }
+    }
+
+    #[test]
+    fn test_trace_id() {
+        assert_eq!(
+            context("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").trace_id(),
+            Some("4bf92f3577b34da6a3ce929d0e0e4736")
+        );
+
+        for traceparent in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+        ] {
+            assert_eq!(context(traceparent).trace_id(), None, "{traceparent}");
+        }
+    }
+}
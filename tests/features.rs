@@ -0,0 +1,93 @@
+//! Each half of the crate is available exactly when its feature is enabled.
+//! Run with the feature combinations of the CI matrix to catch feature leakage.
+
+#[cfg(feature = "toolkit")]
+#[test]
+fn test_toolkit_feature() {
+    let mut service = unifai_sdk::toolkit::ToolkitService::new("UNIFAI_TOOLKIT_API_KEY");
+    service = service.with_request_timeout(std::time::Duration::from_secs(1));
+    assert_eq!(service.config().request_timeout.as_secs(), 1);
+}
+
+#[cfg(feature = "grpc")]
+#[test]
+fn test_grpc_feature() {
+    use unifai_sdk::toolkit::{ToolkitService, TransportPreference};
+
+    let _service =
+        ToolkitService::new("UNIFAI_TOOLKIT_API_KEY").with_transport(TransportPreference::Grpc);
+}
+
+#[cfg(feature = "tools")]
+#[test]
+fn test_tools_feature() {
+    let client = unifai_sdk::tools::UnifaiClient::new("UNIFAI_AGENT_API_KEY");
+    let _tool: unifai_sdk::tools::SearchTools = client.into();
+}
+
+#[cfg(feature = "rig")]
+#[test]
+fn test_rig_feature() {
+    use unifai_sdk::rig::tool::Tool;
+
+    assert_eq!(unifai_sdk::tools::CallTool::NAME, "invoke_service");
+}
+
+#[cfg(feature = "auto-register")]
+mod auto_register {
+    use unifai_sdk::{
+        serde_json::{json, Value},
+        toolkit::{
+            Action, ActionContext, ActionDefinition, ActionParams, ActionResult, ToolkitService,
+        },
+    };
+
+    struct Registered;
+
+    impl Action for Registered {
+        const NAME: &'static str = "registered";
+
+        type Error = std::convert::Infallible;
+        type Args = Value;
+        type Output = Value;
+
+        async fn definition(&self) -> ActionDefinition {
+            ActionDefinition {
+                description: "Registered where it's defined".to_string(),
+                payload: json!({}),
+                payment: None,
+                ..Default::default()
+            }
+        }
+
+        async fn call(
+            &self,
+            _ctx: ActionContext,
+            params: ActionParams<Self::Args>,
+        ) -> Result<ActionResult<Self::Output>, Self::Error> {
+            Ok(ActionResult {
+                payload: params.payload,
+                payment: None,
+            })
+        }
+    }
+
+    unifai_sdk::register_action!(Registered);
+
+    #[test]
+    fn test_auto_register_feature() {
+        let mut service = ToolkitService::new("UNIFAI_TOOLKIT_API_KEY");
+        service.add_registered_actions().unwrap();
+
+        let error = service.add_registered_actions().unwrap_err();
+        assert_eq!(error.name, "registered");
+        assert_eq!(error.first, "ToolkitService::add_action");
+        assert!(error.second.starts_with("tests/features.rs:"));
+    }
+}
+
+#[test]
+fn test_config_is_always_available() {
+    let config = unifai_sdk::config::UnifaiConfig::default();
+    assert!(config.agent_api_key.is_none());
+}
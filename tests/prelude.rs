@@ -0,0 +1,42 @@
+//! The prelude re-exports the echo example doesn't already use, which is built as
+//! `examples/echo_toolkit.rs` with nothing but the prelude import.
+
+use unifai_sdk::prelude::*;
+
+#[test]
+fn test_prelude_reexports_config() {
+    let config = UnifaiConfig::default();
+    let _: &ClientOptions = &config.client;
+    let _: Option<ConfigError> = None;
+
+    let value: Value = serde_json::from_str(r#"{"content": "hi"}"#).unwrap();
+    assert_eq!(value, json!({ "content": "hi" }));
+}
+
+#[test]
+fn test_prelude_reexports_toolkit() {
+    let definition = ActionDefinition {
+        description: "Echo the message".to_string(),
+        payload: json!({}),
+        payment: None,
+        ..Default::default()
+    };
+    let mut service = ToolkitService::new("UNIFAI_TOOLKIT_API_KEY");
+    service.add_action(FunctionAction::new(
+        "echo",
+        definition,
+        |_, content: String| async move { Ok(content) },
+    ));
+
+    let _: Option<(ToolkitError, ToolkitHandle)> = None;
+    let _: Option<ToolkitRuntimeEvent> = None;
+}
+
+#[cfg(feature = "tools")]
+#[test]
+fn test_prelude_reexports_tools() {
+    let _: fn(&str) -> (SearchTools, CallTool) = get_tools;
+    let _: fn(&UnifaiConfig) -> Result<(SearchTools, CallTool), ConfigError> =
+        get_tools_from_config;
+    let _: Option<(CallToolArgs, SearchToolsArgs, ToolsError, UnifaiClient)> = None;
+}
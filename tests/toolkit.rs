@@ -3,10 +3,12 @@ use std::{
     time::{SystemTime, UNIX_EPOCH},
 };
 use thiserror::Error;
+#[cfg(feature = "test-utils")]
+use unifai_sdk::config::UnifaiConfig;
 use unifai_sdk::{
     rig::tool::Tool,
     serde::{Deserialize, Serialize},
-    serde_json::{json, Value},
+    serde_json::json,
     toolkit::{
         Action, ActionContext, ActionDefinition, ActionParams, ActionResult, ToolkitInfo,
         ToolkitService,
@@ -44,6 +46,7 @@ impl Action for EchoSlam {
                 }
             }),
             payment: None,
+            ..Default::default()
         }
     }
 
@@ -91,46 +94,221 @@ async fn test_toolkit() {
 
     service.add_action(EchoSlam);
 
-    let _ = service.start().await.unwrap();
-
-    let action_name = {
-        let search_tools = SearchTools::new(&unifai_agent_api_key);
-        let search_result = search_tools
-            .call(SearchToolsArgs {
-                query: unique_toolkit_name.clone(),
-                limit: None,
-            })
-            .await
-            .unwrap();
-
-        let search_result: Value = serde_json::from_str(&search_result).unwrap();
-
-        search_result
-            .as_array()
-            .unwrap()
-            .iter()
-            .find_map(|action| {
-                let action_name = action["action"].as_str().unwrap();
-                if action_name.contains(&unique_toolkit_name) {
-                    Some(action_name.to_string())
-                } else {
-                    None
-                }
-            })
-            .unwrap()
-    };
+    let _runner = service.start().await.unwrap();
 
+    let search_tools = SearchTools::new(&unifai_agent_api_key);
     let call_tool = CallTool::new(&unifai_agent_api_key);
+    let response = echo_through_tools(&search_tools, &call_tool, &unique_toolkit_name).await;
+
+    assert!(response.contains("How are you"));
+}
+
+#[cfg(feature = "test-utils")]
+#[tokio::test]
+async fn test_toolkit_loopback() {
+    let mut service = ToolkitService::new("loopback");
+    service.add_action(EchoSlam);
+
+    let (search_tools, call_tool) =
+        unifai_sdk::testing::loopback(service, &UnifaiConfig::default()).await;
+    let response = echo_through_tools(&search_tools, &call_tool, "echo").await;
+
+    assert!(response.contains("How are you"));
+
+    // Called by an identifier composed like the backend does, as in `test_toolkit`.
     let response = call_tool
         .call(CallToolArgs {
-            action: action_name,
+            action: "test_echo_1700000000/123/echo".to_string(),
             payload: json!({
-                "content": "How are you".to_string(),
+                "content": "Composed".to_string(),
             }),
             payment: None,
+            ..Default::default()
         })
         .await
         .unwrap();
 
-    assert!(response.contains("How are you"));
+    assert!(response.contains("Composed"));
+}
+
+#[cfg(feature = "test-utils")]
+#[tokio::test]
+async fn test_toolkit_harness() {
+    use unifai_sdk::toolkit::{
+        messages::{ActionCallParams, ToolkitMessage},
+        testing::ToolkitTestHarness,
+    };
+
+    let mut service = ToolkitService::new("offline");
+    service.add_action(EchoSlam);
+    let mut harness = ToolkitTestHarness::start(service).await.unwrap();
+
+    // The frames the server would send, and those the service answers with.
+    harness.send_raw(ToolkitMessage::Action {
+        data: ActionCallParams::new("echo")
+            .with_action_id(1)
+            .with_agent_id(7)
+            .with_payload(json!({ "content": "Offline" })),
+    });
+    let frame = harness.next_frame().await.unwrap();
+    let Ok(ToolkitMessage::ActionResult { data }) = ToolkitMessage::from_frame(frame.as_bytes())
+    else {
+        panic!("unexpected frame: {frame}");
+    };
+    assert_eq!(data.action_id, 1);
+    assert_eq!(
+        data.payload.get(),
+        r#""You are agent <$7>, you said \"Offline\".""#
+    );
+
+    harness.shutdown().await.unwrap();
+}
+
+#[cfg(feature = "test-utils")]
+#[tokio::test]
+async fn test_toolkit_channel_transport() {
+    use unifai_sdk::toolkit::{
+        messages::{ActionCallParams, ToolkitMessage},
+        testing::ChannelTransport,
+        ShutdownReason,
+    };
+
+    let mut service = ToolkitService::new("offline");
+    service.add_action(EchoSlam);
+    let (transport, mut peer) = ChannelTransport::new();
+    let handle = service.start_over(transport).await.unwrap();
+
+    let frame = peer.recv().await.unwrap();
+    let Ok(ToolkitMessage::RegisterActions { data }) = ToolkitMessage::from_frame(frame.as_bytes())
+    else {
+        panic!("unexpected frame: {frame}");
+    };
+    assert!(data.actions.contains_key("echo"));
+
+    let call = ToolkitMessage::Action {
+        data: ActionCallParams::new("echo")
+            .with_action_id(1)
+            .with_agent_id(7)
+            .with_payload(json!({ "content": "Offline" })),
+    };
+    peer.send(unifai_sdk::serde_json::to_string(&call).unwrap());
+    let frame = peer.recv().await.unwrap();
+    let Ok(ToolkitMessage::ActionResult { data }) = ToolkitMessage::from_frame(frame.as_bytes())
+    else {
+        panic!("unexpected frame: {frame}");
+    };
+    assert_eq!(data.action_id, 1);
+    assert_eq!(
+        data.payload.get(),
+        r#""You are agent <$7>, you said \"Offline\".""#
+    );
+
+    peer.close();
+    assert!(matches!(
+        handle.await.unwrap().unwrap(),
+        ShutdownReason::ServerClosed { code: None, .. }
+    ));
+}
+
+#[cfg(feature = "test-utils")]
+#[tokio::test]
+async fn test_toolkit_chaos_over_custom_transport() {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use unifai_sdk::toolkit::{
+        messages::{ActionCallParams, ToolkitMessage},
+        testing::{ChannelTransport, ChaosTransport, Direction, Fault, FaultPlan},
+        Incoming, ToolkitError, ToolkitTransport,
+    };
+
+    /// A transport of the application's own, counting the frames it sends.
+    struct Counting {
+        inner: ChannelTransport,
+        sent: Arc<AtomicUsize>,
+    }
+
+    impl ToolkitTransport for Counting {
+        async fn send(&mut self, text: String) -> Result<(), ToolkitError> {
+            self.sent.fetch_add(1, Ordering::SeqCst);
+            self.inner.send(text).await
+        }
+
+        async fn ping(&mut self) -> Result<(), ToolkitError> {
+            self.inner.ping().await
+        }
+
+        async fn recv(&mut self) -> Option<Result<Incoming, ToolkitError>> {
+            self.inner.recv().await
+        }
+
+        async fn close(&mut self) -> Result<(), ToolkitError> {
+            self.inner.close().await
+        }
+    }
+
+    let (inner, mut peer) = ChannelTransport::new();
+    let sent = Arc::new(AtomicUsize::new(0));
+    let plan = FaultPlan::seeded(0).with_fault_at(Direction::Outgoing, 0, Fault::Duplicate);
+    let transport = ChaosTransport::new(
+        Counting {
+            inner,
+            sent: sent.clone(),
+        },
+        plan,
+    );
+    let counters = transport.counters();
+    let mut service = ToolkitService::new("offline");
+    service.add_action(EchoSlam);
+    let handle = service.start_over(transport).await.unwrap();
+    peer.recv().await.unwrap();
+
+    let call = ToolkitMessage::Action {
+        data: ActionCallParams::new("echo")
+            .with_action_id(1)
+            .with_payload(json!({ "content": "Twice" })),
+    };
+    peer.send(unifai_sdk::serde_json::to_string(&call).unwrap());
+    let first = peer.recv().await.unwrap();
+    assert_eq!(peer.recv().await.unwrap(), first);
+
+    assert_eq!(counters.get().duplicated, 1);
+    // The registration, then the result twice.
+    assert_eq!(sent.load(Ordering::SeqCst), 3);
+    handle.shutdown().await.unwrap().unwrap();
+}
+
+/// Search for the echo action with `query`, and call it like an agent would.
+async fn echo_through_tools(
+    search_tools: &SearchTools,
+    call_tool: &CallTool,
+    query: &str,
+) -> String {
+    let search_result = search_tools
+        .search(&SearchToolsArgs {
+            query: query.to_string(),
+            limit: None,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+    let action_name = search_result
+        .into_iter()
+        .map(|tool| tool.action)
+        .find(|action_name| action_name.contains(query))
+        .unwrap();
+
+    call_tool
+        .call(CallToolArgs {
+            action: action_name,
+            payload: json!({
+                "content": "How are you".to_string(),
+            }),
+            payment: None,
+            ..Default::default()
+        })
+        .await
+        .unwrap()
 }